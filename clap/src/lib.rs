@@ -0,0 +1,524 @@
+// ---------------------------- CLAP plugin ----------------------------
+//
+// The pitch-to-note engine as a CLAP audio plugin: put it on a DAW track and
+// it passes the audio through untouched while sending a note on whenever a
+// note has been held in tune for a few analysis frames, and the note off when
+// it stops or changes. The host routes those notes anywhere (instruments,
+// MIDI out, automation); keystrokes stay with the standalone program.
+//
+// CLAP is a plain C ABI, so the few structures we need are declared here
+// instead of pulling in bindings.
+
+use rusty_strings_control::pitch::{self, PitchDetector};
+use rusty_strings_control::{Config, Detection};
+use std::ffi::{c_char, c_void, CStr};
+use std::ptr;
+
+// Channels passed through; the ports ask for stereo
+const MAX_CHANNELS: usize = 8;
+
+// ---------------------------- Engine ----------------------------
+
+enum NoteEvent {
+    On(i16, f64),
+    Off(i16),
+}
+
+struct Engine {
+    // The standalone program's defaults
+    settings: Detection,
+    hop_size: usize,
+    // Circular history of the last window of (mono) input
+    history: Vec<f32>,
+    pos: usize,
+    // Samples since the last analysis, and the window in time order
+    since_hop: usize,
+    window: Vec<f32>,
+    detector: pitch::Autocorr,
+    // Candidate note and how many frames in a row it was heard in tune
+    candidate: Option<i16>,
+    stable: usize,
+    // Frames without an in-tune note, and the note currently on
+    quiet: usize,
+    sounding: Option<i16>,
+}
+
+impl Engine {
+    fn new(sample_rate: f32) -> Self {
+        let settings = Config::default().detection();
+        let (min_hz, max_hz) = (settings.min_hz, settings.max_hz);
+        // As the standalone program: ~50 ms, but at least three periods of min_hz
+        let periods = (3.0 * sample_rate / min_hz.max(1.0)) as usize;
+        let window_size = ((sample_rate / 20.0) as usize).max(periods).next_power_of_two().clamp(1024, 16384);
+        let hop_size = (window_size / 4).min(sample_rate as usize / 50).max(1);
+        let mut detector = pitch::Autocorr::new(pitch::SearchRange { sample_rate, min_hz, max_hz });
+        detector.prepare(window_size);
+        Self {
+            settings,
+            hop_size,
+            history: vec![0.0; window_size],
+            pos: 0,
+            since_hop: 0,
+            window: vec![0.0; window_size],
+            detector,
+            candidate: None,
+            stable: 0,
+            quiet: 0,
+            sounding: None,
+        }
+    }
+
+    /// Forget the audio heard so far; returns the note that was on, which
+    /// still needs its note off.
+    fn reset(&mut self) -> Option<i16> {
+        self.history.iter_mut().for_each(|s| *s = 0.0);
+        self.since_hop = 0;
+        self.candidate = None;
+        self.stable = 0;
+        self.quiet = 0;
+        self.sounding.take()
+    }
+
+    /// Add one sample; after each hop, report what changed (at most an off
+    /// and an on).
+    fn push(&mut self, sample: f32, events: &mut Vec<NoteEvent>) {
+        let n = self.history.len();
+        self.history[self.pos] = sample;
+        self.pos = (self.pos + 1) % n;
+        self.since_hop += 1;
+        if self.since_hop < self.hop_size { return; }
+        self.since_hop = 0;
+
+        let (older, newer) = self.history.split_at(self.pos);
+        self.window[..newer.len()].copy_from_slice(newer);
+        self.window[newer.len()..].copy_from_slice(older);
+        let s = self.settings;
+        let heard = self.detector.detect(&self.window).filter(|e| e.clarity >= s.corr_threshold).and_then(|e| {
+            let midi = 69.0 + 12.0 * (e.hz / s.a4_hz).log2();
+            let key = midi.round();
+            ((midi - key).abs() * 100.0 <= s.tolerance_cents && (0.0..=127.0).contains(&key)).then_some(key as i16)
+        });
+
+        match heard {
+            Some(key) => {
+                self.quiet = 0;
+                if self.candidate == Some(key) {
+                    self.stable += 1;
+                } else {
+                    self.candidate = Some(key);
+                    self.stable = 1;
+                }
+                if self.stable >= s.note_hold_frames && self.sounding != Some(key) {
+                    if let Some(old) = self.sounding.take() { events.push(NoteEvent::Off(old)); }
+                    let level = (self.window.iter().map(|s| s * s).sum::<f32>() / n as f32).sqrt();
+                    events.push(NoteEvent::On(key, (level as f64 * 4.0).clamp(0.1, 1.0)));
+                    self.sounding = Some(key);
+                }
+            }
+            None => {
+                self.candidate = None;
+                self.stable = 0;
+                self.quiet += 1;
+                // Brief dropouts (bow changes) don't end the note
+                if self.quiet >= s.note_hold_frames {
+                    if let Some(old) = self.sounding.take() { events.push(NoteEvent::Off(old)); }
+                }
+            }
+        }
+    }
+}
+
+// ---------------------------- CLAP ABI ----------------------------
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ClapVersion {
+    major: u32,
+    minor: u32,
+    revision: u32,
+}
+
+const CLAP_VERSION: ClapVersion = ClapVersion { major: 1, minor: 2, revision: 0 };
+
+#[repr(C)]
+pub struct ClapPluginEntry {
+    clap_version: ClapVersion,
+    init: unsafe extern "C" fn(*const c_char) -> bool,
+    deinit: unsafe extern "C" fn(),
+    get_factory: unsafe extern "C" fn(*const c_char) -> *const c_void,
+}
+
+#[repr(C)]
+struct ClapPluginFactory {
+    get_plugin_count: unsafe extern "C" fn(*const ClapPluginFactory) -> u32,
+    get_plugin_descriptor: unsafe extern "C" fn(*const ClapPluginFactory, u32) -> *const ClapPluginDescriptor,
+    create_plugin: unsafe extern "C" fn(*const ClapPluginFactory, *const c_void, *const c_char) -> *const ClapPlugin,
+}
+
+#[repr(C)]
+struct ClapPluginDescriptor {
+    clap_version: ClapVersion,
+    id: *const c_char,
+    name: *const c_char,
+    vendor: *const c_char,
+    url: *const c_char,
+    manual_url: *const c_char,
+    support_url: *const c_char,
+    version: *const c_char,
+    description: *const c_char,
+    features: *const *const c_char,
+}
+
+// Only points at string literals
+unsafe impl Sync for ClapPluginDescriptor {}
+
+#[repr(C)]
+struct ClapPlugin {
+    desc: *const ClapPluginDescriptor,
+    plugin_data: *mut c_void,
+    init: unsafe extern "C" fn(*const ClapPlugin) -> bool,
+    destroy: unsafe extern "C" fn(*const ClapPlugin),
+    activate: unsafe extern "C" fn(*const ClapPlugin, f64, u32, u32) -> bool,
+    deactivate: unsafe extern "C" fn(*const ClapPlugin),
+    start_processing: unsafe extern "C" fn(*const ClapPlugin) -> bool,
+    stop_processing: unsafe extern "C" fn(*const ClapPlugin),
+    reset: unsafe extern "C" fn(*const ClapPlugin),
+    process: unsafe extern "C" fn(*const ClapPlugin, *const ClapProcess) -> i32,
+    get_extension: unsafe extern "C" fn(*const ClapPlugin, *const c_char) -> *const c_void,
+    on_main_thread: unsafe extern "C" fn(*const ClapPlugin),
+}
+
+#[repr(C)]
+struct ClapProcess {
+    steady_time: i64,
+    frames_count: u32,
+    transport: *const c_void,
+    audio_inputs: *const ClapAudioBuffer,
+    audio_outputs: *mut ClapAudioBuffer,
+    audio_inputs_count: u32,
+    audio_outputs_count: u32,
+    in_events: *const c_void,
+    out_events: *const ClapOutputEvents,
+}
+
+#[repr(C)]
+struct ClapAudioBuffer {
+    data32: *mut *mut f32,
+    data64: *mut *mut f64,
+    channel_count: u32,
+    latency: u32,
+    constant_mask: u64,
+}
+
+#[repr(C)]
+struct ClapOutputEvents {
+    ctx: *mut c_void,
+    try_push: unsafe extern "C" fn(*const ClapOutputEvents, *const ClapEventHeader) -> bool,
+}
+
+#[repr(C)]
+struct ClapEventHeader {
+    size: u32,
+    time: u32,
+    space_id: u16,
+    event_type: u16,
+    flags: u32,
+}
+
+#[repr(C)]
+struct ClapEventNote {
+    header: ClapEventHeader,
+    note_id: i32,
+    port_index: i16,
+    channel: i16,
+    key: i16,
+    velocity: f64,
+}
+
+const CLAP_EVENT_NOTE_ON: u16 = 0;
+const CLAP_EVENT_NOTE_OFF: u16 = 1;
+const CLAP_PROCESS_CONTINUE: i32 = 1;
+const CLAP_NAME_SIZE: usize = 256;
+const CLAP_AUDIO_PORT_IS_MAIN: u32 = 1;
+const CLAP_NOTE_DIALECT_CLAP: u32 = 1;
+const CLAP_NOTE_DIALECT_MIDI: u32 = 2;
+
+#[repr(C)]
+struct ClapPluginAudioPorts {
+    count: unsafe extern "C" fn(*const ClapPlugin, bool) -> u32,
+    get: unsafe extern "C" fn(*const ClapPlugin, u32, bool, *mut ClapAudioPortInfo) -> bool,
+}
+
+#[repr(C)]
+struct ClapAudioPortInfo {
+    id: u32,
+    name: [c_char; CLAP_NAME_SIZE],
+    flags: u32,
+    channel_count: u32,
+    port_type: *const c_char,
+    in_place_pair: u32,
+}
+
+#[repr(C)]
+struct ClapPluginNotePorts {
+    count: unsafe extern "C" fn(*const ClapPlugin, bool) -> u32,
+    get: unsafe extern "C" fn(*const ClapPlugin, u32, bool, *mut ClapNotePortInfo) -> bool,
+}
+
+#[repr(C)]
+struct ClapNotePortInfo {
+    id: u32,
+    supported_dialects: u32,
+    preferred_dialect: u32,
+    name: [c_char; CLAP_NAME_SIZE],
+}
+
+// ---------------------------- Entry points ----------------------------
+
+macro_rules! cstr {
+    ($s:literal) => {
+        concat!($s, "\0").as_ptr() as *const c_char
+    };
+}
+
+struct Features([*const c_char; 5]);
+// Only points at string literals
+unsafe impl Sync for Features {}
+
+static FEATURES: Features =
+    Features([cstr!("audio-effect"), cstr!("analyzer"), cstr!("note-detector"), cstr!("stereo"), ptr::null()]);
+
+static DESCRIPTOR: ClapPluginDescriptor = ClapPluginDescriptor {
+    clap_version: CLAP_VERSION,
+    id: cstr!("com.github.grahampaasch.rusty-strings"),
+    name: cstr!("Rusty Strings"),
+    vendor: cstr!("Rusty Strings Control"),
+    url: cstr!("https://github.com/GrahamPaasch/rusty-strings-control"),
+    manual_url: cstr!(""),
+    support_url: cstr!(""),
+    version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+    description: cstr!("Sends a note for every note held in tune"),
+    features: &FEATURES.0 as *const _ as *const *const c_char,
+};
+
+static FACTORY: ClapPluginFactory = ClapPluginFactory { get_plugin_count, get_plugin_descriptor, create_plugin };
+
+static AUDIO_PORTS: ClapPluginAudioPorts = ClapPluginAudioPorts { count: audio_ports_count, get: audio_ports_get };
+static NOTE_PORTS: ClapPluginNotePorts = ClapPluginNotePorts { count: note_ports_count, get: note_ports_get };
+
+/// The symbol CLAP hosts look up in the library.
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+pub static clap_entry: ClapPluginEntry = ClapPluginEntry { clap_version: CLAP_VERSION, init, deinit, get_factory };
+
+unsafe extern "C" fn init(_path: *const c_char) -> bool {
+    true
+}
+
+unsafe extern "C" fn deinit() {}
+
+unsafe extern "C" fn get_factory(id: *const c_char) -> *const c_void {
+    if !id.is_null() && CStr::from_ptr(id) == c"clap.plugin-factory" {
+        &FACTORY as *const _ as *const c_void
+    } else {
+        ptr::null()
+    }
+}
+
+unsafe extern "C" fn get_plugin_count(_factory: *const ClapPluginFactory) -> u32 {
+    1
+}
+
+unsafe extern "C" fn get_plugin_descriptor(_factory: *const ClapPluginFactory, index: u32) -> *const ClapPluginDescriptor {
+    if index == 0 { &DESCRIPTOR } else { ptr::null() }
+}
+
+// The host sees `clap`; `plugin_data` points back at the whole instance
+struct Instance {
+    clap: ClapPlugin,
+    engine: Option<Engine>,
+    events: Vec<NoteEvent>,
+    // A note left on by a reset, stop or deactivation; its note off goes
+    // out with the next process call, the only place events can be sent
+    pending_off: Option<i16>,
+    // The buffers' channels during process, kept to not allocate there
+    ins: Vec<*mut f32>,
+    outs: Vec<*mut f32>,
+}
+
+unsafe extern "C" fn create_plugin(
+    _factory: *const ClapPluginFactory,
+    _host: *const c_void,
+    id: *const c_char,
+) -> *const ClapPlugin {
+    if id.is_null() || CStr::from_ptr(id) != CStr::from_ptr(DESCRIPTOR.id) {
+        return ptr::null();
+    }
+    let instance = Box::into_raw(Box::new(Instance {
+        clap: ClapPlugin {
+            desc: &DESCRIPTOR,
+            plugin_data: ptr::null_mut(),
+            init: plugin_init,
+            destroy: plugin_destroy,
+            activate: plugin_activate,
+            deactivate: plugin_deactivate,
+            start_processing: plugin_start_processing,
+            stop_processing: plugin_stop_processing,
+            reset: plugin_reset,
+            process: plugin_process,
+            get_extension: plugin_get_extension,
+            on_main_thread: plugin_on_main_thread,
+        },
+        engine: None,
+        events: Vec::with_capacity(4),
+        pending_off: None,
+        ins: Vec::with_capacity(MAX_CHANNELS),
+        outs: Vec::with_capacity(MAX_CHANNELS),
+    }));
+    (*instance).clap.plugin_data = instance as *mut c_void;
+    &(*instance).clap
+}
+
+unsafe fn instance<'a>(plugin: *const ClapPlugin) -> &'a mut Instance {
+    &mut *((*plugin).plugin_data as *mut Instance)
+}
+
+unsafe extern "C" fn plugin_init(_plugin: *const ClapPlugin) -> bool {
+    true
+}
+
+unsafe extern "C" fn plugin_destroy(plugin: *const ClapPlugin) {
+    drop(Box::from_raw((*plugin).plugin_data as *mut Instance));
+}
+
+unsafe extern "C" fn plugin_activate(plugin: *const ClapPlugin, sample_rate: f64, _min: u32, _max: u32) -> bool {
+    // Allocate here: the audio thread must not
+    instance(plugin).engine = Some(Engine::new(sample_rate as f32));
+    true
+}
+
+unsafe extern "C" fn plugin_deactivate(plugin: *const ClapPlugin) {
+    let inst = instance(plugin);
+    if let Some(note) = inst.engine.take().and_then(|e| e.sounding) { inst.pending_off = Some(note); }
+}
+
+unsafe extern "C" fn plugin_start_processing(_plugin: *const ClapPlugin) -> bool {
+    true
+}
+
+unsafe extern "C" fn plugin_stop_processing(plugin: *const ClapPlugin) {
+    plugin_reset(plugin);
+}
+
+unsafe extern "C" fn plugin_reset(plugin: *const ClapPlugin) {
+    let inst = instance(plugin);
+    if let Some(note) = inst.engine.as_mut().and_then(Engine::reset) { inst.pending_off = Some(note); }
+}
+
+unsafe extern "C" fn plugin_on_main_thread(_plugin: *const ClapPlugin) {}
+
+unsafe extern "C" fn plugin_get_extension(_plugin: *const ClapPlugin, id: *const c_char) -> *const c_void {
+    if id.is_null() { return ptr::null(); }
+    match CStr::from_ptr(id).to_bytes() {
+        b"clap.audio-ports" => &AUDIO_PORTS as *const _ as *const c_void,
+        b"clap.note-ports" => &NOTE_PORTS as *const _ as *const c_void,
+        _ => ptr::null(),
+    }
+}
+
+unsafe extern "C" fn plugin_process(plugin: *const ClapPlugin, process: *const ClapProcess) -> i32 {
+    let inst = instance(plugin);
+    let Some(engine) = inst.engine.as_mut() else { return CLAP_PROCESS_CONTINUE };
+    let p = &*process;
+    if let Some(note) = inst.pending_off.take() {
+        if !p.out_events.is_null() { send_note(&*p.out_events, 0, NoteEvent::Off(note)); }
+    }
+    let frames = p.frames_count as usize;
+    let input = (p.audio_inputs_count > 0 && !p.audio_inputs.is_null()).then(|| &*p.audio_inputs);
+    let output = (p.audio_outputs_count > 0 && !p.audio_outputs.is_null()).then(|| &*p.audio_outputs);
+
+    let channels = |buf: Option<&ClapAudioBuffer>, into: &mut Vec<*mut f32>| {
+        into.clear();
+        let Some(buf) = buf.filter(|b| !b.data32.is_null()) else { return };
+        let count = (buf.channel_count as usize).min(MAX_CHANNELS);
+        into.extend((0..count).map(|c| *buf.data32.add(c)).filter(|d| !d.is_null()));
+    };
+    channels(input, &mut inst.ins);
+    channels(output, &mut inst.outs);
+    let (ins, outs) = (&inst.ins, &inst.outs);
+
+    for i in 0..frames {
+        let mono = if ins.is_empty() {
+            0.0
+        } else {
+            ins.iter().map(|c| *c.add(i)).sum::<f32>() / ins.len() as f32
+        };
+        // Pass the audio through (a no-op when the host processes in place)
+        for (o, out) in outs.iter().enumerate() {
+            *out.add(i) = ins.get(o).or(ins.first()).map_or(0.0, |c| *c.add(i));
+        }
+        engine.push(mono, &mut inst.events);
+        for ev in inst.events.drain(..) {
+            if !p.out_events.is_null() { send_note(&*p.out_events, i as u32, ev); }
+        }
+    }
+    CLAP_PROCESS_CONTINUE
+}
+
+unsafe fn send_note(out: &ClapOutputEvents, time: u32, ev: NoteEvent) {
+    let (event_type, key, velocity) = match ev {
+        NoteEvent::On(k, v) => (CLAP_EVENT_NOTE_ON, k, v),
+        NoteEvent::Off(k) => (CLAP_EVENT_NOTE_OFF, k, 0.0),
+    };
+    let note = ClapEventNote {
+        header: ClapEventHeader {
+            size: std::mem::size_of::<ClapEventNote>() as u32,
+            time,
+            space_id: 0,
+            event_type,
+            flags: 0,
+        },
+        note_id: -1,
+        port_index: 0,
+        channel: 0,
+        key,
+        velocity,
+    };
+    (out.try_push)(out, &note.header);
+}
+
+unsafe extern "C" fn audio_ports_count(_plugin: *const ClapPlugin, _is_input: bool) -> u32 {
+    1
+}
+
+unsafe extern "C" fn audio_ports_get(_plugin: *const ClapPlugin, index: u32, is_input: bool, info: *mut ClapAudioPortInfo) -> bool {
+    if index != 0 || info.is_null() { return false; }
+    let info = &mut *info;
+    info.id = 0;
+    write_name(&mut info.name, if is_input { "Instrument in" } else { "Instrument out" });
+    info.flags = CLAP_AUDIO_PORT_IS_MAIN;
+    info.channel_count = 2;
+    info.port_type = cstr!("stereo");
+    info.in_place_pair = 0;
+    true
+}
+
+unsafe extern "C" fn note_ports_count(_plugin: *const ClapPlugin, is_input: bool) -> u32 {
+    u32::from(!is_input)
+}
+
+unsafe extern "C" fn note_ports_get(_plugin: *const ClapPlugin, index: u32, is_input: bool, info: *mut ClapNotePortInfo) -> bool {
+    if is_input || index != 0 || info.is_null() { return false; }
+    let info = &mut *info;
+    info.id = 0;
+    info.supported_dialects = CLAP_NOTE_DIALECT_CLAP | CLAP_NOTE_DIALECT_MIDI;
+    info.preferred_dialect = CLAP_NOTE_DIALECT_CLAP;
+    write_name(&mut info.name, "Detected notes");
+    true
+}
+
+fn write_name(dst: &mut [c_char; CLAP_NAME_SIZE], name: &str) {
+    let n = name.len().min(CLAP_NAME_SIZE - 1);
+    for (d, b) in dst.iter_mut().zip(name.bytes().take(n)) { *d = b as c_char; }
+    dst[n] = 0;
+}
@@ -0,0 +1,147 @@
+// ---------------------------- Articulation ----------------------------
+//
+// Classifies how each note was started from the level envelope after its
+// attack: a pluck jumps to full level at once, a strum smears the attack over
+// several strings, a bowed note swells in slowly, and a palm-muted chug
+// dies away almost immediately.
+
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ArticulationConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    // Level jump (ratio over the recent minimum) that counts as an attack
+    #[serde(default = "default_onset_ratio")]
+    pub onset_ratio: f32,
+    // Rise times at or above these read as a strum / a bowed swell
+    #[serde(default = "default_strum_rise_ms")]
+    pub strum_rise_ms: u64,
+    #[serde(default = "default_bowed_rise_ms")]
+    pub bowed_rise_ms: u64,
+    // Level 120 ms after the peak, relative to the peak, below which a note is muted
+    #[serde(default = "default_muted_decay")]
+    pub muted_decay: f32,
+}
+
+fn default_onset_ratio() -> f32 { 2.0 }
+fn default_strum_rise_ms() -> u64 { 20 }
+fn default_bowed_rise_ms() -> u64 { 80 }
+fn default_muted_decay() -> f32 { 0.35 }
+
+impl Default for ArticulationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            onset_ratio: default_onset_ratio(),
+            strum_rise_ms: default_strum_rise_ms(),
+            bowed_rise_ms: default_bowed_rise_ms(),
+            muted_decay: default_muted_decay(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Articulation {
+    Pluck,
+    Strum,
+    Bowed,
+    Muted,
+}
+
+impl Articulation {
+    /// Name used in note_map keys, e.g. "A3:muted".
+    pub fn name(self) -> &'static str {
+        match self {
+            Articulation::Pluck => "pluck",
+            Articulation::Strum => "strum",
+            Articulation::Bowed => "bowed",
+            Articulation::Muted => "muted",
+        }
+    }
+}
+
+/// Whether a key qualifier ("A3:muted" -> "muted") names an articulation.
+pub fn is_name(qualifier: &str) -> bool {
+    matches!(qualifier, "pluck" | "strum" | "bowed" | "muted")
+}
+
+// Time after the peak at which decay is judged
+const DECAY_PROBE: Duration = Duration::from_millis(120);
+// Give up waiting for a peak after this long (a very slow swell)
+const MAX_RISE: Duration = Duration::from_millis(400);
+// Hops of envelope history used as the "before the attack" reference
+const HISTORY: usize = 6;
+
+struct Attack {
+    start: Instant,
+    peak: f32,
+    peak_at: Instant,
+}
+
+pub struct ArticulationDetector {
+    onset_ratio: f32,
+    strum_rise: Duration,
+    bowed_rise: Duration,
+    muted_decay: f32,
+    floor: f32,
+    history: Vec<f32>,
+    attack: Option<Attack>,
+    last: Option<Articulation>,
+}
+
+impl ArticulationDetector {
+    pub fn new(cfg: &ArticulationConfig, floor: f32) -> Self {
+        Self {
+            onset_ratio: cfg.onset_ratio,
+            strum_rise: Duration::from_millis(cfg.strum_rise_ms),
+            bowed_rise: Duration::from_millis(cfg.bowed_rise_ms),
+            muted_decay: cfg.muted_decay,
+            floor: floor.max(1e-4),
+            history: Vec::with_capacity(HISTORY),
+            attack: None,
+            last: None,
+        }
+    }
+
+    /// Feed the RMS level of the newest hop.
+    pub fn update(&mut self, level: f32, now: Instant) {
+        let reference = self.history.iter().copied().fold(f32::MAX, f32::min);
+        if self.history.len() == HISTORY { self.history.remove(0); }
+        self.history.push(level);
+
+        if let Some(a) = self.attack.as_mut() {
+            if level > a.peak {
+                a.peak = level;
+                a.peak_at = now;
+            }
+            let rise = a.peak_at.duration_since(a.start);
+            let since_peak = now.duration_since(a.peak_at);
+            if since_peak >= DECAY_PROBE || now.duration_since(a.start) >= MAX_RISE {
+                let kind = if rise >= self.bowed_rise {
+                    Articulation::Bowed
+                } else if level < self.muted_decay * a.peak {
+                    Articulation::Muted
+                } else if rise >= self.strum_rise {
+                    Articulation::Strum
+                } else {
+                    Articulation::Pluck
+                };
+                self.last = Some(kind);
+                self.attack = None;
+            }
+            return;
+        }
+
+        if level > self.floor && reference < f32::MAX && level > self.onset_ratio * reference.max(self.floor / 4.0) {
+            self.attack = Some(Attack { start: now, peak: level, peak_at: now });
+            self.last = None;
+        }
+    }
+
+    /// Articulation of the latest attack, or None while it is still being judged.
+    pub fn current(&self) -> Option<Articulation> { self.last }
+
+    pub fn pending(&self) -> bool { self.attack.is_some() }
+}
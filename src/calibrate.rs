@@ -0,0 +1,232 @@
+// ---------------------------- Calibration ----------------------------
+//
+// `calibrate` measures the room and the player: a few seconds of silence give
+// the noise floor and how confidently (and for how long) noise passes for a
+// pitch; then each mapped note is played a few times to see how far the
+// player's intonation spreads, how clearly the detector hears the note, and
+// how long each attack stays on it. The suggestions are written into the
+// note_map entries of config.toml, keeping the rest of the file as it was.
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::path::Path;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CalibrateConfig {
+    // Attacks to collect per note before moving on
+    #[serde(default = "default_repeats")]
+    pub repeats: usize,
+    // Give up on a note after this many seconds
+    #[serde(default = "default_note_secs")]
+    pub note_secs: u64,
+    // Length of the silent noise measurement
+    #[serde(default = "default_noise_secs")]
+    pub noise_secs: u64,
+    // Measure the noise at every start and gate min_rms above it, for that
+    // session only
+    #[serde(default)]
+    pub noise_on_start: bool,
+}
+
+fn default_repeats() -> usize { 5 }
+fn default_note_secs() -> u64 { 15 }
+fn default_noise_secs() -> u64 { 3 }
+
+impl Default for CalibrateConfig {
+    fn default() -> Self {
+        Self {
+            repeats: default_repeats(),
+            note_secs: default_note_secs(),
+            noise_secs: default_noise_secs(),
+            noise_on_start: false,
+        }
+    }
+}
+
+// Fewest on-note frames worth making suggestions from
+const MIN_FRAMES: usize = 5;
+
+/// Detector behavior while the room is quiet.
+#[derive(Default)]
+pub struct NoiseStats {
+    levels: Vec<f32>,
+    clarities: Vec<f32>,
+    // Longest run of frames on one (spurious) note, and the current run
+    longest_run: usize,
+    run: Option<(i32, usize)>,
+}
+
+impl NoiseStats {
+    /// One frame: its level and the detected note and clarity, if any.
+    pub fn record(&mut self, level: f32, detected: Option<(i32, f32)>) {
+        self.levels.push(level);
+        self.run = match (detected, self.run) {
+            (Some((midi, clarity)), run) => {
+                self.clarities.push(clarity);
+                let len = match run {
+                    Some((m, n)) if m == midi => n + 1,
+                    _ => 1,
+                };
+                self.longest_run = self.longest_run.max(len);
+                Some((midi, len))
+            }
+            (None, _) => None,
+        };
+    }
+
+    /// Typical loud end of the noise (95th percentile RMS).
+    pub fn level(&self) -> f32 {
+        percentile(&self.levels, 0.95).unwrap_or(0.0)
+    }
+
+    /// Share of frames in which the noise produced a pitch.
+    pub fn false_rate(&self) -> f32 {
+        self.clarities.len() as f32 / self.levels.len().max(1) as f32
+    }
+}
+
+/// Frames heard while the player repeats one note.
+pub struct NoteStats {
+    target: i32,
+    cents: Vec<f32>,
+    clarities: Vec<f32>,
+    levels: Vec<f32>,
+    // Lengths of finished runs on the target note, and the current run
+    runs: Vec<usize>,
+    run: usize,
+}
+
+impl NoteStats {
+    pub fn new(target: i32) -> Self {
+        Self { target, cents: Vec::new(), clarities: Vec::new(), levels: Vec::new(), runs: Vec::new(), run: 0 }
+    }
+
+    /// One frame: its level and the detected (midi, cents, clarity), if any.
+    pub fn record(&mut self, level: f32, detected: Option<(i32, f32, f32)>) {
+        match detected {
+            Some((midi, cents, clarity)) if midi == self.target => {
+                self.cents.push(cents);
+                self.clarities.push(clarity);
+                self.levels.push(level);
+                self.run += 1;
+            }
+            _ => self.end_run(),
+        }
+    }
+
+    fn end_run(&mut self) {
+        // A single frame is a passing transient, not an attack
+        if self.run >= 2 { self.runs.push(self.run); }
+        self.run = 0;
+    }
+
+    /// Attacks recorded so far.
+    pub fn attacks(&self) -> usize {
+        self.runs.len()
+    }
+
+    /// Suggested settings for this note, if it was heard often enough.
+    pub fn suggest(mut self, noise: &NoiseStats) -> Option<Suggestion> {
+        self.end_run();
+        if self.cents.len() < MIN_FRAMES { return None; }
+        let spread: Vec<f32> = self.cents.iter().map(|c| c.abs()).collect();
+        let spread = percentile(&spread, 0.95)?;
+        // Cover 95% of the player's frames plus a small margin; past 50 cents
+        // the frame would belong to the neighboring note anyway
+        let tolerance_cents = (spread + 5.0).ceil().clamp(10.0, 50.0);
+
+        // Halfway between the noise's best and the note's weak frames keeps
+        // both error margins equal; if they overlap, favor hearing the note
+        let weak = percentile(&self.clarities, 0.1)?;
+        let noise_best = percentile(&noise.clarities, 0.99).unwrap_or(0.0);
+        let corr = if weak > noise_best { (weak + noise_best) / 2.0 } else { weak * 0.95 };
+        let corr_threshold = ((corr * 100.0).round() / 100.0).clamp(0.1, 0.95);
+
+        // Longer than any spurious run in the noise, but short enough that
+        // three quarters of the attacks last long enough to trigger
+        let short_run = self.runs.iter().copied().map(|r| r as f32).collect::<Vec<_>>();
+        let short_run = percentile(&short_run, 0.25).map_or(1, |r| r as usize);
+        let note_hold_frames = (noise.longest_run + 1).max(2).min(short_run.max(1));
+
+        Some(Suggestion {
+            tolerance_cents,
+            corr_threshold,
+            note_hold_frames,
+            spread,
+            quiet_level: percentile(&self.levels, 0.05).unwrap_or(0.0),
+            attacks: self.runs.len(),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Suggestion {
+    pub tolerance_cents: f32,
+    pub corr_threshold: f32,
+    pub note_hold_frames: usize,
+    // 95th percentile intonation error (cents) and quietest on-note level
+    pub spread: f32,
+    pub quiet_level: f32,
+    pub attacks: usize,
+}
+
+/// A min_rms 6 dB above the loud end of the measured noise.
+pub fn noise_gate(noise: &NoiseStats) -> f32 {
+    (noise.level() * 2.0 * 10_000.0).ceil() / 10_000.0
+}
+
+/// A min_rms that gates the measured noise but none of the played notes.
+pub fn suggest_min_rms(noise: &NoiseStats, notes: &[(String, Suggestion)]) -> Option<f32> {
+    let quietest = notes.iter().map(|(_, s)| s.quiet_level).fold(f32::INFINITY, f32::min);
+    let gate = noise_gate(noise);
+    // Leave a 6 dB margin below the quietest note
+    (gate > 0.0 && quietest.is_finite() && gate * 2.0 <= quietest).then_some(gate)
+}
+
+/// Write min_rms alone (`calibrate-noise`), preserving comments.
+pub fn save_min_rms(path: &Path, min_rms: f32) -> Result<()> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("Reading {}", path.display()))?;
+    let mut doc: toml_edit::DocumentMut = text.parse().with_context(|| format!("Parsing {}", path.display()))?;
+    doc["min_rms"] = toml_edit::value((min_rms as f64 * 10_000.0).round() / 10_000.0);
+    std::fs::write(path, doc.to_string()).with_context(|| format!("Writing {}", path.display()))
+}
+
+/// Write the suggestions into the note_map entries (every key for the note,
+/// so "E3@5" and "E3:muted" get them too) and min_rms, preserving comments.
+pub fn write_back(path: &Path, notes: &[(String, Suggestion)], min_rms: Option<f32>) -> Result<usize> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("Reading {}", path.display()))?;
+    let mut doc: toml_edit::DocumentMut = text.parse().with_context(|| format!("Parsing {}", path.display()))?;
+    let round = |x: f32, places: i32| {
+        let scale = 10f64.powi(places);
+        (x as f64 * scale).round() / scale
+    };
+    let mut updated = 0;
+    let map = doc
+        .get_mut("note_map")
+        .and_then(|m| m.as_table_like_mut())
+        .ok_or_else(|| anyhow!("{} has no [note_map] to update", path.display()))?;
+    for (key, entry) in map.iter_mut() {
+        let normalized = crate::strings::normalize_key(key.get());
+        let Some((_, s)) = notes.iter().find(|(n, _)| n == crate::key_note(&normalized)) else { continue };
+        let Some(entry) = entry.as_table_like_mut() else { continue };
+        entry.insert("tolerance_cents", toml_edit::value(round(s.tolerance_cents, 0)));
+        entry.insert("corr_threshold", toml_edit::value(round(s.corr_threshold, 2)));
+        entry.insert("note_hold_frames", toml_edit::value(s.note_hold_frames as i64));
+        entry.fmt();
+        updated += 1;
+    }
+    if let Some(level) = min_rms {
+        doc["min_rms"] = toml_edit::value(round(level, 4));
+    }
+    std::fs::write(path, doc.to_string()).with_context(|| format!("Writing {}", path.display()))?;
+    Ok(updated)
+}
+
+// Nearest-rank percentile (q in 0..1)
+fn percentile(values: &[f32], q: f32) -> Option<f32> {
+    if values.is_empty() { return None; }
+    let mut v = values.to_vec();
+    v.sort_by(|a, b| a.total_cmp(b));
+    let i = ((q * v.len() as f32).ceil() as usize).clamp(1, v.len()) - 1;
+    Some(v[i])
+}
@@ -0,0 +1,447 @@
+// ---------------------------- Cheat sheet ----------------------------
+//
+// `export cheatsheet` prints the mappings as a chart to keep on the music
+// stand: every note drawn on a staff (and on a fretboard when [strings] is
+// set up) next to what it does. There is one section per mapping layer (the
+// note_map and each profile layered over it, per performer if there are any).
+// HTML and PDF share one set of drawing primitives; Markdown is text only.
+
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::{action_name, chords, key_note, note_keys, metronome::Quantize, note_to_midi, notation, polyphony, strings, Config, Mapping};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Html,
+    Markdown,
+    Pdf,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "html" => Ok(Self::Html),
+            "md" | "markdown" => Ok(Self::Markdown),
+            "pdf" => Ok(Self::Pdf),
+            other => Err(anyhow!("Unknown cheat sheet format {other:?} (html, md, pdf)")),
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Html => "html",
+            Self::Markdown => "md",
+            Self::Pdf => "pdf",
+        }
+    }
+}
+
+struct Entry {
+    key: String,
+    // MIDI note; None for "tap"/"slap"
+    note: Option<i32>,
+    // (string, fret) when [strings] is enabled
+    position: Option<(usize, i32)>,
+    action: String,
+}
+
+pub struct Layer {
+    title: String,
+    entries: Vec<Entry>,
+    // Strings on the fretboard diagrams
+    strings: usize,
+}
+
+/// The resolved mapping layers of `cfg`, as trigger mode would use them.
+pub fn layers(cfg: &Config) -> Vec<Layer> {
+    if !cfg.performers.is_empty() {
+        return cfg
+            .performers
+            .iter()
+            .flat_map(|p| {
+                let name = p.performer.as_deref().unwrap_or_default().to_string();
+                config_layers(p).into_iter().map(move |mut l| {
+                    l.title = format!("{name}: {}", l.title);
+                    l
+                })
+            })
+            .collect();
+    }
+    config_layers(cfg)
+}
+
+fn config_layers(cfg: &Config) -> Vec<Layer> {
+    let open: Vec<i32> = cfg.strings.tuning.iter().filter_map(|n| note_to_midi(n)).collect();
+    let fretboard = cfg
+        .strings
+        .enabled
+        .then(|| (strings::StringEstimator::new(open.clone(), cfg.strings.frets, None), open.clone()));
+    let label = |m: &Mapping| {
+        let mut action = action_name(&m.action);
+        if m.holds() { action.push_str(" (held)"); }
+        match m.quantize {
+            Quantize::Off => {}
+            Quantize::Beat => action.push_str(" (on the beat)"),
+            Quantize::Bar => action.push_str(" (on the bar)"),
+        }
+        if let Some(release) = &m.on_release { action = format!("{action}, on release {}", action_name(release)); }
+        action
+    };
+    let layer = |title: String, map: &HashMap<String, Mapping>| {
+        let mut entries: Vec<Entry> = map
+            .iter()
+            .map(|(key, m)| {
+                let note = note_to_midi(key_note(key));
+                let position = fretboard.as_ref().zip(note).and_then(|((est, open), midi)| {
+                    // "E3@5" names its string; otherwise the lowest fret position
+                    let string = match key.split_once('@') {
+                        Some((_, s)) => s.split(':').next()?.parse().ok()?,
+                        None => est.estimate(midi, None)?,
+                    };
+                    Some((string, midi - open.get(string.checked_sub(1)?)?))
+                });
+                Entry { key: key.clone(), note, position, action: label(m) }
+            })
+            .collect();
+        // Low to high, unpitched keys last
+        entries.sort_by(|a, b| {
+            let rank = |e: &Entry| e.note.unwrap_or(i32::MAX);
+            rank(a).cmp(&rank(b)).then_with(|| a.key.cmp(&b.key))
+        });
+        // Split zone catch-alls, sequences and rhythms go with the unpitched keys
+        if let Some(point) = &cfg.split.point {
+            let point = notation::spell(point);
+            let zones = [
+                (format!("other notes below {point}"), &cfg.split.lower),
+                (format!("other notes from {point} up"), &cfg.split.upper),
+            ];
+            for (key, m) in zones {
+                let Some(m) = m else { continue };
+                entries.push(Entry { key, note: None, position: None, action: label(m) });
+            }
+        }
+        for sequence in &cfg.sequences {
+            let action = format!("{} (within {} ms)", label(&sequence.mapping), sequence.within_ms);
+            entries.push(Entry { key: sequence.notes.join(" "), note: None, position: None, action });
+        }
+        for rhythm in &cfg.rhythms {
+            let action = format!("{} (within {} ms)", label(&rhythm.mapping), rhythm.within_ms);
+            entries.push(Entry { key: rhythm.label(), note: None, position: None, action });
+        }
+        Layer { title, entries, strings: open.len() }
+    };
+    let mut layers = vec![layer("note_map".to_string(), &cfg.note_map)];
+    for (name, p) in &cfg.profiles {
+        let mut map = cfg.note_map.clone();
+        map.extend(p.note_map.clone());
+        layers.push(layer(format!("profile {name}"), &map));
+    }
+    layers
+}
+
+pub fn render(layers: &[Layer], format: Format) -> Vec<u8> {
+    match format {
+        Format::Markdown => markdown(layers).into_bytes(),
+        Format::Html => html(layers).into_bytes(),
+        Format::Pdf => pdf(layers),
+    }
+}
+
+fn position_text(position: Option<(usize, i32)>) -> String {
+    match position {
+        Some((s, 0)) => format!("{} string, open", strings::ordinal(s)),
+        Some((s, f)) => format!("{} string, fret {f}", strings::ordinal(s)),
+        None => String::new(),
+    }
+}
+
+fn markdown(layers: &[Layer]) -> String {
+    let mut out = String::from("# Rusty Strings Control mappings\n");
+    for layer in layers {
+        let frets = layer.entries.iter().any(|e| e.position.is_some());
+        let _ = writeln!(out, "\n## {}\n", layer.title);
+        if frets {
+            out.push_str("| Key | Note | Position | Action |\n|---|---|---|---|\n");
+        } else {
+            out.push_str("| Key | Note | Action |\n|---|---|---|\n");
+        }
+        for e in &layer.entries {
+            let note = match e.note {
+                Some(midi) => notation::spell_midi(midi),
+                None if matches!(key_note(&e.key), "tap" | "slap") => "(unpitched)".to_string(),
+                None if chords::parse(&e.key).is_some() => "(chord)".to_string(),
+                None if polyphony::parse(&e.key).is_some() => "(together)".to_string(),
+                None => match note_keys::parse(&e.key) {
+                    Some(note_keys::Pattern::PitchClass(_)) => "(any octave)".to_string(),
+                    Some(note_keys::Pattern::Range(..)) => "(range)".to_string(),
+                    None if e.key.split(' ').all(|n| note_to_midi(n).is_some()) => "(sequence)".to_string(),
+                    None => "(any)".to_string(),
+                },
+            };
+            let action = e.action.replace('|', "\\|");
+            if frets {
+                let _ = writeln!(out, "| `{}` | {note} | {} | {action} |", e.key, position_text(e.position));
+            } else {
+                let _ = writeln!(out, "| `{}` | {note} | {action} |", e.key);
+            }
+        }
+    }
+    out
+}
+
+// ---------------------------- Drawing ----------------------------
+
+enum Shape {
+    Line(f32, f32, f32, f32),
+    // Note head / fret dot: center, radii, filled
+    Ellipse(f32, f32, f32, f32, bool),
+    // Baseline start, font size, text
+    Text(f32, f32, f32, String),
+}
+
+const ROW_HEIGHT: f32 = 64.0;
+const STAFF_SPACE: f32 = 6.0;
+
+// Diatonic step of a note as displayed (C4 = 28), and whether it carries an
+// accidental
+fn staff_step(midi: i32) -> (i32, bool) {
+    let (letter, altered) = notation::letter(midi);
+    let index = "CDEFGAB".find(letter).unwrap_or(0) as i32;
+    (midi.div_euclid(12) * 7 - 7 + index, altered)
+}
+
+// A five-line staff in a 70 x ROW_HEIGHT box at (x, y) with the note on it
+fn draw_staff(shapes: &mut Vec<Shape>, x: f32, y: f32, midi: i32) {
+    // Treble from middle C up, bass below; bottom lines E4 / G2
+    let (bottom, clef) = if midi >= 60 { (30, "treble") } else { (18, "bass") };
+    let base = y + ROW_HEIGHT / 2.0 + 2.0 * STAFF_SPACE;
+    let step_y = |step: i32| base - (step - bottom) as f32 * STAFF_SPACE / 2.0;
+    for line in 0..5 {
+        let ly = step_y(bottom + 2 * line);
+        shapes.push(Shape::Line(x, ly, x + 60.0, ly));
+    }
+    let (step, altered) = staff_step(midi);
+    let hx = x + 40.0;
+    // Ledger lines between the staff and the note
+    let top = bottom + 8;
+    let mut ledger = |k: i32| shapes.push(Shape::Line(hx - 7.0, step_y(k), hx + 7.0, step_y(k)));
+    (step..bottom).filter(|k| (bottom - k) % 2 == 0).for_each(&mut ledger);
+    (top + 1..=step).filter(|k| (k - top) % 2 == 0).for_each(&mut ledger);
+    shapes.push(Shape::Ellipse(hx, step_y(step), 4.5, 3.2, true));
+    if altered {
+        let sign = match (notation::current().accidentals, notation::current().unicode) {
+            (notation::Accidentals::Sharps, false) => "#",
+            (notation::Accidentals::Sharps, true) => "♯",
+            (notation::Accidentals::Flats, false) => "b",
+            (notation::Accidentals::Flats, true) => "♭",
+        };
+        shapes.push(Shape::Text(hx - 16.0, step_y(step) + 3.5, 10.0, sign.to_string()));
+    }
+    shapes.push(Shape::Text(x, y + ROW_HEIGHT - 4.0, 7.0, clef.to_string()));
+}
+
+// A fretboard window of five frets around the position, 1st string on top
+fn draw_fretboard(shapes: &mut Vec<Shape>, x: f32, y: f32, strings: usize, (string, fret): (usize, i32)) {
+    let gap = 6.0;
+    let fret_w = 18.0;
+    let first = (fret - 2).max(1);
+    let height = gap * strings.saturating_sub(1) as f32;
+    let top = y + (ROW_HEIGHT - height) / 2.0 - 4.0;
+    for s in 0..strings {
+        let sy = top + gap * s as f32;
+        shapes.push(Shape::Line(x, sy, x + 5.0 * fret_w, sy));
+    }
+    for f in 0..=5 {
+        let fx = x + fret_w * f as f32;
+        shapes.push(Shape::Line(fx, top, fx, top + height));
+        // Double line for the nut
+        if f == 0 && first == 1 { shapes.push(Shape::Line(fx + 1.5, top, fx + 1.5, top + height)); }
+    }
+    let sy = top + gap * (string - 1) as f32;
+    if fret == 0 {
+        shapes.push(Shape::Ellipse(x - 6.0, sy, 3.0, 3.0, false));
+    } else {
+        shapes.push(Shape::Ellipse(x + fret_w * ((fret - first) as f32 + 0.5), sy, 3.5, 3.5, true));
+    }
+    shapes.push(Shape::Text(x + fret_w * 0.3, top + height + 11.0, 7.0, format!("fret {first}")));
+}
+
+// Shapes for each of a layer's rows, each row in its own 0..ROW_HEIGHT band
+fn draw_rows(layer: &Layer) -> Vec<Vec<Shape>> {
+    let frets = layer.entries.iter().any(|e| e.position.is_some());
+    let text_x = if frets { 190.0 } else { 90.0 };
+    let text_y = ROW_HEIGHT / 2.0 + 4.0;
+    layer
+        .entries
+        .iter()
+        .map(|e| {
+            let mut shapes = Vec::new();
+            if let Some(midi) = e.note { draw_staff(&mut shapes, 0.0, 0.0, midi); }
+            if let Some(p) = e.position { draw_fretboard(&mut shapes, 90.0, 0.0, layer.strings, p); }
+            shapes.push(Shape::Text(text_x, text_y, 12.0, e.key.clone()));
+            shapes.push(Shape::Text(text_x + 90.0, text_y, 12.0, e.action.clone()));
+            shapes
+        })
+        .collect()
+}
+
+fn html(layers: &[Layer]) -> String {
+    let mut out = String::from(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Mappings</title>\n\
+         <style>body{font-family:sans-serif;margin:2em}h2{border-bottom:1px solid #888}\
+         svg{display:block}@media print{h2{break-after:avoid}}</style></head><body>\n\
+         <h1>Rusty Strings Control mappings</h1>\n",
+    );
+    for layer in layers {
+        let rows = draw_rows(layer);
+        let height = ROW_HEIGHT * rows.len() as f32;
+        let _ = writeln!(out, "<h2>{}</h2>", escape_html(&layer.title));
+        let _ = writeln!(out, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"640\" height=\"{height}\" font-family=\"sans-serif\">");
+        for (i, row) in rows.iter().enumerate() {
+            let _ = writeln!(out, "<g transform=\"translate(0 {})\">", ROW_HEIGHT * i as f32);
+            for shape in row { svg_shape(&mut out, shape); }
+            out.push_str("</g>\n");
+        }
+        out.push_str("</svg>\n");
+    }
+    out.push_str("</body></html>\n");
+    out
+}
+
+fn svg_shape(out: &mut String, shape: &Shape) {
+    let _ = match shape {
+        Shape::Line(x1, y1, x2, y2) => {
+            writeln!(out, "<line x1=\"{x1}\" y1=\"{y1}\" x2=\"{x2}\" y2=\"{y2}\" stroke=\"black\" stroke-width=\"0.8\"/>")
+        }
+        Shape::Ellipse(cx, cy, rx, ry, filled) => writeln!(
+            out,
+            "<ellipse cx=\"{cx}\" cy=\"{cy}\" rx=\"{rx}\" ry=\"{ry}\" fill=\"{}\" stroke=\"black\"/>",
+            if *filled { "black" } else { "none" }
+        ),
+        Shape::Text(x, y, size, text) => {
+            writeln!(out, "<text x=\"{x}\" y=\"{y}\" font-size=\"{size}\">{}</text>", escape_html(text))
+        }
+    };
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// ---------------------------- PDF ----------------------------
+
+// A4 in points, and the page margin
+const PAGE_W: f32 = 595.0;
+const PAGE_H: f32 = 842.0;
+const MARGIN: f32 = 50.0;
+
+fn pdf(layers: &[Layer]) -> Vec<u8> {
+    // Lay out rows top to bottom, starting a new page when one is full
+    let mut pages: Vec<String> = vec![String::new()];
+    let mut y = MARGIN;
+    let draw = |pages: &mut Vec<String>, shapes: &[Shape], y0: f32| {
+        let page = pages.last_mut().expect("at least one page");
+        for shape in shapes {
+            pdf_shape(page, shape, MARGIN, y0);
+        }
+    };
+    draw(&mut pages, &[Shape::Text(0.0, 18.0, 18.0, "Rusty Strings Control mappings".to_string())], y);
+    y += 36.0;
+    for layer in layers {
+        if y + 30.0 + ROW_HEIGHT > PAGE_H - MARGIN {
+            pages.push(String::new());
+            y = MARGIN;
+        }
+        draw(&mut pages, &[Shape::Text(0.0, 20.0, 14.0, layer.title.clone())], y);
+        y += 30.0;
+        for row in draw_rows(layer) {
+            if y + ROW_HEIGHT > PAGE_H - MARGIN {
+                pages.push(String::new());
+                y = MARGIN;
+            }
+            draw(&mut pages, &row, y);
+            y += ROW_HEIGHT;
+        }
+        y += 12.0;
+    }
+
+    // Objects: 1 catalog, 2 page tree, 3 font, then a page and its content per page
+    let mut objects = vec![
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            (0..pages.len()).map(|i| format!("{} 0 R", 4 + 2 * i)).collect::<Vec<_>>().join(" "),
+            pages.len()
+        ),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>".to_string(),
+    ];
+    for (i, content) in pages.iter().enumerate() {
+        objects.push(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {PAGE_W} {PAGE_H}] \
+             /Resources << /Font << /F1 3 0 R >> >> /Contents {} 0 R >>",
+            5 + 2 * i
+        ));
+        objects.push(format!("<< /Length {} >>\nstream\n{content}endstream", content.len()));
+    }
+    let mut out = b"%PDF-1.4\n".to_vec();
+    let mut offsets = Vec::new();
+    for (i, obj) in objects.iter().enumerate() {
+        offsets.push(out.len());
+        out.extend_from_slice(format!("{} 0 obj\n{obj}\nendobj\n", i + 1).as_bytes());
+    }
+    let xref = out.len();
+    let mut table = format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+    for off in offsets {
+        let _ = writeln!(table, "{off:010} 00000 n ");
+    }
+    let _ = write!(table, "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{xref}\n%%EOF\n", objects.len() + 1);
+    out.extend_from_slice(table.as_bytes());
+    out
+}
+
+// Append one shape in y-down layout coordinates offset by (dx, dy)
+fn pdf_shape(out: &mut String, shape: &Shape, dx: f32, dy: f32) {
+    let px = |x: f32| x + dx;
+    let py = |y: f32| PAGE_H - (y + dy);
+    let _ = match shape {
+        Shape::Line(x1, y1, x2, y2) => {
+            writeln!(out, "0.8 w {:.2} {:.2} m {:.2} {:.2} l S", px(*x1), py(*y1), px(*x2), py(*y2))
+        }
+        Shape::Ellipse(cx, cy, rx, ry, filled) => {
+            // Four Bezier quarter arcs
+            let (x, y) = (px(*cx), py(*cy));
+            let (kx, ky) = (0.5523 * rx, 0.5523 * ry);
+            writeln!(
+                out,
+                "{:.2} {:.2} m {:.2} {:.2} {:.2} {:.2} {:.2} {:.2} c {:.2} {:.2} {:.2} {:.2} {:.2} {:.2} c \
+                 {:.2} {:.2} {:.2} {:.2} {:.2} {:.2} c {:.2} {:.2} {:.2} {:.2} {:.2} {:.2} c {}",
+                x + rx, y,
+                x + rx, y + ky, x + kx, y + ry, x, y + ry,
+                x - kx, y + ry, x - rx, y + ky, x - rx, y,
+                x - rx, y - ky, x - kx, y - ry, x, y - ry,
+                x + kx, y - ry, x + rx, y - ky, x + rx, y,
+                if *filled { "f" } else { "S" }
+            )
+        }
+        Shape::Text(x, y, size, text) => {
+            writeln!(out, "BT /F1 {size} Tf {:.2} {:.2} Td ({}) Tj ET", px(*x), py(*y), escape_pdf(text))
+        }
+    };
+}
+
+// PDF string literal; Helvetica here only covers ASCII, so note signs fall
+// back to their ASCII spelling
+fn escape_pdf(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '(' | ')' | '\\' => format!("\\{c}"),
+            ' '..='~' => c.to_string(),
+            '♯' => "#".to_string(),
+            '♭' => "b".to_string(),
+            '′' => "'".to_string(),
+            _ => "?".to_string(),
+        })
+        .collect()
+}
@@ -0,0 +1,231 @@
+// ---------------------------- Chord recognition ----------------------------
+//
+// note_map keys such as "Am", "Cmaj" or "G#dom7" fire when that chord is
+// strummed. The pitch detector follows one note, so chords get their own
+// stage: the energy at every semitone from C3 to C6 is folded into twelve
+// pitch classes (a chroma vector), the overtones of lower notes are taken
+// out, and the result is compared with a template per chord. Only keys that
+// aren't note names are chords: "A7" is the note, its chord is "Adom7".
+
+use serde::Deserialize;
+use std::f32::consts::PI;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ChordConfig {
+    // Similarity (0..1) to a chord's template needed to recognize it
+    #[serde(default = "default_min_score")]
+    pub min_score: f32,
+    // Consecutive frames the same chord must be heard before it fires
+    #[serde(default = "default_hold_frames")]
+    pub hold_frames: usize,
+    // Length of audio analysed; longer separates low notes better but
+    // reacts later
+    #[serde(default = "default_window_ms")]
+    pub window_ms: u32,
+}
+
+fn default_min_score() -> f32 { 0.8 }
+fn default_hold_frames() -> usize { 3 }
+fn default_window_ms() -> u32 { 170 }
+
+impl Default for ChordConfig {
+    fn default() -> Self {
+        Self { min_score: default_min_score(), hold_frames: default_hold_frames(), window_ms: default_window_ms() }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quality {
+    Major,
+    Minor,
+    Diminished,
+    Augmented,
+    Sus2,
+    Sus4,
+    Dominant7,
+    Major7,
+    Minor7,
+}
+
+impl Quality {
+    const ALL: [Quality; 9] = [
+        Quality::Major, Quality::Minor, Quality::Diminished, Quality::Augmented, Quality::Sus2,
+        Quality::Sus4, Quality::Dominant7, Quality::Major7, Quality::Minor7,
+    ];
+
+    // Semitones above the root
+    fn intervals(self) -> &'static [usize] {
+        match self {
+            Quality::Major => &[0, 4, 7],
+            Quality::Minor => &[0, 3, 7],
+            Quality::Diminished => &[0, 3, 6],
+            Quality::Augmented => &[0, 4, 8],
+            Quality::Sus2 => &[0, 2, 7],
+            Quality::Sus4 => &[0, 5, 7],
+            Quality::Dominant7 => &[0, 4, 7, 10],
+            Quality::Major7 => &[0, 4, 7, 11],
+            Quality::Minor7 => &[0, 3, 7, 10],
+        }
+    }
+
+    fn suffix(self) -> &'static str {
+        match self {
+            Quality::Major => "maj",
+            Quality::Minor => "m",
+            Quality::Diminished => "dim",
+            Quality::Augmented => "aug",
+            Quality::Sus2 => "sus2",
+            Quality::Sus4 => "sus4",
+            Quality::Dominant7 => "dom7",
+            Quality::Major7 => "maj7",
+            Quality::Minor7 => "m7",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chord {
+    // Pitch class of the root, 0 = C
+    pub root: usize,
+    pub quality: Quality,
+}
+
+impl Chord {
+    pub fn name(self) -> String {
+        format!("{}{}", crate::NOTE_NAMES[self.root], self.quality.suffix())
+    }
+}
+
+/// The chord a note_map key names, e.g. "Am", "Amin", "Bbmaj7", "F#sus4";
+/// None for note names and anything else.
+pub fn parse(key: &str) -> Option<Chord> {
+    if crate::note_to_midi(crate::key_note(key)).is_some() { return None; }
+    let mut chars = key.trim().chars();
+    let letter = chars.next()?.to_ascii_uppercase();
+    let mut root = crate::NOTE_NAMES.iter().position(|n| *n == letter.to_string())? as i32;
+    let mut rest = chars.as_str();
+    if let Some(r) = rest.strip_prefix(['#', '♯']) {
+        root += 1;
+        rest = r;
+    } else if let Some(r) = rest.strip_prefix(['b', '♭']) {
+        root -= 1;
+        rest = r;
+    }
+    // "M" is major and "m" minor; the longer spellings ignore case
+    let quality = match rest {
+        "M" => Quality::Major,
+        "m" | "-" => Quality::Minor,
+        "M7" => Quality::Major7,
+        "m7" | "-7" => Quality::Minor7,
+        "+" => Quality::Augmented,
+        "°" | "o" => Quality::Diminished,
+        "sus" => Quality::Sus4,
+        _ => match rest.to_ascii_lowercase().as_str() {
+            "maj" | "major" => Quality::Major,
+            "min" | "minor" => Quality::Minor,
+            "dim" => Quality::Diminished,
+            "aug" => Quality::Augmented,
+            "sus2" => Quality::Sus2,
+            "sus4" => Quality::Sus4,
+            "dom7" => Quality::Dominant7,
+            "maj7" => Quality::Major7,
+            "min7" => Quality::Minor7,
+            _ => return None,
+        },
+    };
+    Some(Chord { root: root.rem_euclid(12) as usize, quality })
+}
+
+// Semitones analysed (MIDI C3..C6): below C3 neighbouring semitones blur
+// together at usable window lengths
+const LOWEST: i32 = 48;
+const HIGHEST: i32 = 84;
+// Overtones of a note land this many semitones above it (2nd to 5th
+// harmonic), with roughly this much of its energy
+const OVERTONES: [(usize, f32); 4] = [(12, 0.5), (19, 0.3), (24, 0.2), (28, 0.15)];
+
+pub struct ChordDetector {
+    cfg: ChordConfig,
+    buffer: Vec<f32>,
+    hann: Vec<f32>,
+    current: Option<Chord>,
+    count: usize,
+}
+
+impl ChordDetector {
+    pub fn new(cfg: &ChordConfig) -> Self {
+        Self { cfg: cfg.clone(), buffer: Vec::new(), hann: Vec::new(), current: None, count: 0 }
+    }
+
+    /// Feed one hop; `loud` is false below the input threshold. Returns the
+    /// chord on the frame it has been heard for hold_frames.
+    pub fn update(&mut self, hop: &[f32], sample_rate: f32, loud: bool) -> Option<Chord> {
+        let len = (sample_rate * self.cfg.window_ms as f32 / 1000.0) as usize;
+        self.buffer.extend_from_slice(hop);
+        if self.buffer.len() > len { self.buffer.drain(..self.buffer.len() - len); }
+        let heard = if loud && self.buffer.len() == len {
+            if self.hann.len() != len {
+                self.hann = (0..len).map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / (len - 1) as f32).cos()).collect();
+            }
+            classify(&self.chroma(sample_rate), self.cfg.min_score)
+        } else {
+            None
+        };
+        if heard == self.current {
+            self.count += 1;
+        } else {
+            self.current = heard;
+            self.count = 1;
+        }
+        self.current.filter(|_| self.count == self.cfg.hold_frames.max(1))
+    }
+
+    /// Whether a chord (stable or not) is sounding right now.
+    pub fn hearing(&self) -> bool {
+        self.current.is_some()
+    }
+
+    fn chroma(&self, sample_rate: f32) -> [f32; 12] {
+        let a4 = crate::a4_hz();
+        let power: Vec<f32> = (LOWEST..=HIGHEST)
+            .map(|m| goertzel(&self.buffer, &self.hann, a4 * 2f32.powf((m - 69) as f32 / 12.0) / sample_rate))
+            .collect();
+        let mut chroma = [0.0f32; 12];
+        for (i, p) in power.iter().enumerate() {
+            let overtones: f32 = OVERTONES
+                .iter()
+                .filter_map(|&(up, share)| i.checked_sub(up).map(|below| share * power[below]))
+                .sum();
+            chroma[(LOWEST as usize + i) % 12] += (p - overtones).max(0.0).sqrt();
+        }
+        chroma
+    }
+}
+
+// Power of the windowed signal at `freq` cycles per sample
+fn goertzel(x: &[f32], window: &[f32], freq: f32) -> f32 {
+    let coeff = 2.0 * (2.0 * PI * freq).cos();
+    let (mut s1, mut s2) = (0.0f32, 0.0f32);
+    for (v, w) in x.iter().zip(window) {
+        let s = v * w + coeff * s1 - s2;
+        s2 = s1;
+        s1 = s;
+    }
+    s1 * s1 + s2 * s2 - coeff * s1 * s2
+}
+
+// Best matching chord by cosine similarity, if it scores at least min_score
+fn classify(chroma: &[f32; 12], min_score: f32) -> Option<Chord> {
+    let norm = chroma.iter().map(|c| c * c).sum::<f32>().sqrt();
+    if norm <= f32::EPSILON { return None; }
+    let mut best: Option<(f32, Chord)> = None;
+    for quality in Quality::ALL {
+        let tones = quality.intervals();
+        for root in 0..12 {
+            let sum: f32 = tones.iter().map(|t| chroma[(root + t) % 12]).sum();
+            let score = sum / (norm * (tones.len() as f32).sqrt());
+            if best.is_none_or(|(b, _)| score > b) { best = Some((score, Chord { root, quality })); }
+        }
+    }
+    best.filter(|(score, _)| *score >= min_score).map(|(_, chord)| chord)
+}
@@ -0,0 +1,97 @@
+// ---------------------------- Local time ----------------------------
+//
+// The standard library only knows UTC. Schedules are written in wall-clock
+// time, so ask the OS for the local date and time.
+
+/// Wall-clock time in the local time zone.
+#[derive(Debug, Clone, Copy)]
+pub struct LocalTime {
+    // 0 = Monday ... 6 = Sunday
+    pub weekday: u32,
+    // Minutes since local midnight
+    pub minute_of_day: u32,
+    // Minutes since 1970-01-01 00:00 on the local calendar, and in UTC
+    pub local_minutes: i64,
+    pub utc_minutes: i64,
+}
+
+/// Minutes since 1970-01-01 00:00 for a calendar date and time.
+pub fn civil_minutes(year: i64, month: u32, day: u32, hour: u32, minute: u32) -> i64 {
+    // Days from civil, after Howard Hinnant's algorithm
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    days * 1440 + hour as i64 * 60 + minute as i64
+}
+
+fn utc_minutes() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| (d.as_secs() / 60) as i64)
+}
+
+#[cfg(unix)]
+pub fn now() -> LocalTime {
+    let utc = utc_minutes();
+    let t: libc::time_t = (utc * 60) as libc::time_t;
+    // SAFETY: localtime_r only writes to the tm we pass in
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&t, &mut tm) }.is_null() {
+        return from_utc(utc);
+    }
+    LocalTime {
+        weekday: ((tm.tm_wday + 6) % 7) as u32,
+        minute_of_day: (tm.tm_hour * 60 + tm.tm_min) as u32,
+        local_minutes: civil_minutes(
+            tm.tm_year as i64 + 1900,
+            tm.tm_mon as u32 + 1,
+            tm.tm_mday as u32,
+            tm.tm_hour as u32,
+            tm.tm_min as u32,
+        ),
+        utc_minutes: utc,
+    }
+}
+
+#[cfg(windows)]
+pub fn now() -> LocalTime {
+    use windows_sys::Win32::Foundation::SYSTEMTIME;
+    use windows_sys::Win32::System::SystemInformation::GetLocalTime;
+    // SAFETY: GetLocalTime fills the SYSTEMTIME we pass in
+    let mut st: SYSTEMTIME = unsafe { std::mem::zeroed() };
+    unsafe { GetLocalTime(&mut st) };
+    LocalTime {
+        weekday: (st.wDayOfWeek as u32 + 6) % 7,
+        minute_of_day: st.wHour as u32 * 60 + st.wMinute as u32,
+        local_minutes: civil_minutes(
+            st.wYear as i64,
+            st.wMonth as u32,
+            st.wDay as u32,
+            st.wHour as u32,
+            st.wMinute as u32,
+        ),
+        utc_minutes: utc_minutes(),
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn now() -> LocalTime {
+    from_utc(utc_minutes())
+}
+
+// Fallback when the local zone is unknown: treat UTC as local
+#[cfg_attr(windows, allow(dead_code))]
+fn from_utc(utc: i64) -> LocalTime {
+    let days = utc.div_euclid(1440);
+    LocalTime {
+        // 1970-01-01 was a Thursday
+        weekday: (days + 3).rem_euclid(7) as u32,
+        minute_of_day: utc.rem_euclid(1440) as u32,
+        local_minutes: utc,
+        utc_minutes: utc,
+    }
+}
@@ -0,0 +1,136 @@
+// ---------------------------- Control commands ----------------------------
+//
+// A running instance takes one-line text commands on a local UDP port, so
+// scripts and other programs can steer it: `profile daw`, `profile next`,
+// `profile default`, `actions off`, `actions toggle`, `pause`, `resume`,
+// `reload`, `note E4` (play a note's mapping as if it was heard) and
+// `state`. The `profile` and `actions` subcommands send one and wait for the
+// reply. Only 127.0.0.1 is listened on. The HTTP API runs the same commands.
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::net::UdpSocket;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::{hotkeys, profiles, reload, tray};
+
+// Notes sent with `note`, waiting for the trigger loop
+static INJECTED: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+// More than this waiting means no trigger loop is taking them
+const MAX_INJECTED: usize = 16;
+
+/// The oldest note sent with `note` that no trigger loop has taken yet.
+pub fn injected() -> Option<String> {
+    INJECTED.lock().unwrap_or_else(|e| e.into_inner()).pop_front()
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ControlConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(default = "default_port")]
+    pub port: u16,
+}
+
+fn default_enabled() -> bool { true }
+fn default_port() -> u16 { 47800 }
+
+impl Default for ControlConfig {
+    fn default() -> Self {
+        Self { enabled: default_enabled(), port: default_port() }
+    }
+}
+
+/// Take commands on the control port for as long as the program runs. A
+/// port already in use (another instance) only gets a warning.
+pub fn listen(cfg: &ControlConfig) {
+    if !cfg.enabled { return; }
+    let socket = match UdpSocket::bind(("127.0.0.1", cfg.port)) {
+        Ok(s) => s,
+        Err(e) => {
+            tracing::warn!("control port {} unavailable: {e}", cfg.port);
+            return;
+        }
+    };
+    std::thread::spawn(move || {
+        let mut buf = [0u8; 512];
+        while let Ok((n, from)) = socket.recv_from(&mut buf) {
+            let reply = match run(&String::from_utf8_lossy(&buf[..n])) {
+                Ok(text) => text,
+                Err(e) => format!("error: {e:#}"),
+            };
+            socket.send_to(reply.as_bytes(), from).ok();
+        }
+    });
+}
+
+/// Carry out one command; the reply.
+pub fn run(command: &str) -> Result<String> {
+    let (verb, arg) = command.trim().split_once(' ').unwrap_or((command.trim(), ""));
+    match verb {
+        "profile" if !arg.trim().is_empty() => {
+            profiles::request(profiles::Switch::parse(arg));
+            Ok(format!("ok: profile {}", arg.trim()))
+        }
+        "profile" => Err(anyhow!("profile needs a name, next or default")),
+        "actions" => {
+            let on = match arg.trim() {
+                "toggle" => hotkeys::toggle_actions(),
+                "on" | "off" => {
+                    hotkeys::set_actions(arg.trim() == "on");
+                    arg.trim() == "on"
+                }
+                other => return Err(anyhow!("actions takes on, off or toggle, not {other:?}")),
+            };
+            Ok(format!("ok: actions {}", if on { "on" } else { "off" }))
+        }
+        "pause" | "resume" => {
+            tray::set_paused(verb == "pause");
+            Ok(format!("ok: {verb}"))
+        }
+        "reload" if reload::watching() => {
+            reload::request();
+            Ok("ok: reload".to_string())
+        }
+        "reload" => Err(anyhow!("this run can't reload its config (trigger mode with a config file only)")),
+        "note" if !arg.trim().is_empty() => {
+            let mut queue = INJECTED.lock().unwrap_or_else(|e| e.into_inner());
+            if queue.len() >= MAX_INJECTED { return Err(anyhow!("notes aren't being taken (trigger mode only)")); }
+            queue.push_back(arg.trim().to_string());
+            Ok(format!("ok: note {}", arg.trim()))
+        }
+        "note" => Err(anyhow!("note needs a note_map key, such as E4")),
+        "state" => Ok(state().to_string()),
+        other => Err(anyhow!("unknown command {other:?}")),
+    }
+}
+
+// What `state` reports, as JSON
+fn state() -> serde_json::Value {
+    let (profiles, active) = tray::profiles();
+    serde_json::json!({
+        "paused": tray::paused(),
+        "actions": hotkeys::actions_on(),
+        "profile": active,
+        "profiles": profiles,
+        "reloadable": reload::watching(),
+    })
+}
+
+/// Send a command to the instance running on `cfg`'s port; its reply.
+pub fn send(cfg: &ControlConfig, command: &str) -> Result<String> {
+    let socket = UdpSocket::bind(("127.0.0.1", 0)).context("Opening a local socket")?;
+    socket.set_read_timeout(Some(Duration::from_secs(1)))?;
+    socket.send_to(command.as_bytes(), ("127.0.0.1", cfg.port))?;
+    let mut buf = [0u8; 512];
+    let n = socket
+        .recv(&mut buf)
+        .map_err(|_| anyhow!("Nothing answered on control port {}; is it running with [control] enabled?", cfg.port))?;
+    let reply = String::from_utf8_lossy(&buf[..n]).to_string();
+    match reply.strip_prefix("error: ") {
+        Some(e) => Err(anyhow!("{e}")),
+        None => Ok(reply),
+    }
+}
@@ -0,0 +1,148 @@
+// ---------------------------- D-Bus service ----------------------------
+//
+// With `[dbus] enabled = true` on Linux, a running instance owns the name
+// io.github.GrahamPaasch.RustyStringsControl on the session bus, so desktop
+// shortcuts, widgets and scripts can steer it and follow along without
+// polling. The object /io/github/GrahamPaasch/RustyStringsControl has the
+// interface io.github.GrahamPaasch.RustyStringsControl1:
+//
+//   methods     Pause(), Resume(), SetProfile(s name), ReloadConfig(),
+//               PlayNote(s key)
+//   properties  Paused (b), Profile (s, "" = default), read on demand
+//   signals     Note(s performer, s note, d hz, d cents) when the heard
+//               note changes, Silence(s performer) when it stops,
+//               Trigger(s performer, s note, s action) when a mapping fires
+//
+// performer is "" outside [performers] and [hexaphonic]. Methods run the
+// control commands of the same names.
+
+use serde::Deserialize;
+
+use crate::pipeline::NoteEvent;
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Default)]
+pub struct DbusConfig {
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+pub use service::{active, publish, start};
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod service {
+    use crossbeam_channel::{bounded, Receiver, Sender};
+    use std::sync::OnceLock;
+    use zbus::fdo;
+    use zbus::object_server::SignalEmitter;
+
+    use super::{DbusConfig, NoteEvent};
+    use crate::status::NoteChanges;
+    use crate::{control, tray};
+
+    const NAME: &str = "io.github.GrahamPaasch.RustyStringsControl";
+    const PATH: &str = "/io/github/GrahamPaasch/RustyStringsControl";
+    const INTERFACE: &str = "io.github.GrahamPaasch.RustyStringsControl1";
+    // Events waiting to be signalled; more than this and new ones are dropped
+    const QUEUE: usize = 256;
+
+    static EVENTS: OnceLock<Sender<(Option<String>, NoteEvent)>> = OnceLock::new();
+
+    struct Service;
+
+    // A control command's outcome as a method reply
+    fn run(command: &str) -> fdo::Result<()> {
+        control::run(command).map(drop).map_err(|e| fdo::Error::Failed(format!("{e:#}")))
+    }
+
+    #[zbus::interface(name = "io.github.GrahamPaasch.RustyStringsControl1")]
+    impl Service {
+        fn pause(&self) -> fdo::Result<()> { run("pause") }
+
+        fn resume(&self) -> fdo::Result<()> { run("resume") }
+
+        fn set_profile(&self, name: &str) -> fdo::Result<()> { run(&format!("profile {name}")) }
+
+        fn reload_config(&self) -> fdo::Result<()> { run("reload") }
+
+        fn play_note(&self, key: &str) -> fdo::Result<()> { run(&format!("note {key}")) }
+
+        #[zbus(property(emits_changed_signal = "false"))]
+        fn paused(&self) -> bool { tray::paused() }
+
+        #[zbus(property(emits_changed_signal = "false"))]
+        fn profile(&self) -> String { tray::profiles().1.unwrap_or_default() }
+
+        #[zbus(signal)]
+        async fn note(emitter: &SignalEmitter<'_>, performer: &str, note: &str, hz: f64, cents: f64) -> zbus::Result<()>;
+
+        #[zbus(signal)]
+        async fn silence(emitter: &SignalEmitter<'_>, performer: &str) -> zbus::Result<()>;
+
+        #[zbus(signal)]
+        async fn trigger(emitter: &SignalEmitter<'_>, performer: &str, note: &str, action: &str) -> zbus::Result<()>;
+    }
+
+    /// Take the bus name and serve for as long as the program runs. Without
+    /// a session bus, or with the name taken, there is only a warning.
+    pub fn start(cfg: &DbusConfig) {
+        if !cfg.enabled || EVENTS.get().is_some() { return; }
+        let connection = zbus::blocking::connection::Builder::session()
+            .and_then(|b| b.name(NAME))
+            .and_then(|b| b.serve_at(PATH, Service))
+            .and_then(|b| b.build());
+        let connection = match connection {
+            Ok(c) => c,
+            Err(e) => {
+                tracing::warn!("no D-Bus service: {e}");
+                return;
+            }
+        };
+        let (tx, rx) = bounded(QUEUE);
+        std::thread::spawn(move || signal(connection, rx));
+        EVENTS.set(tx).ok();
+        println!("D-Bus: serving {NAME}");
+    }
+
+    /// Whether events are being signalled.
+    pub fn active() -> bool { EVENTS.get().is_some() }
+
+    /// Signal `event` (triggers and changes of note only); `performer` labels it.
+    pub fn publish(performer: Option<&str>, event: &NoteEvent) {
+        let Some(tx) = EVENTS.get() else { return };
+        if matches!(event, NoteEvent::Status(_)) { return; }
+        tx.try_send((performer.map(str::to_string), event.clone())).ok();
+    }
+
+    fn signal(connection: zbus::blocking::Connection, events: Receiver<(Option<String>, NoteEvent)>) {
+        let mut changes = NoteChanges::default();
+        for (performer, event) in events {
+            if !changes.changed(performer.as_deref(), &event) { continue; }
+            let performer = performer.unwrap_or_default();
+            let sent = match &event {
+                NoteEvent::Pitch { note, hz, cents, .. } => {
+                    connection.emit_signal(None::<()>, PATH, INTERFACE, "Note", &(&performer, note, *hz as f64, *cents as f64))
+                }
+                NoteEvent::Silence => connection.emit_signal(None::<()>, PATH, INTERFACE, "Silence", &(&performer,)),
+                NoteEvent::Trigger { note, action } => {
+                    let action = crate::action_name(action);
+                    connection.emit_signal(None::<()>, PATH, INTERFACE, "Trigger", &(&performer, note, &action))
+                }
+                NoteEvent::Status(_) => continue,
+            };
+            if let Err(e) = sent { tracing::debug!("D-Bus signal failed: {e}"); }
+        }
+    }
+}
+
+/// D-Bus is a Linux desktop service; elsewhere `[dbus]` only gets a warning.
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+pub fn start(cfg: &DbusConfig) {
+    if cfg.enabled { tracing::warn!("[dbus] is only available on Linux"); }
+}
+
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+pub fn active() -> bool { false }
+
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+pub fn publish(_performer: Option<&str>, _event: &NoteEvent) {}
@@ -0,0 +1,61 @@
+// ---------------------------- Dynamics ----------------------------
+//
+// How loud a note was played, for "A4:piano" / "A4:mezzo" / "A4:forte" keys.
+// The level is the highest hop RMS since the note began, so the attack
+// counts rather than the decay by the time the note is recognized.
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct DynamicsConfig {
+    // Notes peaking below this level (dBFS RMS) are piano
+    #[serde(default = "default_piano_below_db")]
+    pub piano_below_db: f32,
+    // Notes peaking at or above this level are forte; mezzo in between
+    #[serde(default = "default_forte_from_db")]
+    pub forte_from_db: f32,
+}
+
+fn default_piano_below_db() -> f32 { -35.0 }
+fn default_forte_from_db() -> f32 { -18.0 }
+
+impl Default for DynamicsConfig {
+    fn default() -> Self {
+        Self { piano_below_db: default_piano_below_db(), forte_from_db: default_forte_from_db() }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dynamic {
+    Piano,
+    Mezzo,
+    Forte,
+}
+
+impl Dynamic {
+    /// Name used in note_map keys, e.g. "A4:forte".
+    pub fn name(self) -> &'static str {
+        match self {
+            Dynamic::Piano => "piano",
+            Dynamic::Mezzo => "mezzo",
+            Dynamic::Forte => "forte",
+        }
+    }
+}
+
+/// Whether a key qualifier ("A4:forte" -> "forte") names a dynamic.
+pub fn is_name(qualifier: &str) -> bool {
+    matches!(qualifier, "piano" | "mezzo" | "forte")
+}
+
+/// Dynamic of a note whose loudest hop had this RMS (0..1).
+pub fn classify(cfg: &DynamicsConfig, peak_rms: f32) -> Dynamic {
+    let db = 20.0 * peak_rms.max(1e-9).log10();
+    if db < cfg.piano_below_db {
+        Dynamic::Piano
+    } else if db >= cfg.forte_from_db {
+        Dynamic::Forte
+    } else {
+        Dynamic::Mezzo
+    }
+}
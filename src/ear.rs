@@ -0,0 +1,141 @@
+// ---------------------------- Ear training ----------------------------
+//
+// Picks a random target, describes it for the prompt, and keeps score.
+// The game loop itself (tone playback and listening) lives in main.rs.
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Exercise {
+    // Hear a note, play the same note
+    #[default]
+    Note,
+    // Hear a root note, play the named interval above it
+    Interval,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct EarTrainingConfig {
+    #[serde(default)]
+    pub exercise: Exercise,
+    // Range that reference notes and answers are drawn from
+    #[serde(default = "default_low")]
+    pub low: String,
+    #[serde(default = "default_high")]
+    pub high: String,
+    // Intervals (in semitones) asked in interval exercises
+    #[serde(default = "default_intervals")]
+    pub intervals: Vec<i32>,
+    // Number of rounds (0 = endless)
+    #[serde(default = "default_rounds")]
+    pub rounds: u32,
+    // Reference tone length and loudness (0..1)
+    #[serde(default = "default_tone_ms")]
+    pub tone_ms: u64,
+    #[serde(default = "default_volume")]
+    pub volume: f32,
+    // Time allowed to answer after the tone ends
+    #[serde(default = "default_answer_secs")]
+    pub answer_secs: u64,
+}
+
+fn default_low() -> String { "G3".to_string() }
+fn default_high() -> String { "E5".to_string() }
+fn default_intervals() -> Vec<i32> { vec![3, 4, 5, 7, 12] }
+fn default_rounds() -> u32 { 20 }
+fn default_tone_ms() -> u64 { 1200 }
+fn default_volume() -> f32 { 0.3 }
+fn default_answer_secs() -> u64 { 5 }
+
+impl Default for EarTrainingConfig {
+    fn default() -> Self {
+        Self {
+            exercise: Exercise::default(),
+            low: default_low(),
+            high: default_high(),
+            intervals: default_intervals(),
+            rounds: default_rounds(),
+            tone_ms: default_tone_ms(),
+            volume: default_volume(),
+            answer_secs: default_answer_secs(),
+        }
+    }
+}
+
+/// One round: the note that is played and the MIDI note expected back.
+pub struct Question {
+    pub reference: i32,
+    pub target: i32,
+    pub prompt: String,
+}
+
+/// Common interval names by semitone distance (0..=12).
+pub fn interval_name(semitones: i32) -> &'static str {
+    const NAMES: [&str; 13] = [
+        "unison", "minor 2nd", "major 2nd", "minor 3rd", "major 3rd", "perfect 4th",
+        "tritone", "perfect 5th", "minor 6th", "major 6th", "minor 7th", "major 7th", "octave",
+    ];
+    NAMES.get(semitones.unsigned_abs() as usize).copied().unwrap_or("interval")
+}
+
+/// Build a question with the reference and target both inside `low..=high`.
+/// `pick(n)` must return a value in `0..n`.
+pub fn make_question(
+    cfg: &EarTrainingConfig,
+    low: i32,
+    high: i32,
+    mut pick: impl FnMut(usize) -> usize,
+    name: impl Fn(i32) -> String,
+) -> Option<Question> {
+    if high < low { return None; }
+    match cfg.exercise {
+        Exercise::Note => {
+            let n = low + pick((high - low + 1) as usize) as i32;
+            Some(Question { reference: n, target: n, prompt: "Play the note you hear".to_string() })
+        }
+        Exercise::Interval => {
+            let candidates: Vec<i32> = cfg.intervals.iter().copied().filter(|i| high - low >= i.abs()).collect();
+            if candidates.is_empty() { return None; }
+            let iv = candidates[pick(candidates.len())];
+            // Choose a root so root + interval stays in range
+            let (lo, hi) = if iv >= 0 { (low, high - iv) } else { (low - iv, high) };
+            let root = lo + pick((hi - lo + 1) as usize) as i32;
+            let dir = if iv >= 0 { "above" } else { "below" };
+            Some(Question {
+                reference: root,
+                target: root + iv,
+                prompt: format!("Play a {} {dir} {}", interval_name(iv), name(root)),
+            })
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Score {
+    pub asked: u32,
+    pub correct: u32,
+    pub streak: u32,
+    pub best_streak: u32,
+}
+
+impl Score {
+    pub fn record(&mut self, correct: bool) {
+        self.asked += 1;
+        if correct {
+            self.correct += 1;
+            self.streak += 1;
+            self.best_streak = self.best_streak.max(self.streak);
+        } else {
+            self.streak = 0;
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        let pct = if self.asked > 0 { 100.0 * self.correct as f32 / self.asked as f32 } else { 0.0 };
+        format!(
+            "{}/{} correct ({pct:.0}%), streak {}, best streak {}",
+            self.correct, self.asked, self.streak, self.best_streak
+        )
+    }
+}
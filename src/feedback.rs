@@ -0,0 +1,162 @@
+// ---------------------------- Feedback ----------------------------
+//
+// Optional outputs that mirror what the detector hears (lights, haptics) so
+// the player gets confirmation without looking at the console. Every sink
+// gets the same events; sinks that talk to other programs or devices do
+// their I/O on their own thread so the audio loop never waits on them.
+
+use anyhow::Result;
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+
+use crate::{openrgb, rumble, wled};
+
+pub trait Feedback {
+    /// A pitch was detected: nearest MIDI note and its offset in cents.
+    fn pitch(&mut self, _midi: i32, _cents: f32) {}
+    /// No confident pitch this frame.
+    fn silence(&mut self) {}
+    /// A mapping fired.
+    fn trigger(&mut self) {}
+    /// A mapped note was held, but out of tune.
+    fn rejected(&mut self) {}
+}
+
+/// All enabled feedback sinks.
+pub struct FeedbackSet {
+    sinks: Vec<Box<dyn Feedback>>,
+}
+
+impl FeedbackSet {
+    pub fn new(cfg: &crate::Config) -> Self {
+        let mut sinks: Vec<Box<dyn Feedback>> = Vec::new();
+        if cfg.openrgb.enabled {
+            sinks.push(Box::new(openrgb::start(&cfg.openrgb, cfg.tolerance_cents)));
+        }
+        if cfg.wled.enabled {
+            if cfg.wled.host.is_empty() {
+                tracing::warn!("[wled] needs a host; LED feedback disabled");
+            } else {
+                sinks.push(Box::new(wled::start(&cfg.wled, cfg.tolerance_cents)));
+            }
+        }
+        if cfg.rumble.enabled {
+            sinks.push(Box::new(rumble::Rumble::start(&cfg.rumble)));
+        }
+        Self { sinks }
+    }
+
+    pub fn pitch(&mut self, midi: i32, cents: f32) {
+        for s in &mut self.sinks { s.pitch(midi, cents); }
+    }
+
+    pub fn silence(&mut self) {
+        for s in &mut self.sinks { s.silence(); }
+    }
+
+    pub fn trigger(&mut self) {
+        for s in &mut self.sinks { s.trigger(); }
+    }
+
+    pub fn rejected(&mut self) {
+        for s in &mut self.sinks { s.rejected(); }
+    }
+}
+
+pub enum LightMsg {
+    Color([u8; 3]),
+    Flash,
+}
+
+/// A light (LEDs, keyboard backlight, ...) showing the note color, dark in
+/// silence and flashing white on triggers. The device side runs `drive_light`.
+pub struct Light {
+    tx: Sender<LightMsg>,
+    tolerance: f32,
+    last: Option<[u8; 3]>,
+}
+
+impl Light {
+    pub fn new(tx: Sender<LightMsg>, tolerance: f32) -> Self {
+        Self { tx, tolerance, last: None }
+    }
+
+    fn send_color(&mut self, color: [u8; 3]) {
+        if self.last != Some(color) && self.tx.try_send(LightMsg::Color(color)).is_ok() {
+            self.last = Some(color);
+        }
+    }
+}
+
+impl Feedback for Light {
+    fn pitch(&mut self, midi: i32, cents: f32) {
+        self.send_color(pitch_color(midi, cents, self.tolerance));
+    }
+
+    fn silence(&mut self) {
+        self.send_color([0, 0, 0]);
+    }
+
+    fn trigger(&mut self) {
+        let _ = self.tx.try_send(LightMsg::Flash);
+    }
+}
+
+/// Apply light messages with `set` until the sender goes away (Ok) or `set`
+/// fails. Backlogged colors are skipped, a flash shows white for `flash`
+/// before the color returns, and the color is re-sent every second while
+/// idle so devices with a realtime timeout stay lit.
+pub fn drive_light(rx: &Receiver<LightMsg>, flash: Duration, mut set: impl FnMut([u8; 3]) -> Result<()>) -> Result<()> {
+    let mut color = [0u8; 3];
+    let mut flash_until: Option<Instant> = None;
+    loop {
+        let timeout = flash_until.map_or(Duration::from_secs(1), |t| t.saturating_duration_since(Instant::now()));
+        let first = match rx.recv_timeout(timeout) {
+            Ok(m) => m,
+            Err(RecvTimeoutError::Timeout) => {
+                flash_until = None;
+                set(color)?;
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        };
+        let mut flashed = false;
+        for msg in std::iter::once(first).chain(rx.try_iter()) {
+            match msg {
+                LightMsg::Color(c) => color = c,
+                LightMsg::Flash => flashed = true,
+            }
+        }
+        if flashed {
+            flash_until = Some(Instant::now() + flash);
+            set([255, 255, 255])?;
+        } else if flash_until.is_none() {
+            set(color)?;
+        }
+    }
+}
+
+/// Color for a note: hue by pitch class (C = red, around the color wheel),
+/// brightness by how close to the center it is, `tolerance` cents away
+/// being dimmest.
+pub fn pitch_color(midi: i32, cents: f32, tolerance: f32) -> [u8; 3] {
+    let hue = midi.rem_euclid(12) as f32 / 12.0;
+    let accuracy = 1.0 - (cents.abs() / tolerance.max(1.0)).min(1.0);
+    hsv(hue, 1.0, 0.15 + 0.85 * accuracy)
+}
+
+// h, s, v in 0..1
+fn hsv(h: f32, s: f32, v: f32) -> [u8; 3] {
+    let h6 = (h.fract() * 6.0).max(0.0);
+    let f = h6.fract();
+    let (p, q, t) = (v * (1.0 - s), v * (1.0 - s * f), v * (1.0 - s * (1.0 - f)));
+    let (r, g, b) = match h6 as u32 {
+        0 => (v, t, p),
+        1 => (q, v, p),
+        2 => (p, v, t),
+        3 => (p, q, v),
+        4 => (t, p, v),
+        _ => (v, p, q),
+    };
+    [(r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8]
+}
@@ -0,0 +1,97 @@
+// ---------------------------- Focused application ----------------------------
+//
+// Profiles named after an application (or listing it in `apps`) switch on
+// while it has the keyboard focus. A background thread asks the system which
+// application that is every focus_ms: the process image name of the
+// foreground window on Windows, the frontmost application on macOS, and the
+// process of the active X11 window (through xdotool) elsewhere. Wayland
+// doesn't tell other programs which window is focused, so there app
+// profiles stay off.
+
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+// The application last seen in focus
+static CURRENT: Mutex<Option<String>> = Mutex::new(None);
+static STARTED: OnceLock<()> = OnceLock::new();
+
+/// The application with the focus, as last seen. The first call starts
+/// watching it, every `interval`.
+pub fn current(interval: Duration) -> Option<String> {
+    STARTED.get_or_init(|| {
+        std::thread::spawn(move || {
+            let mut first = true;
+            loop {
+                let app = focused_app();
+                if app.is_none() && first {
+                    tracing::warn!("can't tell which application is focused; app profiles stay off");
+                }
+                first = false;
+                *CURRENT.lock().unwrap_or_else(|e| e.into_inner()) = app;
+                std::thread::sleep(interval);
+            }
+        });
+    });
+    CURRENT.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Whether an application name from the config means `app`: case doesn't
+/// matter, nor does a trailing ".exe", so "code" matches Code.exe.
+pub fn same_app(wanted: &str, app: &str) -> bool {
+    let bare = |s: &str| {
+        let s = s.trim().to_lowercase();
+        s.strip_suffix(".exe").map(str::to_string).unwrap_or(s)
+    };
+    bare(wanted) == bare(app)
+}
+
+#[cfg(windows)]
+fn focused_app() -> Option<String> {
+    use windows_sys::Win32::Foundation::CloseHandle;
+    use windows_sys::Win32::System::Threading::{
+        OpenProcess, QueryFullProcessImageNameW, PROCESS_QUERY_LIMITED_INFORMATION,
+    };
+    use windows_sys::Win32::UI::WindowsAndMessaging::{GetForegroundWindow, GetWindowThreadProcessId};
+    // SAFETY: the window and process handles come straight from the API
+    // calls, the process handle is closed before returning, and the name
+    // buffer outlives the call that fills it
+    unsafe {
+        let window = GetForegroundWindow();
+        if window == 0 { return None; }
+        let mut pid = 0u32;
+        GetWindowThreadProcessId(window, &mut pid);
+        if pid == 0 { return None; }
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if process == 0 { return None; }
+        let mut buf = [0u16; 1024];
+        let mut len = buf.len() as u32;
+        let ok = QueryFullProcessImageNameW(process, 0, buf.as_mut_ptr(), &mut len);
+        CloseHandle(process);
+        if ok == 0 { return None; }
+        let path = String::from_utf16_lossy(&buf[..len as usize]);
+        path.rsplit('\\').next().map(str::to_string)
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn focused_app() -> Option<String> {
+    let script = "tell application \"System Events\" to get name of first application process whose frontmost is true";
+    output("osascript", &["-e", script])
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn focused_app() -> Option<String> {
+    let pid = output("xdotool", &["getactivewindow", "getwindowpid"])?;
+    let comm = std::fs::read_to_string(format!("/proc/{pid}/comm")).ok()?;
+    Some(comm.trim().to_string())
+}
+
+// First line a command prints, if it succeeds
+#[cfg(unix)]
+fn output(program: &str, args: &[&str]) -> Option<String> {
+    let out = std::process::Command::new(program).args(args).output().ok()?;
+    if !out.status.success() { return None; }
+    let text = String::from_utf8_lossy(&out.stdout);
+    let line = text.lines().next()?.trim();
+    (!line.is_empty()).then(|| line.to_string())
+}
@@ -0,0 +1,514 @@
+// ---------------------------- Virtual gamepad ----------------------------
+//
+// `type = "gamepad"` mappings press buttons and set sticks and triggers on a
+// virtual Xbox 360 controller, for games that ignore synthetic keystrokes.
+// `[gamepad]` can also have the playing move an axis all the time: the pitch
+// bend since the note started (vibrato, slides) and the loudness. On Linux
+// the controller is a uinput device, written here like the evdev rumble; on
+// Windows it is a ViGEm target, through ViGEmClient.dll, which needs the
+// ViGEmBus driver. The controller is created at start when anything uses it,
+// so games see it before the first note, and stays until the program exits.
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct GamepadConfig {
+    // Device name games show (Linux)
+    #[serde(default = "default_name")]
+    pub name: String,
+    // Moved by how far the pitch has bent from the note it started on
+    #[serde(default)]
+    pub bend_axis: Option<Axis>,
+    // Semitones of bend for a full deflection
+    #[serde(default = "default_bend_range")]
+    pub bend_range: f32,
+    // Moved by the loudness: level_min_db rests, level_max_db is full
+    #[serde(default)]
+    pub level_axis: Option<Axis>,
+    #[serde(default = "default_level_min_db")]
+    pub level_min_db: f32,
+    #[serde(default = "default_level_max_db")]
+    pub level_max_db: f32,
+}
+
+fn default_name() -> String { "Rusty Strings Control".to_string() }
+fn default_bend_range() -> f32 { 1.0 }
+fn default_level_min_db() -> f32 { -50.0 }
+fn default_level_max_db() -> f32 { -10.0 }
+
+impl Default for GamepadConfig {
+    fn default() -> Self {
+        Self {
+            name: default_name(),
+            bend_axis: None,
+            bend_range: default_bend_range(),
+            level_axis: None,
+            level_min_db: default_level_min_db(),
+            level_max_db: default_level_max_db(),
+        }
+    }
+}
+
+impl GamepadConfig {
+    /// Whether the playing moves an axis.
+    pub fn follows(&self) -> bool { self.bend_axis.is_some() || self.level_axis.is_some() }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Button {
+    A,
+    B,
+    X,
+    Y,
+    // Shoulder buttons
+    Lb,
+    Rb,
+    Back,
+    Start,
+    Guide,
+    // Stick clicks
+    LeftStick,
+    RightStick,
+    // D-pad
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Axis {
+    LeftX,
+    LeftY,
+    RightX,
+    RightY,
+    LeftTrigger,
+    RightTrigger,
+}
+
+impl Axis {
+    const ALL: [Axis; 6] = [Axis::LeftX, Axis::LeftY, Axis::RightX, Axis::RightY, Axis::LeftTrigger, Axis::RightTrigger];
+
+    fn is_trigger(self) -> bool { matches!(self, Axis::LeftTrigger | Axis::RightTrigger) }
+
+    // Sticks go from -1 (left, down) to 1 (right, up), triggers from 0 to 1
+    fn clamp(self, value: f32) -> f32 { value.clamp(if self.is_trigger() { 0.0 } else { -1.0 }, 1.0) }
+}
+
+/// Parameters of a `type = "gamepad"` mapping.
+#[derive(Debug, Deserialize, Clone)]
+pub struct GamepadAction {
+    // Pressed for length_ms, or for as long as the note sounds with mode = "hold"
+    #[serde(default)]
+    pub button: Option<Button>,
+    // Set to `value`, where it stays until something moves it
+    #[serde(default)]
+    pub axis: Option<Axis>,
+    #[serde(default)]
+    pub value: f32,
+    #[serde(default = "default_length_ms")]
+    pub length_ms: u64,
+}
+
+fn default_length_ms() -> u64 { 60 }
+
+impl GamepadAction {
+    pub fn label(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(b) = self.button { parts.push(name(b)); }
+        if let Some(a) = self.axis { parts.push(format!("{}={}", name(a), a.clamp(self.value))); }
+        if parts.is_empty() { parts.push("nothing".to_string()); }
+        format!("pad:{}", parts.join(" "))
+    }
+}
+
+// "left-stick" as written in the config
+fn name(v: impl std::fmt::Debug) -> String {
+    let mut out = String::new();
+    for (i, c) in format!("{v:?}").chars().enumerate() {
+        if c.is_uppercase() && i > 0 { out.push('-'); }
+        out.push(c.to_ascii_lowercase());
+    }
+    out
+}
+
+// What the controller reports: buttons held down and axis positions
+#[derive(Clone, PartialEq, Default)]
+struct State {
+    buttons: Vec<Button>,
+    axes: [f32; 6],
+}
+
+static PAD: Mutex<Option<(Device, State)>> = Mutex::new(None);
+// The [gamepad] name, for a controller created at the first use
+static NAME: RwLock<Option<String>> = RwLock::new(None);
+
+/// The name a controller created later gets.
+pub fn set_config(cfg: &GamepadConfig) {
+    *NAME.write().unwrap_or_else(|e| e.into_inner()) = Some(cfg.name.clone());
+}
+
+/// Create the controller, unless it is there already.
+pub fn open(cfg: &GamepadConfig) -> Result<()> {
+    set_config(cfg);
+    let mut pad = PAD.lock().unwrap_or_else(|e| e.into_inner());
+    create(&mut pad)
+}
+
+fn create(pad: &mut Option<(Device, State)>) -> Result<()> {
+    if pad.is_none() {
+        let name = NAME.read().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_else(default_name);
+        *pad = Some((Device::open(&name)?, State::default()));
+        println!("Gamepad: virtual controller ready");
+    }
+    Ok(())
+}
+
+// Change the state and send it
+fn update(change: impl FnOnce(&mut State)) -> Result<()> {
+    let mut pad = PAD.lock().unwrap_or_else(|e| e.into_inner());
+    create(&mut pad)?;
+    let Some((device, state)) = pad.as_mut() else { return Ok(()) };
+    let mut next = state.clone();
+    change(&mut next);
+    if next != *state {
+        device.send(state, &next)?;
+        *state = next;
+    }
+    Ok(())
+}
+
+/// Press (and after length_ms release) the button, and set the axis.
+pub fn execute(action: &GamepadAction) -> Result<()> {
+    update(|s| {
+        if let Some(a) = action.axis { s.axes[a as usize] = a.clamp(action.value); }
+        if let Some(b) = action.button.filter(|b| !s.buttons.contains(b)) { s.buttons.push(b); }
+    })?;
+    if let Some(b) = action.button {
+        let length = Duration::from_millis(action.length_ms);
+        std::thread::spawn(move || {
+            std::thread::sleep(length);
+            if let Err(e) = update(|s| s.buttons.retain(|x| *x != b)) { tracing::error!("Gamepad: {e:#}"); }
+        });
+    }
+    Ok(())
+}
+
+/// Hold the mapping's button down, or let go of it (mode = "hold").
+pub fn hold(action: &GamepadAction, down: bool) -> Result<()> {
+    let Some(b) = action.button else { return execute(action) };
+    update(|s| {
+        if let Some(a) = action.axis.filter(|_| down) { s.axes[a as usize] = a.clamp(action.value); }
+        s.buttons.retain(|x| *x != b);
+        if down { s.buttons.push(b); }
+    })
+}
+
+/// Moves the [gamepad] axes with the playing, frame by frame.
+pub struct Follower {
+    cfg: GamepadConfig,
+    // The note (in semitones) the pitch started on
+    anchor: Option<f32>,
+}
+
+impl Follower {
+    pub fn new(cfg: &GamepadConfig) -> Option<Self> {
+        cfg.follows().then(|| Self { cfg: cfg.clone(), anchor: None })
+    }
+
+    /// `exact`: the pitch in semitones (None when silent); `rms`: the input level.
+    pub fn update(&mut self, exact: Option<f32>, rms: f32) -> Result<()> {
+        let anchor = match exact {
+            Some(e) => *self.anchor.get_or_insert(e.round()),
+            None => {
+                self.anchor = None;
+                0.0
+            }
+        };
+        let bend = exact.map_or(0.0, |e| (e - anchor) / self.cfg.bend_range.max(0.01));
+        let level = if exact.is_some() {
+            let db = 20.0 * rms.max(1e-9).log10();
+            (db - self.cfg.level_min_db) / (self.cfg.level_max_db - self.cfg.level_min_db).max(1.0)
+        } else {
+            0.0
+        };
+        update(|s| {
+            if let Some(a) = self.cfg.bend_axis { s.axes[a as usize] = a.clamp(bend); }
+            if let Some(a) = self.cfg.level_axis { s.axes[a as usize] = a.clamp(level); }
+        })
+    }
+}
+
+// ---- Linux: uinput ----
+
+#[cfg(target_os = "linux")]
+struct Device {
+    file: std::fs::File,
+}
+
+#[cfg(target_os = "linux")]
+impl Device {
+    const EV_SYN: u16 = 0x00;
+    const EV_KEY: u16 = 0x01;
+    const EV_ABS: u16 = 0x03;
+    const ABS_HAT0X: u16 = 0x10;
+    const ABS_HAT0Y: u16 = 0x11;
+    // An Xbox 360 pad's ids, so games and SDL use their mapping for it
+    const VENDOR: u16 = 0x045e;
+    const PRODUCT: u16 = 0x028e;
+
+    // The evdev code of a button, as xpad reports a real pad's
+    fn code(b: Button) -> Option<u16> {
+        Some(match b {
+            Button::A => 0x130,
+            Button::B => 0x131,
+            Button::X => 0x133,
+            Button::Y => 0x134,
+            Button::Lb => 0x136,
+            Button::Rb => 0x137,
+            Button::Back => 0x13a,
+            Button::Start => 0x13b,
+            Button::Guide => 0x13c,
+            Button::LeftStick => 0x13d,
+            Button::RightStick => 0x13e,
+            // The d-pad is the hat axes
+            Button::Up | Button::Down | Button::Left | Button::Right => return None,
+        })
+    }
+
+    // ABS code and range of an axis
+    fn abs(a: Axis) -> (u16, i32, i32) {
+        match a {
+            Axis::LeftX => (0x00, -32768, 32767),
+            Axis::LeftY => (0x01, -32768, 32767),
+            Axis::RightX => (0x03, -32768, 32767),
+            Axis::RightY => (0x04, -32768, 32767),
+            Axis::LeftTrigger => (0x02, 0, 255),
+            Axis::RightTrigger => (0x05, 0, 255),
+        }
+    }
+
+    fn open(name: &str) -> Result<Self> {
+        use anyhow::Context;
+        use std::os::fd::AsRawFd;
+        let file = std::fs::OpenOptions::new().write(true).open("/dev/uinput").context(
+            "cannot open /dev/uinput for the virtual gamepad: load the uinput module and give your user write access \
+             (e.g. a udev rule KERNEL==\"uinput\", GROUP=\"input\", MODE=\"0660\" and membership of the input group)",
+        )?;
+        let fd = file.as_raw_fd();
+        let set = |nr: u64, value: u16| -> Result<()> {
+            // SAFETY: the UI_SET_*BIT ioctls take an int by value
+            if unsafe { libc::ioctl(fd, uinput_ioc(1, nr, std::mem::size_of::<libc::c_int>()) as _, value as libc::c_int) } < 0 {
+                return Err(anyhow!("uinput setup failed: {}", std::io::Error::last_os_error()));
+            }
+            Ok(())
+        };
+        // UI_SET_EVBIT, UI_SET_KEYBIT, UI_SET_ABSBIT
+        set(100, Self::EV_KEY)?;
+        set(100, Self::EV_ABS)?;
+        let buttons = [Button::A, Button::B, Button::X, Button::Y, Button::Lb, Button::Rb, Button::Back, Button::Start, Button::Guide];
+        for code in buttons.into_iter().chain([Button::LeftStick, Button::RightStick]).filter_map(Self::code) {
+            set(101, code)?;
+        }
+        let mut axes: Vec<(u16, i32, i32)> = Axis::ALL.into_iter().map(Self::abs).collect();
+        axes.extend([(Self::ABS_HAT0X, -1, 1), (Self::ABS_HAT0Y, -1, 1)]);
+        for (code, minimum, maximum) in axes {
+            set(103, code)?;
+            // SAFETY: plain data
+            let mut setup: libc::uinput_abs_setup = unsafe { std::mem::zeroed() };
+            setup.code = code;
+            setup.absinfo.minimum = minimum;
+            setup.absinfo.maximum = maximum;
+            // UI_ABS_SETUP
+            let req = uinput_ioc(1, 4, std::mem::size_of::<libc::uinput_abs_setup>());
+            // SAFETY: the kernel reads the struct we pass
+            if unsafe { libc::ioctl(fd, req as _, &setup) } < 0 {
+                return Err(anyhow!("uinput axis setup failed: {}", std::io::Error::last_os_error()));
+            }
+        }
+        // SAFETY: plain data
+        let mut setup: libc::uinput_setup = unsafe { std::mem::zeroed() };
+        setup.id.bustype = 0x03; // BUS_USB
+        setup.id.vendor = Self::VENDOR;
+        setup.id.product = Self::PRODUCT;
+        setup.id.version = 1;
+        for (dst, src) in setup.name.iter_mut().zip(name.bytes().take(libc::UINPUT_MAX_NAME_SIZE - 1)) {
+            *dst = src as libc::c_char;
+        }
+        // UI_DEV_SETUP, then UI_DEV_CREATE
+        // SAFETY: the kernel reads the struct we pass; UI_DEV_CREATE takes no argument
+        let ok = unsafe {
+            libc::ioctl(fd, uinput_ioc(1, 3, std::mem::size_of::<libc::uinput_setup>()) as _, &setup) >= 0
+                && libc::ioctl(fd, uinput_ioc(0, 1, 0) as _) >= 0
+        };
+        if !ok { return Err(anyhow!("cannot create the virtual gamepad: {}", std::io::Error::last_os_error())); }
+        Ok(Self { file })
+    }
+
+    // Report what changed from `old` to `new`
+    fn send(&mut self, old: &State, new: &State) -> Result<()> {
+        use std::io::Write;
+        let mut events = Vec::new();
+        let pressed = new.buttons.iter().filter(|b| !old.buttons.contains(b)).map(|b| (*b, 1));
+        let released = old.buttons.iter().filter(|b| !new.buttons.contains(b)).map(|b| (*b, 0));
+        for (b, value) in pressed.chain(released) {
+            if let Some(code) = Self::code(b) { events.push((Self::EV_KEY, code, value)); }
+        }
+        for a in Axis::ALL {
+            let (value, old_value) = (new.axes[a as usize], old.axes[a as usize]);
+            if value == old_value { continue; }
+            let (code, minimum, maximum) = Self::abs(a);
+            // evdev's Y axes point down
+            let value = if matches!(a, Axis::LeftY | Axis::RightY) { -value } else { value };
+            let raw = if a.is_trigger() { value * maximum as f32 } else if value < 0.0 { -value * minimum as f32 } else { value * maximum as f32 };
+            events.push((Self::EV_ABS, code, raw.round() as i32));
+        }
+        let hat = |s: &State, minus: Button, plus: Button| s.buttons.contains(&plus) as i32 - s.buttons.contains(&minus) as i32;
+        for (code, minus, plus) in [(Self::ABS_HAT0X, Button::Left, Button::Right), (Self::ABS_HAT0Y, Button::Up, Button::Down)] {
+            let value = hat(new, minus, plus);
+            if value != hat(old, minus, plus) { events.push((Self::EV_ABS, code, value)); }
+        }
+        events.push((Self::EV_SYN, 0, 0));
+        let mut bytes = Vec::new();
+        for (type_, code, value) in events {
+            // SAFETY: input_event is plain data
+            let mut event: libc::input_event = unsafe { std::mem::zeroed() };
+            event.type_ = type_;
+            event.code = code;
+            event.value = value;
+            // SAFETY: viewing plain data as bytes
+            bytes.extend_from_slice(unsafe {
+                std::slice::from_raw_parts(&event as *const _ as *const u8, std::mem::size_of::<libc::input_event>())
+            });
+        }
+        self.file.write_all(&bytes).map_err(|e| anyhow!("virtual gamepad: {e}"))
+    }
+}
+
+// Linux _IOC(dir, 'U', nr, size) for the uinput ioctls
+#[cfg(target_os = "linux")]
+pub(crate) fn uinput_ioc(dir: u64, nr: u64, size: usize) -> u64 {
+    (dir << 30) | ((size as u64) << 16) | (0x55 << 8) | nr
+}
+
+// ---- Windows: ViGEm ----
+
+#[cfg(windows)]
+struct Device {
+    client: *mut std::ffi::c_void,
+    target: *mut std::ffi::c_void,
+    update: unsafe extern "C" fn(*mut std::ffi::c_void, *mut std::ffi::c_void, XusbReport) -> u32,
+}
+
+// The handles are only used under the PAD lock
+#[cfg(windows)]
+unsafe impl Send for Device {}
+
+// XUSB_REPORT from ViGEmClient.h, passed by value
+#[cfg(windows)]
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct XusbReport {
+    buttons: u16,
+    left_trigger: u8,
+    right_trigger: u8,
+    thumb_lx: i16,
+    thumb_ly: i16,
+    thumb_rx: i16,
+    thumb_ry: i16,
+}
+
+#[cfg(windows)]
+impl Device {
+    // VIGEM_ERROR_NONE
+    const OK: u32 = 0x2000_0000;
+
+    // XUSB_GAMEPAD_* bits
+    fn bit(b: Button) -> u16 {
+        match b {
+            Button::Up => 0x0001,
+            Button::Down => 0x0002,
+            Button::Left => 0x0004,
+            Button::Right => 0x0008,
+            Button::Start => 0x0010,
+            Button::Back => 0x0020,
+            Button::LeftStick => 0x0040,
+            Button::RightStick => 0x0080,
+            Button::Lb => 0x0100,
+            Button::Rb => 0x0200,
+            Button::Guide => 0x0400,
+            Button::A => 0x1000,
+            Button::B => 0x2000,
+            Button::X => 0x4000,
+            Button::Y => 0x8000,
+        }
+    }
+
+    fn open(_name: &str) -> Result<Self> {
+        use windows_sys::Win32::System::LibraryLoader::{GetProcAddress, LoadLibraryW};
+        let dll: Vec<u16> = "ViGEmClient.dll".encode_utf16().chain([0]).collect();
+        // SAFETY: a NUL-terminated wide string
+        let lib = unsafe { LoadLibraryW(dll.as_ptr()) };
+        if lib == 0 {
+            return Err(anyhow!(
+                "the virtual gamepad needs ViGEmClient.dll next to the program (or on the PATH) and the ViGEmBus driver"
+            ));
+        }
+        let function = |name: &[u8]| {
+            // SAFETY: a NUL-terminated name looked up in the loaded library
+            unsafe { GetProcAddress(lib, name.as_ptr()) }.ok_or_else(|| anyhow!("ViGEmClient.dll lacks {}", String::from_utf8_lossy(&name[..name.len() - 1])))
+        };
+        // SAFETY: the signatures are those of ViGEmClient.h
+        unsafe {
+            let alloc: unsafe extern "C" fn() -> *mut std::ffi::c_void = std::mem::transmute(function(b"vigem_alloc\0")?);
+            let connect: unsafe extern "C" fn(*mut std::ffi::c_void) -> u32 = std::mem::transmute(function(b"vigem_connect\0")?);
+            let x360: unsafe extern "C" fn() -> *mut std::ffi::c_void = std::mem::transmute(function(b"vigem_target_x360_alloc\0")?);
+            let add: unsafe extern "C" fn(*mut std::ffi::c_void, *mut std::ffi::c_void) -> u32 =
+                std::mem::transmute(function(b"vigem_target_add\0")?);
+            let update = std::mem::transmute(function(b"vigem_target_x360_update\0")?);
+            let client = alloc();
+            if client.is_null() { return Err(anyhow!("ViGEm: out of memory")); }
+            let code = connect(client);
+            if code != Self::OK { return Err(anyhow!("cannot reach the ViGEmBus driver (error {code:#x}); is it installed?")); }
+            let target = x360();
+            let code = add(client, target);
+            if code != Self::OK { return Err(anyhow!("ViGEm couldn't plug in the virtual controller (error {code:#x})")); }
+            Ok(Self { client, target, update })
+        }
+    }
+
+    fn send(&mut self, _old: &State, new: &State) -> Result<()> {
+        let stick = |a: Axis| (new.axes[a as usize] * 32767.0).round() as i16;
+        let trigger = |a: Axis| (new.axes[a as usize] * 255.0).round() as u8;
+        let report = XusbReport {
+            buttons: new.buttons.iter().fold(0, |bits, b| bits | Self::bit(*b)),
+            left_trigger: trigger(Axis::LeftTrigger),
+            right_trigger: trigger(Axis::RightTrigger),
+            thumb_lx: stick(Axis::LeftX),
+            thumb_ly: stick(Axis::LeftY),
+            thumb_rx: stick(Axis::RightX),
+            thumb_ry: stick(Axis::RightY),
+        };
+        // SAFETY: client and target are the ones ViGEm gave us
+        let code = unsafe { (self.update)(self.client, self.target, report) };
+        if code != Self::OK { return Err(anyhow!("ViGEm report failed (error {code:#x})")); }
+        Ok(())
+    }
+}
+
+// ---- Elsewhere ----
+
+#[cfg(not(any(windows, target_os = "linux")))]
+struct Device;
+
+#[cfg(not(any(windows, target_os = "linux")))]
+impl Device {
+    fn open(_name: &str) -> Result<Self> { Err(anyhow!("the virtual gamepad is only available on Linux and Windows")) }
+
+    fn send(&mut self, _old: &State, _new: &State) -> Result<()> { Ok(()) }
+}
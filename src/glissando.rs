@@ -0,0 +1,147 @@
+// ---------------------------- Glissando gestures ----------------------------
+//
+// A slide up or down the neck becomes a stream of steps: once the pitch has
+// travelled `min_semitones` within `max_ms`, every semitone it covers runs
+// the `up` or `down` action once (scroll ticks, arrow keys) and moves an
+// optional MIDI CC ramp. The slide ends when the pitch turns back, stops
+// moving or the note dies.
+
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+use crate::Action;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct GlissandoConfig {
+    // Travel (semitones) within max_ms that starts a slide
+    #[serde(default = "default_min_semitones")]
+    pub min_semitones: f32,
+    #[serde(default = "default_max_ms")]
+    pub max_ms: u64,
+    // A slide that doesn't reach another semitone for this long has ended
+    #[serde(default = "default_stall_ms")]
+    pub stall_ms: u64,
+    // Run once per semitone climbed / descended
+    #[serde(default, deserialize_with = "crate::optional_action")]
+    pub up: Option<Action>,
+    #[serde(default, deserialize_with = "crate::optional_action")]
+    pub down: Option<Action>,
+    // MIDI controller moved by cc_step per semitone (starts at 64)
+    #[serde(default)]
+    pub cc: Option<u8>,
+    #[serde(default = "default_cc_step")]
+    pub cc_step: u8,
+}
+
+fn default_min_semitones() -> f32 { 3.0 }
+fn default_max_ms() -> u64 { 500 }
+fn default_stall_ms() -> u64 { 250 }
+fn default_cc_step() -> u8 { 8 }
+
+impl Default for GlissandoConfig {
+    fn default() -> Self {
+        Self {
+            min_semitones: default_min_semitones(),
+            max_ms: default_max_ms(),
+            stall_ms: default_stall_ms(),
+            up: None,
+            down: None,
+            cc: None,
+            cc_step: default_cc_step(),
+        }
+    }
+}
+
+impl GlissandoConfig {
+    /// Slides are only followed when they do something.
+    pub fn active(&self) -> bool {
+        self.up.is_some() || self.down.is_some() || self.cc.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Up,
+    Down,
+}
+
+impl Direction {
+    fn sign(self) -> f32 {
+        match self {
+            Direction::Up => 1.0,
+            Direction::Down => -1.0,
+        }
+    }
+}
+
+struct Slide {
+    direction: Direction,
+    // Pitch of the last step
+    reached: f32,
+    reached_at: Instant,
+}
+
+pub struct GlissandoTracker {
+    cfg: GlissandoConfig,
+    // Recent pitch in fractional MIDI notes
+    track: Vec<(Instant, f32)>,
+    slide: Option<Slide>,
+    cc_value: u8,
+}
+
+impl GlissandoTracker {
+    pub fn new(cfg: &GlissandoConfig) -> Self {
+        Self { cfg: cfg.clone(), track: Vec::new(), slide: None, cc_value: 64 }
+    }
+
+    /// Feed one frame (pitch in fractional MIDI notes, None when silent).
+    /// Returns the direction and number of semitone steps taken this frame.
+    pub fn update(&mut self, now: Instant, pitch: Option<f32>) -> Option<(Direction, usize)> {
+        let Some(p) = pitch else {
+            self.track.clear();
+            self.slide = None;
+            return None;
+        };
+        if let Some(slide) = self.slide.as_mut() {
+            let travelled = (p - slide.reached) * slide.direction.sign();
+            let stalled = now.duration_since(slide.reached_at) > Duration::from_millis(self.cfg.stall_ms);
+            if travelled >= 1.0 {
+                let steps = travelled.floor();
+                slide.reached += steps * slide.direction.sign();
+                slide.reached_at = now;
+                return Some((slide.direction, steps as usize));
+            }
+            if travelled > -1.0 && !stalled { return None; }
+            // Turned back or stopped: a new slide needs the full travel again
+            self.slide = None;
+            self.track.clear();
+        }
+        let window = Duration::from_millis(self.cfg.max_ms);
+        self.track.retain(|(t, _)| now.duration_since(*t) <= window);
+        self.track.push((now, p));
+        let low = self.track.iter().map(|(_, v)| *v).fold(f32::INFINITY, f32::min);
+        let high = self.track.iter().map(|(_, v)| *v).fold(f32::NEG_INFINITY, f32::max);
+        let (direction, from) = if p - low >= self.cfg.min_semitones {
+            (Direction::Up, low)
+        } else if high - p >= self.cfg.min_semitones {
+            (Direction::Down, high)
+        } else {
+            return None;
+        };
+        let steps = (p - from).abs().floor();
+        self.slide = Some(Slide { direction, reached: from + steps * direction.sign(), reached_at: now });
+        Some((direction, steps as usize))
+    }
+
+    /// Whether a slide is under way.
+    pub fn sliding(&self) -> bool {
+        self.slide.is_some()
+    }
+
+    /// Move the CC ramp by `steps` in `direction`; the new value.
+    pub fn ramp(&mut self, direction: Direction, steps: usize) -> u8 {
+        let delta = (self.cfg.cc_step as usize * steps).min(127) as i32 * direction.sign() as i32;
+        self.cc_value = (self.cc_value as i32 + delta).clamp(0, 127) as u8;
+        self.cc_value
+    }
+}
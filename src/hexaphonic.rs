@@ -0,0 +1,61 @@
+// ---------------------------- Hexaphonic input ----------------------------
+//
+// A divided pickup (Roland GK-3 and the like) gives every string a channel of
+// its own. Each string then gets its own pipeline, as a performer would: one
+// note at a time per string adds up to real polyphony without a polyphonic
+// detector, and the string a note was played on is known rather than guessed
+// from its timbre, so "E3@5" mappings are exact. A string's section can
+// override any setting for that string alone, note_map included.
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct HexaphonicConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    // Input channel of each string, the 1st (highest) string first
+    #[serde(default = "default_channels")]
+    pub channels: Vec<usize>,
+    // [hexaphonic.strings.<n>]: settings for string n only
+    #[serde(default)]
+    pub strings: BTreeMap<String, toml::Table>,
+}
+
+fn default_channels() -> Vec<usize> { (1..=6).collect() }
+
+impl Default for HexaphonicConfig {
+    fn default() -> Self {
+        Self { enabled: false, channels: default_channels(), strings: BTreeMap::new() }
+    }
+}
+
+impl HexaphonicConfig {
+    /// Every string's number and the keys its pipeline overrides: its own
+    /// section, plus its input channel.
+    pub fn strings(&self) -> Result<Vec<(usize, toml::Table)>> {
+        if self.channels.is_empty() { return Err(anyhow!("hexaphonic.channels lists no strings")); }
+        if let Some(ch) = self.channels.iter().find(|&&c| c == 0) {
+            return Err(anyhow!("hexaphonic.channels must be channel numbers from 1, not {ch}"));
+        }
+        for key in self.strings.keys() {
+            if !key.parse::<usize>().is_ok_and(|n| (1..=self.channels.len()).contains(&n)) {
+                return Err(anyhow!("[hexaphonic.strings.{key}]: strings are numbered 1 to {}", self.channels.len()));
+            }
+            if self.strings[key].contains_key("input_channel") {
+                return Err(anyhow!("[hexaphonic.strings.{key}] can't set input_channel; hexaphonic.channels does"));
+            }
+        }
+        Ok(self
+            .channels
+            .iter()
+            .enumerate()
+            .map(|(i, &channel)| {
+                let mut section = self.strings.get(&(i + 1).to_string()).cloned().unwrap_or_default();
+                section.insert("input_channel".into(), toml::Value::Integer(channel as i64));
+                (i + 1, section)
+            })
+            .collect())
+    }
+}
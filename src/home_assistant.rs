@@ -0,0 +1,125 @@
+// ---------------------------- Home Assistant ----------------------------
+//
+// `type = "home-assistant"` mappings call a Home Assistant service through
+// its REST API (POST /api/services/<domain>/<service>), so a note can switch
+// lights, activate scenes and run scripts by entity id. Home Assistant wants
+// a long-lived access token (your profile page > Security). `url` may be the
+// local http:// address or an https:// one (Nabu Casa, a reverse proxy).
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::sync::RwLock;
+use std::time::Duration;
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct HomeAssistantConfig {
+    // Where Home Assistant answers, e.g. "http://homeassistant.local:8123"
+    #[serde(default = "default_url")]
+    pub url: String,
+    // A long-lived access token
+    #[serde(default)]
+    pub token: String,
+}
+
+fn default_url() -> String { "http://homeassistant.local:8123".to_string() }
+
+impl Default for HomeAssistantConfig {
+    fn default() -> Self {
+        Self { url: default_url(), token: String::new() }
+    }
+}
+
+/// Parameters of a `type = "home-assistant"` mapping.
+#[derive(Debug, Deserialize, Clone)]
+pub struct HomeAssistantAction {
+    // domain.service, e.g. "light.turn_on" or "script.turn_on"; the default
+    // toggles any kind of entity
+    #[serde(default = "default_service")]
+    pub service: String,
+    // One entity id or several
+    #[serde(default)]
+    pub entity: Entities,
+    // Service data besides the entity, e.g. { brightness_pct = 40 }
+    #[serde(default)]
+    pub data: Map<String, Value>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(untagged)]
+pub enum Entities {
+    #[default]
+    None,
+    One(String),
+    Many(Vec<String>),
+}
+
+fn default_service() -> String { "homeassistant.toggle".to_string() }
+
+impl HomeAssistantAction {
+    pub fn label(&self) -> String {
+        match &self.entity {
+            Entities::None => format!("ha:{}", self.service),
+            Entities::One(e) => format!("ha:{} {e}", self.service),
+            Entities::Many(e) => format!("ha:{} {}", self.service, e.join(",")),
+        }
+    }
+}
+
+// How long Home Assistant gets to answer
+const TIMEOUT: Duration = Duration::from_secs(3);
+
+static CONFIG: RwLock<Option<HomeAssistantConfig>> = RwLock::new(None);
+
+/// Where home-assistant mappings send their calls.
+pub fn set_config(cfg: &HomeAssistantConfig) {
+    *CONFIG.write().unwrap_or_else(|e| e.into_inner()) = Some(cfg.clone());
+}
+
+/// Call the mapping's service.
+pub fn execute(action: &HomeAssistantAction) -> Result<()> {
+    let cfg = CONFIG.read().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_default();
+    if cfg.token.is_empty() { return Err(anyhow!("home-assistant mappings need [home_assistant] token")); }
+    let (domain, service) = action
+        .service
+        .split_once('.')
+        .ok_or_else(|| anyhow!("service {:?} should be domain.service, e.g. light.toggle", action.service))?;
+    let mut body = action.data.clone();
+    let entity_id: Option<Value> = match &action.entity {
+        Entities::None => None,
+        Entities::One(e) => Some(e.clone().into()),
+        Entities::Many(e) => Some(e.clone().into()),
+    };
+    if let Some(id) = entity_id { body.insert("entity_id".to_string(), id); }
+    let (status, reply) = post(&cfg, &format!("/api/services/{domain}/{service}"), &Value::Object(body).to_string())?;
+    match status {
+        200..=299 => Ok(()),
+        401 => Err(anyhow!("Home Assistant refused the token (401); check [home_assistant] token")),
+        _ => {
+            // Home Assistant explains itself in {"message": "..."} or plain text
+            let reason = serde_json::from_str::<Value>(&reply)
+                .ok()
+                .and_then(|v| v["message"].as_str().map(str::to_string))
+                .unwrap_or_else(|| reply.trim().chars().take(200).collect());
+            Err(anyhow!("Home Assistant answered {status} to {}: {reason}", action.service))
+        }
+    }
+}
+
+// The status code and body of a POST of `body` to `path` under the URL
+fn post(cfg: &HomeAssistantConfig, path: &str, body: &str) -> Result<(u16, String)> {
+    // A reverse proxy may serve Home Assistant under a path
+    let url = format!("{}{path}", cfg.url.trim_end_matches('/'));
+    let response = ureq::post(&url)
+        .timeout(TIMEOUT)
+        .set("Authorization", &format!("Bearer {}", cfg.token))
+        .set("Content-Type", "application/json")
+        .send_string(body);
+    let response = match response {
+        Ok(r) => r,
+        Err(ureq::Error::Status(_, r)) => r,
+        Err(ureq::Error::Transport(e)) => return Err(anyhow!("cannot reach Home Assistant at {}: {e}", cfg.url)),
+    };
+    let status = response.status();
+    Ok((status, response.into_string().unwrap_or_default()))
+}
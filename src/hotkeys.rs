@@ -0,0 +1,143 @@
+// ---------------------------- Global hotkeys ----------------------------
+//
+// A key combination pressed anywhere turns actions off and on again, so
+// practicing doesn't type into whatever window is focused: detection and the
+// status line keep running, only the mapped actions are skipped. Another can
+// step to the next profile. Windows registers them with the system; on other
+// platforms the desktop's own keyboard shortcut settings run
+// `rusty-strings-control actions toggle` (or `profile next`), which reaches
+// the running instance through the control port.
+
+use serde::Deserialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct HotkeysConfig {
+    // Turns actions off and on, e.g. "Ctrl+Alt+P"
+    #[serde(default)]
+    pub toggle_actions: Option<String>,
+    // Switches to the next profile, like `profile next`
+    #[serde(default)]
+    pub next_profile: Option<String>,
+}
+
+// Actions are skipped (toggled by the hotkey or `actions off`)
+static ACTIONS_OFF: AtomicBool = AtomicBool::new(false);
+
+/// Whether mapped actions run.
+pub fn actions_on() -> bool {
+    !ACTIONS_OFF.load(Ordering::Relaxed)
+}
+
+pub fn set_actions(on: bool) {
+    ACTIONS_OFF.store(!on, Ordering::Relaxed);
+}
+
+/// Turn actions off if on and on if off; whether they are now on.
+pub fn toggle_actions() -> bool {
+    // Off before the flip means on after it
+    ACTIONS_OFF.fetch_xor(true, Ordering::Relaxed)
+}
+
+/// Start listening for the configured hotkeys.
+pub fn register(cfg: &HotkeysConfig) {
+    if cfg.toggle_actions.is_none() && cfg.next_profile.is_none() { return; }
+    listen(cfg);
+}
+
+#[cfg(not(windows))]
+fn listen(_cfg: &HotkeysConfig) {
+    tracing::warn!(
+        "[hotkeys] are only registered on Windows; bind `rusty-strings-control actions toggle` \
+         (or `profile next`) to a shortcut in your desktop's keyboard settings instead"
+    );
+}
+
+#[cfg(windows)]
+fn listen(cfg: &HotkeysConfig) {
+    use crate::{keys, profiles};
+    use windows_sys::Win32::UI::Input::KeyboardAndMouse::{
+        RegisterHotKey, MOD_ALT, MOD_CONTROL, MOD_NOREPEAT, MOD_SHIFT, MOD_WIN,
+    };
+    use windows_sys::Win32::UI::WindowsAndMessaging::{GetMessageW, MSG, WM_HOTKEY};
+
+    const TOGGLE: i32 = 1;
+    const NEXT: i32 = 2;
+    let wanted: Vec<(i32, &'static str, String)> = [
+        (TOGGLE, "toggle_actions", cfg.toggle_actions.clone()),
+        (NEXT, "next_profile", cfg.next_profile.clone()),
+    ]
+    .into_iter()
+    .filter_map(|(id, name, seq)| Some((id, name, seq?)))
+    .collect();
+
+    // Hotkeys belong to the thread that registers them, which then gets
+    // WM_HOTKEY in its message queue
+    std::thread::spawn(move || {
+        for (id, name, sequence) in &wanted {
+            let combo = match keys::parse(sequence) {
+                Ok(c) => c,
+                Err(e) => {
+                    tracing::warn!("hotkeys.{name}: {e:#}");
+                    continue;
+                }
+            };
+            let Some(vk) = virtual_key(combo.key) else {
+                tracing::warn!("hotkeys.{name}: {sequence:?} has no key on this keyboard layout");
+                continue;
+            };
+            let mods = combo.modifiers.iter().fold(MOD_NOREPEAT, |m, k| {
+                m | match k {
+                    keys::Modifier::Ctrl => MOD_CONTROL,
+                    keys::Modifier::Shift => MOD_SHIFT,
+                    keys::Modifier::Alt => MOD_ALT,
+                    keys::Modifier::Win => MOD_WIN,
+                }
+            });
+            // SAFETY: a thread hotkey (no window), registered on this thread
+            if unsafe { RegisterHotKey(0, *id, mods, vk) } == 0 {
+                tracing::warn!("hotkeys.{name}: {sequence} is taken by another program");
+            }
+        }
+        // SAFETY: MSG is plain data that GetMessageW fills in
+        let mut msg: MSG = unsafe { std::mem::zeroed() };
+        while unsafe { GetMessageW(&mut msg, 0, 0, 0) } > 0 {
+            if msg.message != WM_HOTKEY { continue; }
+            match msg.wParam as i32 {
+                TOGGLE => { toggle_actions(); }
+                NEXT => profiles::request(profiles::Switch::Next),
+                _ => {}
+            }
+        }
+    });
+}
+
+// The virtual-key code of a main key; characters go through the keyboard layout
+#[cfg(windows)]
+fn virtual_key(key: crate::keys::Key) -> Option<u32> {
+    use crate::keys::Key;
+    use windows_sys::Win32::UI::Input::KeyboardAndMouse::{
+        VkKeyScanW, VK_BACK, VK_DELETE, VK_DOWN, VK_ESCAPE, VK_LEFT, VK_RETURN, VK_RIGHT, VK_SPACE, VK_TAB, VK_UP,
+    };
+    let vk = match key {
+        Key::Space => VK_SPACE,
+        Key::Enter => VK_RETURN,
+        Key::Tab => VK_TAB,
+        Key::Esc => VK_ESCAPE,
+        Key::Up => VK_UP,
+        Key::Down => VK_DOWN,
+        Key::Left => VK_LEFT,
+        Key::Right => VK_RIGHT,
+        Key::Backspace => VK_BACK,
+        Key::Delete => VK_DELETE,
+        Key::Char(c) => {
+            let mut units = [0u16; 2];
+            let [unit] = c.encode_utf16(&mut units) else { return None };
+            // SAFETY: VkKeyScanW only reads its argument
+            let scan = unsafe { VkKeyScanW(*unit) };
+            if scan == -1 { return None; }
+            (scan & 0xff) as u16
+        }
+    };
+    Some(vk as u32)
+}
@@ -0,0 +1,203 @@
+// ---------------------------- HTTP API ----------------------------
+//
+// `[http_api]` runs the control commands over HTTP on a local port, for
+// scripts, Stream Deck buttons and anything else that can send a request:
+//
+//   GET  /state                  {"paused":false,"actions":true,"profile":null,...}
+//   POST /pause, /resume         stop and start listening
+//   POST /reload                 read the config file again
+//   POST /profile/<name>         a profile's name, next or default
+//   POST /actions/<state>        on, off or toggle
+//   POST /note/<key>             run a note_map key's mapping, e.g. /note/E4
+//                                (/note/C%234 for C#4)
+//
+// Replies are JSON: {"ok":"..."} or {"error":"..."}. Commands must be POSTs,
+// so following a link or loading an image can't set one off. A web page can
+// still POST here without asking, so requests with an Origin header (which
+// browsers add to them) and requests for any Host but 127.0.0.1 or localhost
+// on this port (a DNS-rebound site) are refused. Only 127.0.0.1 is listened
+// on, and requests are answered one at a time; the protocol is small enough
+// not to need a library.
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use serde_json::json;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+use crate::control;
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct HttpApiConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_port")]
+    pub port: u16,
+}
+
+fn default_port() -> u16 { 47801 }
+
+impl Default for HttpApiConfig {
+    fn default() -> Self {
+        Self { enabled: false, port: default_port() }
+    }
+}
+
+// Longest request head read; nothing here needs more
+const MAX_HEAD: usize = 8192;
+// A client that sends nothing for this long is hung up on
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Answer requests on the API port for as long as the program runs. A
+/// port already in use only gets a warning.
+pub fn listen(cfg: &HttpApiConfig) {
+    if !cfg.enabled { return; }
+    let listener = match TcpListener::bind(("127.0.0.1", cfg.port)) {
+        Ok(l) => l,
+        Err(e) => {
+            tracing::warn!("HTTP API port {} unavailable: {e}", cfg.port);
+            return;
+        }
+    };
+    println!("HTTP API on http://127.0.0.1:{}", cfg.port);
+    let port = cfg.port;
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = serve(stream, port) { tracing::debug!("HTTP API: {e:#}"); }
+        }
+    });
+}
+
+fn serve(mut stream: TcpStream, port: u16) -> Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let head = read_head(&mut stream)?;
+    let request_line = head.lines().next().unwrap_or_default();
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+    // The query string, if any, is ignored
+    let path = target.split('?').next().unwrap_or_default();
+    let routed = match forbidden(&head, port) {
+        Some(e) => Err(("403 Forbidden", e)),
+        None => route(method, path),
+    };
+    let (status, body) = match routed {
+        // state is JSON already
+        Ok(("state", Ok(state))) => ("200 OK", state),
+        Ok((_, Ok(reply))) => ("200 OK", json!({ "ok": reply.strip_prefix("ok: ").unwrap_or(&reply) }).to_string()),
+        Ok((_, Err(e))) => ("400 Bad Request", json!({ "error": format!("{e:#}") }).to_string()),
+        Err((status, e)) => (status, json!({ "error": e }).to_string()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    Ok(())
+}
+
+// Up to the blank line that ends the headers; a body is left unread
+fn read_head(stream: &mut TcpStream) -> Result<String> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        if head.len() > MAX_HEAD { return Err(anyhow!("request head too long")); }
+        let n = stream.read(&mut buf)?;
+        if n == 0 { return Err(anyhow!("connection closed mid-request")); }
+        head.extend_from_slice(&buf[..n]);
+    }
+    Ok(String::from_utf8_lossy(&head).into_owned())
+}
+
+// Why a request can't be from a local program, if it can't
+fn forbidden(head: &str, port: u16) -> Option<String> {
+    let header = |name: &str| {
+        head.lines().skip(1).find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.trim().eq_ignore_ascii_case(name).then(|| value.trim())
+        })
+    };
+    if let Some(origin) = header("origin") { return Some(format!("requests from web pages ({origin}) are refused")); }
+    let host = header("host").unwrap_or_default();
+    if host != format!("127.0.0.1:{port}") && host != format!("localhost:{port}") {
+        return Some(format!("Host must be 127.0.0.1:{port} or localhost:{port}, not {host:?}"));
+    }
+    None
+}
+
+// Run the control command a request stands for: the command's verb and its
+// reply, or an HTTP error
+type Routed = std::result::Result<(&'static str, Result<String>), (&'static str, String)>;
+
+fn route(method: &str, path: &str) -> Routed {
+    let segments: Vec<String> = path.trim_matches('/').split('/').map(percent_decode).collect();
+    let (verb, arg) = match segments.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["state"] => ("state", ""),
+        ["pause"] => ("pause", ""),
+        ["resume"] => ("resume", ""),
+        ["reload"] => ("reload", ""),
+        ["profile", name] => ("profile", name),
+        ["actions", state] => ("actions", state),
+        ["note", key] => ("note", key),
+        _ => return Err(("404 Not Found", format!("no such endpoint: {path}"))),
+    };
+    let wanted = if verb == "state" { "GET" } else { "POST" };
+    if method != wanted {
+        return Err(("405 Method Not Allowed", format!("{path} takes {wanted}, not {method}")));
+    }
+    Ok((verb, control::run(&format!("{verb} {arg}"))))
+}
+
+// "%23" and the like back to the bytes they stand for
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| u8::from_str_radix(std::str::from_utf8(h).ok()?, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // serve's reply to `request`, sent over a real connection
+    fn reply(request: &str) -> String {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let request = request.replace("PORT", &port.to_string());
+        let client = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            stream.write_all(request.as_bytes()).unwrap();
+            let mut reply = String::new();
+            stream.read_to_string(&mut reply).unwrap();
+            reply
+        });
+        let (stream, _) = listener.accept().unwrap();
+        serve(stream, port).unwrap();
+        client.join().unwrap()
+    }
+
+    #[test]
+    fn web_pages_are_refused() {
+        let from_page = reply("POST /note/E4 HTTP/1.1\r\nHost: 127.0.0.1:PORT\r\nOrigin: https://example.com\r\n\r\n");
+        assert!(from_page.starts_with("HTTP/1.1 403 Forbidden"), "{from_page}");
+        let rebound = reply("POST /pause HTTP/1.1\r\nHost: evil.example:PORT\r\n\r\n");
+        assert!(rebound.starts_with("HTTP/1.1 403 Forbidden"), "{rebound}");
+        assert_eq!(forbidden("GET /state HTTP/1.1\r\nhost: localhost:47801\r\n\r\n", 47801), None);
+        assert!(forbidden("GET /state HTTP/1.1\r\n\r\n", 47801).is_some());
+    }
+}
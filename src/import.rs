@@ -0,0 +1,372 @@
+// ---------------------------- Mapping import ----------------------------
+//
+// `import ahk <script>` / `import karabiner <file>` read the key combinations
+// an AutoHotkey script or Karabiner-Elements config sends, then ask which
+// note should trigger each one and add the answers to [note_map]. Only
+// outputs a "keys" action can send (modifiers plus one key) are imported;
+// everything else is listed and skipped.
+
+use anyhow::{anyhow, Context, Result};
+use serde_json::Value;
+use std::io::Write;
+use std::path::Path;
+
+use crate::{key_note, midi_to_name, note_to_midi, strings};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Ahk,
+    Karabiner,
+}
+
+impl Source {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "ahk" | "autohotkey" => Ok(Self::Ahk),
+            "karabiner" => Ok(Self::Karabiner),
+            other => Err(anyhow!("Unknown import source {other:?} (ahk, karabiner)")),
+        }
+    }
+}
+
+/// A key combination found in the imported file.
+pub struct Imported {
+    // Where it came from: the hotkey or rule description
+    pub origin: String,
+    // In note_map syntax, e.g. "Ctrl+Shift+Z"
+    pub sequence: String,
+}
+
+pub fn read(source: Source, path: &Path) -> Result<Vec<Imported>> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("Reading {}", path.display()))?;
+    match source {
+        Source::Ahk => Ok(ahk(&text)),
+        Source::Karabiner => karabiner(&text).with_context(|| format!("Parsing {}", path.display())),
+    }
+}
+
+// ---------------------------- AutoHotkey ----------------------------
+
+// Hotkeys ("^s::Send ^+z", or a block of lines ending in "return"/"}") and
+// remaps ("CapsLock::Esc"); hotstrings and other commands are skipped
+fn ahk(text: &str) -> Vec<Imported> {
+    let mut found = Vec::new();
+    let mut current: Option<(String, Vec<String>)> = None;
+    let mut in_comment = false;
+    let mut finish = |current: &mut Option<(String, Vec<String>)>| {
+        let Some((hotkey, sends)) = current.take() else { return };
+        match sends.as_slice() {
+            [] => eprintln!("Skipping {hotkey}: it doesn't send keys"),
+            [keys] => match ahk_send(keys) {
+                Ok(sequence) => found.push(Imported { origin: hotkey, sequence }),
+                Err(e) => eprintln!("Skipping {hotkey}: {e}"),
+            },
+            _ => eprintln!("Skipping {hotkey}: it sends several times; only one key combination can be imported"),
+        }
+    };
+    for line in text.lines() {
+        let line = line.trim();
+        if in_comment {
+            in_comment = !line.starts_with("*/");
+            continue;
+        }
+        if line.starts_with("/*") {
+            in_comment = true;
+            continue;
+        }
+        let line = strip_ahk_comment(line);
+        if line.is_empty() { continue; }
+        if line.starts_with(':') {
+            // Hotstring (":*:btw::by the way"): text, not keys
+            finish(&mut current);
+            continue;
+        }
+        if let Some((hotkey, action)) = line.split_once("::") {
+            finish(&mut current);
+            let hotkey = hotkey.trim().to_string();
+            let action = action.trim();
+            match action {
+                "" | "{" => current = Some((hotkey, Vec::new())),
+                _ => {
+                    let sends = if let Some(keys) = ahk_send_arg(action) {
+                        vec![keys]
+                    } else if !action.contains(char::is_whitespace) && !action.contains('(') {
+                        // Remap: the target is a key name, optionally with modifiers
+                        let key = action.trim_start_matches(['^', '+', '!', '#']);
+                        let mods = &action[..action.len() - key.len()];
+                        vec![if key.chars().count() == 1 { action.to_string() } else { format!("{mods}{{{key}}}") }]
+                    } else {
+                        Vec::new()
+                    };
+                    current = Some((hotkey, sends));
+                    finish(&mut current);
+                }
+            }
+            continue;
+        }
+        let lower = line.to_ascii_lowercase();
+        if lower == "return" || line == "}" {
+            finish(&mut current);
+        } else if let Some((_, sends)) = &mut current {
+            if let Some(keys) = ahk_send_arg(line) { sends.push(keys); }
+        }
+    }
+    finish(&mut current);
+    found
+}
+
+// ";" starts a comment at the line start or after whitespace
+fn strip_ahk_comment(line: &str) -> &str {
+    if line.starts_with(';') { return ""; }
+    match line.find(" ;").or_else(|| line.find("\t;")) {
+        Some(i) => line[..i].trim_end(),
+        None => line,
+    }
+}
+
+// The keys of a Send command: "Send, ^s" / "SendInput ^s" (v1) or
+// `Send "^s"` / `Send("^s")` (v2)
+fn ahk_send_arg(line: &str) -> Option<String> {
+    let lower = line.to_ascii_lowercase();
+    let command = ["sendinput", "sendevent", "sendplay", "send"].into_iter().find(|c| lower.starts_with(c))?;
+    let rest = &line[command.len()..];
+    if rest.starts_with(|c: char| c.is_alphanumeric()) { return None; }
+    let rest = rest.trim_start_matches([',', ' ', '\t', '(']).trim_end_matches(')').trim();
+    Some(rest.trim_matches('"').trim_matches('\'').to_string())
+}
+
+// Send syntax to a note_map sequence: "^+z" -> "Ctrl+Shift+Z"
+fn ahk_send(keys: &str) -> Result<String, String> {
+    let keys = keys.strip_prefix("{Blind}").unwrap_or(keys);
+    let mut chords = Vec::new();
+    let mut mods: Vec<&str> = Vec::new();
+    let mut chars = keys.chars().peekable();
+    while let Some(c) = chars.next() {
+        let key = match c {
+            '^' => { mods.push("Ctrl"); continue; }
+            '+' => { mods.push("Shift"); continue; }
+            '!' => { mods.push("Alt"); continue; }
+            '#' => { mods.push("Win"); continue; }
+            '{' => {
+                let mut name = String::new();
+                // "{}}" and "{{}" are literal braces
+                if let Some(&b) = chars.peek() { name.push(b); chars.next(); }
+                for n in chars.by_ref() {
+                    if n == '}' { break; }
+                    name.push(n);
+                }
+                ahk_key(&name).ok_or_else(|| format!("key {{{name}}} isn't supported by keys actions"))?
+            }
+            // Letters written the way note_map does ("Ctrl+Z")
+            c => c.to_ascii_uppercase().to_string(),
+        };
+        let mut chord: Vec<String> = mods.drain(..).map(str::to_string).collect();
+        chord.push(key);
+        chords.push(chord.join("+"));
+    }
+    match chords.as_slice() {
+        [chord] => Ok(chord.clone()),
+        [] => Err("empty Send".to_string()),
+        _ => Err(format!("it types {} keys; only one key combination can be imported", chords.len())),
+    }
+}
+
+// A braced AutoHotkey key name that send_keys understands
+fn ahk_key(name: &str) -> Option<String> {
+    let name = name.trim();
+    if name.chars().count() == 1 { return Some(name.to_string()); }
+    let key = match name.to_ascii_lowercase().as_str() {
+        "enter" | "return" => "Enter",
+        "space" => "Space",
+        "tab" => "Tab",
+        "esc" | "escape" => "Esc",
+        "up" => "Up",
+        "down" => "Down",
+        "left" => "Left",
+        "right" => "Right",
+        "bs" | "backspace" => "Backspace",
+        "del" | "delete" => "Delete",
+        _ => return None,
+    };
+    Some(key.to_string())
+}
+
+// ---------------------------- Karabiner ----------------------------
+
+// Manipulators from complex_modifications rules (a karabiner.json or a rules
+// file) and simple_modifications
+fn karabiner(text: &str) -> Result<Vec<Imported>> {
+    let json: Value = serde_json::from_str(text)?;
+    let mut found = Vec::new();
+    karabiner_walk(&json, None, &mut found);
+    Ok(found)
+}
+
+fn karabiner_walk(v: &Value, description: Option<&str>, found: &mut Vec<Imported>) {
+    match v {
+        Value::Object(obj) => {
+            let description = obj.get("description").and_then(Value::as_str).or(description);
+            let manipulators = ["manipulators", "simple_modifications"].into_iter().filter_map(|k| obj.get(k)?.as_array());
+            let mut leaf = false;
+            for list in manipulators {
+                leaf = true;
+                for m in list { karabiner_manipulator(m, description, found); }
+            }
+            if !leaf {
+                for child in obj.values() { karabiner_walk(child, description, found); }
+            }
+        }
+        Value::Array(items) => {
+            for child in items { karabiner_walk(child, description, found); }
+        }
+        _ => {}
+    }
+}
+
+fn karabiner_manipulator(m: &Value, description: Option<&str>, found: &mut Vec<Imported>) {
+    let from = m.get("from").map(|f| {
+        let mut keys = karabiner_modifiers(f.get("modifiers").and_then(|m| m.get("mandatory")));
+        keys.push(f.get("key_code").and_then(Value::as_str).unwrap_or("?").to_string());
+        keys.join("+")
+    });
+    let origin = match (description, from) {
+        (Some(d), Some(f)) => format!("{d} ({f})"),
+        (Some(d), None) => d.to_string(),
+        (None, f) => f.unwrap_or_else(|| "?".to_string()),
+    };
+    // "to" is a list of events (older files: a single event)
+    let to: Vec<&Value> = match m.get("to") {
+        Some(Value::Array(events)) => events.iter().collect(),
+        Some(event) => vec![event],
+        None => Vec::new(),
+    };
+    let sequence = match to.as_slice() {
+        [event] => karabiner_event(event),
+        [] => Err("it doesn't send keys".to_string()),
+        _ => Err(format!("it sends {} events; only one key combination can be imported", to.len())),
+    };
+    match sequence {
+        Ok(sequence) => found.push(Imported { origin, sequence }),
+        Err(e) => eprintln!("Skipping {origin}: {e}"),
+    }
+}
+
+fn karabiner_event(event: &Value) -> Result<String, String> {
+    let code = event.get("key_code").and_then(Value::as_str).ok_or("it doesn't send a key")?;
+    let key = karabiner_key(code).ok_or_else(|| format!("key {code} isn't supported by keys actions"))?;
+    let mut keys = karabiner_modifiers(event.get("modifiers"));
+    keys.push(key);
+    Ok(keys.join("+"))
+}
+
+// Modifier names in note_map syntax; Command becomes Ctrl, which plays the
+// same role for shortcuts on Windows and Linux
+fn karabiner_modifiers(mods: Option<&Value>) -> Vec<String> {
+    let names: Vec<&str> = match mods {
+        Some(Value::Array(list)) => list.iter().filter_map(Value::as_str).collect(),
+        Some(Value::String(s)) => vec![s.as_str()],
+        _ => Vec::new(),
+    };
+    let mut out: Vec<String> = Vec::new();
+    for name in names {
+        let m = match name.trim_start_matches("left_").trim_start_matches("right_") {
+            "command" | "control" => "Ctrl",
+            "shift" => "Shift",
+            "option" => "Alt",
+            _ => continue,
+        };
+        if !out.iter().any(|o| o == m) { out.push(m.to_string()); }
+    }
+    out
+}
+
+fn karabiner_key(code: &str) -> Option<String> {
+    if code.chars().count() == 1 { return Some(code.to_ascii_uppercase()); }
+    let key = match code {
+        "return_or_enter" | "keypad_enter" => "Enter",
+        "spacebar" => "Space",
+        "tab" => "Tab",
+        "escape" => "Esc",
+        "up_arrow" => "Up",
+        "down_arrow" => "Down",
+        "left_arrow" => "Left",
+        "right_arrow" => "Right",
+        "delete_or_backspace" => "Backspace",
+        "delete_forward" => "Delete",
+        "hyphen" => "-",
+        "equal_sign" => "=",
+        "open_bracket" => "[",
+        "close_bracket" => "]",
+        "backslash" => "\\",
+        "semicolon" => ";",
+        "quote" => "'",
+        "grave_accent_and_tilde" => "`",
+        "comma" => ",",
+        "period" => ".",
+        "slash" => "/",
+        _ => return None,
+    };
+    Some(key.to_string())
+}
+
+// ---------------------------- Note assignment ----------------------------
+
+/// Ask for the note of each imported combination and add the answers to
+/// [note_map] in `path`. Returns how many mappings were written.
+pub fn assign(found: &[Imported], path: &Path) -> Result<usize> {
+    if found.is_empty() {
+        println!("Nothing to import.");
+        return Ok(0);
+    }
+    let text = std::fs::read_to_string(path).with_context(|| format!("Reading {}", path.display()))?;
+    let mut doc: toml_edit::DocumentMut = text.parse().with_context(|| format!("Parsing {}", path.display()))?;
+    let map = doc
+        .entry("note_map")
+        .or_insert(toml_edit::table())
+        .as_table_like_mut()
+        .ok_or_else(|| anyhow!("note_map in {} isn't a table", path.display()))?;
+
+    println!("Found {} key combination(s). Type the note for each (e.g. A4, E3@5), Enter to skip, q to stop.", found.len());
+    let mut added = 0;
+    for item in found {
+        let key = loop {
+            print!("{} -> {}: ", item.origin, item.sequence);
+            std::io::stdout().flush()?;
+            let mut line = String::new();
+            if std::io::stdin().read_line(&mut line)? == 0 { break None; }
+            let answer = line.trim();
+            if answer.is_empty() || answer.eq_ignore_ascii_case("q") { break Some(answer.to_string()); }
+            match note_key(answer) {
+                Some(key) => break Some(key),
+                None => println!("  Not a note: {answer:?}"),
+            }
+        };
+        match key.as_deref() {
+            None | Some("q") | Some("Q") => break,
+            Some("") => continue,
+            Some(key) => {
+                if map.contains_key(key) { println!("  Replacing the existing {key} mapping"); }
+                let mut entry = toml_edit::InlineTable::new();
+                entry.insert("type", "keys".into());
+                entry.insert("sequence", item.sequence.as_str().into());
+                let mut value = toml_edit::Value::InlineTable(entry);
+                value.decor_mut().set_suffix(format!(" # {}", item.origin.replace(['\n', '\r'], " ")));
+                map.insert(key, toml_edit::Item::Value(value));
+                added += 1;
+            }
+        }
+    }
+    if added > 0 {
+        std::fs::write(path, doc.to_string()).with_context(|| format!("Writing {}", path.display()))?;
+    }
+    Ok(added)
+}
+
+// A typed note_map key with its note spelled the usual way ("a4" -> "A4")
+fn note_key(answer: &str) -> Option<String> {
+    let key = strings::normalize_key(answer);
+    let note = key_note(&key);
+    if note == "tap" || note == "slap" { return Some(key); }
+    let midi = note_to_midi(note)?;
+    Some(format!("{}{}", midi_to_name(midi), &key[note.len()..]))
+}
@@ -0,0 +1,139 @@
+// ---------------------------- Interval keys ----------------------------
+//
+// Two-note keys can be written as a pair, "A3+E4", or as an interval from a
+// note, "P5 above A3" or "m3 below C5". Both are read as the "A3&E4" form
+// polyphony uses, so they fire when the notes sound together. They also fire
+// when the two notes are played one right after the other, either way round,
+// within [intervals] within_ms: a double stop the single-note detector hears
+// as two notes, or one played as a quick broken interval.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::Mapping;
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct IntervalsConfig {
+    // Longest gap between the two notes played one after the other; 0 only
+    // fires two-note keys when the notes sound together
+    #[serde(default = "default_within_ms")]
+    pub within_ms: u64,
+}
+
+fn default_within_ms() -> u64 { 300 }
+
+impl Default for IntervalsConfig {
+    fn default() -> Self {
+        Self { within_ms: default_within_ms() }
+    }
+}
+
+// Interval names and their size in semitones
+const INTERVALS: [(&str, i32); 20] = [
+    ("m2", 1),
+    ("M2", 2),
+    ("m3", 3),
+    ("M3", 4),
+    ("P4", 5),
+    ("TT", 6),
+    ("d5", 6),
+    ("P5", 7),
+    ("m6", 8),
+    ("M6", 9),
+    ("m7", 10),
+    ("M7", 11),
+    ("P8", 12),
+    ("m9", 13),
+    ("M9", 14),
+    ("m10", 15),
+    ("M10", 16),
+    ("P11", 17),
+    ("P12", 19),
+    ("P15", 24),
+];
+
+/// The two MIDI notes of a key such as "A3+E4" or "P5 above A3", lowest
+/// first.
+pub fn parse(key: &str) -> Option<[i32; 2]> {
+    let (a, b) = match key.split_once('+') {
+        Some((a, b)) => (crate::note_to_midi(a.trim())?, crate::note_to_midi(b.trim())?),
+        None => {
+            let [interval, direction, note] = key.split_whitespace().collect::<Vec<_>>().try_into().ok()?;
+            let size = INTERVALS.iter().find(|(name, _)| *name == interval)?.1;
+            let note = crate::note_to_midi(note)?;
+            match direction {
+                "above" => (note, note + size),
+                "below" => (note - size, note),
+                _ => return None,
+            }
+        }
+    };
+    let (lo, hi) = (a.min(b), a.max(b));
+    (lo != hi && lo >= 0 && hi <= 127).then_some([lo, hi])
+}
+
+/// An interval key in the "A3&E4" form.
+pub fn normalize(key: &str) -> Option<String> {
+    parse(key).map(|[lo, hi]| format!("{}&{}", crate::midi_to_name(lo), crate::midi_to_name(hi)))
+}
+
+/// The two-note key for exactly these notes.
+pub fn find(map: &HashMap<String, Mapping>, notes: [i32; 2]) -> Option<(&String, &Mapping)> {
+    map.iter().find(|(k, _)| crate::polyphony::parse(k).is_some_and(|n| n == notes))
+}
+
+/// Notices two different notes recognized one right after the other.
+pub struct IntervalTracker {
+    within: Duration,
+    last: Option<(i32, Instant)>,
+}
+
+impl IntervalTracker {
+    pub fn new(cfg: &IntervalsConfig) -> Self {
+        Self { within: Duration::from_millis(cfg.within_ms), last: None }
+    }
+
+    /// `midi` was recognized; returns it and the note before it, lowest
+    /// first, if that was close enough. A pair is used once.
+    pub fn note(&mut self, midi: i32, now: Instant) -> Option<[i32; 2]> {
+        let pair = self
+            .last
+            .filter(|(last, at)| *last != midi && now.duration_since(*at) <= self.within)
+            .map(|(last, _)| [last.min(midi), last.max(midi)]);
+        self.last = if pair.is_some() { None } else { Some((midi, now)) };
+        pair
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intervals_are_spelled_as_note_pairs() {
+        assert_eq!(normalize("A3+E4").as_deref(), Some("A3&E4"));
+        assert_eq!(normalize("E4 + Bb3").as_deref(), Some("A#3&E4"));
+        assert_eq!(normalize("P5 above A3").as_deref(), Some("A3&E4"));
+        assert_eq!(normalize("m3 below C5").as_deref(), Some("A4&C5"));
+        assert_eq!(normalize("P8 above La2").as_deref(), Some("A2&A3"));
+        assert_eq!(normalize("A3+vibrato"), None);
+        assert_eq!(normalize("A3+A3"), None);
+        assert_eq!(normalize("p5 above A3"), None);
+        assert_eq!(normalize("P5 beside A3"), None);
+    }
+
+    #[test]
+    fn two_notes_in_quick_succession_make_a_pair() {
+        let mut tracker = IntervalTracker::new(&IntervalsConfig::default());
+        let t0 = Instant::now();
+        let ms = |n| t0 + Duration::from_millis(n);
+        assert_eq!(tracker.note(64, t0), None);
+        assert_eq!(tracker.note(57, ms(200)), Some([57, 64]));
+        // Used up: the next note starts over
+        assert_eq!(tracker.note(64, ms(300)), None);
+        assert_eq!(tracker.note(64, ms(400)), None);
+        assert_eq!(tracker.note(57, ms(1000)), None);
+        assert!(IntervalTracker::new(&IntervalsConfig { within_ms: 0 }).note(57, t0).is_none());
+    }
+}
@@ -0,0 +1,381 @@
+// ---------------------------- Keyboard injection ----------------------------
+//
+// Outside Windows, which sends keys through enigo, keys and text go through a
+// backend chosen with `[keyboard] backend`:
+//   uinput   a virtual keyboard made by the kernel (/dev/uinput). It works the
+//            same under Wayland, X11 and on the console, since the compositor
+//            reads it like a real keyboard. Keys are sent as US-layout key
+//            codes; text is limited to what that layout can type.
+//   xdotool  the xdotool program, which types any character, but only into
+//            X11 windows (Wayland compositors ignore it, which is why keys
+//            used to go nowhere on GNOME and KDE).
+//   auto     xdotool in an X11 session that has it installed, uinput otherwise.
+// The keyboard is made at start when anything sends keys, so the compositor
+// knows it before the first note, and stays until the program exits.
+
+#![cfg_attr(windows, allow(dead_code))]
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::sync::{Mutex, RwLock};
+
+use crate::keys::{self, Combination, Key, Modifier};
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    #[default]
+    Auto,
+    Uinput,
+    Xdotool,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Default)]
+pub struct KeyboardConfig {
+    // Ignored on Windows
+    #[serde(default)]
+    pub backend: Backend,
+}
+
+enum Injector {
+    Uinput(Device),
+    Xdotool,
+}
+
+static KEYBOARD: Mutex<Option<Injector>> = Mutex::new(None);
+// The [keyboard] backend, for a keyboard made at the first use
+static BACKEND: RwLock<Backend> = RwLock::new(Backend::Auto);
+
+/// The backend a keyboard made later uses.
+pub fn set_config(cfg: &KeyboardConfig) {
+    *BACKEND.write().unwrap_or_else(|e| e.into_inner()) = cfg.backend;
+}
+
+/// Make the keyboard, unless it is there already.
+pub fn open(cfg: &KeyboardConfig) -> Result<()> {
+    set_config(cfg);
+    let mut keyboard = KEYBOARD.lock().unwrap_or_else(|e| e.into_inner());
+    create(&mut keyboard)
+}
+
+fn create(keyboard: &mut Option<Injector>) -> Result<()> {
+    if keyboard.is_some() { return Ok(()); }
+    let backend = *BACKEND.read().unwrap_or_else(|e| e.into_inner());
+    *keyboard = Some(match choose(backend, x11_session(), on_path("xdotool")) {
+        Backend::Xdotool => {
+            println!("Keyboard: sending keys through xdotool");
+            Injector::Xdotool
+        }
+        _ => {
+            let device = Device::open()?;
+            println!("Keyboard: virtual keyboard ready");
+            Injector::Uinput(device)
+        }
+    });
+    Ok(())
+}
+
+// The backend `auto` stands for in this session
+fn choose(backend: Backend, x11: bool, xdotool: bool) -> Backend {
+    match backend {
+        Backend::Auto if x11 && xdotool => Backend::Xdotool,
+        Backend::Auto => Backend::Uinput,
+        chosen => chosen,
+    }
+}
+
+// Whether windows are X11's. XWayland sets DISPLAY too, so the session type
+// (or a Wayland display) comes first.
+fn x11_session() -> bool {
+    match std::env::var("XDG_SESSION_TYPE").as_deref() {
+        Ok("x11") => true,
+        Ok("wayland") => false,
+        _ => std::env::var_os("WAYLAND_DISPLAY").is_none() && std::env::var_os("DISPLAY").is_some(),
+    }
+}
+
+fn on_path(program: &str) -> bool {
+    std::env::var_os("PATH").is_some_and(|path| std::env::split_paths(&path).any(|dir| dir.join(program).is_file()))
+}
+
+fn with(send: impl FnOnce(&mut Injector) -> Result<()>) -> Result<()> {
+    let mut keyboard = KEYBOARD.lock().unwrap_or_else(|e| e.into_inner());
+    create(&mut keyboard)?;
+    let Some(injector) = keyboard.as_mut() else { return Ok(()) };
+    send(injector)
+}
+
+/// Press and release a key sequence ("Ctrl+S").
+pub fn send(sequence: &str) -> Result<()> {
+    let combination = keys::parse(sequence)?;
+    with(|k| match k {
+        Injector::Uinput(device) => {
+            let codes = codes(&combination)?;
+            let mut events: Vec<(u16, i32)> = codes.iter().map(|c| (*c, 1)).collect();
+            events.extend(codes.iter().rev().map(|c| (*c, 0)));
+            device.send(&events)
+        }
+        Injector::Xdotool => xdotool(&["key", "--clearmodifiers", &keysyms(&combination)]),
+    })
+}
+
+/// Hold a key sequence down, or let go of it: modifiers then the main key go
+/// down, and come up in reverse.
+pub fn press(sequence: &str, down: bool) -> Result<()> {
+    let combination = keys::parse(sequence)?;
+    with(|k| match k {
+        Injector::Uinput(device) => {
+            let codes = codes(&combination)?;
+            let events: Vec<(u16, i32)> = match down {
+                true => codes.iter().map(|c| (*c, 1)).collect(),
+                false => codes.iter().rev().map(|c| (*c, 0)).collect(),
+            };
+            device.send(&events)
+        }
+        Injector::Xdotool => xdotool(&[if down { "keydown" } else { "keyup" }, &keysyms(&combination)]),
+    })
+}
+
+/// Type `text` as it is written.
+pub fn type_text(text: &str) -> Result<()> {
+    with(|k| match k {
+        Injector::Uinput(device) => {
+            let mut events = Vec::new();
+            for c in text.chars() {
+                let (code, shift) = char_code(c).ok_or_else(|| {
+                    anyhow!("cannot type {c:?} with the virtual keyboard, which has a US layout; use [keyboard] backend = \"xdotool\" under X11")
+                })?;
+                if shift { events.push((KEY_LEFTSHIFT, 1)); }
+                events.extend([(code, 1), (code, 0)]);
+                if shift { events.push((KEY_LEFTSHIFT, 0)); }
+            }
+            device.send(&events)
+        }
+        Injector::Xdotool => xdotool(&["type", "--clearmodifiers", "--delay", "0", "--", text]),
+    })
+}
+
+fn xdotool(args: &[&str]) -> Result<()> {
+    let out = std::process::Command::new("xdotool").args(args).output().context("cannot run xdotool")?;
+    if !out.status.success() {
+        return Err(anyhow!("xdotool {} failed: {}", args[0], String::from_utf8_lossy(&out.stderr).trim()));
+    }
+    Ok(())
+}
+
+// ---- Key names and codes ----
+
+// xdotool's name for a combination: "ctrl+shift+s"
+fn keysyms(c: &Combination) -> String {
+    let modifiers = c.modifiers.iter().map(|m| match m {
+        Modifier::Ctrl => "ctrl",
+        Modifier::Shift => "shift",
+        Modifier::Alt => "alt",
+        Modifier::Win => "super",
+    });
+    let key = match c.key {
+        Key::Space => "space".to_string(),
+        Key::Enter => "Return".to_string(),
+        Key::Tab => "Tab".to_string(),
+        Key::Esc => "Escape".to_string(),
+        Key::Up => "Up".to_string(),
+        Key::Down => "Down".to_string(),
+        Key::Left => "Left".to_string(),
+        Key::Right => "Right".to_string(),
+        Key::Backspace => "BackSpace".to_string(),
+        Key::Delete => "Delete".to_string(),
+        Key::Char(ch) if ch.is_ascii_alphanumeric() => ch.to_string(),
+        // X11 names any character by its code point
+        Key::Char(ch) => format!("U{:04X}", ch as u32),
+    };
+    modifiers.map(str::to_string).chain([key]).collect::<Vec<_>>().join("+")
+}
+
+// Linux input event codes (input-event-codes.h)
+const KEY_LEFTCTRL: u16 = 29;
+const KEY_LEFTSHIFT: u16 = 42;
+const KEY_LEFTALT: u16 = 56;
+const KEY_LEFTMETA: u16 = 125;
+// Enough to cover every code below
+const KEY_MAX_USED: u16 = 127;
+
+// The codes a combination holds down, modifiers first. A letter is the key
+// it is on whatever its case, as in a shortcut; a shifted symbol adds Shift.
+fn codes(c: &Combination) -> Result<Vec<u16>> {
+    let (key, shift) = match c.key {
+        Key::Space => (57, false),
+        Key::Enter => (28, false),
+        Key::Tab => (15, false),
+        Key::Esc => (1, false),
+        Key::Up => (103, false),
+        Key::Down => (108, false),
+        Key::Left => (105, false),
+        Key::Right => (106, false),
+        Key::Backspace => (14, false),
+        Key::Delete => (111, false),
+        Key::Char(ch) if ch.is_ascii_alphabetic() => (char_code(ch.to_ascii_lowercase()).map_or(0, |(code, _)| code), false),
+        Key::Char(ch) => char_code(ch).ok_or_else(|| anyhow!("{ch:?} has no key on the virtual keyboard's US layout"))?,
+    };
+    let mut modifiers: Vec<u16> = c
+        .modifiers
+        .iter()
+        .map(|m| match m {
+            Modifier::Ctrl => KEY_LEFTCTRL,
+            Modifier::Shift => KEY_LEFTSHIFT,
+            Modifier::Alt => KEY_LEFTALT,
+            Modifier::Win => KEY_LEFTMETA,
+        })
+        .collect();
+    if shift && !modifiers.contains(&KEY_LEFTSHIFT) { modifiers.push(KEY_LEFTSHIFT); }
+    modifiers.push(key);
+    Ok(modifiers)
+}
+
+// The key that types `c` on a US keyboard, and whether Shift is held for it
+fn char_code(c: char) -> Option<(u16, bool)> {
+    const LETTERS: &str = "qwertyuiop\0\0\0\0asdfghjkl\0\0\0\0\0zxcvbnm";
+    const DIGITS: &str = "1234567890";
+    const SHIFTED_DIGITS: &str = "!@#$%^&*()";
+    // Symbol keys: unshifted, shifted, code
+    const SYMBOLS: [(char, char, u16); 11] = [
+        ('-', '_', 12),
+        ('=', '+', 13),
+        ('[', '{', 26),
+        (']', '}', 27),
+        (';', ':', 39),
+        ('\'', '"', 40),
+        ('`', '~', 41),
+        ('\\', '|', 43),
+        (',', '<', 51),
+        ('.', '>', 52),
+        ('/', '?', 53),
+    ];
+    match c {
+        ' ' => return Some((57, false)),
+        '\n' => return Some((28, false)),
+        '\t' => return Some((15, false)),
+        _ => {}
+    }
+    let lower = c.to_ascii_lowercase();
+    if c.is_ascii_alphabetic() {
+        // Q is 16, A is 30, Z is 44
+        let at = LETTERS.find(lower)?;
+        return Some((16 + at as u16, c.is_ascii_uppercase()));
+    }
+    if let Some(at) = DIGITS.find(c) { return Some((2 + at as u16, false)); }
+    if let Some(at) = SHIFTED_DIGITS.find(c) { return Some((2 + at as u16, true)); }
+    SYMBOLS.iter().find_map(|&(plain, shifted, code)| match c {
+        _ if c == plain => Some((code, false)),
+        _ if c == shifted => Some((code, true)),
+        _ => None,
+    })
+}
+
+// ---- Linux: uinput ----
+
+#[cfg(target_os = "linux")]
+struct Device {
+    file: std::fs::File,
+}
+
+#[cfg(target_os = "linux")]
+impl Device {
+    const EV_SYN: u16 = 0x00;
+    const EV_KEY: u16 = 0x01;
+
+    fn open() -> Result<Self> {
+        use std::os::fd::AsRawFd;
+        let file = std::fs::OpenOptions::new().write(true).open("/dev/uinput").context(
+            "cannot open /dev/uinput for the virtual keyboard: load the uinput module and give your user write access \
+             (e.g. a udev rule KERNEL==\"uinput\", GROUP=\"input\", MODE=\"0660\" and membership of the input group), \
+             or use [keyboard] backend = \"xdotool\" under X11",
+        )?;
+        let fd = file.as_raw_fd();
+        let set = |nr: u64, value: u16| -> Result<()> {
+            // SAFETY: the UI_SET_*BIT ioctls take an int by value
+            if unsafe { libc::ioctl(fd, crate::gamepad::uinput_ioc(1, nr, std::mem::size_of::<libc::c_int>()) as _, value as libc::c_int) } < 0 {
+                return Err(anyhow!("uinput setup failed: {}", std::io::Error::last_os_error()));
+            }
+            Ok(())
+        };
+        // UI_SET_EVBIT, then UI_SET_KEYBIT for every key
+        set(100, Self::EV_KEY)?;
+        for code in 1..=KEY_MAX_USED { set(101, code)?; }
+        // SAFETY: plain data
+        let mut setup: libc::uinput_setup = unsafe { std::mem::zeroed() };
+        setup.id.bustype = 0x06; // BUS_VIRTUAL
+        setup.id.version = 1;
+        for (dst, src) in setup.name.iter_mut().zip("Rusty Strings Control keyboard".bytes()) {
+            *dst = src as libc::c_char;
+        }
+        // UI_DEV_SETUP, then UI_DEV_CREATE
+        // SAFETY: the kernel reads the struct we pass; UI_DEV_CREATE takes no argument
+        let ok = unsafe {
+            libc::ioctl(fd, crate::gamepad::uinput_ioc(1, 3, std::mem::size_of::<libc::uinput_setup>()) as _, &setup) >= 0
+                && libc::ioctl(fd, crate::gamepad::uinput_ioc(0, 1, 0) as _) >= 0
+        };
+        if !ok { return Err(anyhow!("cannot create the virtual keyboard: {}", std::io::Error::last_os_error())); }
+        Ok(Self { file })
+    }
+
+    // Key changes (code, 1 down / 0 up), each reported on its own so
+    // programs see every press in order
+    fn send(&mut self, keys: &[(u16, i32)]) -> Result<()> {
+        use std::io::Write;
+        let mut bytes = Vec::new();
+        for &(code, value) in keys {
+            for (type_, code, value) in [(Self::EV_KEY, code, value), (Self::EV_SYN, 0, 0)] {
+                // SAFETY: input_event is plain data
+                let mut event: libc::input_event = unsafe { std::mem::zeroed() };
+                event.type_ = type_;
+                event.code = code;
+                event.value = value;
+                // SAFETY: viewing plain data as bytes
+                bytes.extend_from_slice(unsafe {
+                    std::slice::from_raw_parts(&event as *const _ as *const u8, std::mem::size_of::<libc::input_event>())
+                });
+            }
+        }
+        self.file.write_all(&bytes).map_err(|e| anyhow!("virtual keyboard: {e}"))
+    }
+}
+
+// ---- Elsewhere ----
+
+#[cfg(not(target_os = "linux"))]
+struct Device;
+
+#[cfg(not(target_os = "linux"))]
+impl Device {
+    fn open() -> Result<Self> { Err(anyhow!("the virtual keyboard is only available on Linux; use [keyboard] backend = \"xdotool\" under X11")) }
+    fn send(&mut self, _keys: &[(u16, i32)]) -> Result<()> { Ok(()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn characters_map_to_us_layout_keys() {
+        assert_eq!(char_code('q'), Some((16, false)));
+        assert_eq!(char_code('A'), Some((30, true)));
+        assert_eq!(char_code('m'), Some((50, false)));
+        assert_eq!(char_code('0'), Some((11, false)));
+        assert_eq!(char_code('@'), Some((3, true)));
+        assert_eq!(char_code('?'), Some((53, true)));
+        assert_eq!(char_code('é'), None);
+        // Shortcut letters ignore case; shifted symbols bring Shift
+        assert_eq!(codes(&keys::parse("Ctrl+S").unwrap()).unwrap(), [KEY_LEFTCTRL, 31]);
+        assert_eq!(codes(&keys::parse("Ctrl+?").unwrap()).unwrap(), [KEY_LEFTCTRL, KEY_LEFTSHIFT, 53]);
+        assert_eq!(keysyms(&keys::parse("Ctrl+Shift+Enter").unwrap()), "ctrl+shift+Return");
+        assert_eq!(keysyms(&keys::parse("Alt+/").unwrap()), "alt+U002F");
+    }
+
+    #[test]
+    fn auto_prefers_xdotool_only_on_x11() {
+        assert_eq!(choose(Backend::Auto, true, true), Backend::Xdotool);
+        assert_eq!(choose(Backend::Auto, true, false), Backend::Uinput);
+        assert_eq!(choose(Backend::Auto, false, true), Backend::Uinput);
+        assert_eq!(choose(Backend::Xdotool, false, false), Backend::Xdotool);
+    }
+}
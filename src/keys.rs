@@ -0,0 +1,92 @@
+// ---------------------------- Key sequences ----------------------------
+//
+// "Ctrl+Shift+S", "Enter", "Alt+Left", "A": any modifiers, then one main
+// key, joined with '+'. Parsing doesn't depend on the platform, so `check`
+// and `learn` can reject a typo anywhere; enigo (Windows) or the keyboard
+// module (elsewhere) turns the result into key presses.
+
+use anyhow::{anyhow, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Modifier {
+    Ctrl,
+    Shift,
+    Alt,
+    Win,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Space,
+    Enter,
+    Tab,
+    Esc,
+    Up,
+    Down,
+    Left,
+    Right,
+    Backspace,
+    Delete,
+    // A single character, typed with the current keyboard layout
+    Char(char),
+}
+
+/// Modifiers to hold, in order, and the key pressed with them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Combination {
+    pub modifiers: Vec<Modifier>,
+    pub key: Key,
+}
+
+const KNOWN: &str = "modifiers Ctrl, Shift, Alt, Win; keys Space, Enter, Tab, Esc, Up, Down, Left, Right, Backspace, Delete or one character";
+
+pub fn parse(sequence: &str) -> Result<Combination> {
+    let tokens: Vec<&str> = sequence.split('+').map(str::trim).filter(|s| !s.is_empty()).collect();
+    if tokens.is_empty() { return Err(anyhow!("Empty key sequence")); }
+
+    let mut modifiers = Vec::new();
+    let mut key = None;
+    for t in &tokens {
+        let named = match t.to_ascii_lowercase().as_str() {
+            "ctrl" | "control" => {
+                modifiers.push(Modifier::Ctrl);
+                continue;
+            }
+            "shift" => {
+                modifiers.push(Modifier::Shift);
+                continue;
+            }
+            "alt" => {
+                modifiers.push(Modifier::Alt);
+                continue;
+            }
+            "win" | "meta" => {
+                modifiers.push(Modifier::Win);
+                continue;
+            }
+            "space" => Key::Space,
+            "enter" | "return" => Key::Enter,
+            "tab" => Key::Tab,
+            "esc" | "escape" => Key::Esc,
+            "up" | "uparrow" => Key::Up,
+            "down" | "downarrow" => Key::Down,
+            "left" | "leftarrow" => Key::Left,
+            "right" | "rightarrow" => Key::Right,
+            "backspace" | "bksp" => Key::Backspace,
+            "delete" | "del" => Key::Delete,
+            _ => {
+                let mut chars = t.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Key::Char(c),
+                    _ => return Err(anyhow!("Unknown key {t:?} in {sequence:?} (expected {KNOWN})")),
+                }
+            }
+        };
+        if key.is_some() {
+            return Err(anyhow!("{sequence:?} has more than one main key; only modifiers can be combined"));
+        }
+        key = Some(named);
+    }
+    let key = key.ok_or_else(|| anyhow!("{sequence:?} has no main key, only modifiers"))?;
+    Ok(Combination { modifiers, key })
+}
@@ -0,0 +1,179 @@
+// ---------------------------- Latency measurement ----------------------------
+//
+// With --measure-latency every trigger is timed on its way through the
+// program, and the median and 95th percentile of each stage are printed
+// every REPORT_EVERY (when something triggered) and at the end:
+//
+//   buffer    the window's newest sample arriving from the device until its
+//             analysis began: time spent waiting in the ring
+//   analysis  detection, voting and smoothing of that window
+//   confirm   the first frame that heard the note until the trigger was
+//             decided: note_hold_frames, voting, chords, sequences
+//   action    running the action: key injection, MIDI, a request...
+//   total     the note's first frame arriving until its action was done
+//
+// None of these cover the device's own buffering, or the window filling
+// with the new note (up to window_size samples), which depend only on the
+// settings; the report prints the window and hop for comparison.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// How often a report is printed while triggers happen
+const REPORT_EVERY: Duration = Duration::from_secs(10);
+// Triggers kept per stage; the oldest go first
+const KEPT: usize = 1000;
+
+const STAGES: [&str; 5] = ["buffer", "analysis", "confirm", "action", "total"];
+
+static ON: AtomicBool = AtomicBool::new(false);
+static TIMES: Mutex<Option<Times>> = Mutex::new(None);
+
+// When each step of a frame happened
+#[derive(Clone, Copy)]
+struct Frame {
+    // The newest sample of the window arrived
+    arrived: Instant,
+    // Its analysis began and ended
+    began: Instant,
+    ended: Instant,
+}
+
+struct Times {
+    // The analysis settings, for the report
+    window_ms: f32,
+    hop_ms: f32,
+    latest: Option<Frame>,
+    // The note sounding, its first frame, and whether it has triggered
+    note: Option<(i32, Frame, bool)>,
+    stages: [Vec<Duration>; 5],
+    reported: Instant,
+    new: usize,
+}
+
+/// Time triggers from here on.
+pub fn start() {
+    ON.store(true, Ordering::Relaxed);
+}
+
+/// Whether triggers are being timed.
+pub fn measuring() -> bool {
+    ON.load(Ordering::Relaxed)
+}
+
+/// The window and hop the analysis uses, in ms.
+pub fn analysis(window_ms: f32, hop_ms: f32) {
+    if !measuring() { return; }
+    let mut times = TIMES.lock().unwrap_or_else(|e| e.into_inner());
+    let t = times.get_or_insert_with(|| Times {
+        window_ms,
+        hop_ms,
+        latest: None,
+        note: None,
+        stages: Default::default(),
+        reported: Instant::now(),
+        new: 0,
+    });
+    (t.window_ms, t.hop_ms) = (window_ms, hop_ms);
+}
+
+/// A frame was analysed: its newest sample arrived at `arrived`, analysis
+/// began at `began` and has just ended, hearing `note` (a MIDI number).
+pub fn frame(arrived: Instant, began: Instant, note: Option<i32>) {
+    if !measuring() { return; }
+    let mut times = TIMES.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(t) = times.as_mut() else { return };
+    let frame = Frame { arrived: arrived.min(began), began, ended: Instant::now() };
+    t.latest = Some(frame);
+    match note {
+        Some(n) if t.note.is_some_and(|(m, ..)| m == n) => {}
+        Some(n) => t.note = Some((n, frame, false)),
+        None => t.note = None,
+    }
+}
+
+/// A trigger decided at `decided` has just finished its action. Only a
+/// note's first trigger counts; repeats of a held note say nothing about
+/// how quickly it was recognized.
+pub fn fired(decided: Instant) {
+    if !measuring() { return; }
+    let done = Instant::now();
+    let mut times = TIMES.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(t) = times.as_mut() else { return };
+    let Some(latest) = t.latest else { return };
+    let first = match t.note.as_mut() {
+        Some((_, _, true)) => return,
+        Some((_, first, fired)) => {
+            *fired = true;
+            *first
+        }
+        None => latest,
+    };
+    let took = [
+        latest.began - latest.arrived,
+        latest.ended - latest.began,
+        decided.saturating_duration_since(first.ended),
+        done - decided,
+        done - first.arrived,
+    ];
+    for (stage, d) in t.stages.iter_mut().zip(took) {
+        if stage.len() == KEPT { stage.remove(0); }
+        stage.push(d);
+    }
+    t.new += 1;
+    if t.reported.elapsed() >= REPORT_EVERY { print_report(t); }
+}
+
+/// Print what was measured since the last report, if anything.
+pub fn report() {
+    let mut times = TIMES.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(t) = times.as_mut().filter(|t| t.new > 0) { print_report(t); }
+}
+
+fn print_report(t: &mut Times) {
+    println!("\n{}", summary(t));
+    t.reported = Instant::now();
+    t.new = 0;
+}
+
+fn summary(t: &Times) -> String {
+    let stages: Vec<String> = STAGES
+        .iter()
+        .zip(&t.stages)
+        .map(|(name, d)| format!("{name} {:.1}/{:.1}", ms(percentile(d, 0.5)), ms(percentile(d, 0.95))))
+        .collect();
+    format!(
+        "Latency over {} trigger(s), ms p50/p95: {}; plus the device's buffer and up to a {:.0} ms window (hop {:.1} ms)",
+        t.stages[0].len(),
+        stages.join(", "),
+        t.window_ms,
+        t.hop_ms
+    )
+}
+
+fn ms(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+// The value `p` (0..1) of the way up the sorted durations
+fn percentile(durations: &[Duration], p: f64) -> Duration {
+    if durations.is_empty() { return Duration::ZERO; }
+    let mut sorted = durations.to_vec();
+    sorted.sort_unstable();
+    sorted[((sorted.len() - 1) as f64 * p).round() as usize]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_pick_from_the_sorted_times() {
+        let d: Vec<Duration> = [9, 1, 5, 3, 7, 2, 8, 4, 6, 10].into_iter().map(Duration::from_millis).collect();
+        assert_eq!(percentile(&d, 0.5), Duration::from_millis(6));
+        assert_eq!(percentile(&d, 0.95), Duration::from_millis(10));
+        assert_eq!(percentile(&d[..1], 0.95), Duration::from_millis(9));
+        assert_eq!(percentile(&[], 0.5), Duration::ZERO);
+    }
+}
@@ -0,0 +1,199 @@
+// ---------------------------- Learn mode ----------------------------
+//
+// `learn` builds the note_map by playing: the next note held steady is
+// taken, then its key combination is typed ("Ctrl+S") or pressed, and the
+// mapping is written to the config file straight away. toml_edit keeps the
+// file's comments and layout.
+
+use anyhow::{anyhow, Context, Result};
+use std::path::Path;
+
+pub struct NoteCatcher {
+    // Frames a note must last
+    hold: usize,
+    current: Option<i32>,
+    // Cents of its frames so far
+    cents: Vec<f32>,
+}
+
+impl NoteCatcher {
+    pub fn new(hold: usize) -> Self {
+        Self { hold: hold.max(1), current: None, cents: Vec::new() }
+    }
+
+    /// Feed one frame (MIDI note and cents, None when silent). Returns the
+    /// note and its median cents once it has lasted `hold` frames.
+    pub fn push(&mut self, reading: Option<(i32, f32)>) -> Option<(i32, f32)> {
+        let Some((note, cents)) = reading else {
+            self.current = None;
+            self.cents.clear();
+            return None;
+        };
+        if self.current != Some(note) {
+            self.current = Some(note);
+            self.cents.clear();
+        }
+        self.cents.push(cents);
+        if self.cents.len() < self.hold { return None; }
+        self.cents.sort_by(f32::total_cmp);
+        Some((note, self.cents[self.cents.len() / 2]))
+    }
+}
+
+/// Map `key` to the key sequence in the note_map of the config at `path`;
+/// true if it replaced a mapping.
+pub fn save(path: &Path, key: &str, sequence: &str) -> Result<bool> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("Reading {}", path.display()))?;
+    let mut doc: toml_edit::DocumentMut = text.parse().with_context(|| format!("Parsing {}", path.display()))?;
+    let map = doc
+        .entry("note_map")
+        .or_insert(toml_edit::table())
+        .as_table_like_mut()
+        .ok_or_else(|| anyhow!("note_map in {} isn't a table", path.display()))?;
+    let mut entry = toml_edit::InlineTable::new();
+    entry.insert("type", "keys".into());
+    entry.insert("sequence", sequence.into());
+    let entry = toml_edit::Item::Value(toml_edit::Value::InlineTable(entry));
+    // Replacing in place keeps the comment above the old mapping
+    let replaced = match map.get_mut(key) {
+        Some(item) => {
+            *item = entry;
+            true
+        }
+        None => {
+            map.insert(key, entry);
+            false
+        }
+    };
+    std::fs::write(path, doc.to_string()).with_context(|| format!("Writing {}", path.display()))?;
+    Ok(replaced)
+}
+
+// Build "Ctrl+Alt+Shift+K" from the modifiers held and the key
+fn combination(ctrl: bool, alt: bool, shift: bool, key: &str) -> String {
+    let mods = [(ctrl, "Ctrl"), (alt, "Alt"), (shift, "Shift")];
+    let mut parts: Vec<&str> = mods.iter().filter(|(held, _)| *held).map(|(_, name)| *name).collect();
+    parts.push(key);
+    parts.join("+")
+}
+
+/// Wait for one key press on the terminal and return it as a key sequence,
+/// or None for a key that can't be written as one.
+#[cfg(unix)]
+pub fn record_keypress() -> Result<Option<String>> {
+    // SAFETY: termios is plain data that tcgetattr fills in
+    let mut saved: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(0, &mut saved) } != 0 {
+        return Err(anyhow!("Input isn't a terminal; type the keys instead"));
+    }
+    // Raw input: every byte as it comes, with Ctrl+C, Ctrl+S etc. as keys
+    let mut raw = saved;
+    raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+    raw.c_iflag &= !(libc::IXON | libc::ICRNL);
+    raw.c_cc[libc::VMIN] = 1;
+    raw.c_cc[libc::VTIME] = 0;
+    let mut buf = [0u8; 16];
+    // SAFETY: both termios structs are valid and buf outlives the read. An
+    // escape sequence (arrow keys, Alt+key) arrives in a single read.
+    let n = unsafe {
+        libc::tcsetattr(0, libc::TCSANOW, &raw);
+        let n = libc::read(0, buf.as_mut_ptr().cast(), buf.len());
+        libc::tcsetattr(0, libc::TCSANOW, &saved);
+        n
+    };
+    if n <= 0 { return Err(anyhow!("Couldn't read the key press")); }
+    Ok(decode_terminal(&buf[..n as usize]))
+}
+
+// The bytes a terminal sends for one key press
+#[cfg(unix)]
+fn decode_terminal(bytes: &[u8]) -> Option<String> {
+    let arrow = |c: u8| match c {
+        b'A' => Some("Up"),
+        b'B' => Some("Down"),
+        b'C' => Some("Right"),
+        b'D' => Some("Left"),
+        _ => None,
+    };
+    match bytes {
+        [0x1b] => Some("Esc".to_string()),
+        [0x1b, b'[' | b'O', c] => arrow(*c).map(str::to_string),
+        // xterm modifier codes: 1 + (Shift 1, Alt 2, Ctrl 4)
+        [0x1b, b'[', b'1', b';', m @ b'2'..=b'8', c] => {
+            let m = m - b'1';
+            arrow(*c).map(|key| combination(m & 4 != 0, m & 2 != 0, m & 1 != 0, key))
+        }
+        // Alt sends Esc before the key
+        [0x1b, rest @ ..] => decode_terminal(rest).map(|key| match key.split_once('+') {
+            Some(("Ctrl", key)) => format!("Ctrl+Alt+{key}"),
+            _ => format!("Alt+{key}"),
+        }),
+        [b'\r' | b'\n'] => Some("Enter".to_string()),
+        [b'\t'] => Some("Tab".to_string()),
+        [b' '] => Some("Space".to_string()),
+        [c @ 1..=26] => Some(combination(true, false, false, &((b'A' + c - 1) as char).to_string())),
+        [c] if c.is_ascii_uppercase() => Some(combination(false, false, true, &(*c as char).to_string())),
+        [c] if c.is_ascii_graphic() => Some((c.to_ascii_uppercase() as char).to_string()),
+        _ => {
+            let mut chars = std::str::from_utf8(bytes).ok()?.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if !c.is_control() => Some(c.to_string()),
+                _ => None,
+            }
+        }
+    }
+}
+
+/// Wait for one key press on the console and return it as a key sequence,
+/// or None for a key that can't be written as one.
+#[cfg(windows)]
+pub fn record_keypress() -> Result<Option<String>> {
+    use windows_sys::Win32::System::Console::{
+        FlushConsoleInputBuffer, GetStdHandle, ReadConsoleInputW, INPUT_RECORD, STD_INPUT_HANDLE,
+    };
+    const KEY_EVENT: u32 = 0x1;
+    const RIGHT_ALT: u32 = 0x1;
+    const LEFT_ALT: u32 = 0x2;
+    const RIGHT_CTRL: u32 = 0x4;
+    const LEFT_CTRL: u32 = 0x8;
+    const SHIFT: u32 = 0x10;
+    // SAFETY: the handle comes straight from GetStdHandle and the record is
+    // plain data that ReadConsoleInputW fills in; KeyEvent is the variant
+    // EventType says it is
+    unsafe {
+        let input = GetStdHandle(STD_INPUT_HANDLE);
+        // Drop the Enter that led here
+        FlushConsoleInputBuffer(input);
+        loop {
+            let mut record: INPUT_RECORD = std::mem::zeroed();
+            let mut read = 0;
+            if ReadConsoleInputW(input, &mut record, 1, &mut read) == 0 {
+                return Err(anyhow!("Couldn't read the key press; type the keys instead"));
+            }
+            if read == 0 || record.EventType as u32 != KEY_EVENT { continue; }
+            let event = record.Event.KeyEvent;
+            if event.bKeyDown == 0 { continue; }
+            let key = match event.wVirtualKeyCode as u32 {
+                // Shift, Ctrl, Alt and Win on their own
+                0x10 | 0x11 | 0x12 | 0x5B | 0x5C => continue,
+                0x0D => "Enter".to_string(),
+                0x09 => "Tab".to_string(),
+                0x1B => "Esc".to_string(),
+                0x20 => "Space".to_string(),
+                0x25 => "Left".to_string(),
+                0x26 => "Up".to_string(),
+                0x27 => "Right".to_string(),
+                0x28 => "Down".to_string(),
+                vk @ (0x30..=0x39 | 0x41..=0x5A) => (vk as u8 as char).to_string(),
+                _ => match char::from_u32(event.uChar.UnicodeChar as u32).filter(|c| !c.is_control()) {
+                    Some(c) => c.to_string(),
+                    None => return Ok(None),
+                },
+            };
+            let state = event.dwControlKeyState as u32;
+            let ctrl = state & (LEFT_CTRL | RIGHT_CTRL) != 0;
+            let alt = state & (LEFT_ALT | RIGHT_ALT) != 0;
+            return Ok(Some(combination(ctrl, alt, state & SHIFT != 0, &key)));
+        }
+    }
+}
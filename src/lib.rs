@@ -0,0 +1,3895 @@
+use anyhow::{anyhow, Context, Result};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use crossbeam_channel::Receiver;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+mod articulation;
+mod calibrate;
+mod cheatsheet;
+mod chords;
+mod clock;
+mod control;
+mod dbus;
+mod dynamics;
+mod ear;
+mod feedback;
+mod focus;
+mod gamepad;
+mod glissando;
+mod hexaphonic;
+mod home_assistant;
+mod hotkeys;
+mod http_api;
+mod import;
+mod intervals;
+mod keyboard;
+mod keys;
+mod latency;
+mod learn;
+mod loopback;
+mod logging;
+mod metronome;
+mod midi;
+mod morse;
+mod mqtt;
+mod mouse;
+mod net_audio;
+mod notation;
+mod obs;
+mod note_keys;
+mod onset;
+mod openrgb;
+mod osc;
+mod pcm;
+mod percussion;
+mod pipeline;
+pub mod pitch;
+mod plugins;
+mod polyphony;
+mod practice;
+mod presets;
+mod profiles;
+mod record;
+mod reference;
+mod reload;
+mod replay;
+mod resample;
+mod rhythms;
+mod ring;
+mod rumble;
+mod scanning;
+mod script;
+mod sequences;
+mod smoothing;
+mod snr;
+mod speech;
+mod speech_gate;
+mod status;
+mod strikes;
+mod strings;
+mod tempo;
+mod tone;
+mod trainer;
+mod tray;
+mod tuner;
+mod validate;
+mod vibrato;
+mod voting;
+mod websocket;
+mod wled;
+
+pub use pipeline::{NoteEvent, Pipeline, SampleSink};
+pub use pitch::PitchDetector;
+
+// Keystroke injection (Windows only)
+#[cfg(windows)]
+use enigo::{Enigo, Key, KeyboardControllable};
+
+// ---------------------------- Config types ----------------------------
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Action {
+    // Send a key sequence like "Ctrl+S" or "Space" or "A"
+    Keys { sequence: String },
+    // Type a string as it is written, e.g. a snippet or chat message
+    Text { text: String },
+    // Attack this note repeatedly to set the metronome tempo
+    #[serde(rename = "tap-tempo")]
+    TapTempo,
+    // Send the inverse of the most recent trigger (see Mapping::undo)
+    Undo,
+    // Switch to a profile: its name, "next" (the default) or "default"
+    Profile {
+        #[serde(default = "default_profile_switch")]
+        name: String,
+    },
+    // Launch a program, e.g. { type = "command", program = "playerctl", args = ["play-pause"] }
+    Command {
+        program: String,
+        #[serde(default)]
+        args: Vec<String>,
+        // Wait for it to exit (detection pauses meanwhile) instead of detaching
+        #[serde(default)]
+        wait: bool,
+        // Working directory (default: the current one)
+        #[serde(default)]
+        cwd: Option<String>,
+        // Extra environment variables
+        #[serde(default)]
+        env: BTreeMap<String, String>,
+    },
+    // Send a MIDI note or CC on the [midi] output
+    Midi(midi::MidiAction),
+    // Move the pointer, click and/or scroll
+    Mouse(mouse::MouseAction),
+    // Send an OSC message over UDP
+    Osc(osc::OscAction),
+    // Press a button or set an axis on the virtual game controller
+    Gamepad(gamepad::GamepadAction),
+    // Switch scenes, show sources, record or stream in OBS Studio
+    Obs(obs::ObsAction),
+    // Call a Home Assistant service, e.g. toggle a light
+    #[serde(rename = "home-assistant")]
+    HomeAssistant(home_assistant::HomeAssistantAction),
+    // Run a Rhai script that decides what to do from the note it heard
+    Script(script::ScriptAction),
+    // Run an action plugin from [plugins] dir
+    Plugin(plugins::PluginAction),
+    // Several actions in order; a note_map entry written as an array is one
+    Macro { steps: Vec<MacroStep> },
+}
+
+fn default_profile_switch() -> String { "next".to_string() }
+
+// One step of a macro: wait delay_ms, then run the action
+#[derive(Debug, Deserialize, Clone)]
+pub struct MacroStep {
+    #[serde(default)]
+    delay_ms: u64,
+    #[serde(flatten)]
+    action: Action,
+}
+
+// A note_map entry: the action plus per-mapping options
+#[derive(Debug, Deserialize, Clone)]
+#[serde(remote = "Self")]
+struct Mapping {
+    #[serde(flatten)]
+    action: Action,
+    // "hold": keep a keys mapping pressed for as long as the note sounds
+    #[serde(default)]
+    mode: MappingMode,
+    // Run when the note stops (falls silent or another note takes over); an
+    // action table or an array of macro steps
+    #[serde(default, deserialize_with = "optional_action")]
+    on_release: Option<Action>,
+    // Defer firing until the next metronome "beat" or "bar"
+    #[serde(default)]
+    quantize: metronome::Quantize,
+    // Per-note replacements for the global settings (the first three are
+    // written by `calibrate`)
+    #[serde(default)]
+    tolerance_cents: Option<f32>,
+    #[serde(default)]
+    note_hold_frames: Option<usize>,
+    #[serde(default)]
+    corr_threshold: Option<f32>,
+    #[serde(default)]
+    retrigger_ms: Option<u64>,
+    // How an "undo" mapping reverts this one: a key sequence such as "Ctrl+Z",
+    // or "repeat" to send the action again (toggles)
+    #[serde(default)]
+    undo: Option<String>,
+    // Fire again every repeat_ms (100) for as long as the note sounds, like
+    // a key held down on a keyboard
+    #[serde(default)]
+    repeat_while_held: bool,
+    #[serde(default)]
+    repeat_ms: Option<u64>,
+}
+
+// A table is one action with its options; an array is a macro, e.g.
+// A3 = [{ type = "keys", sequence = "Ctrl+S" }, { type = "keys", sequence = "Enter", delay_ms = 200 }]
+// The action may also be given as on_attack = { ... } to pair it with on_release.
+impl<'de> Deserialize<'de> for Mapping {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> std::result::Result<Self, D::Error> {
+        use serde::de::Error;
+        match toml::Value::deserialize(d)? {
+            toml::Value::Array(steps) => action_value(toml::Value::Array(steps)).map(Mapping::from).map_err(D::Error::custom),
+            toml::Value::Table(mut table) => {
+                if let Some(attack) = table.remove("on_attack") {
+                    if table.contains_key("type") { return Err(D::Error::custom("set either type or on_attack, not both")); }
+                    match attack {
+                        toml::Value::Table(fields) => table.extend(fields),
+                        steps => {
+                            table.insert("type".to_string(), "macro".into());
+                            table.insert("steps".to_string(), steps);
+                        }
+                    }
+                }
+                Mapping::deserialize(toml::Value::Table(table)).map_err(D::Error::custom)
+            }
+            other => Err(D::Error::custom(format!("expected an action table or an array of steps, found {}", other.type_str()))),
+        }
+    }
+}
+
+// An action table, or an array of steps for a macro
+fn action_value(value: toml::Value) -> std::result::Result<Action, toml::de::Error> {
+    match value {
+        toml::Value::Array(steps) => Ok(Action::Macro { steps: Vec::<MacroStep>::deserialize(toml::Value::Array(steps))? }),
+        table => Action::deserialize(table),
+    }
+}
+
+// input_device = "Scarlett 2i2" or input_device = 2
+fn device_name_or_index<'de, D: serde::Deserializer<'de>>(d: D) -> std::result::Result<Option<String>, D::Error> {
+    match toml::Value::deserialize(d)? {
+        toml::Value::String(s) => Ok(Some(s)),
+        toml::Value::Integer(i) if i >= 0 => Ok(Some(i.to_string())),
+        other => Err(serde::de::Error::custom(format!("input_device must be a device name or number, not {other}"))),
+    }
+}
+
+// input_channel = 2, input_channel = [1, 3] or input_channel = 0 (all)
+fn channel_or_list<'de, D: serde::Deserializer<'de>>(d: D) -> std::result::Result<Vec<usize>, D::Error> {
+    let channel = |v: &toml::Value| match v {
+        toml::Value::Integer(i) if *i > 0 => Ok(*i as usize),
+        other => Err(serde::de::Error::custom(format!("input_channel must be channel numbers from 1, not {other}"))),
+    };
+    match toml::Value::deserialize(d)? {
+        toml::Value::Integer(0) => Ok(Vec::new()),
+        toml::Value::Array(list) => list.iter().map(channel).collect(),
+        other => channel(&other).map(|c| vec![c]),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BufferSize {
+    Frames(u32),
+    Smallest,
+}
+
+// buffer_size = 128 or buffer_size = "smallest"
+fn frames_or_smallest<'de, D: serde::Deserializer<'de>>(d: D) -> std::result::Result<Option<BufferSize>, D::Error> {
+    match toml::Value::deserialize(d)? {
+        toml::Value::Integer(i) if i > 0 && i <= u32::MAX as i64 => Ok(Some(BufferSize::Frames(i as u32))),
+        toml::Value::String(s) if s.eq_ignore_ascii_case("smallest") => Ok(Some(BufferSize::Smallest)),
+        other => Err(serde::de::Error::custom(format!("buffer_size must be a number of frames or \"smallest\", not {other}"))),
+    }
+}
+
+// For optional action fields such as on_release
+fn optional_action<'de, D: serde::Deserializer<'de>>(d: D) -> std::result::Result<Option<Action>, D::Error> {
+    action_value(toml::Value::deserialize(d)?).map(Some).map_err(serde::de::Error::custom)
+}
+
+impl From<Action> for Mapping {
+    fn from(action: Action) -> Self {
+        Self {
+            action,
+            mode: MappingMode::Trigger,
+            on_release: None,
+            quantize: metronome::Quantize::Off,
+            tolerance_cents: None,
+            note_hold_frames: None,
+            corr_threshold: None,
+            retrigger_ms: None,
+            undo: None,
+            repeat_while_held: false,
+            repeat_ms: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum MappingMode {
+    // Run the action once when the note is recognized
+    #[default]
+    Trigger,
+    // Press the keys down when the note is recognized, release them when it stops
+    Hold,
+}
+
+impl Mapping {
+    fn holds(&self) -> bool {
+        self.mode == MappingMode::Hold
+            && match &self.action {
+                Action::Keys { .. } => true,
+                Action::Gamepad(g) => g.button.is_some(),
+                _ => false,
+            }
+    }
+
+    // Hold mappings keep their keys down instead of repeating them
+    fn repeats(&self) -> bool { self.repeat_while_held && !self.holds() }
+
+    fn repeat_interval(&self) -> Duration { Duration::from_millis(self.repeat_ms.unwrap_or(100).max(10)) }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Mode {
+    // Fire note_map actions when mapped notes are held in tune
+    #[default]
+    Trigger,
+    // Decode short/long notes as Morse code and type the characters
+    Morse,
+    // No actions; collect per-note intonation statistics
+    Practice,
+    // Play reference tones and score the notes/intervals played back
+    #[serde(rename = "ear-training")]
+    EarTraining,
+    // Prompt random note_map entries and time the responses
+    Trainer,
+    // Step through a menu of actions; any confident note selects
+    Scanning,
+    // Record per-string timbre so "E3@5" style mappings can tell strings apart
+    #[serde(rename = "string-calibration")]
+    StringCalibration,
+    // Play the instrument as a MIDI controller: every note to the [midi] output
+    Midi,
+}
+
+#[derive(Debug, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Detector {
+    // Normalized autocorrelation of the Hann-windowed signal
+    #[default]
+    Autocorr,
+    // YIN difference function; steadier on plucked attacks
+    Yin,
+    // McLeod Pitch Method; fewer octave errors on plucked strings
+    Mpm,
+    // A detector plugin from [plugins] dir: { plugin = "name" }
+    Plugin(String),
+}
+
+impl Detector {
+    fn build(&self, range: pitch::SearchRange, plugins: &plugins::PluginsConfig) -> Box<dyn pitch::PitchDetector + Send> {
+        match self {
+            Detector::Autocorr => Box::new(pitch::Autocorr::new(range)),
+            Detector::Yin => Box::new(pitch::Yin(range)),
+            Detector::Mpm => Box::new(pitch::Mpm(range)),
+            Detector::Plugin(name) => match plugins::detector(plugins, name, range) {
+                Ok(d) => Box::new(d),
+                Err(e) => {
+                    tracing::error!("{e:#}; detecting with autocorr instead");
+                    Box::new(pitch::Autocorr::new(range))
+                }
+            },
+        }
+    }
+}
+
+/// Everything config.toml sets. Read one with `Config::load`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Config {
+    // Built-in preset that fills in unset detection settings, e.g. "whistle"
+    #[serde(default)]
+    preset: Option<String>,
+    // Built-in instrument, e.g. "guitar-standard": fills in its pitch range,
+    // window sizes and open strings ([strings] tuning). Nothing is heard below
+    // its lowest open string
+    #[serde(default)]
+    instrument: Option<String>,
+    // Operating mode: "trigger" (default), "morse", "practice", "ear-training", "trainer", "scanning", "string-calibration" or "midi"
+    #[serde(default)]
+    mode: Mode,
+    // Audio system to capture through: "alsa", "jack", "wasapi",
+    // "coreaudio"... (the platform's default if unset)
+    #[serde(default)]
+    host: Option<String>,
+    // Capture from this input device: its number in list-devices, its name,
+    // or part of its name (default device if unset)
+    #[serde(default, deserialize_with = "device_name_or_index")]
+    input_device: Option<String>,
+    // Capture what the computer plays instead: true for the default output,
+    // or an output device (Windows) or monitor source (Linux) by name
+    #[serde(default, deserialize_with = "loopback::true_or_name")]
+    loopback: Option<loopback::Loopback>,
+    // Analyse only this input channel (1 = first), or the mix of a list of
+    // them; empty (or 0) = mix all channels
+    #[serde(default, deserialize_with = "channel_or_list")]
+    input_channel: Vec<usize>,
+    // Frames per audio callback, or "smallest" the device allows (the
+    // device's default if unset). Smaller buffers reach detection sooner
+    #[serde(default, deserialize_with = "frames_or_smallest")]
+    buffer_size: Option<BufferSize>,
+    // Pick up edits to the config file while trigger mode runs
+    #[serde(default = "default_hot_reload")]
+    hot_reload: bool,
+    // Run detection and triggering, but report the actions instead of
+    // running them (also --dry-run)
+    #[serde(default)]
+    dry_run: bool,
+    // Frequency of A4 that all note names are measured from
+    #[serde(default = "default_a4_hz")]
+    a4_hz: f32,
+    // Semitones the instrument sounds above the notes the mappings name: 2
+    // with a capo on fret 2, -2 for a B♭ instrument read as written
+    #[serde(default)]
+    transpose_semitones: i32,
+    // Measure the ensemble's reference note to set a4_hz
+    #[serde(default)]
+    reference: reference::ReferenceConfig,
+    // Pitch gate in cents; note must be within this tolerance of the center
+    #[serde(default = "default_tolerance_cents")]
+    tolerance_cents: f32,
+    // Autocorrelation minimum and maximum pitch bounds
+    #[serde(default = "default_min_hz")]
+    min_hz: f32,
+    #[serde(default = "default_max_hz")]
+    max_hz: f32,
+    // Sample rate the analysis runs at, whatever the device's (0 = the device's own)
+    #[serde(default = "default_analysis_rate")]
+    analysis_rate: u32,
+    // Processing window and hop (in samples at analysis_rate). If 0, auto-choose.
+    #[serde(default)]
+    window_size: usize,
+    #[serde(default)]
+    hop_size: usize,
+    // Analyse every Nth sample to save CPU on large windows (0 = auto, 1 = off)
+    #[serde(default)]
+    decimation: usize,
+    // How many consecutive frames must match the same note before triggering
+    #[serde(default = "default_hold_frames")]
+    note_hold_frames: usize,
+    // Minimum ms between repeated triggers of the same note
+    #[serde(default = "default_retrigger_ms")]
+    retrigger_ms: u64,
+    // Pitch detection algorithm
+    #[serde(default)]
+    detector: Detector,
+    // Optional energy/correlation threshold (0..1). Higher = stricter.
+    #[serde(default = "default_corr_threshold")]
+    corr_threshold: f32,
+    // Frames quieter than this RMS level (0..1) are treated as silence (0 = off)
+    #[serde(default)]
+    min_rms: f32,
+    // Reject pitches less than this many dB above the tracked noise floor (0 = off)
+    #[serde(default)]
+    min_snr_db: f32,
+    // Reject pitches whose zero-crossing rate doesn't match (for sine-like
+    // sources such as whistling, where it filters out breath noise)
+    #[serde(default)]
+    pure_tone_check: bool,
+    // Halve pitches whose odd subharmonics carry energy (octave-up errors on
+    // low strings)
+    #[serde(default)]
+    octave_check: bool,
+    // Confidence-weighted vote over recent frames instead of consecutive
+    // note_hold_frames
+    #[serde(default)]
+    voting: voting::VotingConfig,
+    // Median or HMM smoothing of the pitch track before notes are classified
+    #[serde(default)]
+    smoothing: smoothing::SmoothingConfig,
+    // Attack detection that starts a new note even on a repeat (trigger mode)
+    #[serde(default)]
+    onsets: onset::OnsetConfig,
+    // Note mapping: e.g., "A4" = { type = "keys", sequence = "Ctrl+S" }
+    #[serde(default)]
+    note_map: HashMap<String, Mapping>,
+    // Note phrases that fire an action, e.g. notes = ["C4", "E4", "G4"] (trigger mode)
+    #[serde(default)]
+    sequences: Vec<sequences::Sequence>,
+    // Rhythms tapped out on one note, e.g. pattern = "..-" (trigger mode)
+    #[serde(default)]
+    rhythms: Vec<rhythms::Rhythm>,
+    // How quickly "A4 x2" strikes must follow each other
+    #[serde(default)]
+    strikes: strikes::StrikesConfig,
+    // Morse text-entry settings (used when mode = "morse")
+    #[serde(default)]
+    morse: morse::MorseConfig,
+    // Practice statistics settings (used when mode = "practice")
+    #[serde(default)]
+    practice: practice::PracticeConfig,
+    // Ear-training game settings (used when mode = "ear-training")
+    #[serde(default)]
+    ear_training: ear::EarTrainingConfig,
+    // Mapping trainer settings (used when mode = "trainer")
+    #[serde(default)]
+    trainer: trainer::TrainerConfig,
+    // Estimate which string a note was played on (for "E3@5" style keys)
+    #[serde(default)]
+    strings: strings::StringsConfig,
+    // Classify each attack as pluck/strum/bowed/muted (for "A3:muted" style keys)
+    #[serde(default)]
+    articulation: articulation::ArticulationConfig,
+    // Trigger "tap"/"slap" mappings on unpitched knocks and string slaps
+    #[serde(default)]
+    percussion: percussion::PercussionConfig,
+    // Recognition settings for chord keys ("Am", "Cmaj7") in note_map
+    #[serde(default)]
+    chords: chords::ChordConfig,
+    // Recognition settings for note-set keys ("E3&A3") in note_map
+    #[serde(default)]
+    polyphony: polyphony::PolyphonyConfig,
+    // Two-note keys played one note after the other
+    #[serde(default)]
+    intervals: intervals::IntervalsConfig,
+    // Level thresholds for "A4:piano" / "A4:forte" keys
+    #[serde(default)]
+    dynamics: dynamics::DynamicsConfig,
+    // What counts as vibrato for "A4+vibrato" keys
+    #[serde(default)]
+    vibrato: vibrato::VibratoConfig,
+    // Display of the `tuner` command
+    #[serde(default)]
+    tuner: tuner::TunerConfig,
+    // Slides up/down mapped to repeated actions and a MIDI CC ramp (trigger mode)
+    #[serde(default)]
+    glissando: glissando::GlissandoConfig,
+    // Suppress triggers while the pitch track looks like speech
+    #[serde(default)]
+    speech_gate: speech_gate::SpeechGateConfig,
+    // Screen-reader-friendly status output instead of the redrawn status line
+    #[serde(default)]
+    accessible: status::AccessibleConfig,
+    // How note names are printed (sharps/flats, Unicode, octave numbering)
+    #[serde(default)]
+    display: notation::DisplayConfig,
+    // Switch-scanning menu settings (used when mode = "scanning")
+    #[serde(default)]
+    scanning: scanning::ScanningConfig,
+    // Click track and beat grid for quantized mappings (trigger mode)
+    #[serde(default)]
+    metronome: metronome::MetronomeConfig,
+    // Estimate the tempo from the timing of attacks (trigger mode)
+    #[serde(default)]
+    tempo: tempo::TempoConfig,
+    // Light RGB devices by note and intonation through an OpenRGB server (trigger mode)
+    #[serde(default)]
+    openrgb: openrgb::OpenRgbConfig,
+    // Drive a WLED LED strip by note and intonation (trigger mode)
+    #[serde(default)]
+    wled: wled::WledConfig,
+    // Game-controller rumble on triggers and out-of-tune mapped notes (trigger mode)
+    #[serde(default)]
+    rumble: rumble::RumbleConfig,
+    // Named mapping sets, switched by schedule, a profile action or the control port (trigger mode)
+    #[serde(default)]
+    profiles: BTreeMap<String, profiles::Profile>,
+    #[serde(default)]
+    schedule: profiles::ScheduleConfig,
+    // Local UDP port for commands such as `profile daw`
+    #[serde(default)]
+    control: control::ControlConfig,
+    // REST API on a local port: pause, profiles, reload, state, notes
+    #[serde(default)]
+    http_api: http_api::HttpApiConfig,
+    // Tray icon with pause, profile, reload and quit (trigger mode)
+    #[serde(default)]
+    tray: tray::TrayConfig,
+    // Global keys that turn actions off and on, or switch profile
+    #[serde(default)]
+    hotkeys: hotkeys::HotkeysConfig,
+    // Log file and console verbosity
+    #[serde(default)]
+    log: logging::LogConfig,
+    // Lower/upper zones around a pivot note (trigger mode)
+    #[serde(default)]
+    split: profiles::SplitConfig,
+    // Output port for midi mappings
+    #[serde(default)]
+    midi: midi::MidiConfig,
+    // Default destination of osc mappings
+    #[serde(default)]
+    osc: osc::OscConfig,
+    // OBS Studio's WebSocket server, for obs mappings
+    #[serde(default)]
+    obs: obs::ObsConfig,
+    // The virtual game controller, and the axes the playing moves
+    #[serde(default)]
+    gamepad: gamepad::GamepadConfig,
+    // How keys and text are sent outside Windows
+    #[serde(default)]
+    keyboard: keyboard::KeyboardConfig,
+    // Home Assistant's address and token, for home-assistant mappings
+    #[serde(default)]
+    home_assistant: home_assistant::HomeAssistantConfig,
+    // Where detector = { plugin = "..." } and plugin mappings find their .wasm files
+    #[serde(default)]
+    plugins: plugins::PluginsConfig,
+    // Settings for the `calibrate` subcommand
+    #[serde(default)]
+    calibrate: calibrate::CalibrateConfig,
+    // One pipeline per string of a divided pickup
+    #[serde(default)]
+    hexaphonic: hexaphonic::HexaphonicConfig,
+    // Receive audio over the network instead of from an input device
+    #[serde(default)]
+    network_input: net_audio::NetworkInputConfig,
+    // Broadcast pitch and trigger events as JSON over WebSocket
+    #[serde(default)]
+    websocket: websocket::WebsocketConfig,
+    // Publish notes and triggers to an MQTT broker, and take commands from it
+    #[serde(default)]
+    mqtt: mqtt::MqttConfig,
+    // Serve methods and signals on the D-Bus session bus (Linux)
+    #[serde(default)]
+    dbus: dbus::DbusConfig,
+    // Append every frame, trigger and status event to a JSON-lines file
+    #[serde(default)]
+    record: record::RecordConfig,
+    // Independent pipelines from [performers.<name>] sections or the strings
+    // of [hexaphonic] (built by load_config)
+    #[serde(skip)]
+    performers: Vec<Config>,
+    // Name of the performer this config belongs to
+    #[serde(skip)]
+    performer: Option<String>,
+    // The string a hexaphonic pipeline listens to (1 = highest)
+    #[serde(skip)]
+    string: Option<usize>,
+    // Set by --verbose
+    #[serde(skip)]
+    verbose: bool,
+    // Set by --input: raw PCM to read instead of capturing
+    #[serde(skip)]
+    raw_input: Option<pcm::RawInput>,
+}
+
+fn default_hot_reload() -> bool { true }
+fn default_a4_hz() -> f32 { 440.0 }
+fn default_tolerance_cents() -> f32 { 35.0 }
+fn default_min_hz() -> f32 { 75.0 }
+fn default_max_hz() -> f32 { 2000.0 }
+fn default_analysis_rate() -> u32 { 22050 }
+fn default_hold_frames() -> usize { 3 }
+fn default_retrigger_ms() -> u64 { 600 }
+fn default_corr_threshold() -> f32 { 0.35 }
+
+impl Default for Config {
+    fn default() -> Self {
+        let mut note_map = HashMap::new();
+        // Sample mappings: change freely in config.toml
+        note_map.insert(
+            "A4".to_string(),
+            Action::Keys {
+                sequence: "Ctrl+S".to_string(), // Save
+            }
+            .into(),
+        );
+        note_map.insert(
+            "E4".to_string(),
+            Action::Keys {
+                sequence: "Space".to_string(), // Space bar
+            }
+            .into(),
+        );
+        note_map.insert(
+            "D4".to_string(),
+            Action::Keys {
+                sequence: "Ctrl+Z".to_string(), // Undo
+            }
+            .into(),
+        );
+        note_map.insert(
+            "G3".to_string(),
+            Action::Keys {
+                sequence: "Ctrl+Y".to_string(), // Redo
+            }
+            .into(),
+        );
+
+        Self {
+            preset: None,
+            instrument: None,
+            mode: Mode::default(),
+            host: None,
+            input_device: None,
+            loopback: None,
+            input_channel: Vec::new(),
+            buffer_size: None,
+            hot_reload: default_hot_reload(),
+            dry_run: false,
+            a4_hz: default_a4_hz(),
+            transpose_semitones: 0,
+            reference: reference::ReferenceConfig::default(),
+            tolerance_cents: default_tolerance_cents(),
+            min_hz: default_min_hz(),
+            max_hz: default_max_hz(),
+            analysis_rate: default_analysis_rate(),
+            window_size: 0,
+            hop_size: 0,
+            decimation: 0,
+            note_hold_frames: default_hold_frames(),
+            retrigger_ms: default_retrigger_ms(),
+            detector: Detector::default(),
+            corr_threshold: default_corr_threshold(),
+            min_rms: 0.0,
+            min_snr_db: 0.0,
+            pure_tone_check: false,
+            octave_check: false,
+            voting: voting::VotingConfig::default(),
+            smoothing: smoothing::SmoothingConfig::default(),
+            onsets: onset::OnsetConfig::default(),
+            note_map,
+            sequences: Vec::new(),
+            rhythms: Vec::new(),
+            strikes: strikes::StrikesConfig::default(),
+            morse: morse::MorseConfig::default(),
+            practice: practice::PracticeConfig::default(),
+            ear_training: ear::EarTrainingConfig::default(),
+            trainer: trainer::TrainerConfig::default(),
+            strings: strings::StringsConfig::default(),
+            articulation: articulation::ArticulationConfig::default(),
+            percussion: percussion::PercussionConfig::default(),
+            chords: chords::ChordConfig::default(),
+            polyphony: polyphony::PolyphonyConfig::default(),
+            intervals: intervals::IntervalsConfig::default(),
+            dynamics: dynamics::DynamicsConfig::default(),
+            vibrato: vibrato::VibratoConfig::default(),
+            tuner: tuner::TunerConfig::default(),
+            glissando: glissando::GlissandoConfig::default(),
+            speech_gate: speech_gate::SpeechGateConfig::default(),
+            accessible: status::AccessibleConfig::default(),
+            display: notation::DisplayConfig::default(),
+            scanning: scanning::ScanningConfig::default(),
+            metronome: metronome::MetronomeConfig::default(),
+            tempo: tempo::TempoConfig::default(),
+            openrgb: openrgb::OpenRgbConfig::default(),
+            wled: wled::WledConfig::default(),
+            rumble: rumble::RumbleConfig::default(),
+            profiles: BTreeMap::new(),
+            schedule: profiles::ScheduleConfig::default(),
+            control: control::ControlConfig::default(),
+            http_api: http_api::HttpApiConfig::default(),
+            tray: tray::TrayConfig::default(),
+            hotkeys: hotkeys::HotkeysConfig::default(),
+            log: logging::LogConfig::default(),
+            split: profiles::SplitConfig::default(),
+            midi: midi::MidiConfig::default(),
+            osc: osc::OscConfig::default(),
+            obs: obs::ObsConfig::default(),
+            gamepad: gamepad::GamepadConfig::default(),
+            keyboard: keyboard::KeyboardConfig::default(),
+            home_assistant: home_assistant::HomeAssistantConfig::default(),
+            plugins: plugins::PluginsConfig::default(),
+            calibrate: calibrate::CalibrateConfig::default(),
+            hexaphonic: hexaphonic::HexaphonicConfig::default(),
+            network_input: net_audio::NetworkInputConfig::default(),
+            websocket: websocket::WebsocketConfig::default(),
+            mqtt: mqtt::MqttConfig::default(),
+            dbus: dbus::DbusConfig::default(),
+            record: record::RecordConfig::default(),
+            performers: Vec::new(),
+            performer: None,
+            string: None,
+            verbose: false,
+            raw_input: None,
+        }
+    }
+}
+
+// ---------------------------- Main entry ----------------------------
+
+/// The flags that apply to every command.
+#[derive(clap::Args)]
+pub struct Options {
+    /// Config file [default: ./config.toml]
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<std::path::PathBuf>,
+    /// Capture from this input device: its number in list-devices, or (part of) its name
+    #[arg(long, global = true, value_name = "NAME")]
+    pub device: Option<String>,
+    /// Capture through this audio system, e.g. jack or alsa (see list-devices)
+    #[arg(long, global = true, value_name = "NAME")]
+    pub host: Option<String>,
+    /// Read raw interleaved PCM from this file, or - for standard input, instead of capturing
+    #[arg(long, global = true, value_name = "PATH")]
+    pub input: Option<std::path::PathBuf>,
+    /// Sample rate of --input
+    #[arg(long, global = true, value_name = "HZ", default_value_t = 48000, value_parser = clap::value_parser!(u32).range(1..))]
+    pub rate: u32,
+    /// Channels of --input
+    #[arg(long, global = true, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    pub channels: u16,
+    /// Sample format of --input
+    #[arg(long, global = true, value_enum, default_value = "s16")]
+    pub sample_format: pcm::SampleFormat,
+    /// Print every detected frame on its own line
+    #[arg(short, long, global = true)]
+    pub verbose: bool,
+    /// Report the actions that would run instead of running them
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+    /// Discrete status lines for screen readers (see [accessible])
+    #[arg(long, global = true)]
+    pub accessible_output: bool,
+    /// Stream what is played to the MIDI output (mode = "midi")
+    #[arg(long, global = true)]
+    pub midi_thru: bool,
+    /// Run in the background with a system tray icon (see [tray])
+    #[arg(long, global = true)]
+    pub tray: bool,
+    /// Time every trigger from the audio arriving to its action, and report the p50/p95 of each stage
+    #[arg(long, global = true)]
+    pub measure_latency: bool,
+    /// Show the status line, or print a JSON object per frame and trigger for other programs
+    #[arg(long, global = true, value_enum, default_value = "text")]
+    pub output: status::OutputFormat,
+    /// Append every frame and trigger to this JSON-lines file (see [record])
+    #[arg(long, global = true, value_name = "PATH")]
+    pub record: Option<String>,
+}
+
+#[derive(clap::Subcommand)]
+pub enum Command {
+    /// Run the configured mode (the default)
+    Run,
+    /// List the audio input devices
+    ListDevices,
+    /// Show the detected note and cents offset without running any actions
+    Tuner,
+    /// Load the config and report problems
+    Check,
+    /// Map notes by playing them and typing or pressing their keys
+    Learn,
+    /// Measure per-note settings and write them to the config
+    Calibrate,
+    /// Measure the room noise and write min_rms to the config
+    CalibrateNoise,
+    /// Measure the reference note and write a4_hz to the config
+    Reference,
+    /// Write the mappings in another form
+    Export {
+        #[command(subcommand)]
+        what: Export,
+    },
+    /// Add the key combinations of an AutoHotkey/Karabiner file to note_map
+    Import {
+        /// ahk or karabiner
+        #[arg(value_parser = import::Source::parse)]
+        source: import::Source,
+        file: std::path::PathBuf,
+    },
+    /// Switch the running instance to a profile (a name, next or default)
+    Profile { name: String },
+    /// Turn the running instance's actions on, off, or toggle them
+    Actions {
+        #[arg(default_value = "toggle")]
+        state: String,
+    },
+    /// Play a recorded session back through the current config and compare the triggers
+    Replay {
+        /// A file written by --record
+        session: std::path::PathBuf,
+        /// Play this WAV file instead of the session's own audio
+        #[arg(long, value_name = "WAV")]
+        audio: Option<std::path::PathBuf>,
+    },
+    /// Send the input to another machine's [network_input]
+    SendAudio {
+        /// Its address and port, e.g. 192.168.1.20:7400
+        to: String,
+        /// Over TCP instead of UDP
+        #[arg(long)]
+        tcp: bool,
+    },
+}
+
+#[derive(clap::Subcommand)]
+pub enum Export {
+    /// A printable chart of the mappings
+    Cheatsheet {
+        /// html, md or pdf
+        #[arg(long, default_value = "html", value_parser = cheatsheet::Format::parse)]
+        format: cheatsheet::Format,
+        /// Output file [default: cheatsheet.<format>]
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+}
+
+/// Run one command as the rusty-strings-control binary does.
+pub fn run(options: Options, command: Command) -> Result<()> {
+    let done = run_subcommand(options, command);
+    latency::report();
+    record::finish();
+    match done {
+        // --input has run out
+        Err(e) if e.downcast_ref::<ring::StreamEnded>().is_some() => {
+            println!("\nInput ended");
+            Ok(())
+        }
+        done => done,
+    }
+}
+
+fn run_subcommand(options: Options, command: Command) -> Result<()> {
+    if let Some(path) = options.config { CONFIG_PATH.set(path).ok(); }
+    if let Command::Check = command { return run_check(); }
+    if let Command::ListDevices = command {
+        logging::init(&Default::default());
+        let host = options.host.or_else(|| load_config().ok().and_then(|c| c.host));
+        return list_devices(host.as_deref());
+    }
+
+    let (mut cfg, load_error) = match load_config() {
+        Ok(cfg) => (cfg, None),
+        // A file asked for by name has to be there
+        Err(e) if CONFIG_PATH.get().is_some() => return Err(e),
+        Err(e) => (Config::default(), Some(e)),
+    };
+    logging::init(&cfg.log);
+    if options.output == status::OutputFormat::Json {
+        status::json_output().context("cannot set up --output json")?;
+    }
+    if let Some(e) = load_error { tracing::warn!("using default config: {e:#}"); }
+    if options.accessible_output { cfg.accessible.enabled = true; }
+    if options.midi_thru { cfg.mode = Mode::Midi; }
+    if options.tray { cfg.tray.enabled = true; }
+    if options.device.is_some() { cfg.input_device = options.device; }
+    if options.host.is_some() { cfg.host = options.host; }
+    cfg.verbose = options.verbose;
+    cfg.raw_input = options.input.map(|path| pcm::RawInput {
+        path,
+        format: pcm::Format { sample_rate: options.rate, channels: options.channels, sample: options.sample_format },
+    });
+    for p in &mut cfg.performers {
+        if options.accessible_output { p.accessible.enabled = true; }
+        p.verbose = options.verbose;
+        p.raw_input = cfg.raw_input.clone();
+    }
+    if options.dry_run { cfg.dry_run = true; }
+    if options.measure_latency { latency::start(); }
+    if let Some(file) = options.record {
+        cfg.record.enabled = true;
+        cfg.record.file = file;
+    }
+    apply_settings(&cfg);
+
+    if let Command::Export { what: Export::Cheatsheet { format, output } } = command {
+        let path = output.unwrap_or_else(|| format!("cheatsheet.{}", format.extension()));
+        let sheet = cheatsheet::render(&cheatsheet::layers(&cfg), format);
+        std::fs::write(&path, sheet).with_context(|| format!("Failed to write {path}"))?;
+        println!("Wrote mapping cheat sheet to {path}");
+        return Ok(());
+    }
+    if let Command::Import { source, file } = &command {
+        let found = import::read(*source, file)?;
+        let path = toml_config_path()?;
+        let added = import::assign(&found, &path)?;
+        println!("Added {added} mapping(s) to {}", path.display());
+        return Ok(());
+    }
+    if let Command::Profile { name } = &command {
+        println!("{}", control::send(&cfg.control, &format!("profile {name}"))?);
+        return Ok(());
+    }
+    if let Command::Actions { state } = &command {
+        println!("{}", control::send(&cfg.control, &format!("actions {state}"))?);
+        return Ok(());
+    }
+    if let Command::Replay { session, audio } = &command {
+        return replay::run(&cfg, session, audio.as_deref());
+    }
+
+    println!("Starting Rusty Strings Control");
+    if let Some(p) = &cfg.preset { println!("Preset: {p}"); }
+    if let Some(i) = &cfg.instrument { println!("Instrument: {i} ({})", cfg.strings.tuning.join(" ")); }
+    println!("Tolerance: ±{:.1} cents, range: {:.0}-{:.0} Hz", cfg.tolerance_cents, cfg.min_hz, cfg.max_hz);
+    if cfg.a4_hz != 440.0 { println!("Reference: A4 = {:.1} Hz", cfg.a4_hz); }
+    if cfg.transpose_semitones != 0 { println!("Transposed: notes sound {:+} semitone(s) from their names", cfg.transpose_semitones); }
+    if cfg.verbose { println!("Config: {}", config_path()?.display()); }
+    if DRY_RUN.load(Ordering::Relaxed) { println!("Dry run: actions are reported, not run"); }
+    // Mappings that can't work as written would otherwise only fail when played
+    for p in validate::check(&cfg).iter().filter(|p| p.error) {
+        tracing::warn!("{}: {} (see `check`)", p.place, p.message);
+    }
+    websocket::start(&cfg.websocket);
+    mqtt::start(&cfg.mqtt);
+    dbus::start(&cfg.dbus);
+    record::start(&cfg.record, &config_path()?)?;
+
+    if matches!(command, Command::Run) && !cfg.performers.is_empty() {
+        return run_performers(&cfg);
+    }
+    if let Command::SendAudio { to, tcp } = &command {
+        let (mut rxs, sample_rate, _, _stream) = open_input(&cfg, &[&cfg.input_channel])?;
+        let protocol = if *tcp { net_audio::Protocol::Tcp } else { net_audio::Protocol::Udp };
+        return net_audio::send(rxs.remove(0), sample_rate, to, protocol);
+    }
+
+    // Set up audio capture
+    let (mut input, _stream) = AudioInput::open(&cfg)?;
+
+    match command {
+        Command::Calibrate => return run_calibration(&cfg, &mut input),
+        Command::Learn => return run_learn(&cfg, &mut input),
+        Command::CalibrateNoise => {
+            let path = toml_config_path()?;
+            if !path.exists() {
+                return Err(anyhow!("calibrate-noise writes min_rms to {}, which doesn't exist", path.display()));
+            }
+            println!("\nKeep the room quiet for {} s. Press Enter to start.", cfg.calibrate.noise_secs);
+            std::io::stdin().read_line(&mut String::new())?;
+            let gate = calibrate::noise_gate(&measure_noise(&cfg, &mut input)?);
+            calibrate::save_min_rms(&path, gate)?;
+            println!("Saved min_rms = {gate:.4} to {}", path.display());
+            return Ok(());
+        }
+        Command::Reference => {
+            let path = toml_config_path()?;
+            let a4 = measure_reference(&cfg, &mut input)?;
+            reference::save(&path, a4)?;
+            println!("Saved a4_hz = {a4:.1} to {}", path.display());
+            return Ok(());
+        }
+        Command::Run => {
+            if cfg.calibrate.noise_on_start {
+                println!("\nMeasuring the room noise for {} s; keep quiet", cfg.calibrate.noise_secs);
+                cfg.min_rms = calibrate::noise_gate(&measure_noise(&cfg, &mut input)?);
+                println!("Using min_rms = {:.4} for this session", cfg.min_rms);
+            }
+            if cfg.reference.on_start {
+                cfg.a4_hz = measure_reference(&cfg, &mut input)?;
+                set_a4_hz(cfg.a4_hz);
+                println!("Using A4 = {:.1} Hz for this session", cfg.a4_hz);
+            }
+        }
+        Command::Tuner => return run_tuner(&cfg, &mut input),
+        Command::Check
+        | Command::ListDevices
+        | Command::Export { .. }
+        | Command::Import { .. }
+        | Command::Profile { .. }
+        | Command::Actions { .. }
+        | Command::Replay { .. }
+        | Command::SendAudio { .. } => {
+            unreachable!("handled before opening audio")
+        }
+    }
+    open_outputs(&cfg)?;
+    if let Mode::Trigger = cfg.mode {
+        control::listen(&cfg.control);
+        http_api::listen(&cfg.http_api);
+        hotkeys::register(&cfg.hotkeys);
+        tray::start(&cfg.tray);
+    }
+    match cfg.mode {
+        // The tray can ask for a reload even with hot_reload off
+        Mode::Trigger if (cfg.hot_reload || cfg.tray.enabled) && config_path()?.exists() => {
+            run_trigger_reloading(&cfg, &mut input)
+        }
+        Mode::Trigger => run_trigger(&cfg, &mut input),
+        Mode::Morse => run_morse(&cfg, &mut input),
+        Mode::Practice => run_practice(&cfg, &mut input),
+        Mode::EarTraining => run_ear_training(&cfg, &mut input),
+        Mode::Trainer => run_trainer(&cfg, &mut input),
+        Mode::Scanning => run_scanning(&cfg, &mut input),
+        Mode::StringCalibration => run_string_calibration(&cfg, &mut input),
+        Mode::Midi => run_midi_thru(&cfg, &mut input),
+    }
+}
+
+// The settings that the rest of the program reads from globals
+fn apply_settings(cfg: &Config) {
+    DRY_RUN.store(cfg.dry_run, Ordering::Relaxed);
+    set_a4_hz(cfg.a4_hz);
+    set_transpose(cfg.transpose_semitones);
+    notation::set(cfg.display);
+    osc::set_defaults(&cfg.osc);
+    obs::set_config(&cfg.obs);
+    home_assistant::set_config(&cfg.home_assistant);
+    gamepad::set_config(&cfg.gamepad);
+    keyboard::set_config(&cfg.keyboard);
+    plugins::set_config(&cfg.plugins);
+}
+
+// Open the MIDI output, the virtual gamepad and the keyboard up front when
+// anything uses them, so they are there to connect to before the first trigger
+fn open_outputs(cfg: &Config) -> Result<()> {
+    let profile_maps = cfg.profiles.values().flat_map(|p| p.note_map.values());
+    let actions: Vec<&Action> = cfg
+        .note_map
+        .values()
+        .chain(profile_maps)
+        .chain(cfg.split.lower.iter().chain(cfg.split.upper.iter()))
+        .chain(cfg.sequences.iter().map(|s| &s.mapping))
+        .chain(cfg.rhythms.iter().map(|r| &r.mapping))
+        .flat_map(|m| std::iter::once(&m.action).chain(m.on_release.iter()))
+        .chain(cfg.scanning.items.iter().map(|i| &i.action))
+        .chain(cfg.glissando.up.iter().chain(cfg.glissando.down.iter()))
+        .collect();
+    if actions.iter().any(|a| uses(a, &|a| matches!(a, Action::Midi(_)))) || cfg.glissando.cc.is_some() {
+        midi::open(&cfg.midi)?;
+    }
+    if actions.iter().any(|a| uses(a, &|a| matches!(a, Action::Gamepad(_)))) || cfg.gamepad.follows() {
+        gamepad::open(&cfg.gamepad)?;
+    }
+    let types = actions.iter().any(|a| uses(a, &|a| matches!(a, Action::Keys { .. } | Action::Text { .. })));
+    if cfg!(not(windows)) && !cfg.dry_run && (types || matches!(cfg.mode, Mode::Morse)) { keyboard::open(&cfg.keyboard)?; }
+    if actions.iter().any(|a| uses(a, &|a| matches!(a, Action::Plugin(_)))) { plugins::open(&cfg.plugins); }
+    Ok(())
+}
+
+// Whether `a`, or a step of it, is one that `is` looks for
+fn uses(a: &Action, is: &dyn Fn(&Action) -> bool) -> bool {
+    match a {
+        Action::Macro { steps } => steps.iter().any(|s| uses(&s.action, is)),
+        _ => is(a),
+    }
+}
+
+// Several instruments at once: every performer runs trigger mode with its own
+// config on its own thread. Performers on the same device share one stream.
+fn run_performers(cfg: &Config) -> Result<()> {
+    let performers = &cfg.performers;
+    let mut inputs: Vec<Option<AudioInput>> = performers.iter().map(|_| None).collect();
+    let mut _streams = Vec::new(); // keep streams alive
+    let mut devices: Vec<Option<&str>> = Vec::new();
+    for p in performers {
+        if !devices.contains(&p.input_device.as_deref()) { devices.push(p.input_device.as_deref()); }
+    }
+    for device in devices {
+        let members: Vec<usize> = (0..performers.len()).filter(|&i| performers[i].input_device.as_deref() == device).collect();
+        let taps: Vec<&[usize]> = members.iter().map(|&i| performers[i].input_channel.as_slice()).collect();
+        let (rxs, sample_rate, channels, stream) = open_input(&performers[members[0]], &taps)?;
+        _streams.push(stream);
+        for (&i, rx) in members.iter().zip(rxs) {
+            println!("\nPerformer {}:", performers[i].performer.as_deref().unwrap_or_default());
+            if let Some(pr) = &performers[i].preset { println!("Preset: {pr}"); }
+            if let Some(ins) = &performers[i].instrument { println!("Instrument: {ins}"); }
+            inputs[i] = Some(AudioInput::new(&performers[i], rx, sample_rate, channels));
+        }
+    }
+    let mut inputs: Vec<AudioInput> = inputs.into_iter().flatten().collect();
+
+    // One shared reference: the ensemble tunes together
+    if cfg.reference.on_start {
+        let a4 = measure_reference(&performers[0], &mut inputs[0])?;
+        set_a4_hz(a4);
+        println!("Using A4 = {a4:.1} Hz for this session");
+    }
+
+    for p in performers { open_outputs(p)?; }
+
+    let mut handles = Vec::new();
+    for (p, mut input) in performers.iter().cloned().zip(inputs) {
+        let name = p.performer.clone().unwrap_or_default();
+        let handle = std::thread::Builder::new()
+            .name(name.clone())
+            .spawn(move || run_trigger(&p, &mut input))
+            .context("Failed to start performer thread")?;
+        handles.push((name, handle));
+    }
+    // The others carry on if one performer's input fails
+    for (name, handle) in handles {
+        match handle.join() {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) if e.downcast_ref::<ring::StreamEnded>().is_some() => {}
+            Ok(Err(e)) => tracing::error!("Performer {name} stopped: {e:#}"),
+            Err(_) => tracing::error!("Performer {name} crashed"),
+        }
+    }
+    Ok(())
+}
+
+// How long after an attack the tap note may take to be recognized
+const TAP_CREDIT: Duration = Duration::from_millis(250);
+
+// How many fired mappings "undo" can step back through
+const UNDO_DEPTH: usize = 16;
+
+fn run_trigger(cfg: &Config, input: &mut AudioInput) -> Result<()> {
+    trigger_loop(cfg, input, None, None).map(|_| ())
+}
+
+// Trigger mode that follows edits to the config file (hot_reload) or
+// reloads it when asked to from the tray. The audio stream stays open; the
+// trigger loop starts over with each new config.
+fn run_trigger_reloading(cfg: &Config, input: &mut AudioInput) -> Result<()> {
+    let path = config_path()?;
+    let changes = reload::watch(path.clone(), cfg.hot_reload, load_config);
+    if cfg.hot_reload { println!("Watching {} for changes", path.display()); }
+    let mut cfg = cfg.clone();
+    loop {
+        let Some(mut next) = trigger_loop(&cfg, input, Some(&changes), None)? else { return Ok(()) };
+        // These belong to the running stream and session
+        for (what, changed) in [
+            ("host", next.host != cfg.host),
+            ("input_device", next.input_device != cfg.input_device),
+            ("loopback", next.loopback != cfg.loopback),
+            ("input_channel", next.input_channel != cfg.input_channel),
+            ("network_input", next.network_input != cfg.network_input),
+            ("websocket", next.websocket != cfg.websocket),
+            ("mqtt", next.mqtt != cfg.mqtt),
+            ("dbus", next.dbus != cfg.dbus),
+            ("record", next.record != cfg.record),
+            // The controller and the keyboard exist already
+            ("gamepad.name", next.gamepad.name != cfg.gamepad.name),
+            ("keyboard", next.keyboard != cfg.keyboard),
+            ("buffer_size", next.buffer_size != cfg.buffer_size),
+            ("mode", next.mode != cfg.mode),
+            ("performers", next.performers.iter().any(|p| p.string.is_none())),
+            ("hexaphonic", next.hexaphonic.enabled),
+        ] {
+            if changed { println!("\n{what} changes take effect after a restart"); }
+        }
+        next.host = cfg.host.take();
+        next.input_device = cfg.input_device.take();
+        next.loopback = cfg.loopback.take();
+        next.input_channel = cfg.input_channel;
+        next.network_input = cfg.network_input;
+        next.buffer_size = cfg.buffer_size;
+        next.record = cfg.record.clone();
+        next.verbose = cfg.verbose;
+        next.raw_input = cfg.raw_input.take();
+        if cfg.accessible.enabled { next.accessible.enabled = true; }
+        // An edit can start a dry run but not end one, so saving the file
+        // never starts sending keys to the focused window
+        if cfg.dry_run && !next.dry_run { println!("\ndry_run = false takes effect after a restart"); }
+        next.dry_run |= cfg.dry_run;
+        if next.dry_run && !cfg.dry_run { println!("\nDry run: actions are reported, not run"); }
+        // A reference or noise gate measured at start stays
+        if cfg.reference.on_start { next.a4_hz = cfg.a4_hz; }
+        if cfg.calibrate.noise_on_start { next.min_rms = cfg.min_rms; }
+        apply_settings(&next);
+        if let Err(e) = open_outputs(&next) { tracing::error!("{e:#}"); }
+        input.reconfigure(&next);
+        record::config(&path);
+        println!("Reloaded {}", path.display());
+        cfg = next;
+    }
+}
+
+// The trigger loop; returns a new config when one arrives on `reload`, or
+// None when quit from the tray. `listener` gets what the status line shows.
+fn trigger_loop(
+    cfg: &Config,
+    input: &mut AudioInput,
+    reload: Option<&Receiver<Config>>,
+    listener: Option<status::Listener>,
+) -> Result<Option<Config>> {
+    // State for triggering
+    let mut sender = new_sender();
+    let mut last_note: Option<String> = None;
+    let mut stable_count: usize = 0;
+    // Loudest hop since the current note began (for dynamics keys)
+    let mut note_peak = 0.0f32;
+    // Mapped note currently held out of tune, and for how many frames
+    let mut off_note: Option<String> = None;
+    let mut off_count: usize = 0;
+    let mut last_trigger_time: Option<Instant> = None;
+
+    // Each profile's mappings layered over the top-level note_map
+    let profile_maps: BTreeMap<&str, HashMap<String, Mapping>> = cfg
+        .profiles
+        .iter()
+        .map(|(name, p)| {
+            let mut map = cfg.note_map.clone();
+            map.extend(p.note_map.clone());
+            (name.as_str(), map)
+        })
+        .collect();
+    let all_mappings = || {
+        cfg.note_map.values()
+            .chain(profile_maps.values().flat_map(|m| m.values()))
+            .chain(cfg.split.lower.iter().chain(cfg.split.upper.iter()))
+            .chain(cfg.sequences.iter().map(|s| &s.mapping))
+            .chain(cfg.rhythms.iter().map(|r| &r.mapping))
+    };
+    let mut schedule = if cfg.profiles.is_empty() { None } else {
+        Some(profiles::Schedule::new(&cfg.profiles, &cfg.schedule)?)
+    };
+    let mut active_profile: Option<String> = None;
+    // The schedule's latest choice; a switch asked for at runtime holds
+    // until the schedule changes its mind
+    let mut scheduled: Option<String> = None;
+    let mut next_schedule_check = Instant::now();
+    let mut switches_seen = 0;
+    profiles::requested(&mut switches_seen);
+    tray::set_profiles(cfg.profiles.keys());
+    let mut paused = false;
+    let mut actions_on = hotkeys::actions_on();
+    let mut note_map = &cfg.note_map;
+    let base_settings = profiles::Settings {
+        tolerance_cents: cfg.tolerance_cents,
+        corr_threshold: cfg.corr_threshold,
+        note_hold_frames: cfg.note_hold_frames,
+        retrigger_ms: cfg.retrigger_ms,
+    };
+    let mut settings = base_settings;
+
+    // Split point: notes from the pivot up are the upper zone
+    let split = &cfg.split;
+    let pivot = match &split.point {
+        Some(p) => Some(note_to_midi(p).ok_or_else(|| anyhow!("[split] point {p:?} is not a note name"))?),
+        None => None,
+    };
+    let zone_map = |profile: &Option<String>| match profile {
+        Some(name) => profile_maps
+            .get(name.as_str())
+            .map(Some)
+            .ok_or_else(|| anyhow!("[split] uses unknown profile {name:?}")),
+        None => Ok(None),
+    };
+    let (lower_map, upper_map) = (zone_map(&split.lower_profile)?, zone_map(&split.upper_profile)?);
+    if let Some(p) = &split.point { println!("Split at {}", notation::spell(p)); }
+
+    // Optional metronome: click track plus the grid quantized mappings wait for
+    let mc = &cfg.metronome;
+    let mut _click = None; // keep output stream alive
+    let mut grid = if mc.enabled {
+        if mc.click {
+            let out = tone::ToneOutput::open()?;
+            out.start_clicks(mc.bpm, mc.beats_per_bar, mc.subdivision, mc.volume);
+            _click = Some(out);
+        }
+        println!(
+            "Metronome: {:.0} BPM, {}/bar, count-in {} bar(s)",
+            mc.bpm, mc.beats_per_bar, mc.count_in_bars
+        );
+        Some(metronome::BeatGrid::new(mc, Instant::now()))
+    } else {
+        if all_mappings().any(|m| m.quantize != metronome::Quantize::Off) {
+            tracing::warn!("quantized mappings fire immediately while the metronome is disabled");
+        }
+        if all_mappings().any(|m| matches!(m.action, Action::TapTempo)) {
+            tracing::warn!("tap-tempo mappings have no effect while the metronome is disabled");
+        }
+        None
+    };
+    if all_mappings().any(|m| m.mode == MappingMode::Hold && !m.holds()) {
+        tracing::warn!("mode = \"hold\" only applies to keys and gamepad button mappings; others trigger once");
+    }
+    // The hold mapping whose keys are down
+    let mut held: Option<Held> = None;
+    // Quantized triggers waiting for their grid point
+    let mut pending: Vec<(Instant, String, &Mapping)> = Vec::new();
+    // Recently fired mappings, newest last
+    let mut history: Vec<(String, &Mapping)> = Vec::new();
+    let mut status = status::StatusOutput::new(&cfg.accessible)
+        .labeled(cfg.performer.as_deref())
+        .verbose(cfg.verbose)
+        .listener(listener);
+    let mut feedback = feedback::FeedbackSet::new(cfg);
+    let mut speech_gate = cfg.speech_gate.enabled.then(|| speech_gate::SpeechGate::new(&cfg.speech_gate));
+    let mut speech_classifier = cfg
+        .speech_gate
+        .classifier
+        .then(|| speech_gate::SpeechClassifier::new(&cfg.speech_gate, cfg.min_rms));
+    let string_estimator = if cfg.strings.enabled { Some(string_estimator(cfg)?) } else { None };
+    let mut articulation = cfg
+        .articulation
+        .enabled
+        .then(|| articulation::ArticulationDetector::new(&cfg.articulation, cfg.min_rms));
+    let mut percussion = cfg.percussion.enabled.then(|| percussion::PercussionDetector::new(&cfg.percussion));
+    let mut sequence_tracker = sequences::SequenceTracker::new(&cfg.sequences, Instant::now())?;
+    let mut strike_counter = strikes::StrikeCounter::new(&cfg.strikes);
+    let mut interval_tracker = intervals::IntervalTracker::new(&cfg.intervals);
+    let mut rhythm_tracker =
+        rhythms::RhythmTracker::new(&cfg.rhythms).map(|t| (onset::LevelOnsets::new(2.0, cfg.min_rms), t));
+    // Vibrato is followed only when some key asks for it
+    let mut vibrato_tracker = cfg
+        .note_map
+        .keys()
+        .chain(profile_maps.values().flat_map(|m| m.keys()))
+        .any(|k| k.ends_with(vibrato::MODIFIER))
+        .then(|| vibrato::VibratoTracker::new(&cfg.vibrato));
+    let mut slides = cfg.glissando.active().then(|| glissando::GlissandoTracker::new(&cfg.glissando));
+    // [gamepad] axes that follow the bend and the loudness
+    let mut pad_follower = gamepad::Follower::new(&cfg.gamepad);
+    // Chord recognition runs only when some mapping names a chord
+    let mut chord_detector = cfg
+        .note_map
+        .keys()
+        .chain(profile_maps.values().flat_map(|m| m.keys()))
+        .any(|k| chords::parse(k).is_some())
+        .then(|| chords::ChordDetector::new(&cfg.chords));
+    // Likewise for keys naming notes played together
+    let mut poly_detector = cfg
+        .note_map
+        .keys()
+        .chain(profile_maps.values().flat_map(|m| m.keys()))
+        .any(|k| polyphony::parse(k).is_some())
+        .then(|| polyphony::PolyDetector::new(&cfg.polyphony));
+    // Following the player needs a tempo estimate even if it isn't otherwise enabled
+    let mut tempo = (cfg.tempo.enabled || (mc.enabled && mc.follow)).then(|| {
+        (onset::LevelOnsets::new(cfg.tempo.onset_ratio, cfg.min_rms), tempo::TempoTracker::new(&cfg.tempo))
+    });
+    let mut reported_bpm: Option<f32> = None;
+    // With [onsets], every attack starts a new note; a fresh attack may fire
+    // before retrigger_ms has passed
+    let mut note_onsets = cfg.onsets.enabled.then(|| onset::NoteOnsets::new(&cfg.onsets, cfg.min_rms));
+    let mut fresh_attack = false;
+    // Tap tempo: attacks are timed here and credited once the tap note is recognized
+    let mut taps = all_mappings()
+        .any(|m| matches!(m.action, Action::TapTempo))
+        .then(|| (onset::LevelOnsets::new(2.0, cfg.min_rms), metronome::TapTempo::new(mc)));
+    let mut last_attack: Option<Instant> = None;
+
+    loop {
+        if let Some(next) = reload.and_then(|r| r.try_recv().ok()) {
+            if let Some(h) = held.take() { h.release(&mut sender, &mut status); }
+            return Ok(Some(next));
+        }
+        if tray::quitting() {
+            if let Some(h) = held.take() { h.release(&mut sender, &mut status); }
+            return Ok(None);
+        }
+        // Paused from the tray: keep the audio flowing, but don't listen
+        if tray::paused() {
+            if !paused {
+                if let Some(h) = held.take() { h.release(&mut sender, &mut status); }
+                status.event("Paused");
+                paused = true;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+            input.discard();
+            continue;
+        }
+        if paused {
+            status.event("Listening");
+            paused = false;
+            last_note = None;
+            stable_count = 0;
+        }
+        if hotkeys::actions_on() != actions_on {
+            actions_on = !actions_on;
+            if !actions_on {
+                if let Some(h) = held.take() { h.release(&mut sender, &mut status); }
+            }
+            status.event(if actions_on { "Actions on" } else { "Actions off: notes are shown, not acted on" });
+        }
+        let freq = match input.next_pitch_for(cfg, note_map, settings.corr_threshold) {
+            Ok(f) => f,
+            Err(e) => {
+                if let Some(h) = held.take() { h.release(&mut sender, &mut status); }
+                return Err(e);
+            }
+        };
+        let now = Instant::now();
+        note_peak = if freq.is_some() { note_peak.max(input.hop_level()) } else { 0.0 };
+        let exact = freq.map(|f0| {
+            let (midi, cents) = freq_to_midi(f0);
+            midi as f32 + cents / 100.0
+        });
+        if let Some(v) = vibrato_tracker.as_mut() { v.update(now, exact); }
+        if let Some(f) = pad_follower.as_mut().filter(|_| hotkeys::actions_on() && !dry_run()) {
+            if let Err(e) = f.update(exact, input.hop_level()) {
+                // Said once, not every frame
+                tracing::error!("{e:#}; the gamepad axes stop following");
+                pad_follower = None;
+            }
+        }
+
+        // Slides run their step action once per semitone covered; the notes
+        // passed on the way don't trigger
+        let mut sliding = false;
+        if let Some(tracker) = slides.as_mut() {
+            if let Some((direction, steps)) = tracker.update(now, exact) {
+                let gc = &cfg.glissando;
+                let action = match direction {
+                    glissando::Direction::Up => gc.up.as_ref(),
+                    glissando::Direction::Down => gc.down.as_ref(),
+                };
+                status.event(&format!(
+                    "Glissando {} x{steps}{}",
+                    if direction == glissando::Direction::Up { "up" } else { "down" },
+                    action.map(|a| format!(" => {:?}", action_name(a))).unwrap_or_default()
+                ));
+                for _ in 0..steps {
+                    if let Some(Err(e)) = action.map(|a| execute_action(&mut sender, a)) { tracing::error!("Action failed: {e:#}"); }
+                }
+                if let Some(cc) = gc.cc {
+                    let value = tracker.ramp(direction, steps);
+                    if !hotkeys::actions_on() {
+                        // Turned off; the value still follows the slides
+                    } else if dry_run() {
+                        would(&format!("send CC {cc} = {value}"));
+                    } else if let Err(e) = midi::send([0xB0, cc.min(127), value], None) {
+                        tracing::error!("{e:#}");
+                    }
+                }
+            }
+            sliding = tracker.sliding();
+        }
+
+        // End a held note once it has been gone (silent or another note) for as
+        // many frames as it took to recognize it
+        if let Some(h) = held.as_mut() {
+            let sounding = freq.is_some_and(|f0| freq_to_note(f0).0 == h.note);
+            h.gone = if sounding { 0 } else { h.gone + 1 };
+            if h.gone >= h.mapping.note_hold_frames.unwrap_or(settings.note_hold_frames).max(1) {
+                if let Some(h) = held.take() { h.release(&mut sender, &mut status); }
+            } else if sounding {
+                h.repeat(now, &mut sender);
+            }
+        }
+
+        // Switch mapping sets when asked to, or when the schedule says so
+        let mut wanted = match profiles::requested(&mut switches_seen).map(|s| s.resolve(&cfg.profiles, active_profile.as_deref())) {
+            Some(Ok(profile)) => Some(profile),
+            Some(Err(e)) => {
+                status.event(&format!("{e:#}"));
+                None
+            }
+            None => None,
+        };
+        if let Some(sched) = schedule.as_mut().filter(|_| now >= next_schedule_check) {
+            next_schedule_check = now + cfg.schedule.interval();
+            let active = sched.active().map(str::to_string);
+            if active != scheduled {
+                scheduled = active.clone();
+                wanted = Some(active);
+            }
+        }
+        if let Some(active) = wanted.filter(|w| *w != active_profile) {
+            status.event(&format!("Profile: {}", active.as_deref().unwrap_or("(default)")));
+            tray::set_profile(active.as_deref());
+            note_map = active.as_deref().and_then(|n| profile_maps.get(n)).unwrap_or(&cfg.note_map);
+            settings = base_settings.with(active.as_deref().and_then(|n| cfg.profiles.get(n)));
+            active_profile = active;
+        }
+        // A note sent with the `note` control command fires as if it was played
+        if let Some(note) = control::injected() {
+            let key = note_keys::normalize(&strings::normalize_key(&note));
+            let found = note_map
+                .get_key_value(&key)
+                .or_else(|| note_to_midi(key_note(&key)).and_then(|midi| note_keys::lookup(note_map, midi)));
+            match found {
+                Some((key, mapping)) => {
+                    if dispatch(key, mapping, now, grid.as_ref(), &mut pending, &mut sender, &mut status) == Dispatch::Fired {
+                        last_trigger_time = Some(now);
+                        feedback.trigger();
+                        fired(key, mapping, &mut history, &mut sender, &mut status);
+                    }
+                }
+                None => status.event(&format!("Sent note {note}: not mapped")),
+            }
+        }
+        if let Some(a) = articulation.as_mut() { a.update(input.hop_level(), now); }
+
+        if let Some(onsets) = note_onsets.as_mut() {
+            let (window, rate) = input.raw_window();
+            if onsets.update(window, rate, input.hop_level(), now) {
+                // The previous note ends here, even if the same note follows
+                if let Some(h) = held.take() { h.release(&mut sender, &mut status); }
+                stable_count = 0;
+                last_note = None;
+                note_peak = input.hop_level();
+                fresh_attack = true;
+            }
+        }
+
+        if let Some((onsets, _)) = taps.as_mut() {
+            if onsets.update(input.hop_level(), now) { last_attack = Some(now); }
+        }
+
+        if let Some((onsets, tracker)) = tempo.as_mut() {
+            if onsets.update(input.hop_level(), now) { tracker.onset(now); }
+            // Report (and follow) changes of more than a couple of BPM
+            if let Some(bpm) = tracker.bpm(now).filter(|b| reported_bpm.is_none_or(|r| (b - r).abs() >= 2.0)) {
+                reported_bpm = Some(bpm);
+                status.event(&format!("Tempo: {bpm:.0} BPM"));
+                if let Some(g) = grid.as_mut().filter(|_| mc.follow) {
+                    g.set_bpm(bpm, now);
+                    if let Some(out) = &_click { out.set_click_bpm(bpm); }
+                }
+            }
+        }
+
+        // Rhythms go by the attacks alone
+        if let Some((onsets, tracker)) = rhythm_tracker.as_mut() {
+            let attack = onsets.update(input.hop_level(), now);
+            for i in tracker.update(attack, freq.map(|f0| freq_to_midi(f0).0), now) {
+                let rhythm = &cfg.rhythms[i];
+                let key = rhythm.label();
+                match dispatch(&key, &rhythm.mapping, now, grid.as_ref(), &mut pending, &mut sender, &mut status) {
+                    Dispatch::Fired => {
+                        last_trigger_time = Some(now);
+                        feedback.trigger();
+                        fired(&key, &rhythm.mapping, &mut history, &mut sender, &mut status);
+                    }
+                    Dispatch::Queued => last_trigger_time = Some(now),
+                    Dispatch::Failed => {}
+                }
+            }
+        }
+
+        // Unpitched knocks and slaps are their own trigger class with their own cooldown
+        if let Some(p) = percussion.as_mut() {
+            let (hop, rate) = input.last_hop();
+            let hit = p.update(hop, rate, freq.is_some(), now);
+            let armed = grid.as_ref().is_none_or(|g| g.armed(now));
+            if let Some((key, mapping)) = hit.filter(|_| armed).and_then(|h| note_map.get_key_value(h.name())) {
+                if dispatch(key, mapping, now, grid.as_ref(), &mut pending, &mut sender, &mut status) == Dispatch::Fired {
+                    feedback.trigger();
+                    fired(key, mapping, &mut history, &mut sender, &mut status);
+                }
+            }
+        }
+        // Speech-like pitch tracks never count towards a trigger
+        let sustained = speech_gate.as_mut().is_none_or(|g| g.update(freq, now));
+        let talking = speech_classifier.as_mut().is_some_and(|c| {
+            let (window, rate) = input.raw_window();
+            c.update(window, rate, freq, now)
+        });
+        let sustained = sustained && !talking;
+
+        // A strummed chord or a mapped set of notes fires its mapping, and
+        // single-note mappings stay quiet for as long as it rings
+        let mut strumming = false;
+        let mut together = None;
+        if let Some(detector) = chord_detector.as_mut() {
+            let (hop, rate) = input.last_hop();
+            let chord = detector.update(hop, rate, input.hop_level() >= cfg.min_rms);
+            strumming = detector.hearing();
+            let found = chord.and_then(|c| note_map.iter().find(|(k, _)| chords::parse(k) == Some(c)));
+            if let (Some(c), None) = (chord, found) { status.event(&format!("Chord: {} (not mapped)", c.name())); }
+            together = found;
+        }
+        if let Some(detector) = poly_detector.as_mut() {
+            let (hop, rate) = input.last_hop();
+            let found = detector.update(hop, rate, input.hop_level() >= cfg.min_rms, note_map);
+            strumming |= detector.hearing();
+            together = together.or(found);
+        }
+        if let Some((key, mapping)) = together {
+            let armed = grid.as_ref().is_none_or(|g| g.armed(now));
+            let ready = last_trigger_time.is_none_or(|t| now.duration_since(t) >= Duration::from_millis(settings.retrigger_ms));
+            if armed && ready {
+                match dispatch(key, mapping, now, grid.as_ref(), &mut pending, &mut sender, &mut status) {
+                    Dispatch::Fired => {
+                        last_trigger_time = Some(now);
+                        feedback.trigger();
+                        fired(key, mapping, &mut history, &mut sender, &mut status);
+                    }
+                    Dispatch::Queued => last_trigger_time = Some(now),
+                    Dispatch::Failed => {}
+                }
+            }
+        }
+        let sustained = sustained && !strumming && !sliding;
+
+        // Fire deferred triggers whose beat has arrived
+        let mut i = 0;
+        while i < pending.len() {
+            if pending[i].0 <= now {
+                let (_, note_name, mapping) = pending.remove(i);
+                script::triggering(&note_name);
+                status.trigger(&note_name, &mapping.action, &action_name(&mapping.action));
+                match execute_action(&mut sender, &mapping.action) {
+                    Ok(()) => {
+                        feedback.trigger();
+                        fired(&note_name, mapping, &mut history, &mut sender, &mut status);
+                    }
+                    Err(e) => tracing::error!("Action failed: {e:#}"),
+                }
+            } else {
+                i += 1;
+            }
+        }
+        // Note mappings to fire this frame: the key, the note and the mapping.
+        // A note struck too few times for its "x2" mapping fires once no more
+        // strikes can follow.
+        let mut to_fire: Vec<strikes::Fire> = strike_counter.due(now).into_iter().collect();
+
+        if let Some(f0) = freq {
+            // Convert to nearest musical note and cents offset
+            let (note_name, cents_off) = freq_to_note(f0);
+            // The split zone picks the mappings before the note is looked up
+            let upper = pivot.map(|p| freq_to_midi(f0).0 >= p);
+            let (note_map, zone_mapping) = match upper {
+                Some(false) => (lower_map.unwrap_or(note_map), split.lower.as_ref()),
+                Some(true) => (upper_map.unwrap_or(note_map), split.upper.as_ref()),
+                None => (note_map, None),
+            };
+            let zone_key = format!("{note_name} ({} zone)", if upper == Some(true) { "upper" } else { "lower" });
+            let cents = cents_off.abs();
+            let tolerance = note_setting(note_map, &note_name, |m| m.tolerance_cents).unwrap_or(settings.tolerance_cents);
+            let in_tune = cents <= tolerance;
+            // A won vote is already the stability requirement
+            let hold_frames = if cfg.voting.enabled {
+                1
+            } else {
+                note_setting(note_map, &note_name, |m| m.note_hold_frames).unwrap_or(settings.note_hold_frames)
+            };
+            let retrigger_ms = note_setting(note_map, &note_name, |m| m.retrigger_ms).unwrap_or(settings.retrigger_ms);
+
+            status.pitch(f0, &note_name, cents_off, input.confidence(), now);
+            script::heard(script::Heard {
+                note: note_name.clone(),
+                hz: f0,
+                cents: cents_off,
+                level: input.hop_level(),
+                confidence: input.confidence(),
+            });
+            feedback.pitch(freq_to_midi(f0).0, cents_off);
+            tracing::debug!(hz = f0, note = %note_name, cents = cents_off, tolerance, in_tune, sustained, "pitch");
+
+            // Credit a recent attack to the tap note as soon as it is heard in tune
+            let is_tap = |m: &Mapping| matches!(m.action, Action::TapTempo);
+            let tap_note = in_tune && note_map.get(&note_name).is_some_and(is_tap);
+            let tap_at = last_attack.filter(|t| tap_note && now.duration_since(*t) <= TAP_CREDIT);
+            if let (Some((_, tapper)), Some(at)) = (taps.as_mut(), tap_at) {
+                last_attack = None;
+                if let Some(bpm) = tapper.tap(at) {
+                    status.event(&format!("Tap tempo: {bpm:.0} BPM"));
+                    if let Some(g) = grid.as_mut() {
+                        g.set_bpm(bpm, now);
+                        if let Some(out) = &_click { out.set_click_bpm(bpm); }
+                    }
+                }
+            }
+
+            if in_tune && sustained {
+                if Some(note_name.clone()) == last_note {
+                    stable_count += 1;
+                } else {
+                    // A note played legato starts its own level peak
+                    if last_note.is_some() { note_peak = input.hop_level(); }
+                    last_note = Some(note_name.clone());
+                    stable_count = 1;
+                }
+
+                // Each newly recognized note is a strike, and advances the sequences
+                if stable_count == hold_frames.max(1) {
+                    to_fire.extend(strike_counter.strike(&note_name, now));
+                    // The second note of a two-note key played one note at a time
+                    let pair = interval_tracker.note(freq_to_midi(f0).0, now).and_then(|notes| intervals::find(note_map, notes));
+                    if let Some((key, mapping)) = pair { to_fire.push((key.clone(), note_name.clone(), mapping)); }
+                }
+                if stable_count == hold_frames.max(1) && grid.as_ref().is_none_or(|g| g.armed(now)) {
+                    for i in sequence_tracker.note(freq_to_midi(f0).0, now) {
+                        let sequence = &cfg.sequences[i];
+                        let key = sequence.label();
+                        match dispatch(&key, &sequence.mapping, now, grid.as_ref(), &mut pending, &mut sender, &mut status) {
+                            Dispatch::Fired => {
+                                last_trigger_time = Some(now);
+                                feedback.trigger();
+                                fired(&key, &sequence.mapping, &mut history, &mut sender, &mut status);
+                            }
+                            Dispatch::Queued => last_trigger_time = Some(now),
+                            Dispatch::Failed => {}
+                        }
+                    }
+                }
+
+                // Does the note have "E3@5" / "A3:muted" / "A4:forte" style variants?
+                let has_variant = |sep: char| {
+                    note_map.keys().any(|k| k.contains(sep) && key_note(k) == note_name)
+                };
+                let qualifiers = || {
+                    note_map
+                        .keys()
+                        .filter(|k| key_note(k) == note_name)
+                        .filter_map(|k| k.split_once(':').map(|(_, q)| key_note(q)))
+                };
+                let attack = articulation.as_ref().filter(|_| qualifiers().any(|q| !dynamics::is_name(q)));
+                let wants_vibrato = note_map.keys().any(|k| key_note(k) == note_name && k.ends_with(vibrato::MODIFIER));
+                let vibrato = vibrato_tracker.as_ref().filter(|_| wants_vibrato).map(|v| v.judged(now));
+                // Hold the trigger until the attack and vibrato have been judged
+                let judging = attack.is_some_and(|a| a.pending()) || vibrato == Some(None);
+                let armed = grid.as_ref().is_none_or(|g| g.armed(now));
+                let rested = fresh_attack
+                    || last_trigger_time.is_none_or(|t| now.duration_since(t) >= Duration::from_millis(retrigger_ms));
+                if stable_count >= hold_frames && !(armed && !judging && rested) {
+                    tracing::debug!(note = %note_name, off_beat = !armed, judging, within_retrigger = !rested, "held back");
+                }
+                if armed && !judging && stable_count >= hold_frames && rested {
+                    fresh_attack = false;
+                    // A hexaphonic pipeline knows its string; otherwise it's estimated
+                    let on_string = cfg.string.or_else(|| string_estimator.as_ref().filter(|_| has_variant('@')).and_then(|est| {
+                        let (window, rate) = input.raw_window();
+                        let midi = freq_to_midi(f0).0;
+                        est.estimate(midi, strings::measure(window, rate, f0))
+                    }));
+                    let played = attack.and_then(|a| a.current()).map(|a| a.name());
+                    let dynamic = qualifiers()
+                        .any(dynamics::is_name)
+                        .then(|| dynamics::classify(&cfg.dynamics, note_peak).name());
+                    // The most specific mapping wins: "E3@5:muted", "E3:muted", "E3@5:forte",
+                    // "E3:forte", "E3@5", "E3"
+                    let qualifiers: Vec<&str> = [played, dynamic].into_iter().flatten().collect();
+                    let found = mapping_keys(&note_name, on_string, &qualifiers, vibrato == Some(Some(true)))
+                        .into_iter()
+                        .find_map(|k| note_map.get_key_value(&k))
+                        .or_else(|| note_keys::lookup(note_map, freq_to_midi(f0).0))
+                        .or_else(|| zone_mapping.map(|m| (&zone_key, m)))
+                        .filter(|(_, m)| !is_tap(m));
+                    // A mapping waiting for its note to end doesn't fire again meanwhile
+                    let already_held = held.as_ref().is_some_and(|h| found.is_some_and(|(k, _)| *k == h.key));
+                    match found {
+                        None => tracing::debug!(note = %note_name, ?qualifiers, string = on_string, "no mapping"),
+                        Some((key, _)) if already_held => tracing::debug!(note = %note_name, key, "already held"),
+                        Some(_) => {}
+                    }
+                    let most = strikes::most(note_map, &note_name);
+                    if most > 1 {
+                        // The note has "x2" mappings: fire at the last strike, or wait for more
+                        if let Some(count) = strike_counter.undecided(&note_name) {
+                            let pick = match count {
+                                1 => found,
+                                n => note_map.get_key_value(&strikes::key(&note_name, n)),
+                            };
+                            let pick = pick.map(|(k, m)| (k.clone(), m));
+                            to_fire.extend(strike_counter.decide(pick, count >= most));
+                        }
+                    } else if let Some((key, mapping)) = found.filter(|_| !already_held) {
+                        to_fire.push((key.clone(), note_name.clone(), mapping));
+                    }
+                }
+                off_note = None;
+            } else {
+                // Detected note but not within tolerance (or speech-like); reset stability
+                stable_count = 0;
+                // A mapped note held out of tune gets a "rejected" cue, once
+                let mapped = note_map.keys().any(|k| key_note(k) == note_name)
+                    || note_keys::lookup(note_map, freq_to_midi(f0).0).is_some();
+                if !in_tune && (zone_mapping.is_some() || mapped) {
+                    if off_note.as_ref() == Some(&note_name) {
+                        off_count += 1;
+                    } else {
+                        off_note = Some(note_name.clone());
+                        off_count = 1;
+                    }
+                    if off_count == hold_frames.max(1) { feedback.rejected(); }
+                }
+            }
+        } else {
+            // No confident pitch detected; reset stability
+            tracing::trace!(level = input.hop_level(), snr_db = input.low_snr(), "no pitch");
+            match input.low_snr() {
+                Some(snr) => status.low_snr(snr),
+                None => status.silence(),
+            }
+            feedback.silence();
+            off_note = None;
+            stable_count = 0;
+            last_note = None;
+        }
+
+        for (key, note, mapping) in to_fire {
+            if mapping.holds() {
+                if let Some(h) = held.take() { h.release(&mut sender, &mut status); }
+                held = Held::press(&key, &note, mapping, &mut sender, &mut status);
+                if held.is_some() {
+                    last_trigger_time = Some(now);
+                    feedback.trigger();
+                }
+            } else {
+                let outcome = dispatch(&key, mapping, now, grid.as_ref(), &mut pending, &mut sender, &mut status);
+                match outcome {
+                    Dispatch::Fired => {
+                        last_trigger_time = Some(now);
+                        feedback.trigger();
+                        fired(&key, mapping, &mut history, &mut sender, &mut status);
+                    }
+                    Dispatch::Queued => last_trigger_time = Some(now),
+                    Dispatch::Failed => {}
+                }
+                // A quantized mapping fires once, on its grid point
+                let repeats = mapping.repeats() && outcome == Dispatch::Fired;
+                if repeats || (mapping.on_release.is_some() && outcome != Dispatch::Failed) {
+                    if let Some(h) = held.take() { h.release(&mut sender, &mut status); }
+                    let mut h = Held::new(&key, &note, mapping);
+                    if repeats { h.next_repeat = Some(now + mapping.repeat_interval()); }
+                    held = Some(h);
+                }
+            }
+        }
+    }
+}
+
+// A mapping waiting for its note to end: hold keys that are down, an action
+// that repeats and/or an on_release action to run
+struct Held<'a> {
+    note: String,
+    key: String,
+    mapping: &'a Mapping,
+    // Frames since the note was last heard
+    gone: usize,
+    // When a repeat_while_held mapping fires next
+    next_repeat: Option<Instant>,
+}
+
+impl<'a> Held<'a> {
+    fn new(key: &str, note: &str, mapping: &'a Mapping) -> Self {
+        Self { note: note.to_string(), key: key.to_string(), mapping, gone: 0, next_repeat: None }
+    }
+
+    // Fire the action again once the repeat interval is up
+    fn repeat(&mut self, now: Instant, sender: &mut KeySender) {
+        if self.next_repeat.is_none_or(|due| now < due) { return; }
+        self.next_repeat = Some(now + self.mapping.repeat_interval());
+        tracing::debug!(key = %self.key, "repeat");
+        script::triggering(&self.key);
+        if let Err(e) = execute_action(sender, &self.mapping.action) { tracing::error!("Action failed: {e:#}"); }
+    }
+
+    fn press(key: &str, note: &str, mapping: &'a Mapping, sender: &mut KeySender, status: &mut status::StatusOutput) -> Option<Self> {
+        if !mapping.holds() { return None; }
+        let decided = Instant::now();
+        status.trigger(key, &mapping.action, &format!("{} (hold)", action_name(&mapping.action)));
+        if let Err(e) = hold_action(sender, &mapping.action, true) {
+            tracing::error!("Action failed: {e:#}");
+            return None;
+        }
+        latency::fired(decided);
+        Some(Self::new(key, note, mapping))
+    }
+
+    // The note ended: let go of hold keys, then run on_release
+    fn release(self, sender: &mut KeySender, status: &mut status::StatusOutput) {
+        let key = notation::spell(&self.key);
+        if self.mapping.holds() {
+            status.event(&format!("Released: {key} => {:?}", action_name(&self.mapping.action)));
+            if let Err(e) = hold_action(sender, &self.mapping.action, false) { tracing::error!("Release failed: {e:#}"); }
+        }
+        if let Some(action) = &self.mapping.on_release {
+            status.event(&format!("Released: {key} => {:?}", action_name(action)));
+            script::triggering(&self.key);
+            if let Err(e) = execute_action(sender, action) { tracing::error!("Release failed: {e:#}"); }
+        }
+    }
+}
+
+// Press or let go of what a hold mapping holds: keys or a gamepad button
+fn hold_action(sender: &mut KeySender, action: &Action, down: bool) -> Result<()> {
+    match action {
+        Action::Keys { sequence } => press_keys(sender, sequence, down),
+        Action::Gamepad(g) => {
+            // Releases still go through, so nothing stays held when actions go off
+            if down && !hotkeys::actions_on() { return Ok(()); }
+            if dry_run() {
+                would(&format!("{} {}", if down { "press" } else { "release" }, action_name(action)));
+                return Ok(());
+            }
+            gamepad::hold(g, down)
+        }
+        _ => Ok(()),
+    }
+}
+
+// Outcome of dispatching a mapping
+#[derive(PartialEq, Eq)]
+enum Dispatch {
+    Fired,
+    Queued,
+    Failed,
+}
+
+// Fire a mapping now, or queue it until its grid point when it is quantized
+fn dispatch<'a>(
+    key: &str,
+    mapping: &'a Mapping,
+    now: Instant,
+    grid: Option<&metronome::BeatGrid>,
+    pending: &mut Vec<(Instant, String, &'a Mapping)>,
+    sender: &mut KeySender,
+    status: &mut status::StatusOutput,
+) -> Dispatch {
+    match grid {
+        Some(g) if mapping.quantize != metronome::Quantize::Off => {
+            let due = g.next(mapping.quantize, now);
+            status.event(&format!(
+                "Queued: {key} => {:?} in {} ms",
+                action_name(&mapping.action),
+                (due - now).as_millis()
+            ));
+            pending.push((due, key.to_string(), mapping));
+            Dispatch::Queued
+        }
+        _ => {
+            let decided = Instant::now();
+            script::triggering(key);
+            status.trigger(key, &mapping.action, &action_name(&mapping.action));
+            if let Err(e) = execute_action(sender, &mapping.action) {
+                tracing::error!("Action failed: {e:#}");
+                Dispatch::Failed
+            } else {
+                latency::fired(decided);
+                Dispatch::Fired
+            }
+        }
+    }
+}
+
+// After a mapping fired: carry out an "undo" mapping, or remember it for one
+fn fired<'a>(
+    key: &str,
+    mapping: &'a Mapping,
+    history: &mut Vec<(String, &'a Mapping)>,
+    sender: &mut KeySender,
+    status: &mut status::StatusOutput,
+) {
+    match &mapping.action {
+        Action::Undo => undo_last(history, sender, status),
+        // Taken up by the trigger loop on the next frame
+        Action::Profile { name } => profiles::request(profiles::Switch::parse(name)),
+        Action::TapTempo => {}
+        _ => {
+            if history.len() == UNDO_DEPTH { history.remove(0); }
+            history.push((key.to_string(), mapping));
+        }
+    }
+}
+
+// Send the inverse of the most recent trigger; one without an `undo` setting
+// is reported and dropped rather than skipped over
+fn undo_last(history: &mut Vec<(String, &Mapping)>, sender: &mut KeySender, status: &mut status::StatusOutput) {
+    let Some((key, mapping)) = history.pop() else {
+        status.event("Undo: nothing to undo");
+        return;
+    };
+    let inverse = match mapping.undo.as_deref() {
+        None => {
+            status.event(&format!("Undo: {} has no undo set", notation::spell(&key)));
+            return;
+        }
+        Some(u) if u.eq_ignore_ascii_case("repeat") => mapping.action.clone(),
+        Some(sequence) => Action::Keys { sequence: sequence.to_string() },
+    };
+    status.event(&format!("Undo: {} => {:?}", notation::spell(&key), action_name(&inverse)));
+    if let Err(e) = execute_action(sender, &inverse) {
+        tracing::error!("Undo failed: {e:#}");
+    }
+}
+
+// Note part of a note_map key: "E3@5:muted+vibrato" -> "E3"
+fn key_note(key: &str) -> &str {
+    key.split(['@', ':', '+']).next().unwrap_or(key)
+}
+
+// A per-note setting from the note's mapping: the plain "E3" entry, else any
+// "E3@5" / "E3:muted" variant that sets it, else a range or pitch-class entry
+fn note_setting<T>(map: &HashMap<String, Mapping>, note: &str, get: impl Fn(&Mapping) -> Option<T>) -> Option<T> {
+    map.get(note)
+        .and_then(&get)
+        .or_else(|| map.iter().filter(|(k, _)| key_note(k) == note).find_map(|(_, m)| get(m)))
+        .or_else(|| note_to_midi(note).and_then(|midi| note_keys::lookup(map, midi)).and_then(|(_, m)| get(m)))
+}
+
+// Keys to look up for a note, most specific first; `qualifiers` are the
+// articulation and/or dynamic heard, in order of preference. With vibrato the
+// "+vibrato" variants of all of them come first.
+fn mapping_keys(note: &str, string: Option<usize>, qualifiers: &[&str], vibrato: bool) -> Vec<String> {
+    let mut keys = Vec::new();
+    for q in qualifiers {
+        if let Some(s) = string { keys.push(format!("{}:{q}", strings::string_key(note, s))); }
+        keys.push(format!("{note}:{q}"));
+    }
+    if let Some(s) = string { keys.push(strings::string_key(note, s)); }
+    keys.push(note.to_string());
+    if vibrato {
+        let with: Vec<String> = keys.iter().map(|k| format!("{k}{}", vibrato::MODIFIER)).collect();
+        keys.splice(0..0, with);
+    }
+    keys
+}
+
+fn run_morse(cfg: &Config, input: &mut AudioInput) -> Result<()> {
+    let mut sender = new_sender();
+    let mut decoder = morse::MorseDecoder::new(&cfg.morse);
+    let mut last_note: Option<String> = None;
+    let mut stable_count: usize = 0;
+
+    // By MIDI number, so "Bb4" keys on what is heard as A#4
+    let key_note = cfg.morse.note.as_deref().and_then(note_to_midi);
+    match &cfg.morse.note {
+        Some(n) => println!("Morse mode: key note {n}, dot < {} ms", cfg.morse.dot_max_ms),
+        None => println!("Morse mode: any in-tune note, dot < {} ms", cfg.morse.dot_max_ms),
+    }
+
+    loop {
+        let freq = input.next_pitch(cfg)?;
+        let now = Instant::now();
+
+        // A frame counts as "key down" when an in-tune note (the configured
+        // key note, if any) has been stable for note_hold_frames.
+        let note = freq.and_then(|f0| {
+            let (name, cents_off) = freq_to_note(f0);
+            (cents_off.abs() <= cfg.tolerance_cents).then_some(name)
+        });
+        let note = note.filter(|n| cfg.morse.note.is_none() || note_to_midi(n) == key_note);
+        if note.is_some() && note == last_note {
+            stable_count += 1;
+        } else {
+            stable_count = usize::from(note.is_some());
+            last_note = note;
+        }
+        let key_down = stable_count >= cfg.note_hold_frames;
+
+        if let Some(entry) = decoder.update(key_down, now) {
+            let entered = match &entry {
+                morse::Entry::Text(text) => {
+                    println!("\rMorse {:<8} => {:?}", decoder.last_code(), text);
+                    type_text(&mut sender, text)
+                }
+                morse::Entry::Keys { keys } => {
+                    println!("\rMorse {:<8} => {keys}", decoder.last_code());
+                    execute_action(&mut sender, &Action::Keys { sequence: keys.clone() })
+                }
+            };
+            if let Err(e) = entered {
+                tracing::error!("Typing failed: {e:#}");
+            }
+        } else {
+            print!("\r{}{:<12}", if key_down { '#' } else { ' ' }, decoder.pending());
+            std::io::Write::flush(&mut std::io::stdout()).ok();
+        }
+    }
+}
+
+// Monophonic audio-to-MIDI: a note on for every settled note (velocity from
+// the level), pitch bend for the cents in between, note off on silence
+fn run_midi_thru(cfg: &Config, input: &mut AudioInput) -> Result<()> {
+    let mc = &cfg.midi;
+    midi::open(mc)?;
+    println!(
+        "MIDI mode: channel {}, pitch bend {}",
+        mc.channel,
+        if mc.pitch_bend { format!("±{} semitones", mc.bend_range) } else { "off".to_string() }
+    );
+    let mut status = status::StatusOutput::new(&cfg.accessible).verbose(cfg.verbose);
+    // Sounding note, the note about to replace it and its frame count, and
+    // the last bend sent
+    let mut sounding: Option<u8> = None;
+    let mut candidate: Option<(u8, usize)> = None;
+    let mut silent_frames = 0;
+    let mut bend = 8192u16;
+    // Notes stay put until the pitch is this far away
+    let reach = if mc.pitch_bend { mc.note_change_cents.max(50.0) } else { 50.0 };
+    let result = (|| -> Result<()> {
+        loop {
+            let freq = input.next_pitch(cfg)?;
+            let now = Instant::now();
+            let Some(f0) = freq else {
+                status.silence();
+                candidate = None;
+                silent_frames += 1;
+                // Release after as many silent frames as a note needs to start
+                if silent_frames >= cfg.note_hold_frames {
+                    if let Some(n) = sounding.take() { midi::send([0x80, n, 0], None)?; }
+                }
+                continue;
+            };
+            silent_frames = 0;
+            let (note_name, cents_off) = freq_to_note(f0);
+            status.pitch(f0, &note_name, cents_off, input.confidence(), now);
+            let exact = exact_midi(f0);
+            let nearest = exact.round().clamp(0.0, 127.0) as u8;
+
+            let held = sounding.filter(|&n| ((exact - n as f32) * 100.0).abs() <= reach);
+            let target = match held {
+                Some(n) => n,
+                None => {
+                    // A new note must settle before it replaces the old one
+                    let count = match candidate {
+                        Some((c, k)) if c == nearest => k + 1,
+                        _ => 1,
+                    };
+                    candidate = Some((nearest, count));
+                    if count < cfg.note_hold_frames.max(1) { continue; }
+                    candidate = None;
+                    if let Some(n) = sounding.take() { midi::send([0x80, n, 0], None)?; }
+                    // Bend first so the note starts at the right pitch
+                    if mc.pitch_bend {
+                        bend = midi::bend_value((exact - nearest as f32) * 100.0, mc.bend_range);
+                        midi::pitch_bend(bend)?;
+                    }
+                    midi::send([0x90, nearest, midi::velocity(mc, input.hop_level())], None)?;
+                    sounding = Some(nearest);
+                    nearest
+                }
+            };
+            if mc.pitch_bend {
+                let value = midi::bend_value((exact - target as f32) * 100.0, mc.bend_range);
+                if value != bend {
+                    bend = value;
+                    midi::pitch_bend(bend)?;
+                }
+            }
+        }
+    })();
+    // Don't leave a note hanging in the synth
+    if let Some(n) = sounding { midi::send([0x80, n, 0], None).ok(); }
+    result
+}
+
+// `tuner`: the detected note and how far off it is, nothing else. Accessible
+// and verbose output stay line by line; otherwise the large display.
+fn run_tuner(cfg: &Config, input: &mut AudioInput) -> Result<()> {
+    let mut status = status::StatusOutput::new(&cfg.accessible).verbose(cfg.verbose);
+    let mut display = (!status.is_accessible() && !cfg.verbose).then(|| tuner::TunerDisplay::new(&cfg.tuner));
+    println!("Tuner: no actions will fire\n");
+    loop {
+        let reading = input.next_pitch(cfg)?.map(|f0| (f0, freq_to_note(f0)));
+        match (display.as_mut(), reading) {
+            (Some(d), Some((f0, (note_name, cents_off)))) => d.show(Some((f0, &notation::spell(&note_name), cents_off))),
+            (Some(d), None) => d.show(None),
+            (None, Some((f0, (note_name, cents_off)))) => status.pitch(f0, &note_name, cents_off, input.confidence(), Instant::now()),
+            (None, None) => status.silence(),
+        }
+    }
+}
+
+// How often a practice session's rows are rewritten in the CSV
+const PRACTICE_SAVE_EVERY: Duration = Duration::from_secs(5);
+
+fn run_practice(cfg: &Config, input: &mut AudioInput) -> Result<()> {
+    let pc = &cfg.practice;
+    let previous = pc.export.as_deref().map(|p| practice::load_previous(p.as_ref())).unwrap_or_default();
+    let export = pc.export.as_deref().map(|p| practice::CsvExport::new(p.as_ref()));
+    let mut session = practice::PracticeSession::new();
+    let mut last_note: Option<String> = None;
+    let mut stable_count: usize = 0;
+
+    let started = Instant::now();
+    let session_len = (pc.session_minutes > 0).then(|| Duration::from_secs(pc.session_minutes * 60));
+    let report_every = (pc.report_secs > 0).then(|| Duration::from_secs(pc.report_secs));
+    let mut last_report = started;
+    let mut last_save = started;
+    let mut status = status::StatusOutput::new(&cfg.accessible).verbose(cfg.verbose);
+
+    match session_len {
+        Some(d) => println!("Practice mode: {} min session, no actions will fire", d.as_secs() / 60),
+        None => println!("Practice mode: no actions will fire"),
+    }
+
+    loop {
+        let freq = input.next_pitch(cfg)?;
+        let now = Instant::now();
+
+        if let Some(f0) = freq {
+            let (note_name, cents_off) = freq_to_note(f0);
+            if Some(&note_name) == last_note.as_ref() {
+                stable_count += 1;
+            } else {
+                last_note = Some(note_name.clone());
+                stable_count = 1;
+            }
+            // Skip attack/transition frames; only score notes that have settled
+            if stable_count >= cfg.note_hold_frames {
+                session.record(&note_name, cents_off, cfg.tolerance_cents);
+            }
+            status.pitch(f0, &note_name, cents_off, input.confidence(), now);
+        } else {
+            status.silence();
+            stable_count = 0;
+            last_note = None;
+        }
+
+        if report_every.is_some_and(|d| now.duration_since(last_report) >= d) && !session.is_empty() {
+            last_report = now;
+            println!("\n{}", session.report(&previous));
+        }
+
+        // Keep the file current, in case the session never ends normally
+        if let Some(export) = export.as_ref().filter(|_| now.duration_since(last_save) >= PRACTICE_SAVE_EVERY) {
+            last_save = now;
+            if !session.is_empty() {
+                if let Err(e) = export.save(&session) { tracing::warn!("practice log: {e:#}"); }
+            }
+        }
+
+        if session_len.is_some_and(|d| now.duration_since(started) >= d) {
+            break;
+        }
+    }
+
+    println!("\nSession summary:\n{}", session.report(&previous));
+    if let Some(export) = export.filter(|_| !session.is_empty()) {
+        export.save(&session)?;
+        println!("Appended session to {}", export.path().display());
+    }
+    Ok(())
+}
+
+fn run_ear_training(cfg: &Config, input: &mut AudioInput) -> Result<()> {
+    let ec = &cfg.ear_training;
+    let low = note_to_midi(&ec.low).ok_or_else(|| anyhow!("Unknown note in ear_training.low: {}", ec.low))?;
+    let high = note_to_midi(&ec.high).ok_or_else(|| anyhow!("Unknown note in ear_training.high: {}", ec.high))?;
+    let tone = tone::ToneOutput::open()?;
+    let mut rng = Rng::from_time();
+    let mut score = ear::Score::default();
+    let tone_len = Duration::from_millis(ec.tone_ms);
+    let answer_len = Duration::from_secs(ec.answer_secs);
+
+    println!("Ear training: {:?} exercise, range {}-{}", ec.exercise, ec.low, ec.high);
+    let mut round = 0;
+    while ec.rounds == 0 || round < ec.rounds {
+        round += 1;
+        let q = ear::make_question(ec, low, high, |n| rng.below(n), notation::spell_midi)
+            .ok_or_else(|| anyhow!("No questions fit in range {}-{}", ec.low, ec.high))?;
+
+        println!("\nRound {round}: {}", q.prompt);
+        tone.play(midi_to_freq(q.reference), tone_len, ec.volume);
+        // Don't score the reference tone leaking back into the input
+        std::thread::sleep(tone_len + Duration::from_millis(200));
+        input.discard();
+
+        // Wait for the first settled note within the answer window
+        let deadline = Instant::now() + answer_len;
+        let mut last_note: Option<i32> = None;
+        let mut stable_count: usize = 0;
+        let mut answer: Option<(i32, f32)> = None;
+        while Instant::now() < deadline {
+            let Some(f0) = input.next_pitch(cfg)? else {
+                stable_count = 0;
+                last_note = None;
+                continue;
+            };
+            let (midi, cents) = freq_to_midi(f0);
+            if Some(midi) == last_note {
+                stable_count += 1;
+            } else {
+                last_note = Some(midi);
+                stable_count = 1;
+            }
+            if stable_count >= cfg.note_hold_frames {
+                answer = Some((midi, cents));
+                break;
+            }
+        }
+
+        let target = notation::spell_midi(q.target);
+        let correct = match answer {
+            Some((midi, cents)) if midi == q.target && cents.abs() <= cfg.tolerance_cents => {
+                println!("Correct: {target} ({cents:+.0} cents)");
+                true
+            }
+            Some((midi, cents)) if midi == q.target => {
+                println!("Right note but out of tune: {target} ({cents:+.0} cents)");
+                false
+            }
+            Some((midi, _)) => {
+                println!("Heard {}, expected {target}", notation::spell_midi(midi));
+                false
+            }
+            None => {
+                println!("Time's up, expected {target}");
+                false
+            }
+        };
+        score.record(correct);
+        println!("{}", score.summary());
+        std::thread::sleep(Duration::from_millis(800));
+    }
+
+    println!("\nFinal score: {}", score.summary());
+    Ok(())
+}
+
+fn run_trainer(cfg: &Config, input: &mut AudioInput) -> Result<()> {
+    let tc = &cfg.trainer;
+    let mut notes: Vec<&String> = cfg.note_map.keys().collect();
+    notes.sort();
+    if notes.is_empty() {
+        return Err(anyhow!("Trainer mode needs at least one note_map entry"));
+    }
+    let describe = |note: &str| cfg.note_map.get(note).map(|m| action_name(&m.action)).unwrap_or_default();
+    let mut rng = Rng::from_time();
+    let mut stats = trainer::TrainerStats::default();
+    let timeout = Duration::from_secs(tc.timeout_secs);
+
+    println!("Trainer: {} mappings, {:?} prompts", notes.len(), tc.prompt);
+    let mut previous: Option<&String> = None;
+    let mut round = 0;
+    while tc.rounds == 0 || round < tc.rounds {
+        round += 1;
+        // Avoid asking the same mapping twice in a row when there is a choice
+        let mut note = notes[rng.below(notes.len())];
+        while notes.len() > 1 && Some(note) == previous {
+            note = notes[rng.below(notes.len())];
+        }
+        previous = Some(note);
+        match tc.prompt {
+            trainer::Prompt::Note => println!("\n[{round}] Play {}", notation::spell(note)),
+            trainer::Prompt::Action => println!("\n[{round}] Play the note for {}", describe(note)),
+        }
+
+        input.discard();
+        let asked = Instant::now();
+        let mut last_note: Option<String> = None;
+        let mut stable_count: usize = 0;
+        let mut answer: Option<String> = None;
+        while asked.elapsed() < timeout {
+            let Some(f0) = input.next_pitch(cfg)? else {
+                stable_count = 0;
+                last_note = None;
+                continue;
+            };
+            let (name, cents) = freq_to_note(f0);
+            if cents.abs() > cfg.tolerance_cents {
+                stable_count = 0;
+                continue;
+            }
+            if Some(&name) == last_note.as_ref() {
+                stable_count += 1;
+            } else {
+                last_note = Some(name.clone());
+                stable_count = 1;
+            }
+            if stable_count >= cfg.note_hold_frames {
+                answer = Some(name);
+                break;
+            }
+        }
+
+        let reaction = asked.elapsed();
+        match answer {
+            Some(n) if &n == note || note_keys::parse(note).zip(note_to_midi(&n)).is_some_and(|(p, m)| p.matches(m)) => {
+                println!("Hit {} in {} ms", notation::spell(note), reaction.as_millis());
+                stats.record(note, Some(reaction));
+            }
+            Some(n) => {
+                println!("Played {}; {} is {}", notation::spell(&n), describe(note), notation::spell(note));
+                stats.record(note, None);
+            }
+            None => {
+                println!("Too slow; {} is {}", describe(note), notation::spell(note));
+                stats.record(note, None);
+            }
+        }
+        // Give the player a moment to release before the next prompt
+        std::thread::sleep(Duration::from_millis(600));
+    }
+
+    println!("\nResults (weakest first):\n{}", stats.report(describe));
+    Ok(())
+}
+
+fn run_scanning(cfg: &Config, input: &mut AudioInput) -> Result<()> {
+    let sc = &cfg.scanning;
+    let items = if sc.items.is_empty() {
+        let mut notes: Vec<_> = cfg.note_map.iter().collect();
+        notes.sort_by(|a, b| a.0.cmp(b.0));
+        notes
+            .into_iter()
+            .map(|(_, m)| scanning::ScanItem { label: action_name(&m.action), action: m.action.clone() })
+            .collect()
+    } else {
+        sc.items.clone()
+    };
+    let mut scanner = scanning::Scanner::new(items, sc.interval_ms, Instant::now());
+    if scanner.is_empty() {
+        return Err(anyhow!("Scanning mode needs [[scanning.items]] or note_map entries"));
+    }
+    let mut speaker = speech::Speaker::new(&sc.speak_command);
+    let mut status = status::StatusOutput::new(&cfg.accessible).verbose(cfg.verbose);
+    let mut sender = new_sender();
+    let mut last_note: Option<String> = None;
+    let mut stable_count: usize = 0;
+    // A switch press counts once; the note must stop before it can press again
+    let mut latched = false;
+
+    println!("Scanning mode: play any note to select the highlighted entry");
+    if let Some(s) = speaker.as_mut() { s.say(&scanner.current().label); }
+
+    loop {
+        let freq = input.next_pitch(cfg)?;
+        let now = Instant::now();
+
+        let note = freq.and_then(|f0| {
+            let (name, cents_off) = freq_to_note(f0);
+            (cents_off.abs() <= cfg.tolerance_cents).then_some(name)
+        });
+        match &note {
+            Some(n) if Some(n) == last_note.as_ref() => stable_count += 1,
+            Some(_) => stable_count = 1,
+            None => {
+                stable_count = 0;
+                latched = false;
+            }
+        }
+        last_note = note;
+
+        let mut moved = false;
+        if !latched && stable_count >= cfg.note_hold_frames {
+            latched = true;
+            let pressed = last_note.as_deref().unwrap_or_default();
+            if sc.advance_note.as_deref().and_then(note_to_midi).is_some_and(|n| note_to_midi(pressed) == Some(n)) {
+                scanner.advance(now);
+                moved = true;
+            } else {
+                let item = scanner.current();
+                status.event(&format!("Select: {} => {:?}", item.label, action_name(&item.action)));
+                if let Err(e) = execute_action(&mut sender, &item.action) {
+                    tracing::error!("Action failed: {e:#}");
+                }
+                scanner.hold(now);
+            }
+        } else if !latched {
+            moved = scanner.tick(now);
+        }
+
+        if moved {
+            if let Some(s) = speaker.as_mut() { s.say(&scanner.current().label); }
+            if status.is_accessible() { status.event(&scanner.current().label); }
+        }
+        if !status.is_accessible() {
+            print!("\r{}  ", scanner.render());
+            std::io::Write::flush(&mut std::io::stdout()).ok();
+        }
+    }
+}
+
+fn string_estimator(cfg: &Config) -> Result<strings::StringEstimator> {
+    let open = cfg
+        .strings
+        .tuning
+        .iter()
+        .map(|n| note_to_midi(n).ok_or_else(|| anyhow!("Unknown note in strings.tuning: {n}")))
+        .collect::<Result<Vec<_>>>()?;
+    let calibration = strings::Calibration::load(std::path::Path::new(&cfg.strings.calibration_file));
+    if calibration.is_none() {
+        println!("No string calibration found; assuming lowest-fret positions");
+    }
+    Ok(strings::StringEstimator::new(open, cfg.strings.frets, calibration))
+}
+
+fn run_string_calibration(cfg: &Config, input: &mut AudioInput) -> Result<()> {
+    let sc = &cfg.strings;
+    let path = std::path::Path::new(&sc.calibration_file);
+    let mut calibration = strings::Calibration::default();
+    let per_string = Duration::from_secs(sc.calibration_secs);
+
+    println!("String calibration: play notes all along each string when prompted");
+    for (i, open_name) in sc.tuning.iter().enumerate() {
+        let string = i + 1;
+        let open = note_to_midi(open_name).ok_or_else(|| anyhow!("Unknown note in strings.tuning: {open_name}"))?;
+        println!(
+            "\n{} string (open {open_name}): play notes anywhere on this string for {} s. Press Enter to start.",
+            strings::ordinal(string),
+            per_string.as_secs()
+        );
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line)?;
+        input.discard();
+
+        let mut samples = Vec::new();
+        let mut last_note: Option<i32> = None;
+        let mut stable_count: usize = 0;
+        let started = Instant::now();
+        while started.elapsed() < per_string {
+            let Some(f0) = input.next_pitch(cfg)? else {
+                stable_count = 0;
+                last_note = None;
+                continue;
+            };
+            let (midi, _) = freq_to_midi(f0);
+            if Some(midi) == last_note {
+                stable_count += 1;
+            } else {
+                last_note = Some(midi);
+                stable_count = 1;
+            }
+            // Only settled notes that this string can actually produce
+            if stable_count < cfg.note_hold_frames || midi < open || midi > open + sc.frets {
+                continue;
+            }
+            let (window, rate) = input.raw_window();
+            if let Some(features) = strings::measure(window, rate, f0) {
+                samples.push(strings::Sample { midi, features });
+                print!("\r{} samples  ", samples.len());
+                std::io::Write::flush(&mut std::io::stdout()).ok();
+            }
+        }
+        println!("\r{} samples from the {} string", samples.len(), strings::ordinal(string));
+        calibration.strings.insert(string.to_string(), samples);
+    }
+
+    calibration.save(path)?;
+    println!("Saved string calibration to {}", path.display());
+    Ok(())
+}
+
+fn run_calibration(cfg: &Config, input: &mut AudioInput) -> Result<()> {
+    let cc = &cfg.calibrate;
+    let path = toml_config_path()?;
+    if !path.exists() {
+        return Err(anyhow!("calibrate writes its results to config.toml, which doesn't exist here"));
+    }
+    // Every distinct mapped note, low to high ("tap"/"slap" have no pitch)
+    let mut notes: Vec<(i32, String)> = cfg
+        .note_map
+        .keys()
+        .filter_map(|k| note_to_midi(key_note(k)).map(|m| (m, key_note(k).to_string())))
+        .collect();
+    notes.sort();
+    notes.dedup();
+    if notes.is_empty() {
+        return Err(anyhow!("calibrate needs note_map entries to measure"));
+    }
+    let read_line = || -> Result<String> {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line)?;
+        Ok(line)
+    };
+
+    println!("\nCalibration: keep the room quiet for {} s. Press Enter to start.", cc.noise_secs);
+    read_line()?;
+    let noise = measure_noise(cfg, input)?;
+
+    let mut results = Vec::new();
+    for (midi, name) in &notes {
+        println!(
+            "\nPlay {name} {} times, letting each note ring for a moment. Press Enter to start.",
+            cc.repeats
+        );
+        read_line()?;
+        input.discard();
+        let mut stats = calibrate::NoteStats::new(*midi);
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_secs(cc.note_secs) && stats.attacks() < cc.repeats {
+            let detected = input.next_detection(cfg, 0.0)?;
+            stats.record(input.hop_level(), detected.map(|(f0, clarity)| {
+                let (m, cents) = freq_to_midi(f0);
+                (m, cents, clarity)
+            }));
+            print!("\r{} of {} attacks  ", stats.attacks(), cc.repeats);
+            std::io::Write::flush(&mut std::io::stdout()).ok();
+        }
+        match stats.suggest(&noise) {
+            Some(s) => {
+                println!(
+                    "\r{name}: {} attacks, 95% within {:.0} cents -> tolerance_cents {:.0}, corr_threshold {:.2}, note_hold_frames {}",
+                    s.attacks, s.spread, s.tolerance_cents, s.corr_threshold, s.note_hold_frames
+                );
+                results.push((name.clone(), s));
+            }
+            None => println!("\r{name}: not heard clearly enough; left unchanged"),
+        }
+    }
+    if results.is_empty() {
+        println!("\nNothing measured; config.toml left unchanged");
+        return Ok(());
+    }
+
+    let min_rms = calibrate::suggest_min_rms(&noise, &results);
+    if let Some(level) = min_rms { println!("\nSuggested min_rms: {level:.4}"); }
+    println!("\nWrite these settings to {}? [y/N]", path.display());
+    if !read_line()?.trim().eq_ignore_ascii_case("y") {
+        println!("config.toml left unchanged");
+        return Ok(());
+    }
+    let updated = calibrate::write_back(&path, &results, min_rms)?;
+    println!("Updated {updated} note_map entries in {}", path.display());
+    Ok(())
+}
+
+// `learn`: the next steady note, then its keys, written to the config file
+fn run_learn(cfg: &Config, input: &mut AudioInput) -> Result<()> {
+    let path = toml_config_path()?;
+    if !path.exists() {
+        return Err(anyhow!("learn writes its mappings to {}, which doesn't exist", path.display()));
+    }
+    // A note must last note_hold_frames, and at least 300 ms
+    let hold = cfg.note_hold_frames.max((0.3 * input.sample_rate as f32 / input.hop_size as f32) as usize);
+    let mut status = status::StatusOutput::new(&cfg.accessible).verbose(cfg.verbose);
+    let mut learned: HashMap<String, String> = HashMap::new();
+    loop {
+        println!("\nPlay the note you want to map and let it ring (Ctrl+C to stop)");
+        input.discard();
+        let mut catcher = learn::NoteCatcher::new(hold);
+        let (midi, cents) = loop {
+            let f0 = input.next_pitch(cfg)?;
+            match f0 {
+                Some(f0) => {
+                    let (note_name, cents_off) = freq_to_note(f0);
+                    status.pitch(f0, &note_name, cents_off, input.confidence(), Instant::now());
+                }
+                None => status.silence(),
+            }
+            if let Some(found) = catcher.push(f0.map(freq_to_midi)) { break found; }
+        };
+        let key = midi_to_name(midi);
+        let shown = notation::spell(&key);
+        status.event(&format!("Heard {shown} ({cents:+.0} cents)"));
+        match (learned.get(&key), cfg.note_map.get(&key)) {
+            (Some(sequence), _) => println!("{shown} was just mapped to keys:{sequence}; new keys replace them"),
+            (None, Some(m)) => println!("{shown} is mapped to {}; new keys replace it", action_name(&m.action)),
+            (None, None) => {}
+        }
+        // None to quit, Some(None) to skip this note
+        let answer = loop {
+            print!("Keys for {shown} (e.g. Ctrl+S), Enter to press them instead, s to skip, q to quit: ");
+            std::io::Write::flush(&mut std::io::stdout())?;
+            let mut line = String::new();
+            if std::io::stdin().read_line(&mut line)? == 0 { break None; }
+            let keys = match line.trim() {
+                "q" | "Q" => break None,
+                "s" | "S" => break Some(None),
+                "" => {
+                    println!("Press the key combination now");
+                    match learn::record_keypress()? {
+                        Some(keys) => keys,
+                        None => {
+                            println!("That key can't be sent; type the combination instead");
+                            continue;
+                        }
+                    }
+                }
+                typed => typed.to_string(),
+            };
+            match keys::parse(&keys) {
+                Ok(_) => break Some(Some(keys)),
+                Err(e) => println!("  {e:#}"),
+            }
+        };
+        let Some(answer) = answer else { break };
+        let Some(sequence) = answer else { continue };
+        learn::save(&path, &key, &sequence)?;
+        println!("Mapped {shown} => keys:{sequence}");
+        learned.insert(key, sequence);
+    }
+    println!("Learned {} mapping(s) in {}", learned.len(), path.display());
+    Ok(())
+}
+
+// Record noise_secs of the quiet room
+fn measure_noise(cfg: &Config, input: &mut AudioInput) -> Result<calibrate::NoiseStats> {
+    input.discard();
+    let mut noise = calibrate::NoiseStats::default();
+    let started = Instant::now();
+    while started.elapsed() < Duration::from_secs(cfg.calibrate.noise_secs) {
+        let detected = input.next_detection(cfg, 0.0)?;
+        noise.record(input.hop_level(), detected.map(|(f0, clarity)| (freq_to_midi(f0).0, clarity)));
+    }
+    println!(
+        "Noise level {:.4} RMS; noise read as a pitch in {:.0}% of frames",
+        noise.level(),
+        noise.false_rate() * 100.0
+    );
+    Ok(noise)
+}
+
+// Listen to the sustained reference note and return the A4 it implies
+fn measure_reference(cfg: &Config, input: &mut AudioInput) -> Result<f32> {
+    let rc = &cfg.reference;
+    let note = note_to_midi(&rc.note).ok_or_else(|| anyhow!("Unknown note in reference.note: {}", rc.note))?;
+    let needed = (rc.listen_secs.max(0.5) * input.sample_rate as f32 / input.hop_size as f32) as usize;
+    loop {
+        println!("\nSustain the reference {} steadily. Press Enter to start.", rc.note);
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line)?;
+        input.discard();
+        let mut meter = reference::ReferenceMeter::new(note);
+        let started = Instant::now();
+        // A short gap (bow change, breath) is fine; a longer one restarts
+        let mut gap = 0;
+        while meter.frames() < needed && started.elapsed() < Duration::from_secs(20) {
+            match input.next_pitch(cfg)? {
+                Some(f0) if meter.record(f0) => gap = 0,
+                _ => {
+                    gap += 1;
+                    if gap > cfg.note_hold_frames.max(3) { meter.clear(); }
+                }
+            }
+        }
+        match meter.a4_hz().filter(|_| meter.frames() >= needed) {
+            Some((a4, true)) => {
+                println!("Reference {} measured: A4 = {a4:.1} Hz ({:+.1} cents from 440)", rc.note, 1200.0 * (a4 / 440.0).log2());
+                return Ok(a4);
+            }
+            Some((_, false)) => println!("The pitch wandered too much; try again with a steadier note"),
+            None => println!("Didn't hear {} long enough; try again", rc.note),
+        }
+    }
+}
+
+// ---------------------------- Audio setup ----------------------------
+
+struct AudioInput {
+    rx: ring::Reader,
+    // The stream's rate, and the analysis rate it is resampled to
+    device_rate: u32,
+    sample_rate: u32,
+    channels: u16,
+    // None when the two rates are the same
+    resampler: Option<resample::Resampler>,
+    // Scratch space for device-rate samples on their way to the resampler
+    raw: Vec<f32>,
+    window_size: usize,
+    hop_size: usize,
+    // Analysis runs on every Nth (low-passed) sample
+    decimation: usize,
+    // Rolling buffer
+    buffer: Vec<f32>,
+    // Scratch space for the decimated window
+    decimated: Vec<f32>,
+    noise: snr::NoiseFloor,
+    // SNR of the latest window, and whether it rejected a pitch
+    snr_db: f32,
+    low_snr: bool,
+    // Correlation of the latest detection, 0 without one
+    confidence: f32,
+    // Samples the stream dropped since the last warning about it, and when that was
+    overruns: u64,
+    // Whether samples can also go missing on the way here (network_input)
+    lossy: bool,
+    overrun_warned: Option<Instant>,
+    // The configured detector, at the analysis sample rate
+    detector: Box<dyn pitch::PitchDetector + Send>,
+    // The instrument's lowest open string, as a MIDI note (with `instrument`)
+    lowest_open: Option<i32>,
+    // Votes on and smooths the pitch track handed out by next_pitch
+    vote: voting::NoteVote,
+    smoother: smoothing::Smoother,
+    // When the newest window's last sample arrived and its analysis began
+    // (with --measure-latency)
+    timing: Option<(Instant, Instant)>,
+}
+
+// Dropped input is reported at most this often
+const OVERRUN_WARNING_GAP: Duration = Duration::from_secs(10);
+
+impl AudioInput {
+    /// Capture from the configured device (or network_input) and channel.
+    /// The stream must be kept alive for as long as the input is read.
+    fn open(cfg: &Config) -> Result<(Self, Option<cpal::Stream>)> {
+        let (mut rxs, sample_rate, channels, stream) = open_input(cfg, &[&cfg.input_channel])?;
+        Ok((Self::new(cfg, rxs.remove(0), sample_rate, channels), stream))
+    }
+
+    /// Start the analysis over for a reloaded config, reading the same stream.
+    fn reconfigure(&mut self, cfg: &Config) {
+        *self = Self::new(cfg, std::mem::take(&mut self.rx), self.device_rate, self.channels);
+    }
+
+    /// Analyse samples from `rx` (one channel or a mix) of a running stream.
+    fn new(cfg: &Config, mut rx: ring::Reader, device_rate: u32, channels: u16) -> Self {
+        // Whatever piled up before now isn't worth analysing
+        rx.clear();
+        match cfg.input_channel.as_slice() {
+            [] => println!("Input sample rate: {} Hz, channels: {}", device_rate, channels),
+            [c] => println!("Input sample rate: {} Hz, channel {} of {}", device_rate, c, channels),
+            cs => {
+                let mixed: Vec<String> = cs.iter().map(|c| c.to_string()).collect();
+                println!("Input sample rate: {} Hz, channels {} of {} mixed", device_rate, mixed.join("+"), channels);
+            }
+        }
+        // Everything below works at the analysis rate
+        let sample_rate = if cfg.analysis_rate > 0 { cfg.analysis_rate } else { device_rate };
+        let resampler = (sample_rate != device_rate).then(|| {
+            println!("Resampling: {} Hz to {} Hz for analysis", device_rate, sample_rate);
+            resample::Resampler::new(device_rate, sample_rate)
+        });
+
+        // Choose window and hop
+        let window_size = if cfg.window_size > 0 { cfg.window_size } else {
+            // 46 ms @ 48k ~ 2208, round to 2048/4096 depending on sample rate
+            // Use power of two near sample_rate/20, but always fit at least
+            // three periods of min_hz so low strings (bass B0 ~31 Hz) resolve
+            let periods = (3.0 * sample_rate as f32 / cfg.min_hz.max(1.0)) as usize;
+            nearest_power_of_two(((sample_rate as f32 / 20.0) as usize).max(periods)).clamp(1024, 16384)
+        };
+        // Large windows keep a short hop so triggers aren't delayed further
+        let hop_size = if cfg.hop_size > 0 { cfg.hop_size } else {
+            (window_size / 4).min(sample_rate as usize / 50).max(1)
+        };
+        let decimation = choose_decimation(cfg, sample_rate, window_size);
+        println!(
+            "Window: {} samples ({:.0} ms), Hop: {} samples ({:.1} ms)",
+            window_size,
+            window_size as f32 * 1000.0 / sample_rate as f32,
+            hop_size,
+            hop_size as f32 * 1000.0 / sample_rate as f32
+        );
+        latency::analysis(window_size as f32 * 1000.0 / sample_rate as f32, hop_size as f32 * 1000.0 / sample_rate as f32);
+        if cfg.performer.is_none() { record::audio_format(device_rate); }
+        if decimation > 1 {
+            println!("Decimation: {}x (analysis at {} Hz)", decimation, sample_rate / decimation as u32);
+        }
+
+        Self {
+            rx,
+            device_rate,
+            sample_rate,
+            channels,
+            resampler,
+            raw: Vec::new(),
+            window_size,
+            hop_size,
+            decimation,
+            buffer: Vec::with_capacity(window_size),
+            decimated: Vec::with_capacity(window_size / decimation),
+            noise: snr::NoiseFloor::new(hop_size as f32 / sample_rate as f32),
+            snr_db: 0.0,
+            low_snr: false,
+            confidence: 0.0,
+            overruns: 0,
+            lossy: cfg.network_input.enabled,
+            overrun_warned: None,
+            detector: cfg.detector.build(
+                pitch::SearchRange {
+                    sample_rate: sample_rate as f32 / decimation as f32,
+                    min_hz: cfg.min_hz,
+                    max_hz: cfg.max_hz,
+                },
+                &cfg.plugins,
+            ),
+            lowest_open: cfg.instrument.as_ref().and_then(|_| cfg.strings.tuning.iter().filter_map(|n| note_to_midi(n)).min()),
+            vote: voting::NoteVote::new(&cfg.voting),
+            smoother: smoothing::Smoother::new(&cfg.smoothing, cfg.min_hz, cfg.max_hz),
+            timing: None,
+        }
+    }
+
+    /// Block until another hop of audio has arrived and return the full analysis window.
+    fn next_window(&mut self) -> Result<&[f32]> {
+        loop {
+            // Fill buffer via hop size increments
+            match self.resampler.as_mut() {
+                Some(resampler) => {
+                    self.raw.clear();
+                    self.rx.read(resampler.needed(self.hop_size), &mut self.raw)?;
+                    record::audio(&self.raw);
+                    resampler.process(&self.raw, &mut self.buffer);
+                }
+                None => {
+                    let read = self.buffer.len();
+                    self.rx.read(self.hop_size, &mut self.buffer)?;
+                    record::audio(&self.buffer[read..]);
+                }
+            }
+            let dropped = self.rx.take_dropped();
+            if dropped > 0 {
+                // The window would join audio from either side of the gap;
+                // start over from the live input instead
+                self.discard();
+                self.report_overrun(dropped);
+                continue;
+            }
+            if self.buffer.len() > self.window_size {
+                let overflow = self.buffer.len() - self.window_size;
+                self.buffer.drain(0..overflow);
+            }
+            if self.buffer.len() == self.window_size {
+                if latency::measuring() {
+                    let now = Instant::now();
+                    self.timing = Some((self.rx.arrived(self.device_rate).unwrap_or(now), now));
+                }
+                return Ok(&self.buffer);
+            }
+        }
+    }
+
+    // Warn about dropped audio, at most every OVERRUN_WARNING_GAP
+    fn report_overrun(&mut self, dropped: u64) {
+        self.overruns += dropped;
+        tracing::debug!(dropped, "input overrun");
+        if self.overrun_warned.is_some_and(|at| at.elapsed() < OVERRUN_WARNING_GAP) { return; }
+        let why = if self.lossy { "audio was lost on the network, or detection fell behind it" } else {
+            "detection fell behind the audio (a slow action, or too little CPU for window_size and hop_size)"
+        };
+        tracing::warn!("dropped {} ms of input: {why}", self.overruns * 1000 / self.device_rate as u64);
+        self.overruns = 0;
+        self.overrun_warned = Some(Instant::now());
+    }
+
+    /// The latest undecimated analysis window and its sample rate.
+    fn raw_window(&self) -> (&[f32], f32) {
+        (&self.buffer, self.sample_rate as f32)
+    }
+
+    /// The newest hop of undecimated audio and its sample rate.
+    fn last_hop(&self) -> (&[f32], f32) {
+        (&self.buffer[self.buffer.len().saturating_sub(self.hop_size)..], self.sample_rate as f32)
+    }
+
+    /// RMS level of the newest hop.
+    fn hop_level(&self) -> f32 {
+        rms(self.last_hop().0)
+    }
+
+    /// Drop any buffered audio so the next window starts from "now".
+    fn discard(&mut self) {
+        self.rx.clear();
+        self.buffer.clear();
+        if let Some(r) = self.resampler.as_mut() { r.reset(); }
+    }
+
+    /// Advance one hop and run pitch detection on the new window.
+    fn next_pitch(&mut self, cfg: &Config) -> Result<Option<f32>> {
+        self.next_pitch_for(cfg, &cfg.note_map, cfg.corr_threshold)
+    }
+
+    /// next_pitch with the mappings and threshold of the active profile.
+    fn next_pitch_for(&mut self, cfg: &Config, note_map: &HashMap<String, Mapping>, threshold: f32) -> Result<Option<f32>> {
+        // Detect at the most lenient threshold any note asks for, then hold
+        // each pitch to its own note's threshold
+        let lowest = note_map.values().filter_map(|m| m.corr_threshold).fold(threshold, f32::min);
+        let detected = self.next_detection(cfg, lowest)?.filter(|&(f0, clarity)| {
+            let needed = note_setting(note_map, &freq_to_note(f0).0, |m| m.corr_threshold);
+            clarity >= needed.unwrap_or(threshold)
+        });
+        let f0 = self.vote.push(detected);
+        let f0 = self.smoother.push(f0);
+        if let Some((arrived, began)) = self.timing { latency::frame(arrived, began, f0.map(|f| freq_to_midi(f).0)); }
+        Ok(f0)
+    }
+
+    /// Advance one hop and detect a pitch with correlation of at least
+    /// `threshold`; returns the pitch and its correlation.
+    fn next_detection(&mut self, cfg: &Config, threshold: f32) -> Result<Option<(f32, f32)>> {
+        let decimation = self.decimation;
+        let sample_rate = self.sample_rate as f32 / decimation as f32;
+        self.next_window()?;
+        let window = if decimation > 1 {
+            decimate(&self.buffer, decimation, &mut self.decimated);
+            &self.decimated
+        } else {
+            &self.buffer
+        };
+        let level = rms(window);
+        self.snr_db = self.noise.update(level);
+        self.low_snr = false;
+        self.confidence = 0.0;
+        if cfg.min_rms > 0.0 && level < cfg.min_rms {
+            return Ok(None);
+        }
+        let mut f0 = self.detector.detect(window).filter(|e| e.clarity >= threshold).map(|e| (e.hz, e.clarity));
+        // Half a semitone below the lowest open string, where there is one
+        let floor = self.lowest_open.map(|m| midi_to_freq(m) / 2f32.powf(1.0 / 24.0));
+        if cfg.octave_check {
+            let lowest = floor.map_or(cfg.min_hz, |f| f.max(cfg.min_hz));
+            f0 = f0.map(|(f, clarity)| (pitch::correct_octave(window, sample_rate, f, lowest), clarity));
+        }
+        // The instrument can't sound below its lowest open string: a pitch
+        // under it is an octave low, or isn't the instrument
+        if let Some(floor) = floor {
+            f0 = f0.and_then(|(f, clarity)| [f, f * 2.0].into_iter().find(|&f| f >= floor).map(|f| (f, clarity)));
+        }
+        if cfg.pure_tone_check {
+            f0 = f0.filter(|&(f, _)| zero_crossing_agrees(window, sample_rate, f));
+        }
+        // A confident pitch barely above the noise is usually the noise itself
+        if f0.is_some() && cfg.min_snr_db > 0.0 && self.snr_db < cfg.min_snr_db {
+            self.low_snr = true;
+            return Ok(None);
+        }
+        self.confidence = f0.map_or(0.0, |(_, clarity)| clarity);
+        Ok(f0)
+    }
+
+    /// How clearly periodic the latest window was (its correlation, up to 1).
+    fn confidence(&self) -> f32 {
+        self.confidence
+    }
+
+    /// SNR of the latest window in dB, if the last pitch was rejected for it.
+    fn low_snr(&self) -> Option<f32> {
+        self.low_snr.then_some(self.snr_db)
+    }
+}
+
+// Autocorrelation cost grows with window length times lag range, both of which
+// balloon when min_hz is low. Large windows are therefore analysed at a
+// reduced rate that still leaves 8 samples per period of max_hz.
+fn choose_decimation(cfg: &Config, sample_rate: u32, window_size: usize) -> usize {
+    if cfg.decimation > 0 { return cfg.decimation; }
+    if window_size <= 4096 { return 1; }
+    let mut d = 1;
+    while d < 8 && sample_rate as f32 / (2 * d) as f32 >= 8.0 * cfg.max_hz {
+        d *= 2;
+    }
+    d
+}
+
+// Low-pass (windowed-sinc FIR) and keep every `factor`th sample
+fn decimate(input: &[f32], factor: usize, out: &mut Vec<f32>) {
+    const TAPS: usize = 31;
+    let cutoff = 0.8 / (2.0 * factor as f32); // fraction of the input rate
+    let mut h = [0.0f32; TAPS];
+    let mid = (TAPS / 2) as f32;
+    for (i, tap) in h.iter_mut().enumerate() {
+        let t = i as f32 - mid;
+        let sinc = if t == 0.0 { 2.0 * cutoff } else { (2.0 * PI * cutoff * t).sin() / (PI * t) };
+        let w = 0.5 - 0.5 * (2.0 * PI * i as f32 / (TAPS as f32 - 1.0)).cos();
+        *tap = sinc * w;
+    }
+    let gain: f32 = h.iter().sum();
+
+    out.clear();
+    let mut i = 0;
+    while i < input.len() {
+        let mut acc = 0.0f32;
+        for (k, tap) in h.iter().enumerate() {
+            let j = i as isize + k as isize - mid as isize;
+            if j >= 0 && (j as usize) < input.len() {
+                acc += input[j as usize] * tap;
+            }
+        }
+        out.push(acc / gain);
+        i += factor;
+    }
+}
+
+// `list-devices`: the audio systems there are to capture through, then
+// every input device of the chosen one with the number input_device /
+// --device accept for it, and the formats it supports
+fn list_devices(wanted_host: Option<&str>) -> Result<()> {
+    let host = audio_host(wanted_host);
+    let hosts: Vec<String> = cpal::available_hosts()
+        .into_iter()
+        .map(|id| if id == host.id() { format!("{} (in use)", id.name()) } else { id.name().to_string() })
+        .collect();
+    println!("Hosts: {}\n", hosts.join(", "));
+    let default = host.default_input_device().and_then(|d| d.name().ok());
+    let devices: Vec<cpal::Device> = host.input_devices().context("Failed to list input devices")?.collect();
+    if devices.is_empty() { println!("No input devices found"); }
+    for (i, d) in devices.iter().enumerate() {
+        let name = d.name().unwrap_or_default();
+        let mark = if default.as_ref() == Some(&name) { "  (default)" } else { "" };
+        println!("{i}: {name}{mark}");
+        if let Ok(c) = d.default_input_config() {
+            println!("     default: {} Hz, {} channel(s), {:?}", c.sample_rate().0, c.channels(), c.sample_format());
+        }
+        for c in d.supported_input_configs().into_iter().flatten() {
+            let (lo, hi) = (c.min_sample_rate().0, c.max_sample_rate().0);
+            let rates = if lo == hi { format!("{lo} Hz") } else { format!("{lo}-{hi} Hz") };
+            println!("     {rates}, {} channel(s), {:?}", c.channels(), c.sample_format());
+        }
+    }
+    loopback::list(&host);
+    Ok(())
+}
+
+// The input device `wanted` names: its number in list-devices, a name that
+// matches exactly (ignoring case), or else the first name containing it
+fn find_input_device(host: &cpal::Host, wanted: &str) -> Result<cpal::Device> {
+    find_device(host.input_devices().context("Failed to list input devices")?.collect(), wanted, "input")
+}
+
+// The one of `devices` that `wanted` names, as find_input_device does
+#[cfg_attr(not(windows), allow(dead_code))]
+fn find_device(devices: Vec<cpal::Device>, wanted: &str, kind: &str) -> Result<cpal::Device> {
+    let names: Vec<String> = devices.iter().map(|d| d.name().unwrap_or_default()).collect();
+    let wanted_lower = wanted.trim().to_lowercase();
+    let found = match wanted.trim().parse::<usize>() {
+        Ok(i) => Some(i).filter(|&i| i < devices.len()),
+        Err(_) => names
+            .iter()
+            .position(|n| n.to_lowercase() == wanted_lower)
+            .or_else(|| names.iter().position(|n| n.to_lowercase().contains(&wanted_lower))),
+    };
+    match found {
+        Some(i) => Ok(devices.into_iter().nth(i).expect("index from the same list")),
+        None => Err(anyhow!("No {kind} device matching {wanted:?} (available: {}; see list-devices)", names.join(", "))),
+    }
+}
+
+// The audio system `wanted` names (case doesn't matter), or the platform's
+// default one when it is unset or can't be used
+fn audio_host(wanted: Option<&str>) -> cpal::Host {
+    let Some(wanted) = wanted else { return cpal::default_host() };
+    match find_host(wanted).and_then(|id| cpal::host_from_id(id).map_err(|e| anyhow!("{} can't be used: {e}", id.name()))) {
+        Ok(host) => host,
+        Err(e) => {
+            tracing::warn!("{e:#}; using {} instead", cpal::default_host().id().name());
+            cpal::default_host()
+        }
+    }
+}
+
+// The id of an audio system that is there to use on this machine
+fn find_host(wanted: &str) -> Result<cpal::HostId> {
+    let available = cpal::available_hosts();
+    if let Some(&id) = available.iter().find(|id| id.name().eq_ignore_ascii_case(wanted.trim())) { return Ok(id); }
+    let names: Vec<&str> = available.iter().map(|id| id.name()).collect();
+    let built = cpal::ALL_HOSTS.iter().any(|id| id.name().eq_ignore_ascii_case(wanted.trim()));
+    Err(anyhow!(
+        "audio host {wanted:?} {} (available: {})",
+        if built { "isn't available on this machine" } else { "isn't supported by this build" },
+        names.join(", ")
+    ))
+}
+
+// The configured input: --input, network_input when it's enabled, or a
+// device stream (which has to be kept alive)
+fn open_input(cfg: &Config, taps: &[&[usize]]) -> Result<(Vec<ring::Reader>, u32, u16, Option<cpal::Stream>)> {
+    if let Some(raw) = &cfg.raw_input {
+        let (rxs, sample_rate, channels) = pcm::open(raw, taps)?;
+        return Ok((rxs, sample_rate, channels, None));
+    }
+    if cfg.network_input.enabled {
+        let (rxs, sample_rate, channels) = net_audio::open(&cfg.network_input, taps)?;
+        return Ok((rxs, sample_rate, channels, None));
+    }
+    let (rxs, sample_rate, channels, stream) = build_input_stream(cfg, taps)?;
+    Ok((rxs, sample_rate, channels, Some(stream)))
+}
+
+// Each tap as the frame indices it mixes, worked out before any audio
+// arrives. A tap lists channels from 1; empty mixes them all.
+fn tap_channels(taps: &[&[usize]], channels: u16, source: &str) -> Result<Vec<Vec<usize>>> {
+    if let Some(&ch) = taps.iter().flat_map(|t| t.iter()).find(|&&c| c > channels as usize) {
+        return Err(anyhow!("Input channel {ch} requested, but {source} has {channels}"));
+    }
+    Ok(taps
+        .iter()
+        .map(|t| if t.is_empty() { (0..channels as usize).collect() } else { t.iter().map(|c| c - 1).collect() })
+        .collect())
+}
+
+// Open the input device (the default one, the one input_device names, see
+// find_input_device, or the loopback source) of the configured host and
+// start one stream that feeds a receiver per tap. Each tap is a list of
+// channels (from 1) to mix into one ring; empty mixes them all.
+fn build_input_stream(cfg: &Config, taps: &[&[usize]]) -> Result<(Vec<ring::Reader>, u32, u16, cpal::Stream)> {
+    let host = audio_host(cfg.host.as_deref());
+    if cfg.host.is_some() { println!("Audio host: {}", host.id().name()); }
+    let (device, config) = match &cfg.loopback {
+        Some(which) => loopback::device(&host, which)?,
+        None => {
+            let device = match cfg.input_device.as_deref() {
+                None => host
+                    .default_input_device()
+                    .ok_or_else(|| anyhow!("No default input device"))?,
+                Some(wanted) => find_input_device(&host, wanted)?,
+            };
+            println!("Input device: {}", device.name().unwrap_or_default());
+            let config = device
+                .default_input_config()
+                .context("Failed to get default input config")?;
+            (device, config)
+        }
+    };
+
+    let sample_rate = config.sample_rate().0;
+    let channels = config.channels();
+    let indices = tap_channels(taps, channels, "the device")?;
+
+    let start = |stream_config: &cpal::StreamConfig| -> Result<(Vec<ring::Reader>, cpal::Stream)> {
+        let (txs, rxs): (Vec<_>, Vec<_>) = taps.iter().map(|_| ring::channel(sample_rate as usize)).unzip(); // ~1 second buffer
+        let taps: Vec<_> = indices.iter().cloned().zip(txs).collect();
+        let stream = match config.sample_format() {
+            cpal::SampleFormat::F32 => build_stream::<f32>(&device, stream_config, channels, taps)?,
+            cpal::SampleFormat::I16 => build_stream::<i16>(&device, stream_config, channels, taps)?,
+            cpal::SampleFormat::U16 => build_stream::<u16>(&device, stream_config, channels, taps)?,
+            // Cover any new formats conservatively
+            other => return Err(anyhow!("Unsupported sample format: {:?}", other)),
+        };
+        Ok((rxs, stream))
+    };
+    let mut stream_config = config.config();
+    stream_config.buffer_size = buffer_size(config.buffer_size(), cfg.buffer_size);
+    let (rxs, stream) = match (start(&stream_config), stream_config.buffer_size) {
+        (Err(e), cpal::BufferSize::Fixed(frames)) => {
+            tracing::warn!("the device refused a {frames}-frame buffer ({e:#}); using its default buffer size");
+            start(&config.config())?
+        }
+        (started, cpal::BufferSize::Fixed(frames)) => {
+            println!("Buffer: {frames} frames ({:.1} ms)", frames as f32 * 1000.0 / sample_rate as f32);
+            started?
+        }
+        (started, cpal::BufferSize::Default) => started?,
+    };
+
+    stream.play().context("Failed to start input stream")?;
+
+    Ok((rxs, sample_rate, channels, stream))
+}
+
+// The buffer size to ask for: `wanted` within what the device supports
+fn buffer_size(supported: &cpal::SupportedBufferSize, wanted: Option<BufferSize>) -> cpal::BufferSize {
+    let range = match *supported {
+        cpal::SupportedBufferSize::Range { min, max } => Some((min, max)),
+        cpal::SupportedBufferSize::Unknown => None,
+    };
+    match (wanted, range) {
+        (None, _) => cpal::BufferSize::Default,
+        (Some(BufferSize::Frames(n)), Some((min, max))) => cpal::BufferSize::Fixed(n.clamp(min, max)),
+        (Some(BufferSize::Frames(n)), None) => cpal::BufferSize::Fixed(n),
+        (Some(BufferSize::Smallest), Some((min, _))) => cpal::BufferSize::Fixed(min),
+        (Some(BufferSize::Smallest), None) => {
+            tracing::warn!("buffer_size: the device doesn't say how small its buffer can be; using its default");
+            cpal::BufferSize::Default
+        }
+    }
+}
+
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    channels: u16,
+    mut taps: Vec<(Vec<usize>, ring::Writer)>,
+) -> Result<cpal::Stream>
+where
+    T: cpal::SizedSample,
+    f32: cpal::FromSample<T>,
+{
+    let err_fn = |err| tracing::error!("Stream error: {err}");
+    let stream = device.build_input_stream(
+        config,
+        move |data: &[T], _| {
+            // The whole buffer at once, for each tap
+            for (mixed, tx) in &mut taps {
+                let gain = 1.0 / mixed.len() as f32;
+                let samples = data.chunks(channels as usize).map(|frame| {
+                    mixed.iter().map(|&c| frame.get(c).map_or(0.0, |&s| s.to_sample::<f32>())).sum::<f32>() * gain
+                });
+                tx.write_from(samples);
+            }
+        },
+        err_fn,
+        None,
+    )?;
+    Ok(stream)
+}
+
+// ---------------------------- Pitch detection ----------------------------
+
+fn rms(input: &[f32]) -> f32 {
+    if input.is_empty() { return 0.0; }
+    (input.iter().map(|s| s * s).sum::<f32>() / input.len() as f32).sqrt()
+}
+
+// A near-sinusoid crosses zero twice per period. Broadband noise (breath,
+// hiss) crosses far more often, so a large mismatch means the "pitch" came
+// from noise that happened to correlate.
+fn zero_crossing_agrees(input: &[f32], sample_rate: f32, f0: f32) -> bool {
+    if input.len() < 2 { return false; }
+    (0.7..=1.4).contains(&(zero_crossing_hz(input, sample_rate) / f0))
+}
+
+// Frequency of the sine that would cross zero (around the mean) as often as `input`
+fn zero_crossing_hz(input: &[f32], sample_rate: f32) -> f32 {
+    if input.len() < 2 { return 0.0; }
+    let mean = input.iter().copied().sum::<f32>() / input.len() as f32;
+    let crossings = input
+        .windows(2)
+        .filter(|w| (w[0] - mean) * (w[1] - mean) < 0.0)
+        .count();
+    crossings as f32 * sample_rate / (2.0 * input.len() as f32)
+}
+
+// ---------------------------- Note conversion ----------------------------
+
+static NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+fn freq_to_note(freq: f32) -> (String, f32) {
+    let (midi, cents) = freq_to_midi(freq);
+    (midi_to_name(midi), cents)
+}
+
+// Reference A4 in Hz (as f32 bits): a4_hz from the config, or a measured reference
+static A4_HZ: AtomicU32 = AtomicU32::new(440f32.to_bits());
+
+fn a4_hz() -> f32 {
+    f32::from_bits(A4_HZ.load(Ordering::Relaxed))
+}
+
+fn set_a4_hz(hz: f32) {
+    A4_HZ.store(hz.to_bits(), Ordering::Relaxed);
+}
+
+// transpose_semitones: what is heard is this far above the note it is named as
+static TRANSPOSE: AtomicI32 = AtomicI32::new(0);
+
+fn set_transpose(semitones: i32) {
+    TRANSPOSE.store(semitones, Ordering::Relaxed);
+}
+
+// Fractional MIDI note a frequency is named as
+fn exact_midi(freq: f32) -> f32 {
+    69.0 + 12.0 * (freq / a4_hz()).log2() - TRANSPOSE.load(Ordering::Relaxed) as f32
+}
+
+fn freq_to_midi(freq: f32) -> (i32, f32) {
+    let midi = exact_midi(freq);
+    let nearest = midi.round();
+    let cents = (midi - nearest) * 100.0;
+    (nearest as i32, cents)
+}
+
+fn midi_to_freq(midi: i32) -> f32 {
+    midi_to_freq_at(midi + TRANSPOSE.load(Ordering::Relaxed), a4_hz())
+}
+
+fn midi_to_freq_at(midi: i32, a4_hz: f32) -> f32 {
+    a4_hz * 2f32.powf((midi - 69) as f32 / 12.0)
+}
+
+// Parse names like "A4", "C#3", "Bb3", "D♭5", "G-1" or "La4" (fixed-do
+// solfège), or a MIDI note number such as "61", into a MIDI note number
+fn note_to_midi(name: &str) -> Option<i32> {
+    if !name.is_empty() && name.bytes().all(|b| b.is_ascii_digit()) {
+        return name.parse().ok().filter(|n| (0..=127).contains(n));
+    }
+    let split = name.find(|c: char| c.is_ascii_digit() || c == '-')?;
+    let (pc, octave) = name.split_at(split);
+    let semitones = note_keys::semitones(pc)?;
+    let octave: i32 = octave.parse().ok()?;
+    Some((octave + 1) * 12 + semitones)
+}
+
+fn midi_to_name(midi: i32) -> String {
+    let pitch_class = midi.rem_euclid(12);
+    let octave = midi / 12 - 1;
+    format!("{}{}", NOTE_NAMES[pitch_class as usize], octave)
+}
+
+fn nearest_power_of_two(x: usize) -> usize {
+    let mut p = 1usize;
+    while p < x { p <<= 1; }
+    p
+}
+
+// Small xorshift generator for game prompts; quality doesn't matter here
+struct Rng(u64);
+
+impl Rng {
+    fn from_time() -> Self {
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0x9E37_79B9_7F4A_7C15);
+        Self(seed | 1)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    // Uniform-enough value in 0..n (n > 0)
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n.max(1) as u64) as usize
+    }
+}
+
+// ---------------------------- Actions ----------------------------
+
+// Set by dry_run or --dry-run: actions are reported instead of run
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+fn dry_run() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
+
+// Report what a dry run skips, on the console and in the log
+fn would(what: &str) {
+    println!("(dry run) would {what}");
+    tracing::info!("(dry run) would {what}");
+}
+
+#[cfg(windows)]
+type KeySender = Enigo;
+// Placeholder: on other platforms the keyboard module holds the injector
+#[cfg(not(windows))]
+struct KeySender;
+
+#[cfg(windows)]
+fn new_sender() -> KeySender { Enigo::new() }
+#[cfg(not(windows))]
+fn new_sender() -> KeySender { KeySender }
+
+pub(crate) fn action_name(a: &Action) -> String {
+    match a {
+        Action::Keys { sequence } => format!("keys:{}", sequence),
+        Action::Text { text } if text.chars().count() > 24 => {
+            format!("text:{}...", text.chars().take(24).collect::<String>())
+        }
+        Action::Text { text } => format!("text:{text}"),
+        Action::TapTempo => "tap-tempo".to_string(),
+        Action::Undo => "undo".to_string(),
+        Action::Profile { name } => format!("profile:{name}"),
+        Action::Command { program, args, .. } if args.is_empty() => format!("cmd:{program}"),
+        Action::Command { program, args, .. } => format!("cmd:{} {}", program, args.join(" ")),
+        Action::Midi(m) => m.label(),
+        Action::Mouse(m) => m.label(),
+        Action::Osc(o) => o.label(),
+        Action::Gamepad(g) => g.label(),
+        Action::Obs(o) => o.label(),
+        Action::HomeAssistant(h) => h.label(),
+        Action::Script(s) => s.label(),
+        Action::Plugin(p) => p.label(),
+        Action::Macro { steps } => {
+            let steps: Vec<String> = steps
+                .iter()
+                .map(|s| match s.delay_ms {
+                    0 => action_name(&s.action),
+                    ms => format!("wait {ms}ms, {}", action_name(&s.action)),
+                })
+                .collect();
+            format!("macro:[{}]", steps.join(", "))
+        }
+    }
+}
+
+// Run a macro's steps in order. Detection pauses during the delays, so keep
+// them short.
+fn run_macro(sender: &mut KeySender, steps: &[MacroStep]) -> Result<()> {
+    for step in steps {
+        if step.delay_ms > 0 { std::thread::sleep(Duration::from_millis(step.delay_ms)); }
+        execute_action(sender, &step.action)?;
+    }
+    Ok(())
+}
+
+// Start a program; a detached one is reaped (and its failure reported) by a
+// background thread
+fn run_command(program: &str, args: &[String], wait: bool, cwd: Option<&str>, env: &BTreeMap<String, String>) -> Result<()> {
+    let mut cmd = std::process::Command::new(program);
+    cmd.args(args).envs(env);
+    if let Some(dir) = cwd { cmd.current_dir(dir); }
+    let mut child = cmd.spawn().with_context(|| format!("Failed to start {program}"))?;
+    if wait {
+        let status = child.wait().with_context(|| format!("Waiting for {program}"))?;
+        if !status.success() { return Err(anyhow!("{program} exited with {status}")); }
+        return Ok(());
+    }
+    let program = program.to_string();
+    std::thread::spawn(move || match child.wait() {
+        Ok(status) if !status.success() => tracing::error!("Command {program} exited with {status}"),
+        Ok(_) => {}
+        Err(e) => tracing::error!("Command {program}: {e}"),
+    });
+    Ok(())
+}
+
+#[cfg(windows)]
+fn send_keys(enigo: &mut Enigo, sequence: &str) -> Result<()> {
+    let (modifiers, key) = parse_keys(sequence)?;
+    // Press modifiers
+    for m in &modifiers { enigo.key_down(*m); }
+    // Click main key
+    enigo.key_click(key);
+    // Release modifiers
+    for m in modifiers.into_iter().rev() { enigo.key_up(m); }
+    Ok(())
+}
+
+// Hold mappings: modifiers then the main key go down, and come up in reverse
+#[cfg(windows)]
+fn press_keys(enigo: &mut Enigo, sequence: &str, down: bool) -> Result<()> {
+    let (modifiers, key) = parse_keys(sequence)?;
+    // Releases still go through, so nothing stays held when actions go off
+    if down && !hotkeys::actions_on() { return Ok(()); }
+    if dry_run() {
+        would(&format!("{} keys: {sequence}", if down { "press" } else { "release" }));
+        return Ok(());
+    }
+    if down {
+        for m in &modifiers { enigo.key_down(*m); }
+        enigo.key_down(key);
+    } else {
+        enigo.key_up(key);
+        for m in modifiers.into_iter().rev() { enigo.key_up(m); }
+    }
+    Ok(())
+}
+
+// Modifiers and main key as enigo keys
+#[cfg(windows)]
+fn parse_keys(sequence: &str) -> Result<(Vec<Key>, Key)> {
+    let combination = keys::parse(sequence)?;
+    let modifiers = combination
+        .modifiers
+        .iter()
+        .map(|m| match m {
+            keys::Modifier::Ctrl => Key::Control,
+            keys::Modifier::Shift => Key::Shift,
+            keys::Modifier::Alt => Key::Alt,
+            keys::Modifier::Win => Key::Meta,
+        })
+        .collect();
+    let key = match combination.key {
+        keys::Key::Space => Key::Space,
+        keys::Key::Enter => Key::Return,
+        keys::Key::Tab => Key::Tab,
+        keys::Key::Esc => Key::Escape,
+        keys::Key::Up => Key::UpArrow,
+        keys::Key::Down => Key::DownArrow,
+        keys::Key::Left => Key::LeftArrow,
+        keys::Key::Right => Key::RightArrow,
+        keys::Key::Backspace => Key::Backspace,
+        keys::Key::Delete => Key::Delete,
+        keys::Key::Char(c) => Key::Layout(c),
+    };
+    Ok((modifiers, key))
+}
+
+// ---------------------------- Config loading ----------------------------
+
+// Set by --config
+static CONFIG_PATH: std::sync::OnceLock<std::path::PathBuf> = std::sync::OnceLock::new();
+
+// The config file in the current directory is the first of these that exists
+const CONFIG_NAMES: [&str; 4] = ["config.toml", "config.json", "config.yaml", "config.yml"];
+
+fn config_path() -> Result<std::path::PathBuf> {
+    if let Some(path) = CONFIG_PATH.get() { return Ok(path.clone()); }
+    let dir = std::env::current_dir()?;
+    let found = CONFIG_NAMES.iter().map(|name| dir.join(name)).find(|p| p.exists());
+    Ok(found.unwrap_or_else(|| dir.join(CONFIG_NAMES[0])))
+}
+
+// The config file, for commands that write to it: they keep its comments
+// and layout, which only works for TOML
+fn toml_config_path() -> Result<std::path::PathBuf> {
+    let path = config_path()?;
+    match ConfigFormat::of(&path) {
+        ConfigFormat::Toml => Ok(path),
+        _ => Err(anyhow!("{} can only be written to as TOML; use --config with a .toml file", path.display())),
+    }
+}
+
+#[derive(Clone, Copy)]
+enum ConfigFormat {
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl ConfigFormat {
+    // By extension; anything but .json/.yaml/.yml is TOML
+    fn of(path: &std::path::Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase).as_deref() {
+            Some("json") => Self::Json,
+            Some("yaml" | "yml") => Self::Yaml,
+            _ => Self::Toml,
+        }
+    }
+
+    // The file's contents as the table TOML would give; a null (JSON/YAML)
+    // leaves the key unset
+    fn parse(self, text: &str) -> Result<toml::Table> {
+        let value: serde_json::Value = match self {
+            Self::Toml => return Ok(toml::from_str(text)?),
+            Self::Json => serde_json::from_str(text)?,
+            Self::Yaml => serde_yaml::from_str(text)?,
+        };
+        fn drop_nulls(value: &mut serde_json::Value) {
+            match value {
+                serde_json::Value::Object(map) => {
+                    map.retain(|_, v| !v.is_null());
+                    map.values_mut().for_each(drop_nulls);
+                }
+                serde_json::Value::Array(items) => items.iter_mut().for_each(drop_nulls),
+                _ => {}
+            }
+        }
+        let mut value = value;
+        drop_nulls(&mut value);
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+// `check`: load the config the way `run` does, report what is wrong with
+// it, and summarise it
+fn run_check() -> Result<()> {
+    let path = config_path()?;
+    let cfg = load_config()?;
+    let problems = validate::check(&cfg);
+    let errors = problems.iter().filter(|p| p.error).count();
+    for p in &problems {
+        println!("{}: {}: {}", if p.error { "error" } else { "warning" }, p.place, p.message);
+    }
+    if errors > 0 {
+        return Err(anyhow!("{}: {errors} error(s), {} warning(s)", path.display(), problems.len() - errors));
+    }
+    match problems.len() {
+        0 => println!("{}: OK", path.display()),
+        n => println!("{}: OK, {n} warning(s)", path.display()),
+    }
+    println!(
+        "{} mapping(s), {} profile(s), {} {}",
+        cfg.note_map.len(),
+        cfg.profiles.len(),
+        cfg.performers.len(),
+        if cfg.hexaphonic.enabled { "string(s)" } else { "performer(s)" }
+    );
+    Ok(())
+}
+
+/// Config::detection: the pitch range searched, the clarity and tuning a
+/// note needs, how many frames in a row it must be heard, and the A4 notes
+/// are measured from.
+#[derive(Debug, Clone, Copy)]
+pub struct Detection {
+    pub min_hz: f32,
+    pub max_hz: f32,
+    pub corr_threshold: f32,
+    pub tolerance_cents: f32,
+    pub note_hold_frames: usize,
+    pub a4_hz: f32,
+}
+
+fn load_config() -> Result<Config> {
+    Config::load(&config_path()?)
+}
+
+impl Config {
+    /// Read a config file: TOML, or JSON or YAML by its extension.
+    pub fn load(path: &std::path::Path) -> Result<Self> {
+        if !path.exists() {
+            return Err(anyhow!("{} not found", path.display()));
+        }
+        let text = std::fs::read_to_string(path).with_context(|| format!("Reading {}", path.display()))?;
+        let table = ConfigFormat::of(path).parse(&text).with_context(|| format!("Parsing {}", path.display()))?;
+        Self::from_table(table).with_context(|| format!("Parsing {}", path.display()))
+    }
+
+    /// A config written as config.toml would be.
+    pub fn from_toml(text: &str) -> Result<Self> {
+        Self::from_table(ConfigFormat::Toml.parse(text)?)
+    }
+
+    /// The settings that decide which notes count as heard, for engines
+    /// outside the program (the CLAP plugin).
+    pub fn detection(&self) -> Detection {
+        Detection {
+            min_hz: self.min_hz,
+            max_hz: self.max_hz,
+            corr_threshold: self.corr_threshold,
+            tolerance_cents: self.tolerance_cents,
+            note_hold_frames: self.note_hold_frames,
+            a4_hz: self.a4_hz,
+        }
+    }
+
+    fn from_table(mut table: toml::Table) -> Result<Self> {
+        let performers = match table.remove("performers") {
+            None => toml::Table::new(),
+            Some(toml::Value::Table(t)) => t,
+            Some(_) => return Err(anyhow!("performers must be [performers.<name>] sections")),
+        };
+        let mut cfg = parse_config(table.clone())?;
+        for (name, overrides) in performers {
+            let toml::Value::Table(overrides) = overrides else {
+                return Err(anyhow!("[performers.{name}] must be a table"));
+            };
+            let p = Self::performer(&table, overrides, &format!("performers.{name}"))?;
+            cfg.performers.push(Config { performer: Some(name), ..p });
+        }
+        if cfg.hexaphonic.enabled {
+            if !cfg.performers.is_empty() { return Err(anyhow!("[hexaphonic] and [performers] can't be used together")); }
+            for (string, overrides) in cfg.hexaphonic.strings()? {
+                let p = Self::performer(&table, overrides, &format!("hexaphonic.strings.{string}"))?;
+                let name = format!("{} string", strings::ordinal(string));
+                cfg.performers.push(Config { performer: Some(name), string: Some(string), ..p });
+            }
+        }
+        Ok(cfg)
+    }
+
+    // A performer's keys replace the top-level ones; everything else is inherited
+    fn performer(table: &toml::Table, overrides: toml::Table, place: &str) -> Result<Self> {
+        if let Some(key) = ["a4_hz", "transpose_semitones", "reference", "host", "loopback", "network_input", "websocket", "mqtt", "dbus", "gamepad"].into_iter().find(|k| overrides.contains_key(*k)) {
+            return Err(anyhow!("[{place}] can't set {key}; all performers share the top-level one"));
+        }
+        let mut merged = table.clone();
+        merged.extend(overrides);
+        let p = parse_config(merged).with_context(|| format!("[{place}]"))?;
+        if p.mode != Mode::Trigger {
+            return Err(anyhow!("[{place}]: performers only run mode = \"trigger\""));
+        }
+        Ok(p)
+    }
+}
+
+// Apply the preset to one config table and build the Config from it
+fn parse_config(mut table: toml::Table) -> Result<Config> {
+    presets::apply(&mut table)?;
+    let mut cfg: Config = table.try_into()?;
+    // Merge defaults for any missing fields
+    let def = Config::default();
+    if cfg.window_size == 0 { cfg.window_size = def.window_size; }
+    if cfg.hop_size == 0 { cfg.hop_size = def.hop_size; }
+    if cfg.note_map.is_empty() { cfg.note_map = def.note_map; }
+    cfg.note_map = normalize_keys(std::mem::take(&mut cfg.note_map), "note_map")?;
+    for (name, p) in cfg.profiles.iter_mut() {
+        p.note_map = normalize_keys(std::mem::take(&mut p.note_map), &format!("profiles.{name}.note_map"))?;
+    }
+    Ok(cfg)
+}
+
+// "E3@5th_string" and "E3@5" name the same mapping, as do "Bb3", "A♯3" and
+// "58" ("A#3"); two of them in one map would leave one unused
+fn normalize_keys(map: HashMap<String, Mapping>, place: &str) -> Result<HashMap<String, Mapping>> {
+    let mut written: HashMap<String, String> = HashMap::new();
+    let mut out = HashMap::new();
+    for (key, mapping) in map {
+        let normal = note_keys::normalize(&strings::normalize_key(&key));
+        if let Some(other) = written.insert(normal.clone(), key.clone()) {
+            let (a, b) = if other < key { (other, key) } else { (key, other) };
+            return Err(anyhow!("{place} has both {a:?} and {b:?}, which are the same key ({normal})"));
+        }
+        out.insert(normal, mapping);
+    }
+    Ok(out)
+}
+
+// ---------------------------- Other platforms ----------------------------
+//
+// Keys and text go through the keyboard module's backend; mouse actions
+// are only reported.
+
+#[cfg(not(windows))]
+fn execute_action(sender: &mut KeySender, action: &Action) -> Result<()> {
+    if !matches!(action, Action::TapTempo | Action::Undo | Action::Profile { .. }) {
+        // Turned off from the hotkey; the trigger is still shown
+        if !hotkeys::actions_on() { return Ok(()); }
+        if dry_run() {
+            would(&format!("execute: {}", action_name(action)));
+            return Ok(());
+        }
+    }
+    match action {
+        Action::Command { program, args, wait, cwd, env } => run_command(program, args, *wait, cwd.as_deref(), env),
+        Action::Midi(m) => midi::execute(m),
+        Action::Osc(o) => osc::execute(o),
+        Action::Gamepad(g) => gamepad::execute(g),
+        Action::Obs(o) => obs::execute(o),
+        Action::HomeAssistant(h) => home_assistant::execute(h),
+        Action::Keys { sequence } => keyboard::send(sequence),
+        Action::Text { text } => type_text(sender, text),
+        Action::Script(s) => run_macro(sender, &script::run(s)?),
+        Action::Plugin(p) => run_macro(sender, &plugins::execute(p)?),
+        Action::Macro { steps } => run_macro(sender, steps),
+        // Handled by the trigger loop, which owns the metronome, history and profiles
+        Action::TapTempo | Action::Undo | Action::Profile { .. } => Ok(()),
+        Action::Mouse(_) => {
+            tracing::warn!("{} not sent: mouse actions only work on Windows", action_name(action));
+            Ok(())
+        }
+    }
+}
+
+#[cfg(not(windows))]
+fn press_keys(_dummy: &mut KeySender, sequence: &str, down: bool) -> Result<()> {
+    if down && !hotkeys::actions_on() { return Ok(()); }
+    if dry_run() {
+        would(&format!("{} keys: {sequence}", if down { "press" } else { "release" }));
+        return Ok(());
+    }
+    keyboard::press(sequence, down)
+}
+
+#[cfg(not(windows))]
+fn type_text(_dummy: &mut KeySender, text: &str) -> Result<()> { keyboard::type_text(text) }
+
+#[cfg(windows)]
+fn type_text(enigo: &mut Enigo, text: &str) -> Result<()> {
+    enigo.key_sequence(text);
+    Ok(())
+}
+
+#[cfg(windows)]
+fn execute_action(enigo: &mut Enigo, action: &Action) -> Result<()> {
+    if !matches!(action, Action::TapTempo | Action::Undo | Action::Profile { .. }) {
+        // Turned off from the hotkey; the trigger is still shown
+        if !hotkeys::actions_on() { return Ok(()); }
+        if dry_run() {
+            would(&format!("execute: {}", action_name(action)));
+            return Ok(());
+        }
+    }
+    match action {
+        Action::Keys { sequence } => send_keys(enigo, sequence),
+        Action::Text { text } => type_text(enigo, text),
+        Action::Command { program, args, wait, cwd, env } => run_command(program, args, *wait, cwd.as_deref(), env),
+        Action::Midi(m) => midi::execute(m),
+        Action::Osc(o) => osc::execute(o),
+        Action::Gamepad(g) => gamepad::execute(g),
+        Action::Obs(o) => obs::execute(o),
+        Action::HomeAssistant(h) => home_assistant::execute(h),
+        Action::Mouse(m) => mouse::execute(enigo, m),
+        Action::Script(s) => run_macro(enigo, &script::run(s)?),
+        Action::Plugin(p) => run_macro(enigo, &plugins::execute(p)?),
+        Action::Macro { steps } => run_macro(enigo, steps),
+        // Handled by the trigger loop, which owns the metronome, history and profiles
+        Action::TapTempo | Action::Undo | Action::Profile { .. } => Ok(()),
+    }
+}
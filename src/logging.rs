@@ -0,0 +1,141 @@
+// ---------------------------- Logging ----------------------------
+//
+// Warnings and errors go to the console as before ("Warning: ..."). With
+// [log] file set, a log file also gets every status event and trigger, and
+// at "debug" every detection decision: the note heard, how far out of tune,
+// and why a held note did or didn't fire. The file starts anew each day or
+// hour (or never), and can be written as one JSON object per line for other
+// tools. RUST_LOG, in the usual `tracing` syntax, overrides `level`.
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use tracing::{Event, Level, Subscriber};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::{LookupSpan, Registry};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct LogConfig {
+    // Log file; the date or hour is added to the name when it rotates.
+    // Unset = no file
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+    // What the file gets: "error", "warn", "info" (events and triggers),
+    // "debug" (detection decisions) or "trace"
+    #[serde(default = "default_level")]
+    pub level: String,
+    // What the console gets besides the status line
+    #[serde(default = "default_console_level")]
+    pub console_level: String,
+    // One JSON object per line instead of plain text
+    #[serde(default)]
+    pub json: bool,
+    // Start a new file "daily", "hourly" or "never"
+    #[serde(default = "default_rotation")]
+    pub rotation: String,
+    // Rotated files to keep; unset = all of them
+    #[serde(default)]
+    pub keep_files: Option<usize>,
+}
+
+fn default_level() -> String { "info".into() }
+fn default_console_level() -> String { "warn".into() }
+fn default_rotation() -> String { "daily".into() }
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            file: None,
+            level: default_level(),
+            console_level: default_console_level(),
+            json: false,
+            rotation: default_rotation(),
+            keep_files: None,
+        }
+    }
+}
+
+fn level(name: &str) -> Result<LevelFilter> {
+    name.parse()
+        .map_err(|_| anyhow!("Unknown log level {name:?} (error, warn, info, debug, trace or off)"))
+}
+
+fn rotation(name: &str) -> Result<Rotation> {
+    match name.to_ascii_lowercase().as_str() {
+        "daily" => Ok(Rotation::DAILY),
+        "hourly" => Ok(Rotation::HOURLY),
+        "never" => Ok(Rotation::NEVER),
+        _ => Err(anyhow!("Unknown log rotation {name:?} (daily, hourly or never)")),
+    }
+}
+
+/// Settings that `init` can't use, by field; for `check`.
+pub fn problems(cfg: &LogConfig) -> Vec<(&'static str, String)> {
+    let mut found = Vec::new();
+    if let Err(e) = level(&cfg.level) { found.push(("level", e.to_string())); }
+    if let Err(e) = level(&cfg.console_level) { found.push(("console_level", e.to_string())); }
+    if let Err(e) = rotation(&cfg.rotation) { found.push(("rotation", e.to_string())); }
+    if cfg.file.as_ref().is_some_and(|f| f.file_name().is_none()) {
+        found.push(("file", "needs a file name, not only a folder".to_string()));
+    }
+    found
+}
+
+/// Send log events to the console and the log file. Once per run; the
+/// settings of a reloaded config apply after a restart. Settings that don't
+/// work are reported on the console, which falls back to warnings.
+pub fn init(cfg: &LogConfig) {
+    let mut problems = Vec::new();
+    let console_level = level(&cfg.console_level).unwrap_or_else(|e| {
+        problems.push(e);
+        LevelFilter::WARN
+    });
+    let console = tracing_subscriber::fmt::layer().with_writer(std::io::stderr).event_format(Console);
+    let file = cfg.file.as_ref().and_then(|path| file_layer(cfg, path).map_err(|e| problems.push(e)).ok());
+    // Only fails if already set up
+    let _ = tracing_subscriber::registry().with(file).with(console.with_filter(console_level)).try_init();
+    for e in problems { tracing::warn!("[log] {e:#}"); }
+}
+
+fn file_layer(cfg: &LogConfig, path: &Path) -> Result<Box<dyn Layer<Registry> + Send + Sync>> {
+    let name = path.file_name().ok_or_else(|| anyhow!("file {} has no file name", path.display()))?;
+    let dir = path.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let mut builder = RollingFileAppender::builder()
+        .rotation(rotation(&cfg.rotation)?)
+        .filename_prefix(name.to_string_lossy());
+    if let Some(n) = cfg.keep_files { builder = builder.max_log_files(n.max(1)); }
+    let writer = builder.build(dir).map_err(|e| anyhow!("can't open {}: {e}", path.display()))?;
+    let filter = match std::env::var("RUST_LOG") {
+        Ok(spec) => EnvFilter::try_new(spec).map_err(|e| anyhow!("RUST_LOG: {e}"))?,
+        // Other crates only get to warn
+        Err(_) => EnvFilter::new(format!("warn,{}={}", env!("CARGO_CRATE_NAME"), level(&cfg.level)?)),
+    };
+    let layer = tracing_subscriber::fmt::layer().with_writer(writer).with_ansi(false);
+    Ok(if cfg.json { layer.json().with_filter(filter).boxed() } else { layer.with_filter(filter).boxed() })
+}
+
+// Console lines as they always looked: "Warning: ..." and "Error: ..."
+struct Console;
+
+impl<S, N> FormatEvent<S, N> for Console
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(&self, ctx: &FmtContext<'_, S, N>, mut writer: Writer<'_>, event: &Event<'_>) -> fmt::Result {
+        match *event.metadata().level() {
+            Level::ERROR => write!(writer, "Error: ")?,
+            Level::WARN => write!(writer, "Warning: ")?,
+            _ => {}
+        }
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
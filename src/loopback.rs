@@ -0,0 +1,104 @@
+// ---------------------------- Loopback capture ----------------------------
+//
+// `loopback` listens to what the computer plays (a game, a DAW, a video)
+// instead of a microphone. WASAPI records an output device in loopback mode.
+// On Linux the PulseAudio ALSA plugin, which PipeWire serves as well,
+// records a monitor source, chosen through PULSE_SOURCE before the device is
+// opened. CoreAudio has no loopback of its own; a virtual device such as
+// BlackHole shows up as an ordinary input instead.
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+#[cfg(any(windows, target_os = "linux"))]
+use anyhow::Context;
+#[cfg(any(windows, target_os = "linux"))]
+use cpal::traits::{DeviceTrait, HostTrait};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Loopback {
+    // The default output device, or the default output's monitor
+    Default,
+    // An output device (by name, part of it, or number) or a monitor source
+    Named(String),
+}
+
+// What PulseAudio and PipeWire call the default output's monitor
+#[cfg(target_os = "linux")]
+const DEFAULT_MONITOR: &str = "@DEFAULT_MONITOR@";
+
+// loopback = true or loopback = "name"
+pub fn true_or_name<'de, D: serde::Deserializer<'de>>(d: D) -> std::result::Result<Option<Loopback>, D::Error> {
+    match toml::Value::deserialize(d)? {
+        toml::Value::Boolean(true) => Ok(Some(Loopback::Default)),
+        toml::Value::Boolean(false) => Ok(None),
+        toml::Value::String(s) => Ok(Some(Loopback::Named(s))),
+        other => Err(serde::de::Error::custom(format!("loopback must be true or the name of an output or monitor, not {other}"))),
+    }
+}
+
+/// The device that records `which`, and the config to open it with.
+#[cfg_attr(not(any(windows, target_os = "linux")), allow(unused_variables))]
+pub fn device(host: &cpal::Host, which: &Loopback) -> Result<(cpal::Device, cpal::SupportedStreamConfig)> {
+    #[cfg(windows)]
+    if host.id() == cpal::HostId::Wasapi {
+        let device = match which {
+            Loopback::Default => host.default_output_device().ok_or_else(|| anyhow!("No default output device"))?,
+            Loopback::Named(name) => {
+                let outputs = host.output_devices().context("Failed to list output devices")?.collect();
+                crate::find_device(outputs, name, "output")?
+            }
+        };
+        let config = device.default_output_config().context("Failed to get the output device's config")?;
+        println!("Loopback: recording what {} plays", device.name().unwrap_or_default());
+        return Ok((device, config));
+    }
+    #[cfg(target_os = "linux")]
+    if host.id() == cpal::HostId::Alsa {
+        let source = match which {
+            Loopback::Default => DEFAULT_MONITOR,
+            Loopback::Named(name) => name,
+        };
+        // Read by the plugin as the device opens; no other thread is running yet
+        std::env::set_var("PULSE_SOURCE", source);
+        let device = host
+            .input_devices()
+            .context("Failed to list input devices")?
+            .find(|d| d.name().is_ok_and(|n| n == "pulse"))
+            .ok_or_else(|| {
+                anyhow!(
+                    "loopback needs ALSA's \"pulse\" device, from the PulseAudio ALSA plugin (alsa-plugins-pulseaudio or libasound2-plugins, \
+                     also used with PipeWire's pipewire-pulse)"
+                )
+            })?;
+        let config = device.default_input_config().context("Failed to get default input config")?;
+        println!("Loopback: recording {source}");
+        return Ok((device, config));
+    }
+    Err(anyhow!(
+        "loopback isn't available through {}. With JACK, connect the playback (or monitor) ports to this program's \
+         inputs; on macOS, route the audio through a virtual device such as BlackHole and pick it with input_device",
+        host.id().name()
+    ))
+}
+
+/// For list-devices: what `loopback = "<name>"` can record.
+#[cfg_attr(not(any(windows, target_os = "linux")), allow(unused_variables))]
+pub fn list(host: &cpal::Host) {
+    #[cfg(windows)]
+    if host.id() == cpal::HostId::Wasapi {
+        let outputs: Vec<String> = host.output_devices().into_iter().flatten().filter_map(|d| d.name().ok()).collect();
+        println!("\nOutput devices (loopback = \"<name>\" or its number):");
+        for (i, name) in outputs.iter().enumerate() { println!("{i}: {name}"); }
+    }
+    #[cfg(target_os = "linux")]
+    if host.id() == cpal::HostId::Alsa {
+        // pactl comes with PulseAudio and pipewire-pulse; without it there's nothing to list
+        let Ok(out) = std::process::Command::new("pactl").args(["list", "short", "sources"]).output() else { return };
+        let text = String::from_utf8_lossy(&out.stdout);
+        let monitors: Vec<&str> = text.lines().filter_map(|l| l.split('\t').nth(1)).filter(|n| n.ends_with(".monitor")).collect();
+        if monitors.is_empty() { return; }
+        println!("\nMonitor sources (loopback = \"<name>\"; loopback = true records the default output's):");
+        for name in monitors { println!("   {name}"); }
+    }
+}
@@ -0,0 +1,175 @@
+// ---------------------------- Metronome ----------------------------
+//
+// Beat grid shared by the click track and quantized triggers. The grid starts
+// when the click track starts; beat n falls at start + n * 60/bpm.
+
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct MetronomeConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_bpm")]
+    pub bpm: f32,
+    #[serde(default = "default_beats_per_bar")]
+    pub beats_per_bar: u32,
+    // Clicks per beat (1 = quarter notes, 2 = eighths, ...)
+    #[serde(default = "default_subdivision")]
+    pub subdivision: u32,
+    // Bars of clicks before triggers are armed
+    #[serde(default = "default_count_in_bars")]
+    pub count_in_bars: u32,
+    // Play the click through the output device (false = silent grid only)
+    #[serde(default = "default_click")]
+    pub click: bool,
+    #[serde(default = "default_volume")]
+    pub volume: f32,
+    // Retune the grid and click to the tempo detected from the playing
+    #[serde(default)]
+    pub follow: bool,
+    // Tap intervals outside this tempo range are rejected
+    #[serde(default = "default_tap_min_bpm")]
+    pub tap_min_bpm: f32,
+    #[serde(default = "default_tap_max_bpm")]
+    pub tap_max_bpm: f32,
+}
+
+fn default_bpm() -> f32 { 100.0 }
+fn default_beats_per_bar() -> u32 { 4 }
+fn default_subdivision() -> u32 { 1 }
+fn default_count_in_bars() -> u32 { 1 }
+fn default_click() -> bool { true }
+fn default_volume() -> f32 { 0.5 }
+fn default_tap_min_bpm() -> f32 { 40.0 }
+fn default_tap_max_bpm() -> f32 { 240.0 }
+
+impl Default for MetronomeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bpm: default_bpm(),
+            beats_per_bar: default_beats_per_bar(),
+            subdivision: default_subdivision(),
+            count_in_bars: default_count_in_bars(),
+            click: default_click(),
+            volume: default_volume(),
+            follow: false,
+            tap_min_bpm: default_tap_min_bpm(),
+            tap_max_bpm: default_tap_max_bpm(),
+        }
+    }
+}
+
+// Grid a quantized mapping waits for
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Quantize {
+    // Fire immediately
+    #[default]
+    Off,
+    // Fire on the next beat
+    Beat,
+    // Fire on the next downbeat
+    Bar,
+}
+
+pub struct BeatGrid {
+    start: Instant,
+    beat: Duration,
+    beats_per_bar: u32,
+    count_in_beats: u32,
+}
+
+impl BeatGrid {
+    pub fn new(cfg: &MetronomeConfig, start: Instant) -> Self {
+        Self {
+            start,
+            beat: Duration::from_secs_f32(60.0 / cfg.bpm.max(1.0)),
+            beats_per_bar: cfg.beats_per_bar.max(1),
+            count_in_beats: cfg.count_in_bars * cfg.beats_per_bar.max(1),
+        }
+    }
+
+    /// Index of the beat that `now` falls in (0 = first beat of the count-in).
+    pub fn beat_index(&self, now: Instant) -> u64 {
+        (now.saturating_duration_since(self.start).as_secs_f64() / self.beat.as_secs_f64()) as u64
+    }
+
+    /// Change the tempo without jumping: the position within the current
+    /// beat is kept and only the following beats move.
+    pub fn set_bpm(&mut self, bpm: f32, now: Instant) {
+        let beats = now.saturating_duration_since(self.start).as_secs_f64() / self.beat.as_secs_f64();
+        self.beat = Duration::from_secs_f32(60.0 / bpm.max(1.0));
+        self.start = now - self.beat.mul_f64(beats);
+    }
+
+    /// True once the count-in bars have elapsed.
+    pub fn armed(&self, now: Instant) -> bool {
+        self.beat_index(now) >= self.count_in_beats as u64
+    }
+
+    /// Next grid point strictly after `now` (or `now` itself for Quantize::Off).
+    pub fn next(&self, q: Quantize, now: Instant) -> Instant {
+        let step = match q {
+            Quantize::Off => return now,
+            Quantize::Beat => 1,
+            Quantize::Bar => self.beats_per_bar as u64,
+        };
+        let next = (self.beat_index(now) / step + 1) * step;
+        self.start + self.beat.mul_f64(next as f64)
+    }
+}
+
+// Intervals averaged for the tap tempo
+const TAP_HISTORY: usize = 4;
+// Intervals further than this from the running average are rejected
+const TAP_TOLERANCE: f32 = 0.25;
+
+/// Tap-tempo state: the tempo is the average of the last few tap intervals.
+pub struct TapTempo {
+    shortest: Duration,
+    longest: Duration,
+    last_tap: Option<Instant>,
+    intervals: Vec<f32>,
+}
+
+impl TapTempo {
+    pub fn new(cfg: &MetronomeConfig) -> Self {
+        Self {
+            shortest: Duration::from_secs_f32(60.0 / cfg.tap_max_bpm.max(1.0)),
+            longest: Duration::from_secs_f32(60.0 / cfg.tap_min_bpm.max(1.0)),
+            last_tap: None,
+            intervals: Vec::new(),
+        }
+    }
+
+    /// Register a tap; returns the new tempo once two taps fall in range.
+    pub fn tap(&mut self, now: Instant) -> Option<f32> {
+        let Some(prev) = self.last_tap else {
+            self.last_tap = Some(now);
+            return None;
+        };
+        let gap = now.duration_since(prev);
+        // Too fast: a double attack on one tap, ignore it
+        if gap < self.shortest { return None; }
+        self.last_tap = Some(now);
+        // Too slow: start a new tap sequence
+        if gap > self.longest {
+            self.intervals.clear();
+            return None;
+        }
+        let secs = gap.as_secs_f32();
+        if !self.intervals.is_empty() {
+            let mean = self.intervals.iter().sum::<f32>() / self.intervals.len() as f32;
+            if (secs - mean).abs() > TAP_TOLERANCE * mean {
+                // A different tempo: keep tapping to replace the old one
+                self.intervals.clear();
+            }
+        }
+        if self.intervals.len() == TAP_HISTORY { self.intervals.remove(0); }
+        self.intervals.push(secs);
+        let mean = self.intervals.iter().sum::<f32>() / self.intervals.len() as f32;
+        Some(60.0 / mean)
+    }
+}
@@ -0,0 +1,214 @@
+// ---------------------------- MIDI output ----------------------------
+//
+// One MIDI output for the whole program: `midi` mappings and the audio-to-MIDI
+// mode (mode = "midi") send their messages through it. On Linux and macOS it
+// is a virtual port other programs can connect to; on Windows (no virtual
+// ports) connect it to an existing port, e.g. one made with loopMIDI.
+
+use anyhow::{anyhow, Result};
+use midir::{MidiOutput, MidiOutputConnection};
+use serde::Deserialize;
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct MidiConfig {
+    // Connect to the first output port whose name contains this (required on
+    // Windows); unset = create a virtual port
+    #[serde(default)]
+    pub port: Option<String>,
+    // Name of the virtual port
+    #[serde(default = "default_port_name")]
+    pub port_name: String,
+    // Channel 1-16 for messages that don't choose their own
+    #[serde(default = "default_channel")]
+    pub channel: u8,
+    // Audio-to-MIDI mode: follow the pitch between notes with pitch bend
+    #[serde(default = "default_pitch_bend")]
+    pub pitch_bend: bool,
+    // Pitch-bend range of the receiving synth, in semitones
+    #[serde(default = "default_bend_range")]
+    pub bend_range: f32,
+    // With pitch bend, keep the sounding note until the pitch is this many
+    // cents away, so vibrato and slides bend instead of retriggering
+    #[serde(default = "default_note_change_cents")]
+    pub note_change_cents: f32,
+    // Input levels (dBFS RMS) mapped to velocity 1 and 127
+    #[serde(default = "default_velocity_min_db")]
+    pub velocity_min_db: f32,
+    #[serde(default = "default_velocity_max_db")]
+    pub velocity_max_db: f32,
+}
+
+fn default_port_name() -> String { "Rusty Strings Control".to_string() }
+fn default_channel() -> u8 { 1 }
+fn default_pitch_bend() -> bool { true }
+fn default_bend_range() -> f32 { 2.0 }
+fn default_note_change_cents() -> f32 { 80.0 }
+fn default_velocity_min_db() -> f32 { -50.0 }
+fn default_velocity_max_db() -> f32 { -10.0 }
+
+impl Default for MidiConfig {
+    fn default() -> Self {
+        Self {
+            port: None,
+            port_name: default_port_name(),
+            channel: default_channel(),
+            pitch_bend: default_pitch_bend(),
+            bend_range: default_bend_range(),
+            note_change_cents: default_note_change_cents(),
+            velocity_min_db: default_velocity_min_db(),
+            velocity_max_db: default_velocity_max_db(),
+        }
+    }
+}
+
+/// Parameters of a `type = "midi"` mapping.
+#[derive(Debug, Deserialize, Clone)]
+pub struct MidiAction {
+    #[serde(default)]
+    pub message: Message,
+    // Note name for note messages
+    #[serde(default)]
+    pub note: Option<String>,
+    #[serde(default = "default_velocity")]
+    pub velocity: u8,
+    // Controller number and value for "cc"
+    #[serde(default)]
+    pub controller: Option<u8>,
+    #[serde(default = "default_value")]
+    pub value: u8,
+    // 1-16; unset = [midi] channel
+    #[serde(default)]
+    pub channel: Option<u8>,
+    // How long a "note" message holds the note before its note off
+    #[serde(default = "default_length_ms")]
+    pub length_ms: u64,
+}
+
+fn default_velocity() -> u8 { 100 }
+fn default_value() -> u8 { 127 }
+fn default_length_ms() -> u64 { 250 }
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Message {
+    // Note on, then note off after length_ms
+    #[default]
+    Note,
+    NoteOn,
+    NoteOff,
+    Cc,
+}
+
+impl MidiAction {
+    pub fn label(&self) -> String {
+        let note = self.note.as_deref().unwrap_or("?");
+        match self.message {
+            Message::Note => format!("midi:note {note}"),
+            Message::NoteOn => format!("midi:note-on {note}"),
+            Message::NoteOff => format!("midi:note-off {note}"),
+            Message::Cc => format!("midi:cc {}={}", self.controller.map_or("?".to_string(), |c| c.to_string()), self.value),
+        }
+    }
+}
+
+struct Output {
+    conn: MidiOutputConnection,
+    // Zero-based default channel
+    channel: u8,
+}
+
+static OUTPUT: Mutex<Option<Output>> = Mutex::new(None);
+
+/// Open the output port if it isn't open yet.
+pub fn open(cfg: &MidiConfig) -> Result<()> {
+    let mut out = OUTPUT.lock().unwrap_or_else(|e| e.into_inner());
+    if out.is_some() { return Ok(()); }
+    let channel = cfg.channel.clamp(1, 16) - 1;
+    let midi = MidiOutput::new("Rusty Strings Control").map_err(|e| anyhow!("MIDI unavailable: {e}"))?;
+    let conn = match &cfg.port {
+        Some(wanted) => {
+            let port = midi
+                .ports()
+                .into_iter()
+                .find(|p| midi.port_name(p).is_ok_and(|n| n.contains(wanted.as_str())))
+                .ok_or_else(|| anyhow!("No MIDI output port matching {wanted:?}"))?;
+            let name = midi.port_name(&port).unwrap_or_default();
+            let conn = midi.connect(&port, &cfg.port_name).map_err(|e| anyhow!("Cannot open MIDI port {name}: {e}"))?;
+            println!("MIDI output: {name}");
+            conn
+        }
+        None => virtual_port(midi, &cfg.port_name)?,
+    };
+    *out = Some(Output { conn, channel });
+    Ok(())
+}
+
+#[cfg(unix)]
+fn virtual_port(midi: MidiOutput, name: &str) -> Result<MidiOutputConnection> {
+    use midir::os::unix::VirtualOutput;
+    let conn = midi.create_virtual(name).map_err(|e| anyhow!("Cannot create virtual MIDI port: {e}"))?;
+    println!("MIDI output: virtual port \"{name}\"");
+    Ok(conn)
+}
+
+#[cfg(not(unix))]
+fn virtual_port(_midi: MidiOutput, _name: &str) -> Result<MidiOutputConnection> {
+    Err(anyhow!("Virtual MIDI ports aren't available here; set [midi] port to an existing port (e.g. loopMIDI)"))
+}
+
+/// Send raw bytes; `channel` (0-15) replaces the low nibble of the status
+/// byte, None = the configured channel.
+pub fn send(mut msg: [u8; 3], channel: Option<u8>) -> Result<()> {
+    let mut out = OUTPUT.lock().unwrap_or_else(|e| e.into_inner());
+    let out = out.as_mut().ok_or_else(|| anyhow!("MIDI output is not open"))?;
+    msg[0] = (msg[0] & 0xF0) | channel.unwrap_or(out.channel) & 0x0F;
+    out.conn.send(&msg).map_err(|e| anyhow!("MIDI send failed: {e}"))
+}
+
+/// Carry out a `midi` mapping.
+pub fn execute(action: &MidiAction) -> Result<()> {
+    let channel = action.channel.map(|c| c.clamp(1, 16) - 1);
+    let note = || -> Result<u8> {
+        let name = action.note.as_deref().ok_or_else(|| anyhow!("midi {:?} mapping needs a note", action.message))?;
+        let midi = crate::note_to_midi(name).ok_or_else(|| anyhow!("Not a note name: {name:?}"))?;
+        u8::try_from(midi).ok().filter(|m| *m < 128).ok_or_else(|| anyhow!("{name} is outside the MIDI range"))
+    };
+    let velocity = action.velocity.min(127);
+    match action.message {
+        Message::NoteOn => send([0x90, note()?, velocity], channel),
+        Message::NoteOff => send([0x80, note()?, 0], channel),
+        Message::Cc => {
+            let controller = action.controller.ok_or_else(|| anyhow!("midi cc mapping needs a controller"))?;
+            send([0xB0, controller.min(127), action.value.min(127)], channel)
+        }
+        Message::Note => {
+            let note = note()?;
+            send([0x90, note, velocity], channel)?;
+            let length = Duration::from_millis(action.length_ms);
+            std::thread::spawn(move || {
+                std::thread::sleep(length);
+                if let Err(e) = send([0x80, note, 0], channel) { tracing::error!("{e:#}"); }
+            });
+            Ok(())
+        }
+    }
+}
+
+/// Velocity 1-127 for an RMS input level.
+pub fn velocity(cfg: &MidiConfig, rms: f32) -> u8 {
+    let db = 20.0 * rms.max(1e-9).log10();
+    let span = (cfg.velocity_max_db - cfg.velocity_min_db).max(1.0);
+    (1.0 + 126.0 * ((db - cfg.velocity_min_db) / span).clamp(0.0, 1.0)).round() as u8
+}
+
+/// 14-bit pitch-bend value for an offset in cents (8192 = center).
+pub fn bend_value(cents: f32, range_semitones: f32) -> u16 {
+    let amount = cents / (100.0 * range_semitones.max(0.01));
+    (8192.0 + amount * 8192.0).round().clamp(0.0, 16383.0) as u16
+}
+
+pub fn pitch_bend(value: u16) -> Result<()> {
+    send([0xE0, (value & 0x7F) as u8, (value >> 7) as u8], None)
+}
@@ -0,0 +1,246 @@
+// ---------------------------- Morse text entry ----------------------------
+//
+// Short and long articulations of a single note are read as dots and dashes.
+// A pause longer than `letter_gap_ms` ends the current letter; a pause longer
+// than `word_gap_ms` also inserts a space. `[morse.alphabet]` codes can also
+// press keys, e.g. "..--" = { keys = "Backspace" }.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct MorseConfig {
+    // Only this note keys Morse (e.g., "A4"); any in-tune note if unset
+    #[serde(default)]
+    pub note: Option<String>,
+    // Notes held shorter than this are dots, longer are dashes
+    #[serde(default = "default_dot_max_ms")]
+    pub dot_max_ms: u64,
+    // Silence that completes a letter
+    #[serde(default = "default_letter_gap_ms")]
+    pub letter_gap_ms: u64,
+    // Silence that completes a word (types a space)
+    #[serde(default = "default_word_gap_ms")]
+    pub word_gap_ms: u64,
+    // Extra or replacement codes, e.g. ".-.-" = "\n" or "..--" = { keys = "Backspace" }
+    // Codes use '.' for short and '-' for long; entries override the built-in table.
+    #[serde(default)]
+    pub alphabet: HashMap<String, Entry>,
+}
+
+/// What a code enters: text typed as written, or a key sequence ("Backspace",
+/// "Ctrl+Z") pressed like a keys mapping.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Entry {
+    Text(String),
+    Keys { keys: String },
+}
+
+fn default_dot_max_ms() -> u64 { 250 }
+fn default_letter_gap_ms() -> u64 { 700 }
+fn default_word_gap_ms() -> u64 { 1600 }
+
+impl Default for MorseConfig {
+    fn default() -> Self {
+        Self {
+            note: None,
+            dot_max_ms: default_dot_max_ms(),
+            letter_gap_ms: default_letter_gap_ms(),
+            word_gap_ms: default_word_gap_ms(),
+            alphabet: HashMap::new(),
+        }
+    }
+}
+
+// International Morse code, lower-case letters, digits and common punctuation
+const ITU_TABLE: &[(&str, &str)] = &[
+    (".-", "a"), ("-...", "b"), ("-.-.", "c"), ("-..", "d"), (".", "e"),
+    ("..-.", "f"), ("--.", "g"), ("....", "h"), ("..", "i"), (".---", "j"),
+    ("-.-", "k"), (".-..", "l"), ("--", "m"), ("-.", "n"), ("---", "o"),
+    (".--.", "p"), ("--.-", "q"), (".-.", "r"), ("...", "s"), ("-", "t"),
+    ("..-", "u"), ("...-", "v"), (".--", "w"), ("-..-", "x"), ("-.--", "y"),
+    ("--..", "z"),
+    ("-----", "0"), (".----", "1"), ("..---", "2"), ("...--", "3"), ("....-", "4"),
+    (".....", "5"), ("-....", "6"), ("--...", "7"), ("---..", "8"), ("----.", "9"),
+    (".-.-.-", "."), ("--..--", ","), ("..--..", "?"), (".----.", "'"), ("-.-.--", "!"),
+    ("-..-.", "/"), ("---...", ":"), ("-...-", "="), (".-.-.", "+"), ("-....-", "-"),
+    (".--.-.", "@"),
+];
+
+pub struct MorseDecoder {
+    dot_max: Duration,
+    letter_gap: Duration,
+    word_gap: Duration,
+    table: HashMap<String, Entry>,
+    // When the current tone started, if the key is down
+    tone_start: Option<Instant>,
+    // When the last tone ended
+    last_release: Option<Instant>,
+    // Dots and dashes of the letter being entered
+    code: String,
+    // Code of the most recently decoded letter (for display)
+    last_code: String,
+    // A letter was typed since the last word gap
+    word_open: bool,
+}
+
+impl MorseDecoder {
+    pub fn new(cfg: &MorseConfig) -> Self {
+        let mut table: HashMap<String, Entry> = ITU_TABLE
+            .iter()
+            .map(|(c, t)| (c.to_string(), Entry::Text(t.to_string())))
+            .collect();
+        table.extend(cfg.alphabet.clone());
+        Self {
+            dot_max: Duration::from_millis(cfg.dot_max_ms),
+            letter_gap: Duration::from_millis(cfg.letter_gap_ms),
+            word_gap: Duration::from_millis(cfg.word_gap_ms),
+            table,
+            tone_start: None,
+            last_release: None,
+            code: String::new(),
+            last_code: String::new(),
+            word_open: false,
+        }
+    }
+
+    /// Feed one analysis frame. Returns what to enter when a letter or word completes.
+    pub fn update(&mut self, key_down: bool, now: Instant) -> Option<Entry> {
+        match (key_down, self.tone_start) {
+            (true, None) => {
+                self.tone_start = Some(now);
+                None
+            }
+            (true, Some(_)) => None,
+            (false, Some(start)) => {
+                let held = now.duration_since(start);
+                self.code.push(if held < self.dot_max { '.' } else { '-' });
+                self.tone_start = None;
+                self.last_release = Some(now);
+                None
+            }
+            (false, None) => {
+                let silent = now.duration_since(self.last_release?);
+                if !self.code.is_empty() && silent >= self.letter_gap {
+                    self.last_code = std::mem::take(&mut self.code);
+                    self.word_open = true;
+                    // Unknown codes are dropped rather than guessed
+                    return self.table.get(&self.last_code).cloned();
+                }
+                if self.word_open && silent >= self.word_gap {
+                    self.word_open = false;
+                    self.last_code.clear();
+                    return Some(Entry::Text(" ".to_string()));
+                }
+                None
+            }
+        }
+    }
+
+    /// Dots and dashes entered so far for the current letter.
+    pub fn pending(&self) -> &str { &self.code }
+
+    /// Code of the most recently completed letter.
+    pub fn last_code(&self) -> &str { &self.last_code }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Frames 10 ms apart
+    const FRAME: Duration = Duration::from_millis(10);
+
+    struct Keyer {
+        decoder: MorseDecoder,
+        now: Instant,
+        entered: Vec<Entry>,
+    }
+
+    impl Keyer {
+        fn new(cfg: &MorseConfig) -> Self {
+            Self { decoder: MorseDecoder::new(cfg), now: Instant::now(), entered: Vec::new() }
+        }
+
+        // Hold the note for `down_ms`, then stay silent for `up_ms`
+        fn play(&mut self, down_ms: u64, up_ms: u64) -> &mut Self {
+            for (down, ms) in [(true, down_ms), (false, up_ms)] {
+                for _ in 0..ms / 10 {
+                    self.entered.extend(self.decoder.update(down, self.now));
+                    self.now += FRAME;
+                }
+            }
+            self
+        }
+
+        fn text(&self) -> String {
+            self.entered
+                .iter()
+                .map(|e| match e {
+                    Entry::Text(t) => t.clone(),
+                    Entry::Keys { keys } => format!("<{keys}>"),
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn short_notes_are_dots_and_long_ones_dashes() {
+        let mut k = Keyer::new(&MorseConfig::default());
+        // dot_max_ms is 250
+        k.play(100, 100).play(240, 100).play(260, 100).play(600, 100);
+        assert_eq!(k.decoder.pending(), "..--");
+        assert!(k.entered.is_empty());
+    }
+
+    #[test]
+    fn gaps_end_letters_and_words() {
+        let mut k = Keyer::new(&MorseConfig::default());
+        // "a": gaps within the letter stay below letter_gap_ms (700)
+        k.play(100, 300).play(400, 690);
+        assert!(k.entered.is_empty(), "letter ended early: {:?}", k.text());
+        k.play(0, 20);
+        assert_eq!(k.text(), "a");
+        assert_eq!(k.decoder.last_code(), ".-");
+        // "t", then a word gap (1600 ms of silence) types one space only
+        k.play(400, 1590);
+        assert_eq!(k.text(), "at");
+        k.play(0, 20);
+        assert_eq!(k.text(), "at ");
+        k.play(0, 3000);
+        assert_eq!(k.text(), "at ");
+        // No space at the start either
+        let mut k = Keyer::new(&MorseConfig::default());
+        k.play(0, 3000);
+        assert!(k.entered.is_empty());
+    }
+
+    #[test]
+    fn unknown_codes_are_dropped() {
+        let mut k = Keyer::new(&MorseConfig::default());
+        // "----" isn't International Morse
+        k.play(400, 100).play(400, 100).play(400, 100).play(400, 800);
+        assert_eq!(k.text(), "");
+        assert_eq!(k.decoder.pending(), "");
+    }
+
+    #[test]
+    fn the_alphabet_adds_text_and_keys() {
+        let cfg: MorseConfig = toml::from_str(
+            r#"
+            [alphabet]
+            "..--" = { keys = "Backspace" }
+            ".-.-" = "\n"
+            "." = "E"
+            "#,
+        )
+        .unwrap();
+        let mut k = Keyer::new(&cfg);
+        k.play(100, 100).play(100, 100).play(400, 100).play(400, 800);
+        k.play(100, 100).play(400, 100).play(100, 100).play(400, 800);
+        k.play(100, 800);
+        assert_eq!(k.text(), "<Backspace>\nE");
+    }
+}
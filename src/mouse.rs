@@ -0,0 +1,69 @@
+// ---------------------------- Mouse actions ----------------------------
+//
+// `type = "mouse"` mappings: move the pointer, click and scroll. The steps a
+// mapping sets run in that order, so one note can move to a spot and click.
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Button {
+    Left,
+    Right,
+    Middle,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct MouseAction {
+    // Move to this screen position [x, y] in pixels
+    #[serde(default)]
+    pub move_to: Option<[i32; 2]>,
+    // Move by [dx, dy] pixels from where the pointer is
+    #[serde(default)]
+    pub move_by: Option<[i32; 2]>,
+    #[serde(default)]
+    pub click: Option<Button>,
+    // Click twice
+    #[serde(default)]
+    pub double: bool,
+    // Wheel steps; positive scrolls down / right
+    #[serde(default)]
+    pub scroll: i32,
+    #[serde(default)]
+    pub scroll_x: i32,
+}
+
+impl MouseAction {
+    pub fn label(&self) -> String {
+        let mut steps = Vec::new();
+        if let Some([x, y]) = self.move_to { steps.push(format!("to {x},{y}")); }
+        if let Some([dx, dy]) = self.move_by { steps.push(format!("by {dx:+},{dy:+}")); }
+        if let Some(b) = self.click {
+            let b = format!("{b:?}").to_lowercase();
+            steps.push(if self.double { format!("double {b}") } else { b });
+        }
+        if self.scroll != 0 { steps.push(format!("scroll {:+}", self.scroll)); }
+        if self.scroll_x != 0 { steps.push(format!("scroll-x {:+}", self.scroll_x)); }
+        if steps.is_empty() { steps.push("nothing".to_string()); }
+        format!("mouse:{}", steps.join(" "))
+    }
+}
+
+#[cfg(windows)]
+pub fn execute(enigo: &mut enigo::Enigo, action: &MouseAction) -> anyhow::Result<()> {
+    use enigo::{MouseButton, MouseControllable};
+    if let Some([x, y]) = action.move_to { enigo.mouse_move_to(x, y); }
+    if let Some([dx, dy]) = action.move_by { enigo.mouse_move_relative(dx, dy); }
+    if let Some(b) = action.click {
+        let button = match b {
+            Button::Left => MouseButton::Left,
+            Button::Right => MouseButton::Right,
+            Button::Middle => MouseButton::Middle,
+        };
+        enigo.mouse_click(button);
+        if action.double { enigo.mouse_click(button); }
+    }
+    if action.scroll != 0 { enigo.mouse_scroll_y(action.scroll); }
+    if action.scroll_x != 0 { enigo.mouse_scroll_x(action.scroll_x); }
+    Ok(())
+}
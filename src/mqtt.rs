@@ -0,0 +1,326 @@
+// ---------------------------- MQTT ----------------------------
+//
+// `[mqtt]` publishes what is played to an MQTT broker, the common ground of
+// home-automation systems (Home Assistant, Node-RED, openHAB):
+//
+//   <note_topic>      {"note":"A4","hz":440.2,"cents":0.8} when the heard note
+//                     changes, {"note":null} when it stops
+//   <trigger_topic>   {"note":"A4","action":"keys:Space"} when a mapping fires
+//
+// Performers and hexaphonic strings add "performer". With `command_topic`
+// set, messages published there are run as control commands ("pause",
+// "profile daw", "note E4"...). MQTT 3.1.1 at QoS 0 over plain TCP is all
+// this needs, and small enough to write here. A lost connection is retried
+// every few seconds; events meanwhile are dropped.
+
+use anyhow::{anyhow, Context, Result};
+use crossbeam_channel::{bounded, Receiver, RecvTimeoutError, Sender};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use crate::control;
+use crate::pipeline::NoteEvent;
+use crate::status::NoteChanges;
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct MqttConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    // host:port of the broker
+    #[serde(default = "default_broker")]
+    pub broker: String,
+    #[serde(default = "default_client_id")]
+    pub client_id: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default = "default_note_topic")]
+    pub note_topic: String,
+    #[serde(default = "default_trigger_topic")]
+    pub trigger_topic: String,
+    // Run messages on this topic as control commands (none if unset)
+    #[serde(default)]
+    pub command_topic: Option<String>,
+}
+
+fn default_broker() -> String { "127.0.0.1:1883".to_string() }
+fn default_client_id() -> String { "rusty-strings-control".to_string() }
+fn default_note_topic() -> String { "rusty-strings-control/note".to_string() }
+fn default_trigger_topic() -> String { "rusty-strings-control/trigger".to_string() }
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            broker: default_broker(),
+            client_id: default_client_id(),
+            username: None,
+            password: None,
+            note_topic: default_note_topic(),
+            trigger_topic: default_trigger_topic(),
+            command_topic: None,
+        }
+    }
+}
+
+// Events waiting to go out; more than this and new ones are dropped
+const QUEUE: usize = 256;
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+const RETRY: Duration = Duration::from_secs(5);
+const TIMEOUT: Duration = Duration::from_secs(5);
+// Longest packet taken from the broker: plenty for a command, and a broken
+// or hostile one can't make us allocate hundreds of MiB
+const MAX_PACKET: usize = 64 * 1024;
+
+// Packet types, in the high nibble of the first byte
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const SUBSCRIBE: u8 = 0x82;
+const PINGREQ: u8 = 0xC0;
+
+// A message for the broker: topic and payload
+type Message = (String, String);
+
+static EVENTS: OnceLock<Sender<(Option<String>, NoteEvent)>> = OnceLock::new();
+
+/// Connect and publish events for as long as the program runs.
+pub fn start(cfg: &MqttConfig) {
+    if !cfg.enabled || EVENTS.get().is_some() { return; }
+    let (tx, rx) = bounded(QUEUE);
+    let cfg = cfg.clone();
+    std::thread::spawn(move || run(&cfg, rx));
+    EVENTS.set(tx).ok();
+}
+
+/// Whether events are being published.
+pub fn active() -> bool { EVENTS.get().is_some() }
+
+/// Publish `event` (triggers and changes of note only); `performer` labels it.
+pub fn publish(performer: Option<&str>, event: &NoteEvent) {
+    let Some(tx) = EVENTS.get() else { return };
+    if matches!(event, NoteEvent::Status(_)) { return; }
+    tx.try_send((performer.map(str::to_string), event.clone())).ok();
+}
+
+// Turns events into messages: only a new note, or the end of one, goes out
+struct Notes<'a> {
+    cfg: &'a MqttConfig,
+    changes: NoteChanges,
+}
+
+impl Notes<'_> {
+    fn message(&mut self, performer: Option<String>, event: NoteEvent) -> Option<Message> {
+        if !self.changes.changed(performer.as_deref(), &event) { return None; }
+        let (topic, mut value) = match event {
+            NoteEvent::Pitch { hz, note, cents, .. } => {
+                (&self.cfg.note_topic, json!({ "note": note, "hz": hz, "cents": cents }))
+            }
+            NoteEvent::Silence => (&self.cfg.note_topic, json!({ "note": Value::Null })),
+            NoteEvent::Trigger { note, action } => {
+                (&self.cfg.trigger_topic, json!({ "note": note, "action": crate::action_name(&action) }))
+            }
+            NoteEvent::Status(_) => return None,
+        };
+        if let Some(p) = performer { value["performer"] = p.into(); }
+        Some((topic.clone(), value.to_string()))
+    }
+}
+
+fn run(cfg: &MqttConfig, events: Receiver<(Option<String>, NoteEvent)>) {
+    let mut notes = Notes { cfg, changes: NoteChanges::default() };
+    let mut warned = false;
+    loop {
+        match connect(cfg) {
+            Ok(stream) => {
+                tracing::info!("MQTT: connected to {}", cfg.broker);
+                warned = false;
+                if let Err(e) = session(cfg, stream, &events, &mut notes) {
+                    tracing::warn!("MQTT: {e:#}; reconnecting");
+                }
+            }
+            // Said once until it works, not every retry
+            Err(e) if !warned => {
+                tracing::warn!("MQTT: {e:#}; retrying every {} s", RETRY.as_secs());
+                warned = true;
+            }
+            Err(_) => {}
+        }
+        // Whatever happened meanwhile is stale
+        std::thread::sleep(RETRY);
+        events.try_iter().for_each(drop);
+    }
+}
+
+fn connect(cfg: &MqttConfig) -> Result<TcpStream> {
+    let mut stream = TcpStream::connect(&cfg.broker).with_context(|| format!("cannot connect to {}", cfg.broker))?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    stream.set_nodelay(true)?;
+    // Clean session, and a user name and password if there are any
+    let mut flags = 0x02;
+    let mut payload = string(&cfg.client_id);
+    if let Some(user) = &cfg.username {
+        flags |= 0x80;
+        payload.extend(string(user));
+    }
+    if let Some(password) = &cfg.password {
+        flags |= 0x40;
+        payload.extend(string(password));
+    }
+    let mut body = string("MQTT");
+    body.extend([4, flags]);
+    body.extend((KEEP_ALIVE.as_secs() as u16).to_be_bytes());
+    body.extend(payload);
+    stream.write_all(&packet(CONNECT, &body))?;
+    let (kind, reply) = read_packet(&mut stream)?;
+    match (kind & 0xF0, reply.get(1)) {
+        (CONNACK, Some(0)) => {}
+        (CONNACK, Some(5)) => return Err(anyhow!("{} refused the user name or password", cfg.broker)),
+        (CONNACK, Some(code)) => return Err(anyhow!("{} refused the connection (code {code})", cfg.broker)),
+        _ => return Err(anyhow!("{} didn't answer as an MQTT broker", cfg.broker)),
+    }
+    if let Some(topic) = &cfg.command_topic {
+        // Packet id 1, QoS 0
+        let mut body = vec![0, 1];
+        body.extend(string(topic));
+        body.push(0);
+        stream.write_all(&packet(SUBSCRIBE, &body))?;
+    }
+    Ok(stream)
+}
+
+// Until the connection fails
+fn session(
+    cfg: &MqttConfig,
+    mut stream: TcpStream,
+    events: &Receiver<(Option<String>, NoteEvent)>,
+    notes: &mut Notes,
+) -> Result<()> {
+    let mut reader = stream.try_clone()?;
+    // The broker pings back, so silence for a whole keep-alive means it's gone
+    reader.set_read_timeout(Some(KEEP_ALIVE + TIMEOUT))?;
+    let (failed_tx, failed) = bounded::<anyhow::Error>(1);
+    let command_topic = cfg.command_topic.clone();
+    std::thread::spawn(move || {
+        let e = receive(&mut reader, command_topic.as_deref());
+        failed_tx.send(e).ok();
+    });
+    let result = send(&mut stream, events, notes, &failed);
+    // Ends the reader too
+    stream.shutdown(Shutdown::Both).ok();
+    result
+}
+
+fn send(
+    stream: &mut TcpStream,
+    events: &Receiver<(Option<String>, NoteEvent)>,
+    notes: &mut Notes,
+    failed: &Receiver<anyhow::Error>,
+) -> Result<()> {
+    let mut last_sent = Instant::now();
+    loop {
+        if let Ok(e) = failed.try_recv() { return Err(e); }
+        match events.recv_timeout(KEEP_ALIVE / 2) {
+            Ok((performer, event)) => {
+                let Some((topic, payload)) = notes.message(performer, event) else { continue };
+                let mut body = string(&topic);
+                body.extend(payload.as_bytes());
+                stream.write_all(&packet(PUBLISH, &body))?;
+                last_sent = Instant::now();
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        if last_sent.elapsed() >= KEEP_ALIVE / 2 {
+            stream.write_all(&[PINGREQ, 0])?;
+            last_sent = Instant::now();
+        }
+    }
+}
+
+// Read packets until the connection fails, running commands as they come
+fn receive(stream: &mut TcpStream, command_topic: Option<&str>) -> anyhow::Error {
+    loop {
+        let (kind, body) = match read_packet(stream) {
+            Ok(p) => p,
+            Err(e) => return e,
+        };
+        if kind & 0xF0 != PUBLISH { continue; }
+        let Some((topic, rest)) = split_string(&body) else { continue };
+        // QoS 1 and 2 carry a packet id first; a QoS 0 subscription shouldn't get them
+        let payload = if kind & 0x06 != 0 { rest.get(2..).unwrap_or_default() } else { rest };
+        if Some(topic.as_str()) != command_topic { continue; }
+        let command = String::from_utf8_lossy(payload);
+        match control::run(&command) {
+            Ok(reply) => tracing::info!("MQTT command {:?}: {reply}", command.trim()),
+            Err(e) => tracing::warn!("MQTT command {:?}: {e:#}", command.trim()),
+        }
+    }
+}
+
+// A packet of `kind` around `body`
+fn packet(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut out = vec![kind];
+    // Remaining length: 7 bits at a time, low first
+    let mut len = body.len();
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        out.push(if len > 0 { byte | 0x80 } else { byte });
+        if len == 0 { break; }
+    }
+    out.extend_from_slice(body);
+    out
+}
+
+fn read_packet(stream: &mut impl Read) -> Result<(u8, Vec<u8>)> {
+    let mut byte = [0u8; 1];
+    stream.read_exact(&mut byte).context("connection lost")?;
+    let kind = byte[0];
+    let mut len = 0usize;
+    for shift in (0..28).step_by(7) {
+        stream.read_exact(&mut byte)?;
+        len |= ((byte[0] & 0x7F) as usize) << shift;
+        if byte[0] & 0x80 == 0 { break; }
+    }
+    // The rest can't be skipped without reading it, so the connection goes
+    if len > MAX_PACKET { return Err(anyhow!("the broker sent a {len}-byte packet (at most {MAX_PACKET} taken)")); }
+    let mut body = vec![0u8; len];
+    stream.read_exact(&mut body)?;
+    Ok((kind, body))
+}
+
+// A length-prefixed UTF-8 string
+fn string(s: &str) -> Vec<u8> {
+    let mut out = (s.len() as u16).to_be_bytes().to_vec();
+    out.extend_from_slice(s.as_bytes());
+    out
+}
+
+fn split_string(bytes: &[u8]) -> Option<(String, &[u8])> {
+    let len = u16::from_be_bytes([*bytes.first()?, *bytes.get(1)?]) as usize;
+    let s = bytes.get(2..2 + len)?;
+    Some((String::from_utf8_lossy(s).into_owned(), &bytes[2 + len..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oversized_packets_are_refused() {
+        let publish = packet(PUBLISH, b"\0\x03cmdpause");
+        let (kind, body) = read_packet(&mut publish.as_slice()).unwrap();
+        assert_eq!((kind, body.as_slice()), (PUBLISH, &b"\0\x03cmdpause"[..]));
+        // A remaining length of 256 MiB - 1, with no body behind it
+        let huge = [PUBLISH, 0xFF, 0xFF, 0xFF, 0x7F];
+        let e = read_packet(&mut huge.as_slice()).unwrap_err();
+        assert!(e.to_string().contains("268435455-byte"), "{e}");
+    }
+}
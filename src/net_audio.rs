@@ -0,0 +1,251 @@
+// ---------------------------- Network audio ----------------------------
+//
+// Audio from another machine: raw PCM over UDP or TCP in place of a local
+// input device, for when the instrument and the computer being controlled
+// are in different rooms. Every UDP datagram, and the start of every TCP
+// connection, carries a 16-byte header:
+//
+//   0   "RSCA"
+//   4   sample rate, u32
+//   8   channels, u16
+//   10  sample format, u8: 0 = 16-bit signed, 1 = 32-bit float
+//   11  0
+//   12  frame number, u32: of the datagram's first frame (0 over TCP)
+//
+// followed by interleaved samples. Everything is little-endian. The first
+// header fixes the format for the session, and audio in any other format is
+// ignored. Frame numbers that skip ahead are lost datagrams; they count as
+// dropped input, so no window joins audio from either side of the gap.
+// `send-audio` sends the local input this way.
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream, UdpSocket};
+
+use crate::pcm::{self, Feed, Format, SampleFormat};
+use crate::ring;
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct NetworkInputConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    // Address and port to receive on
+    #[serde(default = "default_listen")]
+    pub listen: String,
+    #[serde(default)]
+    pub protocol: Protocol,
+}
+
+fn default_listen() -> String { "0.0.0.0:7400".to_string() }
+
+impl Default for NetworkInputConfig {
+    fn default() -> Self {
+        Self { enabled: false, listen: default_listen(), protocol: Protocol::default() }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    #[default]
+    Udp,
+    Tcp,
+}
+
+impl Protocol {
+    fn name(self) -> &'static str {
+        match self {
+            Protocol::Udp => "udp",
+            Protocol::Tcp => "tcp",
+        }
+    }
+}
+
+const MAGIC: &[u8; 4] = b"RSCA";
+const HEADER_LEN: usize = 16;
+// Larger than any datagram
+const MAX_DATAGRAM: usize = 65536;
+// A frame number further ahead than this is a restarted sender, not a gap
+const MAX_GAP_SECS: u64 = 10;
+
+fn header(format: Format, frame: u32) -> [u8; HEADER_LEN] {
+    let mut h = [0u8; HEADER_LEN];
+    h[..4].copy_from_slice(MAGIC);
+    h[4..8].copy_from_slice(&format.sample_rate.to_le_bytes());
+    h[8..10].copy_from_slice(&format.channels.to_le_bytes());
+    h[10] = match format.sample {
+        SampleFormat::S16 => 0,
+        SampleFormat::F32 => 1,
+    };
+    h[12..16].copy_from_slice(&frame.to_le_bytes());
+    h
+}
+
+// The format and frame number of a header, if it is one
+fn parse_header(h: &[u8]) -> Option<(Format, u32)> {
+    if h.len() < HEADER_LEN || &h[..4] != MAGIC { return None; }
+    let sample_rate = u32::from_le_bytes(h[4..8].try_into().ok()?);
+    let channels = u16::from_le_bytes(h[8..10].try_into().ok()?);
+    let sample = match h[10] {
+        0 => SampleFormat::S16,
+        1 => SampleFormat::F32,
+        _ => return None,
+    };
+    if sample_rate == 0 || channels == 0 { return None; }
+    Some((Format { sample_rate, channels, sample }, u32::from_le_bytes(h[12..16].try_into().ok()?)))
+}
+
+/// Wait for the first audio from the network, then keep receiving it into
+/// one ring per tap (a list of channels from 1 to mix; empty mixes them
+/// all). Returns the rings, the sender's sample rate and channel count.
+pub fn open(cfg: &NetworkInputConfig, taps: &[&[usize]]) -> Result<(Vec<ring::Reader>, u32, u16)> {
+    let proto = cfg.protocol.name();
+    println!("Waiting for audio on {proto}://{}", cfg.listen);
+    let start = |format: Format| {
+        println!("Receiving audio: {}", format.describe());
+        Feed::new(format, taps, "the sender")
+    };
+    match cfg.protocol {
+        Protocol::Udp => {
+            let socket = UdpSocket::bind(&cfg.listen).with_context(|| format!("Failed to listen on udp://{}", cfg.listen))?;
+            let mut buf = vec![0u8; MAX_DATAGRAM];
+            let (len, frame, format) = loop {
+                let (len, _) = socket.recv_from(&mut buf)?;
+                if let Some((format, frame)) = parse_header(&buf[..len]) { break (len, frame, format); }
+            };
+            let (mut feed, rxs) = start(format)?;
+            let first = Datagram { bytes: buf[HEADER_LEN..len].to_vec(), frame };
+            std::thread::Builder::new()
+                .name("net-audio".into())
+                .spawn(move || receive_udp(socket, &mut feed, first))
+                .context("Failed to start the network audio thread")?;
+            Ok((rxs, format.sample_rate, format.channels))
+        }
+        Protocol::Tcp => {
+            let listener = TcpListener::bind(&cfg.listen).with_context(|| format!("Failed to listen on tcp://{}", cfg.listen))?;
+            let (mut conn, format) = loop {
+                let (mut conn, from) = listener.accept()?;
+                match read_header(&mut conn) {
+                    Some(format) => break (conn, format),
+                    None => tracing::warn!("{from} didn't send an audio header; closed"),
+                }
+            };
+            let (mut feed, rxs) = start(format)?;
+            std::thread::Builder::new()
+                .name("net-audio".into())
+                .spawn(move || {
+                    receive_tcp(&mut conn, &mut feed);
+                    accept_tcp(listener, &mut feed);
+                })
+                .context("Failed to start the network audio thread")?;
+            Ok((rxs, format.sample_rate, format.channels))
+        }
+    }
+}
+
+struct Datagram {
+    bytes: Vec<u8>,
+    frame: u32,
+}
+
+fn receive_udp(socket: UdpSocket, feed: &mut Feed, first: Datagram) {
+    let max_gap = feed.format.sample_rate as u64 * MAX_GAP_SECS;
+    let frame_bytes = feed.format.frame_bytes();
+    feed.write(&first.bytes);
+    let mut expected = first.frame.wrapping_add((first.bytes.len() / frame_bytes) as u32);
+    let mut warned = false;
+    let mut buf = vec![0u8; MAX_DATAGRAM];
+    loop {
+        let (len, from) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) => {
+                tracing::error!("network audio stopped: {e}");
+                return;
+            }
+        };
+        let format = parse_header(&buf[..len]);
+        let Some((_, frame)) = format.filter(|(f, _)| *f == feed.format) else {
+            if !warned {
+                tracing::warn!("ignoring audio from {from}: not {}", feed.format.describe());
+                warned = true;
+            }
+            continue;
+        };
+        let skipped = frame.wrapping_sub(expected) as u64;
+        if skipped > 0 && skipped <= max_gap { feed.gap(skipped); }
+        let payload = &buf[HEADER_LEN..len];
+        feed.write(payload);
+        expected = frame.wrapping_add((payload.len() / frame_bytes) as u32);
+    }
+}
+
+// Later connections, one at a time, in the session's format
+fn accept_tcp(listener: TcpListener, feed: &mut Feed) {
+    for conn in listener.incoming() {
+        let Ok(mut conn) = conn else { continue };
+        let from = conn.peer_addr().map_or_else(|_| "?".to_string(), |a| a.to_string());
+        match read_header(&mut conn) {
+            Some(format) if format == feed.format => {
+                println!("Audio connection from {from}");
+                receive_tcp(&mut conn, feed);
+            }
+            Some(format) => tracing::warn!("refused audio from {from}: {}, not {}", format.describe(), feed.format.describe()),
+            None => tracing::warn!("{from} didn't send an audio header; closed"),
+        }
+    }
+}
+
+// Until the sender hangs up
+fn receive_tcp(conn: &mut TcpStream, feed: &mut Feed) {
+    pcm::pump(conn, feed.format.frame_bytes(), |bytes| {
+        feed.write(bytes);
+        true
+    });
+    println!("Audio connection closed; waiting for another");
+}
+
+fn read_header(conn: &mut TcpStream) -> Option<Format> {
+    let mut h = [0u8; HEADER_LEN];
+    conn.read_exact(&mut h).ok()?;
+    parse_header(&h).map(|(format, _)| format)
+}
+
+/// `send-audio`: send mono audio from `rx` to `to` until the stream ends.
+pub fn send(mut rx: ring::Reader, sample_rate: u32, to: &str, protocol: Protocol) -> Result<()> {
+    let format = Format { sample_rate, channels: 1, sample: SampleFormat::F32 };
+    // 10 ms per datagram
+    let frames = (sample_rate as usize / 100).max(1);
+    let mut samples = Vec::with_capacity(frames);
+    let mut packet = Vec::with_capacity(HEADER_LEN + 4 * frames);
+    println!("Sending audio to {}://{to} ({})", protocol.name(), format.describe());
+    match protocol {
+        Protocol::Udp => {
+            let socket = UdpSocket::bind("0.0.0.0:0")?;
+            socket.connect(to).with_context(|| format!("Failed to reach {to}"))?;
+            let mut frame = 0u32;
+            loop {
+                samples.clear();
+                rx.read(frames, &mut samples)?;
+                packet.clear();
+                packet.extend_from_slice(&header(format, frame));
+                samples.iter().for_each(|s| packet.extend_from_slice(&s.to_le_bytes()));
+                // The receiver may not be up yet; datagrams are lost either way
+                if let Err(e) = socket.send(&packet) { tracing::debug!("send failed: {e}"); }
+                frame = frame.wrapping_add(frames as u32);
+            }
+        }
+        Protocol::Tcp => {
+            let mut conn = TcpStream::connect(to).with_context(|| format!("Failed to connect to {to}"))?;
+            conn.set_nodelay(true)?;
+            conn.write_all(&header(format, 0))?;
+            loop {
+                samples.clear();
+                rx.read(frames, &mut samples)?;
+                packet.clear();
+                samples.iter().for_each(|s| packet.extend_from_slice(&s.to_le_bytes()));
+                conn.write_all(&packet).map_err(|e| anyhow!("the receiver hung up: {e}"))?;
+            }
+        }
+    }
+}
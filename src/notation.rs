@@ -0,0 +1,137 @@
+// ---------------------------- Note spelling ----------------------------
+//
+// How note names are shown: sharps or flats, ASCII or Unicode accidentals,
+// and which octave numbering. This only changes output (status line,
+// announcements, reports, cheat sheets); internally and in config.toml notes
+// stay sharps with scientific octaves ("C#4", middle C = C4).
+
+use serde::Deserialize;
+use std::sync::RwLock;
+
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+pub struct DisplayConfig {
+    // "sharps" (C#) or "flats" (Db) for black keys
+    #[serde(default)]
+    pub accidentals: Accidentals,
+    // ♯ and ♭ instead of # and b
+    #[serde(default)]
+    pub unicode: bool,
+    // "scientific" (middle C = C4), "yamaha" (middle C = C3) or "helmholtz" (c')
+    #[serde(default)]
+    pub octave: Octave,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum Accidentals {
+    #[default]
+    Sharps,
+    Flats,
+}
+
+impl TryFrom<String> for Accidentals {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match loose(&s).as_str() {
+            "sharps" | "sharp" | "#" | "♯" => Ok(Self::Sharps),
+            "flats" | "flat" | "b" | "♭" => Ok(Self::Flats),
+            _ => Err(format!("unknown accidentals {s:?} (sharps, flats)")),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum Octave {
+    #[default]
+    Scientific,
+    Yamaha,
+    Helmholtz,
+}
+
+impl TryFrom<String> for Octave {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match loose(&s).as_str() {
+            "scientific" | "spn" | "international" | "c4" => Ok(Self::Scientific),
+            "yamaha" | "c3" => Ok(Self::Yamaha),
+            "helmholtz" => Ok(Self::Helmholtz),
+            _ => Err(format!("unknown octave numbering {s:?} (scientific, yamaha, helmholtz)")),
+        }
+    }
+}
+
+// Lowercase without spaces, dashes or underscores: "Middle-C = C4" style
+// spellings of the same choice all match
+fn loose(s: &str) -> String {
+    s.chars().filter(|c| !matches!(c, ' ' | '-' | '_')).flat_map(char::to_lowercase).collect()
+}
+
+static DISPLAY: RwLock<DisplayConfig> = RwLock::new(DisplayConfig {
+    accidentals: Accidentals::Sharps,
+    unicode: false,
+    octave: Octave::Scientific,
+});
+
+/// Use these preferences for every note name printed from now on.
+pub fn set(cfg: DisplayConfig) {
+    *DISPLAY.write().unwrap_or_else(|e| e.into_inner()) = cfg;
+}
+
+pub fn current() -> DisplayConfig {
+    *DISPLAY.read().unwrap_or_else(|e| e.into_inner())
+}
+
+const SHARP_LETTERS: [(char, bool); 12] = [
+    ('C', false), ('C', true), ('D', false), ('D', true), ('E', false), ('F', false),
+    ('F', true), ('G', false), ('G', true), ('A', false), ('A', true), ('B', false),
+];
+const FLAT_LETTERS: [(char, bool); 12] = [
+    ('C', false), ('D', true), ('D', false), ('E', true), ('E', false), ('F', false),
+    ('G', true), ('G', false), ('A', true), ('A', false), ('B', true), ('B', false),
+];
+
+/// Letter of a MIDI note and whether it carries an accidental, as displayed.
+pub fn letter(midi: i32) -> (char, bool) {
+    let table = match current().accidentals {
+        Accidentals::Sharps => &SHARP_LETTERS,
+        Accidentals::Flats => &FLAT_LETTERS,
+    };
+    table[midi.rem_euclid(12) as usize]
+}
+
+/// Display name of a MIDI note.
+pub fn spell_midi(midi: i32) -> String {
+    let cfg = current();
+    let (letter, altered) = letter(midi);
+    let accidental = match (altered, cfg.accidentals, cfg.unicode) {
+        (false, _, _) => "",
+        (true, Accidentals::Sharps, false) => "#",
+        (true, Accidentals::Sharps, true) => "♯",
+        (true, Accidentals::Flats, false) => "b",
+        (true, Accidentals::Flats, true) => "♭",
+    };
+    let octave = midi.div_euclid(12) - 1;
+    match cfg.octave {
+        Octave::Scientific => format!("{letter}{accidental}{octave}"),
+        Octave::Yamaha => format!("{letter}{accidental}{}", octave - 1),
+        // C3 = c, C4 = c', C2 = C, C1 = C,
+        Octave::Helmholtz if octave >= 3 => {
+            let marks = if cfg.unicode { "′" } else { "'" }.repeat((octave - 3) as usize);
+            format!("{}{accidental}{marks}", letter.to_ascii_lowercase())
+        }
+        Octave::Helmholtz => format!("{letter}{accidental}{}", ",".repeat((2 - octave) as usize)),
+    }
+}
+
+/// Display form of a note name or note_map key ("C#4", "C#4@2:muted");
+/// anything that doesn't start with a note is returned as is.
+pub fn spell(key: &str) -> String {
+    let note = crate::key_note(key);
+    match crate::note_to_midi(note) {
+        Some(midi) => format!("{}{}", spell_midi(midi), &key[note.len()..]),
+        None => key.to_string(),
+    }
+}
@@ -0,0 +1,113 @@
+// ---------------------------- Note key patterns ----------------------------
+//
+// Besides exact notes ("A4"), note_map keys can cover several notes: a pitch
+// class ("A", "F#", "Bb", "Sol") matches that note in every octave, and a range
+// ("C3-C4") every note between its ends, both included. An exact key wins
+// over a range, and a range over a pitch class; among ranges the narrowest
+// wins.
+
+use std::collections::HashMap;
+
+use crate::Mapping;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    // Lowest and highest MIDI note
+    Range(i32, i32),
+    // 0 = C
+    PitchClass(i32),
+}
+
+impl Pattern {
+    pub fn matches(self, midi: i32) -> bool {
+        match self {
+            Pattern::Range(lo, hi) => (lo..=hi).contains(&midi),
+            Pattern::PitchClass(pc) => midi.rem_euclid(12) == pc,
+        }
+    }
+}
+
+/// The notes a key covers, if it is a range or pitch class.
+pub fn parse(key: &str) -> Option<Pattern> {
+    let key = key.trim();
+    if let Some(pc) = pitch_class(key) { return Some(Pattern::PitchClass(pc)); }
+    // "C-1" is a note, so try every dash as the separator
+    key.match_indices('-').find_map(|(i, _)| {
+        let lo = crate::note_to_midi(key[..i].trim())?;
+        let hi = crate::note_to_midi(key[i + 1..].trim())?;
+        Some(Pattern::Range(lo.min(hi), lo.max(hi)))
+    })
+}
+
+/// Pitch class of a note name without octave ("C#", "Db", "e", "Sol"), 0 = C.
+pub fn pitch_class(name: &str) -> Option<i32> {
+    semitones(name).map(|s| s.rem_euclid(12))
+}
+
+// Fixed-do solfège syllables; "sol" before "so" so the longer one is tried first
+const SYLLABLES: [(&str, i32); 9] =
+    [("do", 0), ("re", 2), ("mi", 4), ("fa", 5), ("sol", 7), ("so", 7), ("la", 9), ("si", 11), ("ti", 11)];
+
+/// Semitones above C of a letter or solfège syllable and its accidental,
+/// without wrapping: "Cb" is -1 and "B#" 12, so the octave stays right.
+pub fn semitones(name: &str) -> Option<i32> {
+    let lower = name.to_lowercase();
+    let (natural, accidental) = SYLLABLES
+        .iter()
+        .find_map(|(syllable, pc)| lower.strip_prefix(syllable).map(|rest| (*pc, rest)))
+        .or_else(|| {
+            let mut chars = lower.chars();
+            let letter = chars.next()?.to_ascii_uppercase();
+            let natural = crate::NOTE_NAMES.iter().position(|n| *n == letter.to_string())? as i32;
+            Some((natural, chars.as_str()))
+        })?;
+    let shift = match accidental {
+        "" => 0,
+        "#" | "♯" => 1,
+        "b" | "♭" => -1,
+        _ => return None,
+    };
+    Some(natural + shift)
+}
+
+/// A note_map key with its notes in the spelling detection produces: "Bb3",
+/// "A♯3", "58" and "La#3" all become "A#3", keeping "@5", ":muted" and
+/// "+vibrato" suffixes; "Bb2&F3" becomes "A#2&F3" and "Bb3 x2" "A#3 x2".
+/// Intervals ("A3+E4", "P5 above A3") become "A3&E4". Other keys are
+/// unchanged.
+pub fn normalize(key: &str) -> String {
+    let spell = |note: &str| crate::note_to_midi(note.trim()).map(crate::midi_to_name);
+    if let Some(pair) = crate::intervals::normalize(key) { return pair; }
+    if key.contains('&') {
+        let notes: Option<Vec<String>> = key.split('&').map(spell).collect();
+        return notes.map_or_else(|| key.to_string(), |n| n.join("&"));
+    }
+    if let Some((note, count)) = crate::strikes::parse(key) {
+        return crate::strikes::key(&spell(note).unwrap_or_else(|| note.to_string()), count);
+    }
+    let end = key.find(['@', ':', '+']).unwrap_or(key.len());
+    match spell(&key[..end]) {
+        Some(note) => format!("{note}{}", &key[end..]),
+        None => key.to_string(),
+    }
+}
+
+/// The range or pitch-class mapping for a note, by the precedence above.
+pub fn lookup(map: &HashMap<String, Mapping>, midi: i32) -> Option<(&String, &Mapping)> {
+    let mut range: Option<(i32, &String, &Mapping)> = None;
+    let mut class = None;
+    for (key, mapping) in map {
+        match parse(key) {
+            Some(Pattern::Range(lo, hi)) if (lo..=hi).contains(&midi) => {
+                // Narrowest first, then by key so the choice is stable
+                let width = hi - lo;
+                if range.is_none_or(|(w, k, _)| (width, key) < (w, k)) { range = Some((width, key, mapping)); }
+            }
+            Some(p @ Pattern::PitchClass(_)) if p.matches(midi) && class.is_none_or(|(k, _)| key < k) => {
+                class = Some((key, mapping));
+            }
+            _ => {}
+        }
+    }
+    range.map(|(_, k, m)| (k, m)).or(class)
+}
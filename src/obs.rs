@@ -0,0 +1,246 @@
+// ---------------------------- OBS actions ----------------------------
+//
+// `type = "obs"` mappings drive OBS Studio through obs-websocket (built into
+// OBS 28 and later, protocol version 5): switch scenes, show and hide
+// sources, start and stop recording and streaming. That works whether or not
+// OBS has focus, unlike hotkeys. The connection is opened at the first obs
+// trigger and kept; a broken one is opened again on the next.
+
+use anyhow::{anyhow, Context, Result};
+use data_encoding::BASE64;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::net::TcpStream;
+use std::sync::Mutex;
+use std::time::Duration;
+use tungstenite::{Message, WebSocket};
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct ObsConfig {
+    // host:port of OBS's WebSocket server (Tools > WebSocket Server Settings)
+    #[serde(default = "default_address")]
+    pub address: String,
+    // The server password, if authentication is on
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+fn default_address() -> String { "127.0.0.1:4455".to_string() }
+
+impl Default for ObsConfig {
+    fn default() -> Self {
+        Self { address: default_address(), password: None }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Output {
+    Start,
+    Stop,
+    Toggle,
+}
+
+/// Parameters of a `type = "obs"` mapping. The steps it sets run in the
+/// order below.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ObsAction {
+    // Switch to this scene; with `source`, only the scene the source is in
+    #[serde(default)]
+    pub scene: Option<String>,
+    // Show or hide a source, in `scene` or else the current scene
+    #[serde(default)]
+    pub source: Option<String>,
+    // true shows the source, false hides it, unset toggles
+    #[serde(default)]
+    pub visible: Option<bool>,
+    #[serde(default)]
+    pub recording: Option<Output>,
+    #[serde(default)]
+    pub streaming: Option<Output>,
+}
+
+impl ObsAction {
+    pub fn label(&self) -> String {
+        let mut steps = Vec::new();
+        match (&self.source, &self.scene) {
+            (Some(source), _) => {
+                let verb = match self.visible {
+                    Some(true) => "show",
+                    Some(false) => "hide",
+                    None => "toggle",
+                };
+                steps.push(format!("{verb} {source}"));
+            }
+            (None, Some(scene)) => steps.push(format!("scene {scene}")),
+            (None, None) => {}
+        }
+        if let Some(o) = self.recording { steps.push(format!("recording {}", format!("{o:?}").to_lowercase())); }
+        if let Some(o) = self.streaming { steps.push(format!("streaming {}", format!("{o:?}").to_lowercase())); }
+        if steps.is_empty() { steps.push("nothing".to_string()); }
+        format!("obs:{}", steps.join(", "))
+    }
+}
+
+// How long OBS gets to answer
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+struct Connection {
+    cfg: ObsConfig,
+    socket: WebSocket<TcpStream>,
+    next_id: u64,
+}
+
+static CONFIG: Mutex<Option<ObsConfig>> = Mutex::new(None);
+static CONNECTION: Mutex<Option<Connection>> = Mutex::new(None);
+
+/// Where obs mappings connect to. A connection to somewhere else is closed.
+pub fn set_config(cfg: &ObsConfig) {
+    *CONFIG.lock().unwrap_or_else(|e| e.into_inner()) = Some(cfg.clone());
+    let mut connection = CONNECTION.lock().unwrap_or_else(|e| e.into_inner());
+    if connection.as_ref().is_some_and(|c| c.cfg != *cfg) { *connection = None; }
+}
+
+/// Send the mapping's requests.
+pub fn execute(action: &ObsAction) -> Result<()> {
+    let cfg = CONFIG.lock().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_default();
+    let mut connection = CONNECTION.lock().unwrap_or_else(|e| e.into_inner());
+    let c = match connection.as_mut() {
+        Some(c) => c,
+        None => connection.insert(Connection::open(cfg)?),
+    };
+    let result = run(c, action);
+    // A request OBS refused leaves the connection usable; anything else doesn't
+    if result.as_ref().is_err_and(|e| e.downcast_ref::<Refused>().is_none()) { *connection = None; }
+    result
+}
+
+fn run(c: &mut Connection, action: &ObsAction) -> Result<()> {
+    match (&action.source, &action.scene) {
+        (Some(source), scene) => {
+            let scene = match scene {
+                Some(s) => s.clone(),
+                None => {
+                    let current = c.request("GetCurrentProgramScene", json!({}))?;
+                    current["currentProgramSceneName"].as_str().unwrap_or_default().to_string()
+                }
+            };
+            let item = c.request("GetSceneItemId", json!({ "sceneName": scene, "sourceName": source }))?["sceneItemId"].clone();
+            let visible = match action.visible {
+                Some(v) => v,
+                None => {
+                    let enabled = c.request("GetSceneItemEnabled", json!({ "sceneName": scene, "sceneItemId": item }))?;
+                    !enabled["sceneItemEnabled"].as_bool().unwrap_or(false)
+                }
+            };
+            c.request("SetSceneItemEnabled", json!({ "sceneName": scene, "sceneItemId": item, "sceneItemEnabled": visible }))?;
+        }
+        (None, Some(scene)) => {
+            c.request("SetCurrentProgramScene", json!({ "sceneName": scene }))?;
+        }
+        (None, None) => {}
+    }
+    if let Some(o) = action.recording {
+        c.request(
+            match o {
+                Output::Start => "StartRecord",
+                Output::Stop => "StopRecord",
+                Output::Toggle => "ToggleRecord",
+            },
+            json!({}),
+        )?;
+    }
+    if let Some(o) = action.streaming {
+        c.request(
+            match o {
+                Output::Start => "StartStream",
+                Output::Stop => "StopStream",
+                Output::Toggle => "ToggleStream",
+            },
+            json!({}),
+        )?;
+    }
+    Ok(())
+}
+
+// OBS answered, but with a failure ("No scene was found by the name of...")
+#[derive(Debug)]
+struct Refused(String);
+
+impl std::fmt::Display for Refused {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result { f.write_str(&self.0) }
+}
+
+impl std::error::Error for Refused {}
+
+// Opcodes
+const HELLO: u64 = 0;
+const IDENTIFY: u64 = 1;
+const IDENTIFIED: u64 = 2;
+const REQUEST: u64 = 6;
+const REQUEST_RESPONSE: u64 = 7;
+
+impl Connection {
+    fn open(cfg: ObsConfig) -> Result<Self> {
+        let stream = TcpStream::connect(&cfg.address)
+            .with_context(|| format!("cannot connect to OBS at {} (is its WebSocket server on?)", cfg.address))?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        stream.set_nodelay(true)?;
+        let (socket, _) = tungstenite::client(format!("ws://{}", cfg.address), stream)
+            .map_err(|e| anyhow!("OBS WebSocket handshake with {} failed: {e}", cfg.address))?;
+        let mut c = Self { cfg, socket, next_id: 0 };
+        let hello = c.receive(HELLO)?;
+        // No events wanted, only replies
+        let mut identify = json!({ "rpcVersion": 1, "eventSubscriptions": 0 });
+        if let Some(auth) = hello.get("authentication") {
+            let password = c.cfg.password.as_deref().ok_or_else(|| anyhow!("OBS wants a password: set [obs] password"))?;
+            let salt = auth["salt"].as_str().unwrap_or_default();
+            let challenge = auth["challenge"].as_str().unwrap_or_default();
+            let secret = BASE64.encode(&Sha256::digest(format!("{password}{salt}")));
+            identify["authentication"] = BASE64.encode(&Sha256::digest(format!("{secret}{challenge}"))).into();
+        }
+        c.send(IDENTIFY, identify)?;
+        // OBS closes the connection on a wrong password
+        c.receive(IDENTIFIED).map_err(|e| anyhow!("OBS didn't accept the connection (wrong [obs] password?): {e}"))?;
+        tracing::info!("Connected to OBS at {}", c.cfg.address);
+        Ok(c)
+    }
+
+    // The response data of a request, or Refused
+    fn request(&mut self, kind: &str, data: Value) -> Result<Value> {
+        self.next_id += 1;
+        let id = self.next_id.to_string();
+        self.send(REQUEST, json!({ "requestType": kind, "requestId": id, "requestData": data }))?;
+        loop {
+            let response = self.receive(REQUEST_RESPONSE)?;
+            if response["requestId"].as_str() != Some(&id) { continue; }
+            let status = &response["requestStatus"];
+            if status["result"].as_bool() != Some(true) {
+                let comment = status["comment"].as_str().unwrap_or("no reason given");
+                return Err(Refused(format!("OBS refused {kind}: {comment}")).into());
+            }
+            return Ok(response["responseData"].clone());
+        }
+    }
+
+    fn send(&mut self, op: u64, d: Value) -> Result<()> {
+        self.socket.send(Message::Text(json!({ "op": op, "d": d }).to_string())).context("OBS connection lost")
+    }
+
+    // The data of the next message with opcode `op`
+    fn receive(&mut self, op: u64) -> Result<Value> {
+        loop {
+            let text = match self.socket.read().context("no answer from OBS")? {
+                Message::Text(t) => t,
+                Message::Close(frame) => {
+                    return Err(anyhow!("OBS closed the connection{}", frame.map(|f| format!(": {}", f.reason)).unwrap_or_default()));
+                }
+                _ => continue,
+            };
+            let message: Value = serde_json::from_str(&text).context("OBS sent something that isn't JSON")?;
+            if message["op"].as_u64() == Some(op) { return Ok(message["d"].clone()); }
+        }
+    }
+}
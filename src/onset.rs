@@ -0,0 +1,167 @@
+// ---------------------------- Onsets ----------------------------
+//
+// Minimal attack detector on the hop level envelope: an onset is a jump to
+// `ratio` times the quietest of the last few hops. Used where only the timing
+// of attacks matters (tempo tracking).
+//
+// Note segmentation ([onsets]) can use it too, but defaults to spectral flux:
+// the rise in energy summed over frequency bands, which also catches a new
+// pluck of a note that is still ringing at the same level.
+
+use serde::Deserialize;
+use std::f32::consts::PI;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Method {
+    // Rise of band energies (spectral flux)
+    #[default]
+    Flux,
+    // Jump of the hop level
+    Level,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct OnsetConfig {
+    // Segment notes at attacks: a new attack re-triggers even the same note
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub method: Method,
+    // Flux over its recent median (flux) or level over the recent minimum
+    // (level) that makes an attack
+    #[serde(default = "default_ratio")]
+    pub ratio: f32,
+}
+
+fn default_ratio() -> f32 { 3.0 }
+
+impl Default for OnsetConfig {
+    fn default() -> Self {
+        Self { enabled: false, method: Method::default(), ratio: default_ratio() }
+    }
+}
+
+// Hops of level history used as the "before the attack" reference
+const HISTORY: usize = 6;
+// Two onsets closer than this are one attack
+const MIN_GAP: Duration = Duration::from_millis(60);
+
+pub struct LevelOnsets {
+    ratio: f32,
+    floor: f32,
+    history: Vec<f32>,
+    last: Option<Instant>,
+}
+
+impl LevelOnsets {
+    pub fn new(ratio: f32, floor: f32) -> Self {
+        Self { ratio, floor: floor.max(1e-4), history: Vec::with_capacity(HISTORY), last: None }
+    }
+
+    /// Feed the RMS level of the newest hop; true if it starts an attack.
+    pub fn update(&mut self, level: f32, now: Instant) -> bool {
+        let reference = self.history.iter().copied().fold(f32::MAX, f32::min);
+        if self.history.len() == HISTORY { self.history.remove(0); }
+        self.history.push(level);
+        let onset = level > self.floor
+            && reference < f32::MAX
+            && level > self.ratio * reference.max(self.floor / 4.0)
+            && self.last.is_none_or(|t| now.duration_since(t) >= MIN_GAP);
+        if onset { self.last = Some(now); }
+        onset
+    }
+}
+
+// Samples of the newest audio the flux is measured on
+const FLUX_FRAME: usize = 1024;
+// Bands, log-spaced between these frequencies
+const BANDS: usize = 40;
+const LOWEST_HZ: f32 = 60.0;
+const HIGHEST_HZ: f32 = 6000.0;
+// Flux below this is never an attack, however quiet the recent frames were
+const MIN_FLUX: f32 = 1.0;
+
+pub struct SpectralFlux {
+    ratio: f32,
+    floor: f32,
+    hann: Vec<f32>,
+    // Log-compressed band magnitudes of the previous frame
+    previous: Option<Vec<f32>>,
+    // Recent flux values, the reference for the next one
+    history: Vec<f32>,
+    last: Option<Instant>,
+}
+
+impl SpectralFlux {
+    pub fn new(ratio: f32, floor: f32) -> Self {
+        let hann = (0..FLUX_FRAME).map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / (FLUX_FRAME - 1) as f32).cos()).collect();
+        Self { ratio, floor: floor.max(1e-3), hann, previous: None, history: Vec::with_capacity(HISTORY), last: None }
+    }
+
+    /// Feed the newest audio (at least FLUX_FRAME samples are used) and the
+    /// hop's RMS level; true if the hop starts an attack.
+    pub fn update(&mut self, audio: &[f32], sample_rate: f32, level: f32, now: Instant) -> bool {
+        let frame = &audio[audio.len().saturating_sub(FLUX_FRAME)..];
+        let bands: Vec<f32> = (0..BANDS)
+            .map(|b| {
+                let hz = LOWEST_HZ * (HIGHEST_HZ / LOWEST_HZ).powf(b as f32 / (BANDS - 1) as f32);
+                if hz >= sample_rate / 2.0 { return 0.0; }
+                // Amplitude of a sine at this frequency, log-compressed
+                let amplitude = 4.0 * band_magnitude(frame, &self.hann, hz / sample_rate) / frame.len() as f32;
+                (1.0 + 100.0 * amplitude).ln()
+            })
+            .collect();
+        let flux = match &self.previous {
+            Some(prev) => bands.iter().zip(prev).map(|(b, p)| (b - p).max(0.0)).sum::<f32>(),
+            None => 0.0,
+        };
+        self.previous = Some(bands);
+        let mut recent = self.history.clone();
+        recent.sort_by(f32::total_cmp);
+        let reference = recent.get(recent.len() / 2).copied();
+        if self.history.len() == HISTORY { self.history.remove(0); }
+        self.history.push(flux);
+        let onset = level > self.floor
+            && reference.is_some_and(|r| flux > MIN_FLUX && flux > self.ratio * r)
+            && self.last.is_none_or(|t| now.duration_since(t) >= MIN_GAP);
+        if onset { self.last = Some(now); }
+        onset
+    }
+}
+
+// Magnitude of the windowed frame at `freq` cycles per sample
+fn band_magnitude(x: &[f32], window: &[f32], freq: f32) -> f32 {
+    let coeff = 2.0 * (2.0 * PI * freq).cos();
+    let (mut s1, mut s2) = (0.0f32, 0.0f32);
+    for (v, w) in x.iter().zip(window) {
+        let s = v * w + coeff * s1 - s2;
+        s2 = s1;
+        s1 = s;
+    }
+    (s1 * s1 + s2 * s2 - coeff * s1 * s2).max(0.0).sqrt()
+}
+
+/// The attack detector chosen in [onsets].
+pub enum NoteOnsets {
+    Flux(SpectralFlux),
+    Level(LevelOnsets),
+}
+
+impl NoteOnsets {
+    pub fn new(cfg: &OnsetConfig, floor: f32) -> Self {
+        match cfg.method {
+            Method::Flux => NoteOnsets::Flux(SpectralFlux::new(cfg.ratio, floor)),
+            Method::Level => NoteOnsets::Level(LevelOnsets::new(cfg.ratio, floor)),
+        }
+    }
+
+    /// Feed the newest audio and the hop's level; true on an attack.
+    pub fn update(&mut self, audio: &[f32], sample_rate: f32, level: f32, now: Instant) -> bool {
+        match self {
+            NoteOnsets::Flux(f) => f.update(audio, sample_rate, level, now),
+            NoteOnsets::Level(l) => l.update(level, now),
+        }
+    }
+}
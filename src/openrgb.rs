@@ -0,0 +1,241 @@
+// ---------------------------- OpenRGB ----------------------------
+//
+// Client for the OpenRGB SDK server (Settings > SDK Server in OpenRGB). The
+// keyboard (or any other RGB device) shows the current note: hue by pitch
+// class, brightness by intonation, with a white flash when a mapping fires.
+// We speak protocol version 0, which every server version accepts.
+
+use anyhow::{anyhow, Context, Result};
+use crossbeam_channel::{bounded, Receiver, RecvTimeoutError};
+use serde::Deserialize;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use crate::feedback::{drive_light, Light, LightMsg};
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct OpenRgbConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_host")]
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    // Controller indices as listed in OpenRGB (empty = all devices)
+    #[serde(default)]
+    pub devices: Vec<u32>,
+    // Only light this zone of each device (e.g. the main keyboard block)
+    #[serde(default)]
+    pub zone: Option<u32>,
+    #[serde(default = "default_flash_ms")]
+    pub flash_ms: u64,
+}
+
+fn default_host() -> String { "127.0.0.1".to_string() }
+fn default_port() -> u16 { 6742 }
+fn default_flash_ms() -> u64 { 150 }
+
+impl Default for OpenRgbConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: default_host(),
+            port: default_port(),
+            devices: Vec::new(),
+            zone: None,
+            flash_ms: default_flash_ms(),
+        }
+    }
+}
+
+// SDK packet ids
+const REQUEST_CONTROLLER_COUNT: u32 = 0;
+const REQUEST_CONTROLLER_DATA: u32 = 1;
+const SET_CLIENT_NAME: u32 = 50;
+const UPDATE_LEDS: u32 = 1050;
+const UPDATE_ZONE_LEDS: u32 = 1051;
+const SET_CUSTOM_MODE: u32 = 1100;
+
+// Wait between reconnection attempts
+const RETRY: Duration = Duration::from_secs(5);
+
+/// Start the client thread; it keeps reconnecting if the server isn't up.
+pub fn start(cfg: &OpenRgbConfig, tolerance: f32) -> Light {
+    let (tx, rx) = bounded(16);
+    let cfg = cfg.clone();
+    std::thread::spawn(move || run(cfg, rx));
+    Light::new(tx, tolerance)
+}
+
+fn run(cfg: OpenRgbConfig, rx: Receiver<LightMsg>) {
+    let flash = Duration::from_millis(cfg.flash_ms);
+    loop {
+        let mut conn = match Connection::open(&cfg) {
+            Ok(c) => c,
+            Err(e) => {
+                tracing::warn!("OpenRGB: {e:#}; retrying in {} s", RETRY.as_secs());
+                // Drop stale updates while waiting; stop when the app exits
+                let until = Instant::now() + RETRY;
+                while let Some(left) = until.checked_duration_since(Instant::now()) {
+                    if let Err(RecvTimeoutError::Disconnected) = rx.recv_timeout(left) { return; }
+                }
+                continue;
+            }
+        };
+        println!("OpenRGB: connected to {} device(s)", conn.targets.len());
+        match drive_light(&rx, flash, |color| conn.fill(color)) {
+            Ok(()) => return,
+            Err(e) => tracing::warn!("OpenRGB: connection lost: {e:#}"),
+        }
+    }
+}
+
+// A device to light: its index and how many LEDs the update covers
+struct Target {
+    device: u32,
+    leds: u16,
+}
+
+struct Connection {
+    stream: TcpStream,
+    zone: Option<u32>,
+    targets: Vec<Target>,
+}
+
+impl Connection {
+    fn open(cfg: &OpenRgbConfig) -> Result<Self> {
+        let stream = TcpStream::connect((cfg.host.as_str(), cfg.port))
+            .with_context(|| format!("cannot connect to {}:{}", cfg.host, cfg.port))?;
+        stream.set_nodelay(true).ok();
+        let mut conn = Self { stream, zone: cfg.zone, targets: Vec::new() };
+        conn.send(0, SET_CLIENT_NAME, b"Rusty Strings Control\0")?;
+        conn.send(0, REQUEST_CONTROLLER_COUNT, &[])?;
+        let count = u32::from_le_bytes(conn.reply(REQUEST_CONTROLLER_COUNT)?.get(..4)
+            .ok_or_else(|| anyhow!("short controller count"))?.try_into()?);
+        let devices: Vec<u32> = if cfg.devices.is_empty() { (0..count).collect() } else { cfg.devices.clone() };
+        for device in devices {
+            if device >= count {
+                tracing::warn!("OpenRGB: no device {device} (server has {count})");
+                continue;
+            }
+            conn.send(device, REQUEST_CONTROLLER_DATA, &[])?;
+            let data = conn.reply(REQUEST_CONTROLLER_DATA)?;
+            let (zones, leds) = parse_controller(&data).context("malformed controller data")?;
+            let leds = match cfg.zone {
+                Some(z) => match zones.get(z as usize) {
+                    Some(&n) => n as u16,
+                    None => {
+                        tracing::warn!("OpenRGB: device {device} has no zone {z}");
+                        continue;
+                    }
+                },
+                None => leds,
+            };
+            conn.send(device, SET_CUSTOM_MODE, &[])?;
+            conn.targets.push(Target { device, leds });
+        }
+        Ok(conn)
+    }
+
+    fn send(&mut self, device: u32, packet: u32, data: &[u8]) -> Result<()> {
+        let mut msg = Vec::with_capacity(16 + data.len());
+        msg.extend_from_slice(b"ORGB");
+        msg.extend_from_slice(&device.to_le_bytes());
+        msg.extend_from_slice(&packet.to_le_bytes());
+        msg.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        msg.extend_from_slice(data);
+        self.stream.write_all(&msg).context("write failed")
+    }
+
+    // Read packets until one with the given id arrives and return its data
+    fn reply(&mut self, packet: u32) -> Result<Vec<u8>> {
+        loop {
+            let mut header = [0u8; 16];
+            self.stream.read_exact(&mut header).context("read failed")?;
+            if &header[..4] != b"ORGB" { return Err(anyhow!("bad packet magic")); }
+            let id = u32::from_le_bytes(header[8..12].try_into()?);
+            let len = u32::from_le_bytes(header[12..16].try_into()?) as usize;
+            let mut data = vec![0u8; len];
+            self.stream.read_exact(&mut data).context("read failed")?;
+            if id == packet { return Ok(data); }
+        }
+    }
+
+    // Set every target LED to one color
+    fn fill(&mut self, [r, g, b]: [u8; 3]) -> Result<()> {
+        let (packet, zone) = match self.zone {
+            Some(z) => (UPDATE_ZONE_LEDS, Some(z)),
+            None => (UPDATE_LEDS, None),
+        };
+        let updates: Vec<(u32, Vec<u8>)> = self
+            .targets
+            .iter()
+            .map(|t| {
+                let size = 4 + zone.map_or(0, |_| 4) + 2 + 4 * t.leds as u32;
+                let mut data = Vec::with_capacity(size as usize);
+                data.extend_from_slice(&size.to_le_bytes());
+                if let Some(z) = zone { data.extend_from_slice(&z.to_le_bytes()); }
+                data.extend_from_slice(&t.leds.to_le_bytes());
+                for _ in 0..t.leds { data.extend_from_slice(&[r, g, b, 0]); }
+                (t.device, data)
+            })
+            .collect();
+        for (device, data) in updates {
+            self.send(device, packet, &data)?;
+        }
+        Ok(())
+    }
+}
+
+// Walk a protocol-0 controller description; returns per-zone LED counts and
+// the total LED count
+fn parse_controller(data: &[u8]) -> Option<(Vec<u32>, u16)> {
+    let mut r = Reader { data, pos: 0 };
+    r.u32()?; // data size
+    r.u32()?; // device type
+    for _ in 0..5 { r.string()?; } // name, description, version, serial, location
+    let modes = r.u16()?;
+    r.u32()?; // active mode
+    for _ in 0..modes {
+        r.string()?;
+        r.skip(9 * 4)?; // value, flags, speed min/max, colors min/max, speed, direction, color mode
+        let colors = r.u16()?;
+        r.skip(4 * colors as usize)?;
+    }
+    let zones = r.u16()?;
+    let mut counts = Vec::with_capacity(zones as usize);
+    for _ in 0..zones {
+        r.string()?;
+        r.skip(3 * 4)?; // type, leds min, leds max
+        counts.push(r.u32()?);
+        let matrix = r.u16()?;
+        r.skip(matrix as usize)?;
+    }
+    let leds = r.u16()?;
+    Some((counts, leds))
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn skip(&mut self, n: usize) -> Option<&[u8]> {
+        let s = self.data.get(self.pos..self.pos + n)?;
+        self.pos += n;
+        Some(s)
+    }
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.skip(2)?.try_into().ok()?))
+    }
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.skip(4)?.try_into().ok()?))
+    }
+    // Length-prefixed (and NUL-terminated) string; contents aren't needed
+    fn string(&mut self) -> Option<()> {
+        let n = self.u16()?;
+        self.skip(n as usize).map(|_| ())
+    }
+}
@@ -0,0 +1,135 @@
+// ---------------------------- OSC output ----------------------------
+//
+// `type = "osc"` mappings send one Open Sound Control message over UDP, for
+// DAWs (Ardour, Reaper), SuperCollider and lighting desks. Messages are
+// encoded here; the format is small enough not to need a library.
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::net::UdpSocket;
+use std::sync::{OnceLock, RwLock};
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct OscConfig {
+    // Where messages go unless a mapping sets its own host/port
+    #[serde(default = "default_host")]
+    pub host: String,
+    #[serde(default)]
+    pub port: Option<u16>,
+}
+
+fn default_host() -> String { "127.0.0.1".to_string() }
+
+impl Default for OscConfig {
+    fn default() -> Self {
+        Self { host: default_host(), port: None }
+    }
+}
+
+/// Parameters of a `type = "osc"` mapping.
+#[derive(Debug, Deserialize, Clone)]
+pub struct OscAction {
+    // e.g. "/transport_play"
+    pub address: String,
+    #[serde(default)]
+    pub args: Vec<Arg>,
+    #[serde(default)]
+    pub host: Option<String>,
+    #[serde(default)]
+    pub port: Option<u16>,
+}
+
+/// Integers are sent as int32 (int64 if they don't fit), floats as float32.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum Arg {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+}
+
+impl OscAction {
+    pub fn label(&self) -> String {
+        let args: Vec<String> = self
+            .args
+            .iter()
+            .map(|a| match a {
+                Arg::Int(i) => i.to_string(),
+                Arg::Float(f) => f.to_string(),
+                Arg::Bool(b) => b.to_string(),
+                Arg::Str(s) => format!("{s:?}"),
+            })
+            .collect();
+        if args.is_empty() { format!("osc:{}", self.address) } else { format!("osc:{} {}", self.address, args.join(" ")) }
+    }
+}
+
+static DEFAULTS: RwLock<Option<OscConfig>> = RwLock::new(None);
+static SOCKET: OnceLock<UdpSocket> = OnceLock::new();
+
+/// Host and port for mappings that don't set them.
+pub fn set_defaults(cfg: &OscConfig) {
+    *DEFAULTS.write().unwrap_or_else(|e| e.into_inner()) = Some(cfg.clone());
+}
+
+/// Send the mapping's message.
+pub fn execute(action: &OscAction) -> Result<()> {
+    let defaults = DEFAULTS.read().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_default();
+    let host = action.host.as_deref().unwrap_or(&defaults.host);
+    let port = action
+        .port
+        .or(defaults.port)
+        .ok_or_else(|| anyhow!("osc mapping {} needs a port (or [osc] port)", action.address))?;
+    let socket = match SOCKET.get() {
+        Some(s) => s,
+        None => {
+            let s = UdpSocket::bind(("0.0.0.0", 0)).context("Cannot open UDP socket for OSC")?;
+            SOCKET.get_or_init(|| s)
+        }
+    };
+    let packet = encode(&action.address, &action.args)?;
+    socket.send_to(&packet, (host, port)).with_context(|| format!("OSC send to {host}:{port} failed"))?;
+    Ok(())
+}
+
+fn encode(address: &str, args: &[Arg]) -> Result<Vec<u8>> {
+    if !address.starts_with('/') { return Err(anyhow!("OSC address {address:?} must start with '/'")); }
+    let mut tags = String::from(",");
+    let mut data = Vec::new();
+    for arg in args {
+        match arg {
+            Arg::Int(i) => match i32::try_from(*i) {
+                Ok(v) => {
+                    tags.push('i');
+                    data.extend_from_slice(&v.to_be_bytes());
+                }
+                Err(_) => {
+                    tags.push('h');
+                    data.extend_from_slice(&i.to_be_bytes());
+                }
+            },
+            Arg::Float(f) => {
+                tags.push('f');
+                data.extend_from_slice(&(*f as f32).to_be_bytes());
+            }
+            Arg::Bool(b) => tags.push(if *b { 'T' } else { 'F' }),
+            Arg::Str(s) => {
+                tags.push('s');
+                push_string(&mut data, s);
+            }
+        }
+    }
+    let mut packet = Vec::new();
+    push_string(&mut packet, address);
+    push_string(&mut packet, &tags);
+    packet.extend_from_slice(&data);
+    Ok(packet)
+}
+
+// OSC string: NUL-terminated and padded to a multiple of four bytes
+fn push_string(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(s.as_bytes());
+    let pad = 4 - s.len() % 4;
+    out.extend(std::iter::repeat_n(0u8, pad));
+}
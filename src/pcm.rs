@@ -0,0 +1,162 @@
+// ---------------------------- Raw PCM input ----------------------------
+//
+// Interleaved PCM from somewhere other than an audio device: standard input
+// or a file (`--input`), or the network (net_audio). The samples go into
+// the taps' rings the way the device callback puts them there. Piped audio
+// is taken in no faster than real time, so a file plays out at its own
+// speed and retrigger_ms, hold times and quantizing behave as they do live.
+// When it ends, so does the input.
+
+use anyhow::{Context, Result};
+use std::io::Read;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::ring;
+
+/// How samples are stored (little-endian).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SampleFormat {
+    /// 16-bit signed integers
+    S16,
+    /// 32-bit floats
+    F32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Format {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub sample: SampleFormat,
+}
+
+impl Format {
+    pub fn frame_bytes(self) -> usize {
+        self.channels as usize
+            * match self.sample {
+                SampleFormat::S16 => 2,
+                SampleFormat::F32 => 4,
+            }
+    }
+
+    fn sample(self, frame: &[u8], channel: usize) -> f32 {
+        match self.sample {
+            SampleFormat::S16 => i16::from_le_bytes([frame[2 * channel], frame[2 * channel + 1]]) as f32 / 32768.0,
+            SampleFormat::F32 => f32::from_le_bytes(frame[4 * channel..4 * channel + 4].try_into().unwrap()),
+        }
+    }
+
+    pub fn describe(self) -> String {
+        let sample = match self.sample {
+            SampleFormat::S16 => "16-bit",
+            SampleFormat::F32 => "32-bit float",
+        };
+        format!("{} Hz, {} channel(s), {sample}", self.sample_rate, self.channels)
+    }
+}
+
+/// Splits interleaved frames into the taps' rings.
+pub struct Feed {
+    pub format: Format,
+    taps: Vec<(Vec<usize>, ring::Writer)>,
+    scratch: Vec<f32>,
+}
+
+impl Feed {
+    /// A feed for `taps` (each a list of channels from 1 to mix; empty
+    /// mixes them all) and the rings it fills, about a second each.
+    /// `source` names where the audio comes from in errors.
+    pub fn new(format: Format, taps: &[&[usize]], source: &str) -> Result<(Self, Vec<ring::Reader>)> {
+        let indices = crate::tap_channels(taps, format.channels, source)?;
+        let (txs, rxs): (Vec<_>, Vec<_>) = taps.iter().map(|_| ring::channel(format.sample_rate as usize)).unzip();
+        Ok((Self { format, taps: indices.into_iter().zip(txs).collect(), scratch: Vec::new() }, rxs))
+    }
+
+    /// Write the whole frames in `bytes`, dropping what there's no room for,
+    /// as live audio must.
+    pub fn write(&mut self, bytes: &[u8]) {
+        let format = self.format;
+        for (mixed, tx) in &mut self.taps {
+            tx.write_from(bytes.chunks_exact(format.frame_bytes()).map(|frame| mix(format, frame, mixed)));
+        }
+    }
+
+    /// Write the whole frames in `bytes`, waiting for room; false once
+    /// nothing reads them any more.
+    pub fn write_all(&mut self, bytes: &[u8]) -> bool {
+        let format = self.format;
+        for (mixed, tx) in &mut self.taps {
+            self.scratch.clear();
+            self.scratch.extend(bytes.chunks_exact(format.frame_bytes()).map(|frame| mix(format, frame, mixed)));
+            if tx.write_all(&self.scratch).is_err() { return false; }
+        }
+        true
+    }
+
+    /// Count `frames` that never arrived as dropped.
+    pub fn gap(&mut self, frames: u64) {
+        for (_, tx) in &mut self.taps { tx.skipped(frames); }
+    }
+}
+
+fn mix(format: Format, frame: &[u8], channels: &[usize]) -> f32 {
+    channels.iter().map(|&c| format.sample(frame, c)).sum::<f32>() / channels.len() as f32
+}
+
+/// Read `from` until it ends, handing `write` whole frames as they arrive
+/// until it returns false.
+pub fn pump(from: &mut impl Read, frame_bytes: usize, mut write: impl FnMut(&[u8]) -> bool) {
+    let mut buf = vec![0u8; 16384.max(frame_bytes)];
+    let mut held = 0; // bytes of a frame split across reads
+    loop {
+        let n = match from.read(&mut buf[held..]) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        };
+        let whole = (held + n) / frame_bytes * frame_bytes;
+        if !write(&buf[..whole]) { break; }
+        buf.copy_within(whole..held + n, 0);
+        held = held + n - whole;
+    }
+}
+
+/// What --input reads: a file, or standard input for "-".
+#[derive(Debug, Clone)]
+pub struct RawInput {
+    pub path: PathBuf,
+    pub format: Format,
+}
+
+// How far piped audio may run ahead of real time
+pub const LEAD: Duration = Duration::from_millis(50);
+
+/// Start reading `input` into one ring per tap. Returns the rings, the
+/// sample rate and the channel count.
+pub fn open(input: &RawInput, taps: &[&[usize]]) -> Result<(Vec<ring::Reader>, u32, u16)> {
+    let format = input.format;
+    let (name, mut from): (String, Box<dyn Read + Send>) = if input.path.as_os_str() == "-" {
+        ("standard input".into(), Box::new(std::io::stdin()))
+    } else {
+        let file = std::fs::File::open(&input.path).with_context(|| format!("Failed to open {}", input.path.display()))?;
+        (input.path.display().to_string(), Box::new(file))
+    };
+    println!("Reading audio from {name} ({})", format.describe());
+    let (mut feed, rxs) = Feed::new(format, taps, "--input")?;
+    std::thread::Builder::new()
+        .name("pcm-input".into())
+        .spawn(move || {
+            let started = Instant::now();
+            let mut frames = 0u64;
+            pump(&mut from, format.frame_bytes(), |bytes| {
+                frames += (bytes.len() / format.frame_bytes()) as u64;
+                let due = Duration::from_secs_f64(frames as f64 / format.sample_rate as f64);
+                if let Some(ahead) = due.checked_sub(started.elapsed() + LEAD) { std::thread::sleep(ahead); }
+                feed.write_all(bytes)
+            });
+            // Dropping the feed ends the input
+        })
+        .context("Failed to start the input thread")?;
+    Ok((rxs, format.sample_rate, format.channels))
+}
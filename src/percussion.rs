@@ -0,0 +1,127 @@
+// ---------------------------- Percussive hits ----------------------------
+//
+// Knocks on the guitar body and slaps across the strings have no pitch, so
+// the detector reports silence for them. They do show up as a sudden jump in
+// level that never settles into a note. A dull knock keeps its energy low in
+// the spectrum; a slap is bright and noisy, which the zero-crossing rate of
+// the attack tells apart cheaply.
+
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct PercussionConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    // Level jump (ratio over the recent minimum) that counts as a hit
+    #[serde(default = "default_onset_ratio")]
+    pub onset_ratio: f32,
+    // Quietest hop RMS (0..1) that can be a hit
+    #[serde(default = "default_min_level")]
+    pub min_level: f32,
+    // Attacks whose zero-crossing rate (in Hz) is at least this are slaps
+    #[serde(default = "default_slap_min_hz")]
+    pub slap_min_hz: f32,
+    // Consecutive unpitched frames required, so plucked notes don't count
+    #[serde(default = "default_hold_frames")]
+    pub hold_frames: usize,
+    // Minimum ms between two hits
+    #[serde(default = "default_cooldown_ms")]
+    pub cooldown_ms: u64,
+}
+
+fn default_onset_ratio() -> f32 { 4.0 }
+fn default_min_level() -> f32 { 0.05 }
+fn default_slap_min_hz() -> f32 { 1500.0 }
+fn default_hold_frames() -> usize { 2 }
+fn default_cooldown_ms() -> u64 { 200 }
+
+impl Default for PercussionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            onset_ratio: default_onset_ratio(),
+            min_level: default_min_level(),
+            slap_min_hz: default_slap_min_hz(),
+            hold_frames: default_hold_frames(),
+            cooldown_ms: default_cooldown_ms(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hit {
+    Tap,
+    Slap,
+}
+
+impl Hit {
+    /// note_map key for this hit.
+    pub fn name(self) -> &'static str {
+        match self {
+            Hit::Tap => "tap",
+            Hit::Slap => "slap",
+        }
+    }
+}
+
+// Hops of level history used as the "before the hit" reference
+const HISTORY: usize = 6;
+
+pub struct PercussionDetector {
+    onset_ratio: f32,
+    min_level: f32,
+    slap_min_hz: f32,
+    hold_frames: usize,
+    cooldown: Duration,
+    history: Vec<f32>,
+    // Hit waiting to be confirmed and how many unpitched frames it has lasted
+    candidate: Option<(Hit, usize)>,
+    last_hit: Option<Instant>,
+}
+
+impl PercussionDetector {
+    pub fn new(cfg: &PercussionConfig) -> Self {
+        Self {
+            onset_ratio: cfg.onset_ratio,
+            min_level: cfg.min_level,
+            slap_min_hz: cfg.slap_min_hz,
+            hold_frames: cfg.hold_frames.max(1),
+            cooldown: Duration::from_millis(cfg.cooldown_ms),
+            history: Vec::with_capacity(HISTORY),
+            candidate: None,
+            last_hit: None,
+        }
+    }
+
+    /// Feed the newest hop of audio and whether this frame had a pitch.
+    /// Returns a hit once it has stayed unpitched for `hold_frames`.
+    pub fn update(&mut self, hop: &[f32], sample_rate: f32, pitched: bool, now: Instant) -> Option<Hit> {
+        let level = crate::rms(hop);
+        let reference = self.history.iter().copied().fold(f32::MAX, f32::min);
+        if self.history.len() == HISTORY { self.history.remove(0); }
+        self.history.push(level);
+
+        if pitched {
+            self.candidate = None;
+            return None;
+        }
+        let cooling = self.last_hit.is_some_and(|t| now.duration_since(t) < self.cooldown);
+        if self.candidate.is_none()
+            && !cooling
+            && level >= self.min_level
+            && reference < f32::MAX
+            && level > self.onset_ratio * reference.max(1e-4)
+        {
+            let kind = if crate::zero_crossing_hz(hop, sample_rate) >= self.slap_min_hz { Hit::Slap } else { Hit::Tap };
+            self.candidate = Some((kind, 0));
+        }
+        let (kind, frames) = self.candidate.as_mut()?;
+        *frames += 1;
+        if *frames < self.hold_frames { return None; }
+        let kind = *kind;
+        self.candidate = None;
+        self.last_hit = Some(now);
+        Some(kind)
+    }
+}
@@ -0,0 +1,74 @@
+// ---------------------------- Embedding ----------------------------
+//
+// Trigger mode without the command line, for programs that build the engine
+// in: a Pipeline reads samples pushed into its SampleSink by the host
+// program's own audio code (or a file, or a network stream) instead of
+// opening an input device, and hands whatever the status line shows to a
+// callback as NoteEvents. Mapped actions run as they do in the binary.
+// a4_hz, transpose_semitones and dry_run are kept per process, so only one
+// Pipeline should run at a time.
+
+use anyhow::{anyhow, Result};
+
+use crate::{apply_settings, open_outputs, ring, status, trigger_loop, Action, AudioInput, Config};
+
+/// What the status line shows, as values.
+#[derive(Debug, Clone)]
+pub enum NoteEvent {
+    /// A pitch this frame: its frequency, the nearest note ("A#4"), how
+    /// far from it in cents, and how clearly periodic the audio was (the
+    /// detector's correlation, up to 1)
+    Pitch { hz: f32, note: String, cents: f32, confidence: f32 },
+    /// No confident pitch this frame
+    Silence,
+    /// A mapping fired; `note` is its note_map key
+    Trigger { note: String, action: Action },
+    /// Anything else: profile switches, releases, tempo, pauses...
+    Status(String),
+}
+
+/// Trigger mode on the audio pushed into its SampleSink.
+pub struct Pipeline {
+    cfg: Config,
+    input: AudioInput,
+    listener: Option<status::Listener>,
+}
+
+/// Where a Pipeline's audio goes in: mono samples at its sample rate.
+pub struct SampleSink(ring::Writer);
+
+impl Pipeline {
+    /// A pipeline for `cfg` that analyses mono audio at `sample_rate`, and
+    /// the sink to push that audio into.
+    pub fn new(cfg: Config, sample_rate: u32) -> (Self, SampleSink) {
+        // About a second, as for an input device
+        let (tx, rx) = ring::channel(sample_rate as usize);
+        let input = AudioInput::new(&cfg, rx, sample_rate, 1);
+        (Self { cfg, input, listener: None }, SampleSink(tx))
+    }
+
+    /// Call `listener` with every event, on the thread running the pipeline.
+    pub fn on_event(mut self, listener: impl FnMut(&NoteEvent) + Send + 'static) -> Self {
+        self.listener = Some(Box::new(listener));
+        self
+    }
+
+    /// Detect notes and run their mappings until the SampleSink has been
+    /// dropped. Blocks, so give it a thread of its own.
+    pub fn run(mut self) -> Result<()> {
+        apply_settings(&self.cfg);
+        open_outputs(&self.cfg)?;
+        match trigger_loop(&self.cfg, &mut self.input, None, self.listener) {
+            // The sink is gone: the audio has ended
+            Err(e) if e.downcast_ref::<ring::StreamEnded>().is_some() => Ok(()),
+            ended => ended.map(|_| ()),
+        }
+    }
+}
+
+impl SampleSink {
+    /// Hand samples to the pipeline; waits while it is a second behind.
+    pub fn push(&mut self, samples: &[f32]) -> Result<()> {
+        self.0.write_all(samples).map_err(|_| anyhow!("the pipeline has stopped"))
+    }
+}
@@ -0,0 +1,633 @@
+// ---------------------------- Pitch detection ----------------------------
+//
+// The pitch detectors: normalized autocorrelation, YIN and MPM, as plain
+// functions and behind the PitchDetector trait. The CLAP plugin (clap/) runs
+// the autocorrelation one on its audio thread.
+
+use std::f32::consts::PI;
+
+/// A detected pitch and how clearly it was heard (0..1; the correlation at
+/// the period, or what plays that role for the detector).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PitchEstimate {
+    pub hz: f32,
+    pub clarity: f32,
+}
+
+/// A pitch detection algorithm run on one analysis window at a time.
+pub trait PitchDetector {
+    /// The pitch of the window, however unclear; callers compare `clarity`
+    /// with their own threshold.
+    fn detect(&mut self, window: &[f32]) -> Option<PitchEstimate>;
+}
+
+/// Sample rate of the windows and the pitch range searched.
+#[derive(Debug, Clone, Copy)]
+pub struct SearchRange {
+    pub sample_rate: f32,
+    pub min_hz: f32,
+    pub max_hz: f32,
+}
+
+/// Normalized autocorrelation (detect_pitch_autocorr). Keeps its window
+/// function and working buffers from one window to the next.
+pub struct Autocorr {
+    range: SearchRange,
+    scratch: Scratch,
+}
+
+impl Autocorr {
+    pub fn new(range: SearchRange) -> Self {
+        Self { range, scratch: Scratch::default() }
+    }
+
+    /// Size the working buffers for windows of `window_len` samples now, so
+    /// that detect doesn't allocate (for real-time threads).
+    pub fn prepare(&mut self, window_len: usize) {
+        let r = self.range;
+        let (_, max_lag) = lag_range(window_len, r.sample_rate, r.min_hz, r.max_hz);
+        let s = &mut self.scratch;
+        s.fit(window_len, max_lag);
+        s.x.reserve_exact(window_len.saturating_sub(s.x.len()));
+        s.r.reserve_exact((max_lag + 3).saturating_sub(s.r.len()));
+    }
+}
+
+/// YIN (detect_pitch_yin).
+pub struct Yin(pub SearchRange);
+
+/// McLeod Pitch Method (detect_pitch_mpm).
+pub struct Mpm(pub SearchRange);
+
+impl PitchDetector for Autocorr {
+    fn detect(&mut self, window: &[f32]) -> Option<PitchEstimate> {
+        let r = self.range;
+        autocorr(&mut self.scratch, window, r.sample_rate, r.min_hz, r.max_hz, 0.0)
+            .map(|(hz, clarity)| PitchEstimate { hz, clarity })
+    }
+}
+
+impl PitchDetector for Yin {
+    fn detect(&mut self, window: &[f32]) -> Option<PitchEstimate> {
+        let r = self.0;
+        detect_pitch_yin(window, r.sample_rate, r.min_hz, r.max_hz, 0.0).map(|(hz, clarity)| PitchEstimate { hz, clarity })
+    }
+}
+
+impl PitchDetector for Mpm {
+    fn detect(&mut self, window: &[f32]) -> Option<PitchEstimate> {
+        let r = self.0;
+        detect_pitch_mpm(window, r.sample_rate, r.min_hz, r.max_hz, 0.0).map(|(hz, clarity)| PitchEstimate { hz, clarity })
+    }
+}
+
+// Returns the pitch and the normalized correlation at its period (0..1)
+pub fn detect_pitch_autocorr(
+    input: &[f32],
+    sample_rate: f32,
+    min_hz: f32,
+    max_hz: f32,
+    corr_threshold: f32,
+) -> Option<(f32, f32)> {
+    autocorr(&mut Scratch::default(), input, sample_rate, min_hz, max_hz, corr_threshold)
+}
+
+// What detect_pitch_autocorr computes for every window of the same length,
+// and its buffers
+#[derive(Default)]
+struct Scratch {
+    hann: Vec<f32>,
+    // The Hann window's own autocorrelation, by lag
+    hann_r: Vec<f64>,
+    x: Vec<f32>,
+    r: Vec<f32>,
+}
+
+impl Scratch {
+    // Window function for n-sample windows, and its autocorrelation up to max_lag + 1
+    fn fit(&mut self, n: usize, max_lag: usize) {
+        if self.hann.len() != n {
+            self.hann = (0..n).map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / (n as f32 - 1.0)).cos()).collect();
+            self.hann_r.clear();
+        }
+        while self.hann_r.len() <= max_lag + 1 { self.hann_r.push(hann_autocorr(self.hann_r.len(), n)); }
+    }
+}
+
+// Lags searched for periods between max_hz and min_hz. Beyond a third of the
+// window the Hann correction divides by very small numbers, so longer periods
+// need a longer window.
+fn lag_range(n: usize, sample_rate: f32, min_hz: f32, max_hz: f32) -> (usize, usize) {
+    let min_lag = ((sample_rate / max_hz).round() as usize).max(2);
+    let max_lag = ((sample_rate / min_hz).round() as usize).min(n / 3);
+    (min_lag, max_lag)
+}
+
+fn autocorr(
+    s: &mut Scratch,
+    input: &[f32],
+    sample_rate: f32,
+    min_hz: f32,
+    max_hz: f32,
+    corr_threshold: f32,
+) -> Option<(f32, f32)> {
+    if input.is_empty() { return None; }
+    let n = input.len();
+    let (min_lag, max_lag) = lag_range(n, sample_rate, min_hz, max_hz);
+    if min_lag + 2 >= max_lag { return None; }
+    s.fit(n, max_lag);
+
+    // Remove DC and apply Hann window
+    let mean = input.iter().copied().sum::<f32>() / n as f32;
+    s.x.clear();
+    s.x.extend(input.iter().zip(&s.hann).map(|(&v, &w)| (v - mean) * w));
+    let x = &s.x;
+
+    // Precompute energy for normalization
+    let energy0 = dot(x, x);
+    if energy0 <= 1e-9 { return None; }
+
+    // Normalized autocorrelation for lags in [min_lag, max_lag]:
+    // r[lag - first] for every lag we look at, including one either side of
+    // the search range so the edges can be interpolated too. Dividing by the
+    // window's own autocorrelation (Boersma 1993) keeps the shrinking overlap
+    // from dragging long-period peaks towards shorter lags, which made low
+    // notes read sharp.
+    let first = min_lag - 1;
+    s.r.clear();
+    s.r.extend((first..=max_lag + 1).map(|lag| match s.hann_r[lag] {
+        rw if rw <= 1e-6 => 0.0,
+        rw => (dot(&x[..n - lag], &x[lag..]) / energy0 / rw) as f32,
+    }));
+    let r = &s.r;
+    let r_at = |lag: usize| r[lag - first];
+
+    let mut best_lag = 0usize;
+
+    // When min_lag is small compared to the period (low notes), the search
+    // starts on the falling slope of the lobe around lag 0, which can beat the
+    // real period peak. Skip lags until that slope bottoms out.
+    let mut best_r = 0.0f32;
+    let mut search_from = max_lag + 1;
+    for lag in min_lag..=max_lag {
+        let r = r_at(lag);
+        if search_from > max_lag {
+            if r <= r_at(lag - 1) { continue; }
+            search_from = lag;
+        }
+        if r > best_r {
+            best_r = r;
+            best_lag = lag;
+        }
+    }
+
+    if best_r < corr_threshold || best_lag == 0 { return None; }
+
+    // Every multiple of the period correlates almost as well as the period
+    // itself, and whichever lands closest to a whole sample wins outright.
+    // Take the first peak that is nearly as strong as the best one instead.
+    if let Some(lag) = (search_from..best_lag)
+        .find(|&l| r_at(l) >= 0.9 * best_r && r_at(l) >= r_at(l - 1) && r_at(l) >= r_at(l + 1))
+    {
+        best_lag = lag;
+    }
+
+    // Parabolic interpolation around best_lag for sub-sample peak
+    let r0 = r_at(best_lag);
+    let r1 = r_at(best_lag - 1);
+    let r2 = r_at(best_lag + 1);
+
+    let denom = r1 - (2.0 * r0) + r2;
+    let delta = if denom.abs() > 1e-6 {
+        0.5 * (r1 - r2) / denom
+    } else { 0.0 };
+    let est_lag = (best_lag as f32) + delta.clamp(-1.0, 1.0);
+
+    let f0 = sample_rate / est_lag;
+    if f0.is_finite() && f0 >= min_hz && f0 <= max_hz { Some((f0, best_r)) } else { None }
+}
+
+// Normalized autocorrelation of an n-sample Hann window at `lag`
+fn hann_autocorr(lag: usize, n: usize) -> f64 {
+    let t = lag as f64 / n as f64;
+    let tau = 2.0 * std::f64::consts::PI * t;
+    (1.0 - t) * (2.0 / 3.0 + tau.cos() / 3.0) + tau.sin() / (2.0 * std::f64::consts::PI)
+}
+
+// Dips of the normalized difference below this count as periods; the first
+// one wins so that multiples of the period don't
+const YIN_DIP: f32 = 0.15;
+
+// YIN (de Cheveigné & Kawahara 2002). Returns the pitch and 1 minus the
+// cumulative-mean-normalized difference at its period, which plays the role
+// of the correlation above (0..1). Comparing the signal with itself rather
+// than multiplying keeps attack transients from swamping the period.
+pub fn detect_pitch_yin(
+    input: &[f32],
+    sample_rate: f32,
+    min_hz: f32,
+    max_hz: f32,
+    corr_threshold: f32,
+) -> Option<(f32, f32)> {
+    let n = input.len();
+    let min_lag = ((sample_rate / max_hz).round() as usize).max(2);
+    let max_lag = ((sample_rate / min_hz).round() as usize).min(n / 2);
+    if min_lag + 2 >= max_lag { return None; }
+
+    // Difference function over a fixed span so every lag sums as many terms
+    let span = n - max_lag - 1;
+    let mut d = vec![0.0f32; max_lag + 2];
+    for (lag, slot) in d.iter_mut().enumerate().skip(1) {
+        *slot = squared_difference(&input[..span], &input[lag..lag + span]) as f32;
+    }
+
+    // Cumulative mean normalization: d'(0) = 1, dips below 1 are periodic
+    let mut running = 0.0f32;
+    let mut cmnd = vec![1.0f32; max_lag + 2];
+    for lag in 1..d.len() {
+        running += d[lag];
+        cmnd[lag] = if running > 0.0 { d[lag] * lag as f32 / running } else { 1.0 };
+    }
+
+    // First dip under YIN_DIP, followed to its bottom; else the deepest dip
+    let best_lag = match (min_lag..=max_lag).find(|&l| cmnd[l] < YIN_DIP) {
+        Some(mut lag) => {
+            while lag < max_lag && cmnd[lag + 1] < cmnd[lag] { lag += 1; }
+            lag
+        }
+        None => (min_lag..=max_lag).min_by(|&a, &b| cmnd[a].total_cmp(&cmnd[b]))?,
+    };
+    let clarity = 1.0 - cmnd[best_lag];
+    if clarity < corr_threshold { return None; }
+
+    // Parabolic interpolation around the dip
+    let (c1, c0, c2) = (cmnd[best_lag - 1], cmnd[best_lag], cmnd[best_lag + 1]);
+    let denom = c1 - 2.0 * c0 + c2;
+    let delta = if denom.abs() > 1e-6 { 0.5 * (c1 - c2) / denom } else { 0.0 };
+    let est_lag = best_lag as f32 + delta.clamp(-1.0, 1.0);
+
+    let f0 = sample_rate / est_lag;
+    if f0.is_finite() && f0 >= min_hz && f0 <= max_hz { Some((f0, clarity.min(1.0))) } else { None }
+}
+
+// Key maxima at least this fraction of the highest count as the period; the
+// first one wins, which is what keeps MPM off the octave below
+const MPM_CUTOFF: f32 = 0.93;
+
+// McLeod Pitch Method (McLeod & Wyvill 2005). The normalized square
+// difference function (NSDF) is the autocorrelation divided by the energy of
+// the overlapping parts, so it stays in -1..1 at every lag. Between each
+// upward and downward zero crossing only the highest peak is a candidate.
+// Returns the pitch and the NSDF at its period (0..1).
+pub fn detect_pitch_mpm(
+    input: &[f32],
+    sample_rate: f32,
+    min_hz: f32,
+    max_hz: f32,
+    corr_threshold: f32,
+) -> Option<(f32, f32)> {
+    if input.is_empty() { return None; }
+    let mean = input.iter().copied().sum::<f32>() / input.len() as f32;
+    let x: Vec<f32> = input.iter().map(|&s| s - mean).collect();
+    let n = x.len();
+    let min_lag = ((sample_rate / max_hz).round() as usize).max(2);
+    let max_lag = ((sample_rate / min_hz).round() as usize).min(n / 2);
+    if min_lag + 2 >= max_lag { return None; }
+
+    // energy[k]: the energy of x[..k], so that of any overlap is a difference
+    let mut energy = Vec::with_capacity(n + 1);
+    energy.push(0.0f64);
+    for &v in &x { energy.push(energy[energy.len() - 1] + v as f64 * v as f64); }
+    let nsdf: Vec<f32> = (0..=max_lag + 1)
+        .map(|lag| {
+            let r = dot(&x[..n - lag], &x[lag..]);
+            let m = energy[n - lag] + (energy[n] - energy[lag]);
+            if m > 1e-12 { (2.0 * r / m) as f32 } else { 0.0 }
+        })
+        .collect();
+
+    // Highest peak of each positive lobe after the one around lag 0
+    let mut peaks = Vec::new();
+    let mut lag = 1;
+    while lag <= max_lag && nsdf[lag] > 0.0 { lag += 1; }
+    while lag <= max_lag {
+        while lag <= max_lag && nsdf[lag] <= 0.0 { lag += 1; }
+        let mut best: Option<usize> = None;
+        while lag <= max_lag && nsdf[lag] > 0.0 {
+            if lag >= min_lag && best.is_none_or(|b| nsdf[lag] > nsdf[b]) { best = Some(lag); }
+            lag += 1;
+        }
+        peaks.extend(best);
+    }
+    let highest = peaks.iter().map(|&p| nsdf[p]).fold(0.0f32, f32::max);
+    let best_lag = *peaks.iter().find(|&&p| nsdf[p] >= MPM_CUTOFF * highest)?;
+    let clarity = nsdf[best_lag];
+    if clarity < corr_threshold { return None; }
+
+    // Parabolic interpolation around the peak
+    let (r1, r0, r2) = (nsdf[best_lag - 1], nsdf[best_lag], nsdf[best_lag + 1]);
+    let denom = r1 - 2.0 * r0 + r2;
+    let delta = if denom.abs() > 1e-6 { 0.5 * (r1 - r2) / denom } else { 0.0 };
+    let est_lag = best_lag as f32 + delta.clamp(-1.0, 1.0);
+
+    let f0 = sample_rate / est_lag;
+    if f0.is_finite() && f0 >= min_hz && f0 <= max_hz { Some((f0, clarity.min(1.0))) } else { None }
+}
+
+// Energy at the odd multiples of f0 / 2, relative to the harmonics of f0,
+// above which the true pitch is an octave lower
+const SUBHARMONIC_RATIO: f32 = 0.1;
+
+// Octave-error check: a detector can lock onto twice the true period's
+// frequency when the fundamental is weak (low bass strings). If f0 were
+// right, nothing would sound at f0/2, 3f0/2, 5f0/2; if those carry real
+// energy the period is twice as long. Repeats while it keeps finding one and
+// the lower pitch stays above min_hz.
+pub fn correct_octave(input: &[f32], sample_rate: f32, f0: f32, min_hz: f32) -> f32 {
+    let n = input.len();
+    if n < 4 { return f0; }
+    let mean = input.iter().copied().sum::<f32>() / n as f32;
+    let x: Vec<f32> = input
+        .iter()
+        .enumerate()
+        .map(|(i, &s)| (s - mean) * (0.5 - 0.5 * (2.0 * PI * i as f32 / (n as f32 - 1.0)).cos()))
+        .collect();
+    let mut f = f0;
+    // The window must hold a few periods of the lower pitch to tell it apart
+    while f / 2.0 >= min_hz && n as f32 >= 4.0 * sample_rate / (f / 2.0) {
+        let half = f / 2.0;
+        let odd: f32 = [1.0, 3.0, 5.0].iter().map(|k| goertzel_power(&x, sample_rate, half * k)).sum();
+        let harmonics: f32 = [1.0, 2.0, 3.0].iter().map(|k| goertzel_power(&x, sample_rate, f * k)).sum();
+        if harmonics <= 0.0 || odd < SUBHARMONIC_RATIO * harmonics { break; }
+        f = half;
+    }
+    f
+}
+
+// Power of `x` at frequency `hz`
+fn goertzel_power(x: &[f32], sample_rate: f32, hz: f32) -> f32 {
+    if hz >= sample_rate / 2.0 { return 0.0; }
+    let coeff = 2.0 * (2.0 * PI * hz / sample_rate).cos();
+    let (mut s1, mut s2) = (0.0f32, 0.0f32);
+    for &v in x {
+        let s0 = v + coeff * s1 - s2;
+        s2 = s1;
+        s1 = s0;
+    }
+    (s1 * s1 + s2 * s2 - coeff * s1 * s2).max(0.0)
+}
+
+// ---- Inner loops ----
+//
+// Every detector spends its time in one of two sums over a window and a
+// shifted copy of it. They run eight f32 lanes at a time: AVX2 with FMA
+// when the CPU has it (checked at run time), NEON on 64-bit ARM, and
+// otherwise plain code the compiler vectorizes for the baseline (SSE2).
+// The lanes are added up in f64.
+
+/// Sum of a[i] * b[i] over the shorter of the two.
+pub fn dot(a: &[f32], b: &[f32]) -> f64 {
+    let n = a.len().min(b.len());
+    let (a, b) = (&a[..n], &b[..n]);
+    vectorized(Kernel::Dot, a, b).unwrap_or_else(|| lanes(a, b, |x, y| x * y))
+}
+
+/// Sum of (a[i] - b[i])² over the shorter of the two.
+pub fn squared_difference(a: &[f32], b: &[f32]) -> f64 {
+    let n = a.len().min(b.len());
+    let (a, b) = (&a[..n], &b[..n]);
+    vectorized(Kernel::SquaredDifference, a, b).unwrap_or_else(|| lanes(a, b, |x, y| (x - y) * (x - y)))
+}
+
+#[derive(Clone, Copy)]
+enum Kernel {
+    Dot,
+    SquaredDifference,
+}
+
+// The sum with this CPU's vector instructions, if there are any to use
+#[cfg(target_arch = "x86_64")]
+fn vectorized(kernel: Kernel, a: &[f32], b: &[f32]) -> Option<f64> {
+    if !(is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma")) { return None; }
+    // SAFETY: the CPU has the features these are compiled for, and a and b
+    // are the same length
+    Some(unsafe {
+        match kernel {
+            Kernel::Dot => x86::dot_avx2(a, b),
+            Kernel::SquaredDifference => x86::squared_difference_avx2(a, b),
+        }
+    })
+}
+
+#[cfg(target_arch = "aarch64")]
+fn vectorized(kernel: Kernel, a: &[f32], b: &[f32]) -> Option<f64> {
+    // SAFETY: every aarch64 CPU has NEON, and a and b are the same length
+    Some(unsafe {
+        match kernel {
+            Kernel::Dot => arm::dot_neon(a, b),
+            Kernel::SquaredDifference => arm::squared_difference_neon(a, b),
+        }
+    })
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn vectorized(_kernel: Kernel, _a: &[f32], _b: &[f32]) -> Option<f64> {
+    None
+}
+
+// Eight independent sums, which the compiler keeps in vector registers
+#[inline(always)]
+fn lanes(a: &[f32], b: &[f32], term: impl Fn(f32, f32) -> f32) -> f64 {
+    let mut acc = [0.0f32; 8];
+    let (ca, cb) = (a.chunks_exact(8), b.chunks_exact(8));
+    let tail: f64 = ca.remainder().iter().zip(cb.remainder()).map(|(&x, &y)| term(x, y) as f64).sum();
+    for (ca, cb) in ca.zip(cb) {
+        for ((s, &x), &y) in acc.iter_mut().zip(ca).zip(cb) { *s += term(x, y); }
+    }
+    acc.iter().map(|&s| s as f64).sum::<f64>() + tail
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    // Two accumulators of eight, so consecutive FMAs don't wait on each other
+    #[target_feature(enable = "avx2,fma")]
+    pub unsafe fn dot_avx2(a: &[f32], b: &[f32]) -> f64 {
+        let (mut s0, mut s1) = (_mm256_setzero_ps(), _mm256_setzero_ps());
+        let mut i = 0;
+        while i + 16 <= a.len() {
+            let (pa, pb) = (a.as_ptr().add(i), b.as_ptr().add(i));
+            s0 = _mm256_fmadd_ps(_mm256_loadu_ps(pa), _mm256_loadu_ps(pb), s0);
+            s1 = _mm256_fmadd_ps(_mm256_loadu_ps(pa.add(8)), _mm256_loadu_ps(pb.add(8)), s1);
+            i += 16;
+        }
+        let tail: f64 = a[i..].iter().zip(&b[i..]).map(|(&x, &y)| x as f64 * y as f64).sum();
+        sum(_mm256_add_ps(s0, s1)) + tail
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub unsafe fn squared_difference_avx2(a: &[f32], b: &[f32]) -> f64 {
+        let (mut s0, mut s1) = (_mm256_setzero_ps(), _mm256_setzero_ps());
+        let mut i = 0;
+        while i + 16 <= a.len() {
+            let (pa, pb) = (a.as_ptr().add(i), b.as_ptr().add(i));
+            let d0 = _mm256_sub_ps(_mm256_loadu_ps(pa), _mm256_loadu_ps(pb));
+            let d1 = _mm256_sub_ps(_mm256_loadu_ps(pa.add(8)), _mm256_loadu_ps(pb.add(8)));
+            s0 = _mm256_fmadd_ps(d0, d0, s0);
+            s1 = _mm256_fmadd_ps(d1, d1, s1);
+            i += 16;
+        }
+        let tail: f64 = a[i..].iter().zip(&b[i..]).map(|(&x, &y)| (x as f64 - y as f64).powi(2)).sum();
+        sum(_mm256_add_ps(s0, s1)) + tail
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    unsafe fn sum(v: __m256) -> f64 {
+        let mut out = [0.0f32; 8];
+        _mm256_storeu_ps(out.as_mut_ptr(), v);
+        out.iter().map(|&s| s as f64).sum()
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod arm {
+    use std::arch::aarch64::*;
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn dot_neon(a: &[f32], b: &[f32]) -> f64 {
+        let (mut s0, mut s1) = (vdupq_n_f32(0.0), vdupq_n_f32(0.0));
+        let mut i = 0;
+        while i + 8 <= a.len() {
+            let (pa, pb) = (a.as_ptr().add(i), b.as_ptr().add(i));
+            s0 = vfmaq_f32(s0, vld1q_f32(pa), vld1q_f32(pb));
+            s1 = vfmaq_f32(s1, vld1q_f32(pa.add(4)), vld1q_f32(pb.add(4)));
+            i += 8;
+        }
+        let tail: f64 = a[i..].iter().zip(&b[i..]).map(|(&x, &y)| x as f64 * y as f64).sum();
+        vaddvq_f32(s0) as f64 + vaddvq_f32(s1) as f64 + tail
+    }
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn squared_difference_neon(a: &[f32], b: &[f32]) -> f64 {
+        let (mut s0, mut s1) = (vdupq_n_f32(0.0), vdupq_n_f32(0.0));
+        let mut i = 0;
+        while i + 8 <= a.len() {
+            let (pa, pb) = (a.as_ptr().add(i), b.as_ptr().add(i));
+            let d0 = vsubq_f32(vld1q_f32(pa), vld1q_f32(pb));
+            let d1 = vsubq_f32(vld1q_f32(pa.add(4)), vld1q_f32(pb.add(4)));
+            s0 = vfmaq_f32(s0, d0, d0);
+            s1 = vfmaq_f32(s1, d1, d1);
+            i += 8;
+        }
+        let tail: f64 = a[i..].iter().zip(&b[i..]).map(|(&x, &y)| (x as f64 - y as f64).powi(2)).sum();
+        vaddvq_f32(s0) as f64 + vaddvq_f32(s1) as f64 + tail
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: f32 = 48000.0;
+    const RANGE: SearchRange = SearchRange { sample_rate: RATE, min_hz: 60.0, max_hz: 2000.0 };
+
+    // `n` samples of a tone at `hz` with harmonics 1, 2, 3... at these amplitudes
+    fn tone(hz: f32, harmonics: &[f32], n: usize) -> Vec<f32> {
+        (0..n)
+            .map(|i| {
+                let t = i as f32 / RATE;
+                harmonics.iter().enumerate().map(|(k, a)| a * (2.0 * PI * hz * (k + 1) as f32 * t).sin()).sum::<f32>()
+            })
+            .collect()
+    }
+
+    fn detectors() -> Vec<(&'static str, Box<dyn PitchDetector>)> {
+        vec![("autocorr", Box::new(Autocorr::new(RANGE))), ("yin", Box::new(Yin(RANGE))), ("mpm", Box::new(Mpm(RANGE)))]
+    }
+
+    fn cents(hz: f32, expected: f32) -> f32 { 1200.0 * (hz / expected).log2() }
+
+    // Open strings of a guitar and a violin, and a high violin note
+    const NOTES: [f32; 6] = [82.41, 146.83, 196.0, 440.0, 659.26, 1318.5];
+
+    #[test]
+    fn sines() {
+        for (name, mut d) in detectors() {
+            for hz in NOTES {
+                let e = d.detect(&tone(hz, &[0.5], 4096)).unwrap_or_else(|| panic!("{name} missed {hz} Hz"));
+                assert!(cents(e.hz, hz).abs() < 5.0, "{name}: {hz} Hz read as {}", e.hz);
+                assert!(e.clarity > 0.9, "{name}: {hz} Hz clarity {}", e.clarity);
+            }
+        }
+    }
+
+    #[test]
+    fn harmonic_tones() {
+        // A bowed string's spectrum: falling harmonics, the second louder than
+        // the fundamental, which tempts detectors an octave up
+        let bowed = [0.3, 0.45, 0.25, 0.2, 0.12, 0.1, 0.06, 0.05];
+        for (name, mut d) in detectors() {
+            for hz in NOTES {
+                let e = d.detect(&tone(hz, &bowed, 4096)).unwrap_or_else(|| panic!("{name} missed {hz} Hz"));
+                assert!(cents(e.hz, hz).abs() < 10.0, "{name}: {hz} Hz read as {}", e.hz);
+                assert!(e.clarity > 0.8, "{name}: {hz} Hz clarity {}", e.clarity);
+            }
+        }
+    }
+
+    #[test]
+    fn silence() {
+        // No pitch, or one nobody would take
+        for (name, mut d) in detectors() {
+            let clarity = d.detect(&vec![0.0; 4096]).map_or(0.0, |e| e.clarity);
+            assert!(clarity < 0.1, "{name} heard a pitch in silence");
+        }
+    }
+
+    // Samples in -1..1 from a fixed seed (xorshift)
+    fn noise(seed: u32, n: usize) -> Vec<f32> {
+        let mut x = seed.max(1);
+        (0..n)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x as f32 / u32::MAX as f32 * 2.0 - 1.0
+            })
+            .collect()
+    }
+
+    #[test]
+    fn vector_sums_match_scalar() {
+        // Every remainder after the 8- and 16-lane loops, and window sizes
+        let lengths = (0..=40).chain([63, 65, 127, 1023, 1024, 4097]);
+        for (i, n) in lengths.enumerate() {
+            let (a, b) = (noise(2 * i as u32 + 1, n + 1), noise(2 * i as u32 + 2, n + 1));
+            // Unaligned too: start one sample in
+            for (a, b) in [(&a[..n], &b[..n]), (&a[1..], &b[1..])] {
+                let exact_dot: f64 = a.iter().zip(b).map(|(&x, &y)| x as f64 * y as f64).sum();
+                let exact_sq: f64 = a.iter().zip(b).map(|(&x, &y)| (x as f64 - y as f64).powi(2)).sum();
+                // f32 lanes round differently from one another; allow for that
+                let tolerance = 1e-5 * (n as f64).max(1.0);
+                for (what, simd, scalar, exact) in [
+                    ("dot", dot(a, b), lanes(a, b, |x, y| x * y), exact_dot),
+                    ("squared_difference", squared_difference(a, b), lanes(a, b, |x, y| (x - y) * (x - y)), exact_sq),
+                ] {
+                    assert!((simd - exact).abs() <= tolerance, "{what} over {n}: {simd} vs {exact}");
+                    assert!((scalar - exact).abs() <= tolerance, "scalar {what} over {n}: {scalar} vs {exact}");
+                }
+            }
+        }
+        // The shorter slice sets the length
+        assert_eq!(dot(&[1.0, 2.0, 3.0], &[4.0, 5.0]), 14.0);
+    }
+
+    #[test]
+    fn prepared_autocorr_detects_the_same() {
+        let window = tone(220.0, &[0.3, 0.2, 0.1], 4096);
+        let mut prepared = Autocorr::new(RANGE);
+        prepared.prepare(window.len());
+        assert_eq!(prepared.detect(&window), Autocorr::new(RANGE).detect(&window));
+    }
+}
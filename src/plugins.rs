@@ -0,0 +1,394 @@
+// ---------------------------- Plugins ----------------------------
+//
+// WebAssembly plugins add pitch detectors and actions without rebuilding the
+// program. A plugin is one .wasm file in [plugins] dir and is named after the
+// file, so plugins/crepe-lite.wasm is "crepe-lite". `detector = { plugin =
+// "crepe-lite" }` detects with it, and `{ type = "plugin", name = "..." }`
+// mappings run it. Plugins run in an interpreter (wasmi) and reach nothing
+// but their own memory and the host functions below: no files, network or
+// devices. Every call has a fuel budget, so a plugin stuck in a loop fails
+// that call instead of stalling detection, and memory is capped.
+//
+// The interface, for any language that builds wasm32 without WASI:
+//
+//   exports  memory
+//            alloc(size: i32) -> i32
+//                room for `size` bytes of input; the host is done with it at
+//                the next alloc call, so one growing buffer will do
+//            detect(ptr: i32, len: i32, sample_rate: f32, min_hz: f32, max_hz: f32) -> f32
+//                detectors: the window is `len` f32s at ptr; returns the
+//                pitch in Hz, or 0 for none
+//            clarity() -> f32
+//                detectors, optional: how clearly the last pitch was heard
+//                (0..1); 1 if not exported
+//            run(ptr: i32, len: i32) -> i32
+//                actions: the event as JSON at ptr (key, note, hz, cents,
+//                level, confidence, and params: the mapping's other fields);
+//                returns 0 on success
+//   imports  host.log(ptr: i32, len: i32)
+//                print a line of UTF-8
+//            host.action(ptr: i32, len: i32) -> i32
+//                queue an action written like a macro step in JSON, e.g.
+//                {"type":"keys","sequence":"Ctrl+S","delay_ms":100}; returns 0
+//                if it is one. Queued actions run after `run` returns.
+
+use crate::pitch::{PitchDetector, PitchEstimate, SearchRange};
+use crate::{script, MacroStep};
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use wasmi::{Caller, Engine, Instance, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, TypedFunc};
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct PluginsConfig {
+    // Where the .wasm files are
+    #[serde(default = "default_dir")]
+    pub dir: String,
+}
+
+fn default_dir() -> String { "plugins".to_string() }
+
+impl Default for PluginsConfig {
+    fn default() -> Self {
+        Self { dir: default_dir() }
+    }
+}
+
+/// Parameters of a `type = "plugin"` mapping: which plugin, and anything
+/// else the mapping sets, passed on to it.
+#[derive(Debug, Deserialize, Clone)]
+pub struct PluginAction {
+    pub name: String,
+    #[serde(flatten)]
+    pub params: Map<String, Value>,
+}
+
+impl PluginAction {
+    pub fn label(&self) -> String {
+        format!("plugin:{}", self.name)
+    }
+}
+
+// Instructions a detector may run on one window, and an action per trigger
+const DETECT_FUEL: u64 = 200_000_000;
+const RUN_FUEL: u64 = 50_000_000;
+// What a plugin's memory may grow to
+const MAX_MEMORY: usize = 64 << 20;
+
+// What the host functions work with
+struct Host {
+    name: String,
+    limits: StoreLimits,
+    queued: Vec<MacroStep>,
+}
+
+// A plugin instance with its own memory
+struct Plugin {
+    store: Store<Host>,
+    instance: Instance,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+}
+
+static ENGINE: OnceLock<Engine> = OnceLock::new();
+static CONFIG: Mutex<Option<PluginsConfig>> = Mutex::new(None);
+// Action plugins by name, loaded at start or at their first trigger
+static LOADED: Mutex<BTreeMap<String, Plugin>> = Mutex::new(BTreeMap::new());
+
+fn engine() -> &'static Engine {
+    ENGINE.get_or_init(|| {
+        let mut config = wasmi::Config::default();
+        config.consume_fuel(true);
+        Engine::new(&config)
+    })
+}
+
+// `len` bytes at `ptr` in the calling plugin's memory, if they are text.
+// Nothing is allocated for a range outside the memory, however long.
+fn read_text(caller: &Caller<'_, Host>, ptr: i32, len: i32) -> Option<String> {
+    let memory = caller.get_export("memory")?.into_memory()?;
+    let start = usize::try_from(ptr).ok()?;
+    let bytes = memory.data(caller).get(start..start.checked_add(usize::try_from(len).ok()?)?)?;
+    String::from_utf8(bytes.to_vec()).ok()
+}
+
+impl Plugin {
+    fn load(cfg: &PluginsConfig, name: &str) -> Result<Self> {
+        let path = Path::new(&cfg.dir).join(format!("{name}.wasm"));
+        let wasm = std::fs::read(&path).with_context(|| format!("cannot read plugin {}", path.display()))?;
+        Self::new(name, &wasm).with_context(|| format!("plugin {}", path.display()))
+    }
+
+    fn new(name: &str, wasm: &[u8]) -> Result<Self> {
+        let module = Module::new(engine(), wasm)?;
+        let host = Host {
+            name: name.to_string(),
+            limits: StoreLimitsBuilder::new().memory_size(MAX_MEMORY).build(),
+            queued: Vec::new(),
+        };
+        let mut store = Store::new(engine(), host);
+        store.limiter(|host| &mut host.limits);
+        let mut linker = <Linker<Host>>::new(engine());
+        linker.func_wrap("host", "log", |caller: Caller<'_, Host>, ptr: i32, len: i32| {
+            if let Some(text) = read_text(&caller, ptr, len) { println!("{}: {text}", caller.data().name); }
+        })?;
+        linker.func_wrap("host", "action", |mut caller: Caller<'_, Host>, ptr: i32, len: i32| -> i32 {
+            let step = read_text(&caller, ptr, len).and_then(|text| serde_json::from_str::<MacroStep>(&text).ok());
+            match step {
+                Some(step) => {
+                    caller.data_mut().queued.push(step);
+                    0
+                }
+                None => -1,
+            }
+        })?;
+        // The start function runs on the action budget
+        store.set_fuel(RUN_FUEL).map_err(|e| anyhow!("{e}"))?;
+        let instance = linker.instantiate(&mut store, &module)?.start(&mut store)?;
+        let memory = instance.get_memory(&store, "memory").ok_or_else(|| anyhow!("it exports no memory"))?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&store, "alloc")
+            .map_err(|_| anyhow!("it exports no alloc(size: i32) -> i32"))?;
+        Ok(Self { store, instance, memory, alloc })
+    }
+
+    // Copy `bytes` into room the plugin made for them
+    fn put(&mut self, bytes: &[u8]) -> Result<i32> {
+        let ptr = self.alloc.call(&mut self.store, bytes.len() as i32)?;
+        self.memory.write(&mut self.store, ptr as usize, bytes).map_err(|e| anyhow!("alloc gave {ptr}: {e}"))?;
+        Ok(ptr)
+    }
+
+    fn func<P: wasmi::WasmParams, R: wasmi::WasmResults>(&self, name: &str, signature: &str) -> Result<TypedFunc<P, R>> {
+        self.instance
+            .get_typed_func::<P, R>(&self.store, name)
+            .map_err(|_| anyhow!("plugin {} exports no {signature}", self.store.data().name))
+    }
+
+    fn detector(self, range: SearchRange) -> Result<Detector> {
+        let detect = self.func("detect", "detect(ptr: i32, len: i32, sample_rate: f32, min_hz: f32, max_hz: f32) -> f32")?;
+        let clarity = self.func("clarity", "clarity() -> f32").ok();
+        Ok(Detector { plugin: self, detect, clarity, range, room: None, bytes: Vec::new(), failed: false })
+    }
+
+    // Hand the event to `run` and collect what it queued
+    fn run(&mut self, event: &Value) -> Result<Vec<MacroStep>> {
+        let run = self.func::<(i32, i32), i32>("run", "run(ptr: i32, len: i32) -> i32")?;
+        let event = event.to_string();
+        self.store.data_mut().queued.clear();
+        self.store.set_fuel(RUN_FUEL).map_err(|e| anyhow!("{e}"))?;
+        let ptr = self.put(event.as_bytes())?;
+        let status = run.call(&mut self.store, (ptr, event.len() as i32))?;
+        let queued = std::mem::take(&mut self.store.data_mut().queued);
+        if status != 0 { return Err(anyhow!("plugin {} failed ({status})", self.store.data().name)); }
+        Ok(queued)
+    }
+}
+
+/// A detector plugin, loaded for one analysis setup.
+pub struct Detector {
+    plugin: Plugin,
+    detect: TypedFunc<(i32, i32, f32, f32, f32), f32>,
+    clarity: Option<TypedFunc<(), f32>>,
+    range: SearchRange,
+    // Where the window goes in the plugin's memory, and its size
+    room: Option<(usize, i32)>,
+    bytes: Vec<u8>,
+    // Reported once; a plugin that fails tends to fail on every window
+    failed: bool,
+}
+
+impl Detector {
+    fn try_detect(&mut self, window: &[f32]) -> Result<Option<PitchEstimate>> {
+        self.bytes.clear();
+        self.bytes.extend(window.iter().flat_map(|s| s.to_le_bytes()));
+        let p = &mut self.plugin;
+        p.store.set_fuel(DETECT_FUEL).map_err(|e| anyhow!("{e}"))?;
+        let ptr = match self.room {
+            Some((len, ptr)) if len == self.bytes.len() => {
+                p.memory.write(&mut p.store, ptr as usize, &self.bytes).map_err(|e| anyhow!("{e}"))?;
+                ptr
+            }
+            _ => p.put(&self.bytes)?,
+        };
+        self.room = Some((self.bytes.len(), ptr));
+        let r = self.range;
+        let hz = self.detect.call(&mut p.store, (ptr, window.len() as i32, r.sample_rate, r.min_hz, r.max_hz))?;
+        if !(hz > 0.0 && hz.is_finite()) { return Ok(None); }
+        let clarity = match &self.clarity {
+            Some(f) => f.call(&mut p.store, ())?.clamp(0.0, 1.0),
+            None => 1.0,
+        };
+        Ok(Some(PitchEstimate { hz, clarity }))
+    }
+}
+
+impl PitchDetector for Detector {
+    fn detect(&mut self, window: &[f32]) -> Option<PitchEstimate> {
+        match self.try_detect(window) {
+            Ok(estimate) => estimate,
+            Err(e) => {
+                if !self.failed { tracing::error!("Detector plugin {}: {e:#}", self.plugin.store.data().name); }
+                self.failed = true;
+                // Its allocations may not have survived the failure
+                self.room = None;
+                None
+            }
+        }
+    }
+}
+
+/// Load the detector plugin `name` for windows at `range`.
+pub fn detector(cfg: &PluginsConfig, name: &str, range: SearchRange) -> Result<Detector> {
+    Plugin::load(cfg, name)?.detector(range)
+}
+
+/// Where plugins are loaded from. Loaded action plugins from elsewhere are
+/// dropped.
+pub fn set_config(cfg: &PluginsConfig) {
+    let mut current = CONFIG.lock().unwrap_or_else(|e| e.into_inner());
+    if current.as_ref().is_some_and(|c| c != cfg) { LOADED.lock().unwrap_or_else(|e| e.into_inner()).clear(); }
+    *current = Some(cfg.clone());
+}
+
+/// Load the action plugins in [plugins] dir, so they are ready for their
+/// first trigger and a broken one shows up now.
+pub fn open(cfg: &PluginsConfig) {
+    set_config(cfg);
+    let Ok(entries) = std::fs::read_dir(&cfg.dir) else { return };
+    let mut loaded = LOADED.lock().unwrap_or_else(|e| e.into_inner());
+    for path in entries.flatten().map(|e| e.path()) {
+        if path.extension().is_none_or(|e| e != "wasm") { continue; }
+        let Some(name) = path.file_stem().and_then(|s| s.to_str()) else { continue };
+        match Plugin::load(cfg, name) {
+            // Detectors are loaded by the analysis that uses them
+            Ok(p) if p.func::<(i32, i32), i32>("run", "run").is_ok() => {
+                loaded.insert(name.to_string(), p);
+            }
+            Ok(_) => {}
+            Err(e) => tracing::warn!("{e:#}"),
+        }
+    }
+    if !loaded.is_empty() { println!("Plugins: {}", loaded.keys().cloned().collect::<Vec<_>>().join(", ")); }
+}
+
+/// Run the mapping's plugin and return the actions it queued.
+pub fn execute(action: &PluginAction) -> Result<Vec<MacroStep>> {
+    let cfg = CONFIG.lock().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_default();
+    let mut loaded = LOADED.lock().unwrap_or_else(|e| e.into_inner());
+    if !loaded.contains_key(&action.name) { loaded.insert(action.name.clone(), Plugin::load(&cfg, &action.name)?); }
+    let plugin = loaded.get_mut(&action.name).expect("just loaded");
+    let (key, heard) = script::event();
+    let event = json!({
+        "key": key,
+        "note": heard.note,
+        "hz": heard.hz,
+        "cents": heard.cents,
+        "level": heard.level,
+        "confidence": heard.confidence,
+        "params": action.params,
+    });
+    let result = plugin.run(&event);
+    // A trap can leave the plugin's memory half updated: start it afresh next time
+    if result.is_err() { loaded.remove(&action.name); }
+    result
+}
+
+/// Whether the plugin `name` loads and exports what a detector (or else an
+/// action) needs.
+pub fn check(cfg: &PluginsConfig, name: &str, as_detector: bool) -> Result<()> {
+    let plugin = Plugin::load(cfg, name)?;
+    if as_detector {
+        plugin.detector(SearchRange { sample_rate: 44100.0, min_hz: 75.0, max_hz: 2000.0 })?;
+    } else {
+        plugin.func::<(i32, i32), i32>("run", "run(ptr: i32, len: i32) -> i32")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A bump allocator over one page, reset by every alloc
+    const ALLOC: &str = r#"
+        (memory (export "memory") 1)
+        (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+    "#;
+
+    fn plugin(body: &str) -> Plugin {
+        let wat = format!("(module {body} {ALLOC})");
+        Plugin::new("test", &wat::parse_str(wat).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn a_detector_reads_the_window() {
+        // Reports the window's first sample as the pitch, and its length as the clarity
+        let mut d = plugin(
+            r#"
+            (global $len (mut i32) (i32.const 0))
+            (func (export "detect") (param $ptr i32) (param $len i32) (param f32 f32 f32) (result f32)
+                (global.set $len (local.get $len))
+                (f32.load (local.get $ptr)))
+            (func (export "clarity") (result f32)
+                (f32.div (f32.convert_i32_u (global.get $len)) (f32.const 1000)))
+            "#,
+        )
+        .detector(SearchRange { sample_rate: 22050.0, min_hz: 75.0, max_hz: 2000.0 })
+        .unwrap();
+        let mut window = vec![0.0f32; 512];
+        window[0] = 440.0;
+        assert_eq!(d.detect(&window), Some(PitchEstimate { hz: 440.0, clarity: 0.512 }));
+        window[0] = 0.0;
+        assert_eq!(d.detect(&window), None);
+    }
+
+    #[test]
+    fn an_action_queues_steps() {
+        let mut p = plugin(
+            r#"
+            (import "host" "action" (func $action (param i32 i32) (result i32)))
+            (data (i32.const 0) "{\"type\":\"keys\",\"sequence\":\"Ctrl+S\",\"delay_ms\":50}")
+            (func (export "run") (param i32 i32) (result i32)
+                (call $action (i32.const 0) (i32.const 49)))
+            "#,
+        );
+        let steps = p.run(&json!({ "params": {} })).unwrap();
+        assert_eq!(steps.len(), 1);
+        assert_eq!((steps[0].delay_ms, crate::action_name(&steps[0].action)), (50, "keys:Ctrl+S".to_string()));
+    }
+
+    #[test]
+    fn text_outside_the_memory_is_refused() {
+        // One page (64 KiB) of memory: a 2 GiB log line, or one running off
+        // the end, is refused, not allocated
+        for (ptr, len) in [(0, i32::MAX), (65530, 10), (-1, 1)] {
+            let mut p = plugin(&format!(
+                r#"
+                (import "host" "action" (func $action (param i32 i32) (result i32)))
+                (func (export "run") (param i32 i32) (result i32)
+                    (call $action (i32.const {ptr}) (i32.const {len})))
+                "#
+            ));
+            assert!(p.run(&json!({ "params": {} })).is_err(), "{ptr}+{len}");
+        }
+    }
+
+    #[test]
+    fn a_runaway_plugin_is_stopped() {
+        let mut d = plugin(r#"(func (export "detect") (param i32 i32 f32 f32 f32) (result f32) (loop $l (br $l)) (f32.const 0))"#)
+            .detector(SearchRange { sample_rate: 22050.0, min_hz: 75.0, max_hz: 2000.0 })
+            .unwrap();
+        assert_eq!(d.detect(&[0.0; 256]), None);
+        assert!(d.failed);
+    }
+
+    #[test]
+    fn plugins_get_nothing_else() {
+        let wat = format!(r#"(module (import "wasi_snapshot_preview1" "fd_write" (func (param i32 i32 i32 i32) (result i32))) {ALLOC})"#);
+        assert!(Plugin::new("test", &wat::parse_str(wat).unwrap()).is_err());
+    }
+}
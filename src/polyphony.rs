@@ -0,0 +1,171 @@
+// ---------------------------- Polyphonic notes ----------------------------
+//
+// note_map keys such as "E3&A3" or "C4&E4&G4" fire when those notes sound
+// together, e.g. two strings plucked at once or both hands on the keyboard.
+// The pitch detector follows one note, so this stage has its own: the energy
+// at every semitone around the mapped notes is measured, the note whose
+// harmonics explain the most of it is taken, its harmonics are subtracted,
+// and the search repeats for the next note (iterative spectral subtraction).
+// A key fires when all its notes are among those heard; extra notes don't
+// matter, and the key with the most notes wins.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+use crate::Mapping;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct PolyphonyConfig {
+    // Most notes taken from one frame
+    #[serde(default = "default_max_notes")]
+    pub max_notes: usize,
+    // A further note must be at least this strong relative to the strongest
+    #[serde(default = "default_min_ratio")]
+    pub min_ratio: f32,
+    // Consecutive frames the same key must be heard before it fires
+    #[serde(default = "default_hold_frames")]
+    pub hold_frames: usize,
+    // Length of audio analysed; longer separates low notes better but
+    // reacts later
+    #[serde(default = "default_window_ms")]
+    pub window_ms: u32,
+}
+
+fn default_max_notes() -> usize { 4 }
+fn default_min_ratio() -> f32 { 0.2 }
+fn default_hold_frames() -> usize { 3 }
+fn default_window_ms() -> u32 { 200 }
+
+impl Default for PolyphonyConfig {
+    fn default() -> Self {
+        Self {
+            max_notes: default_max_notes(),
+            min_ratio: default_min_ratio(),
+            hold_frames: default_hold_frames(),
+            window_ms: default_window_ms(),
+        }
+    }
+}
+
+/// The notes of a key such as "E3&A3", lowest first; None unless it joins
+/// two or more note names with '&'.
+pub fn parse(key: &str) -> Option<Vec<i32>> {
+    if !key.contains('&') { return None; }
+    let mut notes = key.split('&').map(|n| crate::note_to_midi(n.trim())).collect::<Option<Vec<i32>>>()?;
+    notes.sort_unstable();
+    notes.dedup();
+    (notes.len() >= 2).then_some(notes)
+}
+
+// Harmonics 1..5 land this many semitones above the fundamental; their
+// weight in a note's strength and the share of the fundamental's amplitude
+// subtracted at each once the note is taken
+const HARMONICS: [(i32, f32, f32); 5] = [(0, 1.0, 1.0), (12, 0.5, 0.7), (19, 0.33, 0.5), (24, 0.25, 0.4), (28, 0.2, 0.3)];
+
+pub struct PolyDetector {
+    cfg: PolyphonyConfig,
+    buffer: Vec<f32>,
+    hann: Vec<f32>,
+    current: Option<String>,
+    count: usize,
+}
+
+impl PolyDetector {
+    pub fn new(cfg: &PolyphonyConfig) -> Self {
+        Self { cfg: cfg.clone(), buffer: Vec::new(), hann: Vec::new(), current: None, count: 0 }
+    }
+
+    /// Feed one hop; `loud` is false below the input threshold. Returns the
+    /// note-set entry of `map` on the frame it has been heard for hold_frames.
+    pub fn update<'m>(
+        &mut self,
+        hop: &[f32],
+        sample_rate: f32,
+        loud: bool,
+        map: &'m HashMap<String, Mapping>,
+    ) -> Option<(&'m String, &'m Mapping)> {
+        let len = (sample_rate * self.cfg.window_ms as f32 / 1000.0) as usize;
+        self.buffer.extend_from_slice(hop);
+        if self.buffer.len() > len { self.buffer.drain(..self.buffer.len() - len); }
+        let keys: Vec<(Vec<i32>, &String, &Mapping)> =
+            map.iter().filter_map(|(k, m)| parse(k).map(|notes| (notes, k, m))).collect();
+        let heard = if loud && self.buffer.len() == len && !keys.is_empty() {
+            if self.hann.len() != len {
+                self.hann = (0..len).map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / (len - 1) as f32).cos()).collect();
+            }
+            let lowest = keys.iter().map(|(n, ..)| n[0]).min().unwrap_or(0);
+            let highest = keys.iter().filter_map(|(n, ..)| n.last().copied()).max().unwrap_or(0);
+            let notes = self.notes(sample_rate, lowest, highest);
+            // Most notes first, then by key so the choice is stable
+            keys.iter()
+                .filter(|(n, ..)| n.iter().all(|m| notes.contains(m)))
+                .max_by(|a, b| a.0.len().cmp(&b.0.len()).then(b.1.cmp(a.1)))
+                .map(|(_, k, m)| (*k, *m))
+        } else {
+            None
+        };
+        if heard.map(|(k, _)| k) == self.current.as_ref() {
+            self.count += 1;
+        } else {
+            self.current = heard.map(|(k, _)| k.clone());
+            self.count = 1;
+        }
+        heard.filter(|_| self.count == self.cfg.hold_frames.max(1))
+    }
+
+    /// Whether a mapped set of notes (stable or not) is sounding right now.
+    pub fn hearing(&self) -> bool {
+        self.current.is_some()
+    }
+
+    // Notes heard between `lowest` and `highest`. An octave below is searched
+    // as well so that a lower note's harmonics aren't taken for mapped notes.
+    fn notes(&self, sample_rate: f32, lowest: i32, highest: i32) -> Vec<i32> {
+        let a4 = crate::a4_hz();
+        let base = lowest - 12;
+        let top = highest + HARMONICS[HARMONICS.len() - 1].0;
+        let nyquist = sample_rate / 2.0;
+        let mut residual: Vec<f32> = (base..=top)
+            .map(|m| {
+                let hz = a4 * 2f32.powf((m - 69) as f32 / 12.0);
+                if hz < nyquist { goertzel(&self.buffer, &self.hann, hz / sample_rate) } else { 0.0 }
+            })
+            .collect();
+        let at = |m: i32| (m - base) as usize;
+        let mut notes = Vec::new();
+        let mut strongest = None;
+        while notes.len() < self.cfg.max_notes {
+            let strength = |r: &[f32], m: i32| HARMONICS.iter().map(|&(up, w, _)| w * r[at(m + up)].sqrt()).sum::<f32>();
+            let Some((m, s)) = (base..=highest)
+                // A taken note leaks into the semitones either side
+                .filter(|m| notes.iter().all(|n: &i32| (n - m).abs() > 1))
+                .map(|m| (m, strength(&residual, m)))
+                .max_by(|a, b| a.1.total_cmp(&b.1))
+            else {
+                break;
+            };
+            let first = *strongest.get_or_insert(s);
+            if s <= f32::EPSILON || s < self.cfg.min_ratio * first { break; }
+            notes.push(m);
+            let amp = residual[at(m)].sqrt();
+            for &(up, _, share) in &HARMONICS {
+                let r = &mut residual[at(m + up)];
+                *r = (*r - (amp * share).powi(2)).max(0.0);
+            }
+        }
+        notes
+    }
+}
+
+// Power of the windowed signal at `freq` cycles per sample
+fn goertzel(x: &[f32], window: &[f32], freq: f32) -> f32 {
+    let coeff = 2.0 * (2.0 * PI * freq).cos();
+    let (mut s1, mut s2) = (0.0f32, 0.0f32);
+    for (v, w) in x.iter().zip(window) {
+        let s = v * w + coeff * s1 - s2;
+        s2 = s1;
+        s1 = s;
+    }
+    (s1 * s1 + s2 * s2 - coeff * s1 * s2).max(0.0)
+}
@@ -0,0 +1,220 @@
+// ---------------------------- Practice statistics ----------------------------
+//
+// Collects per-note intonation statistics (how far from center each note is
+// played) for a practice session, prints periodic reports and appends the
+// session summary to a CSV file so progress can be tracked across sessions.
+// The summary is kept up to date in the file while the session runs, so a
+// session stopped with Ctrl+C, or one without an end, isn't lost.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct PracticeConfig {
+    // Session length in minutes (0 = no limit)
+    #[serde(default = "default_session_minutes")]
+    pub session_minutes: u64,
+    // Seconds between live reports on the console (0 = off)
+    #[serde(default = "default_report_secs")]
+    pub report_secs: u64,
+    // CSV file that session summaries are appended to (one row per note)
+    #[serde(default = "default_export")]
+    pub export: Option<String>,
+}
+
+fn default_session_minutes() -> u64 { 10 }
+fn default_report_secs() -> u64 { 30 }
+fn default_export() -> Option<String> { Some("practice_log.csv".to_string()) }
+
+impl Default for PracticeConfig {
+    fn default() -> Self {
+        Self {
+            session_minutes: default_session_minutes(),
+            report_secs: default_report_secs(),
+            export: default_export(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoteStats {
+    pub frames: u64,
+    sum: f64,
+    sum_abs: f64,
+    sum_sq: f64,
+    pub in_tune: u64,
+}
+
+impl NoteStats {
+    fn add(&mut self, cents: f32, in_tune: bool) {
+        let c = cents as f64;
+        self.frames += 1;
+        self.sum += c;
+        self.sum_abs += c.abs();
+        self.sum_sq += c * c;
+        if in_tune { self.in_tune += 1; }
+    }
+
+    /// Average signed offset; positive = sharp, negative = flat.
+    pub fn mean(&self) -> f64 { self.sum / self.frames.max(1) as f64 }
+
+    /// Average distance from center regardless of direction.
+    pub fn mean_abs(&self) -> f64 { self.sum_abs / self.frames.max(1) as f64 }
+
+    /// Spread of the offset around its mean.
+    pub fn std_dev(&self) -> f64 {
+        let n = self.frames.max(1) as f64;
+        (self.sum_sq / n - self.mean().powi(2)).max(0.0).sqrt()
+    }
+
+    /// Fraction of frames that were within tolerance.
+    pub fn in_tune_ratio(&self) -> f64 { self.in_tune as f64 / self.frames.max(1) as f64 }
+}
+
+#[derive(Default)]
+pub struct PracticeSession {
+    notes: HashMap<String, NoteStats>,
+}
+
+impl PracticeSession {
+    pub fn new() -> Self { Self::default() }
+
+    /// Record one stable analysis frame for `note` played `cents` off center.
+    pub fn record(&mut self, note: &str, cents: f32, tolerance_cents: f32) {
+        self.notes
+            .entry(note.to_string())
+            .or_default()
+            .add(cents, cents.abs() <= tolerance_cents);
+    }
+
+    pub fn is_empty(&self) -> bool { self.notes.is_empty() }
+
+    /// Human-readable per-note table, sorted by note name. `previous` holds the
+    /// mean absolute cents per note from the last exported session, if any.
+    pub fn report(&self, previous: &HashMap<String, f64>) -> String {
+        let sorted: BTreeMap<_, _> = self.notes.iter().collect();
+        let mut out = String::from("Note  Frames   Mean   |Mean|  StdDev  InTune  Trend\n");
+        for (note, st) in sorted {
+            let trend = match previous.get(note) {
+                Some(prev) if st.mean_abs() < prev - 0.5 => format!("better ({prev:.1})"),
+                Some(prev) if st.mean_abs() > prev + 0.5 => format!("worse ({prev:.1})"),
+                Some(prev) => format!("same ({prev:.1})"),
+                None => "-".to_string(),
+            };
+            out.push_str(&format!(
+                "{:<4} {:>7} {:>+6.1} {:>7.1} {:>7.1} {:>6.0}%  {}\n",
+                crate::notation::spell(note),
+                st.frames,
+                st.mean(),
+                st.mean_abs(),
+                st.std_dev(),
+                st.in_tune_ratio() * 100.0,
+                trend
+            ));
+        }
+        out
+    }
+
+}
+
+/// A session's rows in the CSV file: one per note, after the rows of earlier
+/// sessions and stamped with the session's start.
+pub struct CsvExport {
+    path: PathBuf,
+    // Where the file ended when the session started
+    offset: u64,
+    timestamp: u64,
+}
+
+impl CsvExport {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            offset: std::fs::metadata(path).map(|m| m.len()).unwrap_or(0),
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+        }
+    }
+
+    pub fn path(&self) -> &Path { &self.path }
+
+    /// Write the session's rows as they are now, replacing the ones written
+    /// before; the header goes first in a new file.
+    pub fn save(&self, session: &PracticeSession) -> Result<()> {
+        let mut f = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(&self.path)
+            .with_context(|| format!("Opening {}", self.path.display()))?;
+        f.set_len(self.offset)?;
+        f.seek(SeekFrom::Start(self.offset))?;
+        let mut rows = String::new();
+        if self.offset == 0 {
+            rows.push_str("timestamp,note,frames,mean_cents,mean_abs_cents,std_dev_cents,in_tune_ratio\n");
+        }
+        let sorted: BTreeMap<_, _> = session.notes.iter().collect();
+        for (note, st) in sorted {
+            rows.push_str(&format!(
+                "{},{note},{},{:.2},{:.2},{:.2},{:.3}\n",
+                self.timestamp,
+                st.frames,
+                st.mean(),
+                st.mean_abs(),
+                st.std_dev(),
+                st.in_tune_ratio()
+            ));
+        }
+        f.write_all(rows.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Mean absolute cents per note from the most recent session in an exported CSV.
+pub fn load_previous(path: &Path) -> HashMap<String, f64> {
+    let Ok(text) = std::fs::read_to_string(path) else { return HashMap::new() };
+    let rows: Vec<Vec<&str>> = text
+        .lines()
+        .skip(1)
+        .map(|l| l.split(',').collect::<Vec<_>>())
+        .filter(|r| r.len() >= 5)
+        .collect();
+    let Some(last_ts) = rows.last().map(|r| r[0]) else { return HashMap::new() };
+    rows.iter()
+        .filter(|r| r[0] == last_ts)
+        .filter_map(|r| Some((r[1].to_string(), r[4].parse().ok()?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saving_again_replaces_the_session_rows() {
+        let path = std::env::temp_dir().join(format!("practice_log_{}.csv", std::process::id()));
+        std::fs::write(&path, "timestamp,note,frames,mean_cents,mean_abs_cents,std_dev_cents,in_tune_ratio\n1,A4,10,2.00,3.00,1.00,0.900\n").unwrap();
+        let export = CsvExport::new(&path);
+        let mut session = PracticeSession::new();
+        session.record("A4", 4.0, 10.0);
+        export.save(&session).unwrap();
+        session.record("A4", -2.0, 10.0);
+        session.record("E5", 20.0, 10.0);
+        export.save(&session).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        // The newest session is what the next one compares with
+        let previous = load_previous(&path);
+        std::fs::remove_file(&path).ok();
+
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 4, "{text}");
+        assert!(lines[0].starts_with("timestamp,"));
+        assert_eq!(lines[1], "1,A4,10,2.00,3.00,1.00,0.900");
+        assert!(lines[2].ends_with(",A4,2,1.00,3.00,3.00,1.000"), "{}", lines[2]);
+        assert!(lines[3].ends_with(",E5,1,20.00,20.00,0.00,0.000"), "{}", lines[3]);
+        assert_eq!(previous, HashMap::from([("A4".to_string(), 3.0), ("E5".to_string(), 20.0)]));
+    }
+}
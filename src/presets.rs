@@ -0,0 +1,208 @@
+// ---------------------------- Presets ----------------------------
+//
+// Named bundles of detection settings selected with `preset = "..."`, and of
+// an instrument's range and tuning selected with `instrument = "..."`. Both
+// only fill in keys the config file leaves out, so any explicit setting
+// still wins. Window and hop sizes are in samples at the default
+// analysis_rate (22050 Hz).
+
+use anyhow::{anyhow, Context, Result};
+
+const WHISTLE: &str = r#"
+# Whistles sit roughly between 500 and 3000 Hz and are close to pure sines.
+min_hz = 500.0
+max_hz = 3000.0
+# Short window/hop: no low notes to resolve, so favour latency
+window_size = 512
+hop_size = 128
+# A clean whistle correlates very strongly with itself; breath noise does not
+corr_threshold = 0.6
+# Drop frames whose zero-crossing rate disagrees with the pitch (breath hiss)
+pure_tone_check = true
+# Ignore quiet breath between whistled notes
+min_rms = 0.01
+tolerance_cents = 40.0
+"#;
+
+const VOICE: &str = r#"
+# Bass to soprano fundamentals; formants above this are ignored
+min_hz = 80.0
+max_hz = 1100.0
+# Larger window copes with breathy, formant-rich voices
+window_size = 2048
+hop_size = 256
+corr_threshold = 0.5
+# Vibrato swings the pitch around the center; accept the full semitone
+tolerance_cents = 50.0
+note_hold_frames = 5
+min_rms = 0.005
+
+# Only trigger on sustained, steady pitches, not on conversation
+[speech_gate]
+enabled = true
+sustain_ms = 350
+max_spread_cents = 120.0
+"#;
+
+const GUITAR_STANDARD: &str = r#"
+# Low E (82 Hz) to the 24th fret of the high E string (1319 Hz)
+min_hz = 75.0
+max_hz = 1400.0
+# Three periods of low E; plucked attacks favour MPM and the octave check
+window_size = 1024
+hop_size = 256
+detector = "mpm"
+octave_check = true
+
+[strings]
+tuning = ["E4", "B3", "G3", "D3", "A2", "E2"]
+"#;
+
+const DROP_D: &str = r#"
+# Standard guitar with the low string down to D (73 Hz)
+min_hz = 68.0
+max_hz = 1400.0
+window_size = 1024
+hop_size = 256
+detector = "mpm"
+octave_check = true
+
+[strings]
+tuning = ["E4", "B3", "G3", "D3", "A2", "D2"]
+"#;
+
+const BASS: &str = r#"
+# Four-string bass: low E (41 Hz) to the 24th fret of G (392 Hz), with
+# room for harmonics played higher up
+min_hz = 38.0
+max_hz = 800.0
+# Three periods of low E need a long window; keep the hop short
+window_size = 2048
+hop_size = 256
+detector = "mpm"
+octave_check = true
+
+[strings]
+tuning = ["G2", "D2", "A1", "E1"]
+frets = 24
+"#;
+
+const VIOLIN: &str = r#"
+# Open G (196 Hz) to the top of the E string (about 3 kHz)
+min_hz = 185.0
+max_hz = 3200.0
+# No low notes: a short window answers quickly
+window_size = 512
+hop_size = 128
+# Bowed notes are steady, but vibrato swings them
+tolerance_cents = 45.0
+
+[strings]
+tuning = ["E5", "A4", "D4", "G3"]
+frets = 24
+"#;
+
+const UKULELE: &str = r#"
+# Re-entrant GCEA: the lowest note is the open C (262 Hz), up to the 15th
+# fret of the A string
+min_hz = 245.0
+max_hz = 1200.0
+window_size = 512
+hop_size = 128
+detector = "mpm"
+
+[strings]
+tuning = ["A4", "E4", "C4", "G4"]
+frets = 15
+"#;
+
+/// Names of all built-in presets.
+pub const NAMES: &[&str] = &["whistle", "voice"];
+
+/// Names of all built-in instruments.
+pub const INSTRUMENTS: &[&str] = &["guitar-standard", "drop-d", "bass", "violin", "ukulele"];
+
+fn preset_source(name: &str) -> Option<&'static str> {
+    match name {
+        "whistle" => Some(WHISTLE),
+        "voice" => Some(VOICE),
+        _ => None,
+    }
+}
+
+fn instrument_source(name: &str) -> Option<&'static str> {
+    match name {
+        "guitar-standard" => Some(GUITAR_STANDARD),
+        "drop-d" => Some(DROP_D),
+        "bass" => Some(BASS),
+        "violin" => Some(VIOLIN),
+        "ukulele" => Some(UKULELE),
+        _ => None,
+    }
+}
+
+/// Fill keys missing from `table` with the values of its `instrument` and
+/// then its `preset`, if any.
+pub fn apply(table: &mut toml::Table) -> Result<()> {
+    if let Some(name) = table.get("instrument").and_then(|v| v.as_str()).map(str::to_string) {
+        let src = instrument_source(&name)
+            .ok_or_else(|| anyhow!("Unknown instrument {name:?} (available: {})", INSTRUMENTS.join(", ")))?;
+        let instrument: toml::Table = toml::from_str(src).with_context(|| format!("Built-in instrument {name}"))?;
+        merge_missing(table, instrument);
+    }
+    let Some(name) = table.get("preset").and_then(|v| v.as_str()).map(str::to_string) else {
+        return Ok(());
+    };
+    let src = preset_source(&name)
+        .ok_or_else(|| anyhow!("Unknown preset {name:?} (available: {})", NAMES.join(", ")))?;
+    let preset: toml::Table = toml::from_str(src).with_context(|| format!("Built-in preset {name}"))?;
+    merge_missing(table, preset);
+    Ok(())
+}
+
+// Recursively copy keys from `defaults` that `table` doesn't set
+fn merge_missing(table: &mut toml::Table, defaults: toml::Table) {
+    for (key, value) in defaults {
+        match (table.get_mut(&key), value) {
+            (Some(toml::Value::Table(existing)), toml::Value::Table(sub)) => merge_missing(existing, sub),
+            (Some(_), _) => {}
+            (None, value) => { table.insert(key, value); }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Config, Detector};
+
+    const EXAMPLE: &str = include_str!("../config.toml");
+
+    #[test]
+    fn presets_change_the_example_config() {
+        let whistle = Config::from_toml(&format!("preset = \"whistle\"\n{EXAMPLE}")).unwrap();
+        assert_eq!((whistle.min_hz, whistle.max_hz, whistle.window_size), (500.0, 3000.0, 512));
+        assert!(whistle.pure_tone_check);
+        let voice = Config::from_toml(&format!("preset = \"voice\"\n{EXAMPLE}")).unwrap();
+        assert!(voice.speech_gate.enabled);
+        assert_eq!(voice.note_hold_frames, 5);
+    }
+
+    #[test]
+    fn explicit_keys_override_the_preset() {
+        let cfg = Config::from_toml("preset = \"whistle\"\nmin_hz = 300.0\n").unwrap();
+        assert_eq!((cfg.min_hz, cfg.max_hz), (300.0, 3000.0));
+    }
+
+    #[test]
+    fn instruments_set_the_range_and_the_open_strings() {
+        let bass = Config::from_toml(&format!("instrument = \"bass\"\n{EXAMPLE}")).unwrap();
+        assert_eq!((bass.min_hz, bass.window_size, bass.detector), (38.0, 2048, Detector::Mpm));
+        assert!(bass.octave_check);
+        assert_eq!(bass.strings.tuning, ["G2", "D2", "A1", "E1"]);
+        // Before a preset, and under explicit keys
+        let cfg = Config::from_toml("instrument = \"drop-d\"\npreset = \"voice\"\n[strings]\nfrets = 12\n").unwrap();
+        assert_eq!((cfg.min_hz, cfg.note_hold_frames, cfg.strings.frets), (68.0, 5, 12));
+        assert_eq!(cfg.strings.tuning.last().map(String::as_str), Some("D2"));
+        assert!(Config::from_toml("instrument = \"banjo\"\n").is_err());
+    }
+}
@@ -0,0 +1,394 @@
+// ---------------------------- Profiles ----------------------------
+//
+// Named sets of mappings that replace top-level note_map entries while they
+// are active, optionally with their own tolerance and thresholds. A profile
+// activates itself by rules on the local time, the weekday, and whether an
+// iCalendar file has an event right now ("busy"), or while the application
+// it is named after (or lists in `apps`) has the focus. It can also be
+// switched to by a "profile" mapping or the `profile` command.
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use crate::clock::{self, LocalTime};
+use crate::focus;
+use crate::Mapping;
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct Profile {
+    // Mappings added to (or overriding) the top-level note_map while active
+    #[serde(default)]
+    pub note_map: HashMap<String, Mapping>,
+    // The profile is active while any of these rules matches
+    #[serde(default)]
+    pub when: Vec<Rule>,
+    // ...or while one of these applications (or the one it is named after) has the focus
+    #[serde(default)]
+    pub apps: Vec<String>,
+    // When several profiles are active, the highest priority wins
+    #[serde(default)]
+    pub priority: i32,
+    // Replacements for the global settings while active
+    #[serde(default)]
+    pub tolerance_cents: Option<f32>,
+    #[serde(default)]
+    pub corr_threshold: Option<f32>,
+    #[serde(default)]
+    pub note_hold_frames: Option<usize>,
+    #[serde(default)]
+    pub retrigger_ms: Option<u64>,
+}
+
+/// The global settings a profile can replace.
+#[derive(Debug, Clone, Copy)]
+pub struct Settings {
+    pub tolerance_cents: f32,
+    pub corr_threshold: f32,
+    pub note_hold_frames: usize,
+    pub retrigger_ms: u64,
+}
+
+impl Settings {
+    /// These settings with the replacements of `profile`, if one is active.
+    pub fn with(self, profile: Option<&Profile>) -> Self {
+        let Some(p) = profile else { return self };
+        Self {
+            tolerance_cents: p.tolerance_cents.unwrap_or(self.tolerance_cents),
+            corr_threshold: p.corr_threshold.unwrap_or(self.corr_threshold),
+            note_hold_frames: p.note_hold_frames.unwrap_or(self.note_hold_frames),
+            retrigger_ms: p.retrigger_ms.unwrap_or(self.retrigger_ms),
+        }
+    }
+}
+
+/// A profile change asked for at runtime: "daw", "next" or "default" (the
+/// top-level note_map alone).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Switch {
+    To(Option<String>),
+    Next,
+}
+
+impl Switch {
+    pub fn parse(name: &str) -> Self {
+        match name.trim() {
+            n if n.eq_ignore_ascii_case("next") => Self::Next,
+            n if n.eq_ignore_ascii_case("default") || n.is_empty() => Self::To(None),
+            n => Self::To(Some(n.to_string())),
+        }
+    }
+
+    /// The profile to switch to from `active`, or an error for a profile
+    /// that isn't in `profiles`.
+    pub fn resolve(&self, profiles: &BTreeMap<String, Profile>, active: Option<&str>) -> Result<Option<String>> {
+        match self {
+            Self::To(None) => Ok(None),
+            Self::To(Some(name)) if profiles.contains_key(name) => Ok(Some(name.clone())),
+            Self::To(Some(name)) => Err(anyhow!("Unknown profile {name:?}")),
+            // Through the profiles in name order, then back to the default
+            Self::Next => Ok(match active {
+                None => profiles.keys().next().cloned(),
+                Some(a) => profiles.range::<str, _>((std::ops::Bound::Excluded(a), std::ops::Bound::Unbounded)).next().map(|(k, _)| k.clone()),
+            }),
+        }
+    }
+}
+
+// The latest switch request and how many there have been, so that every
+// trigger loop (one per performer) sees each request once
+static REQUEST: Mutex<(u64, Option<Switch>)> = Mutex::new((0, None));
+
+/// Ask the running trigger loops to change profile.
+pub fn request(switch: Switch) {
+    let mut r = REQUEST.lock().unwrap_or_else(|e| e.into_inner());
+    *r = (r.0 + 1, Some(switch));
+}
+
+/// A request made since `seen`, which is brought up to date.
+pub fn requested(seen: &mut u64) -> Option<Switch> {
+    let r = REQUEST.lock().unwrap_or_else(|e| e.into_inner());
+    if r.0 == *seen { return None; }
+    *seen = r.0;
+    r.1.clone()
+}
+
+/// All conditions that are set must hold.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct Rule {
+    // "mon".."sun", "weekdays" or "weekend"; empty = every day
+    #[serde(default)]
+    pub days: Vec<String>,
+    // Local time window "HH:MM"; may wrap past midnight ("22:00" to "02:00")
+    #[serde(default)]
+    pub from: Option<String>,
+    #[serde(default)]
+    pub to: Option<String>,
+    // true = only during calendar events, false = only when the calendar is free
+    #[serde(default)]
+    pub busy: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ScheduleConfig {
+    // iCalendar (.ics) file whose events count as busy time for `busy` rules
+    #[serde(default)]
+    pub calendar: Option<String>,
+    // Seconds between schedule checks
+    #[serde(default = "default_check_secs")]
+    pub check_secs: u64,
+    // Switch to the profile of the focused application
+    #[serde(default = "default_follow_focus")]
+    pub follow_focus: bool,
+    // Milliseconds between checks of the focused application
+    #[serde(default = "default_focus_ms")]
+    pub focus_ms: u64,
+}
+
+fn default_check_secs() -> u64 { 30 }
+fn default_follow_focus() -> bool { true }
+fn default_focus_ms() -> u64 { 500 }
+
+impl Default for ScheduleConfig {
+    fn default() -> Self {
+        Self {
+            calendar: None,
+            check_secs: default_check_secs(),
+            follow_focus: default_follow_focus(),
+            focus_ms: default_focus_ms(),
+        }
+    }
+}
+
+impl ScheduleConfig {
+    /// Time between schedule checks: focus changes need a quick look.
+    pub fn interval(&self) -> Duration {
+        let check = Duration::from_secs(self.check_secs.max(1));
+        if self.follow_focus { check.min(Duration::from_millis(self.focus_ms.max(50))) } else { check }
+    }
+}
+
+/// Two zones around a pivot note, like a keyboard split: each zone can use
+/// its own profile's mappings and a catch-all mapping for its other notes.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct SplitConfig {
+    // Pivot note; it and everything above is the upper zone (unset = no split)
+    #[serde(default)]
+    pub point: Option<String>,
+    // Profiles whose mappings apply in each zone (unset = the active note_map)
+    #[serde(default)]
+    pub lower_profile: Option<String>,
+    #[serde(default)]
+    pub upper_profile: Option<String>,
+    // Fires for notes of the zone that have no mapping of their own
+    #[serde(default)]
+    pub lower: Option<Mapping>,
+    #[serde(default)]
+    pub upper: Option<Mapping>,
+}
+
+struct ParsedRule {
+    // Bit n set = weekday n (0 = Monday) allowed
+    days: u8,
+    // Minutes since midnight, [from, to)
+    window: Option<(u32, u32)>,
+    busy: Option<bool>,
+}
+
+impl ParsedRule {
+    fn parse(rule: &Rule) -> Result<Self> {
+        let mut days = if rule.days.is_empty() { 0x7f } else { 0 };
+        for d in &rule.days {
+            days |= match d.to_ascii_lowercase().as_str() {
+                "weekdays" => 0x1f,
+                "weekend" => 0x60,
+                other => {
+                    let i = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"]
+                        .iter()
+                        .position(|n| other.starts_with(n))
+                        .ok_or_else(|| anyhow!("Unknown day {d:?}"))?;
+                    1 << i
+                }
+            };
+        }
+        let window = match (&rule.from, &rule.to) {
+            (None, None) => None,
+            (from, to) => Some((
+                from.as_deref().map_or(Ok(0), parse_hhmm)?,
+                to.as_deref().map_or(Ok(24 * 60), parse_hhmm)?,
+            )),
+        };
+        Ok(Self { days, window, busy: rule.busy })
+    }
+
+    fn matches(&self, now: &LocalTime, busy: impl FnOnce() -> bool) -> bool {
+        if self.days & (1 << now.weekday) == 0 { return false; }
+        if let Some((from, to)) = self.window {
+            let t = now.minute_of_day;
+            let inside = if from <= to { t >= from && t < to } else { t >= from || t < to };
+            if !inside { return false; }
+        }
+        self.busy.is_none_or(|want| busy() == want)
+    }
+}
+
+fn parse_hhmm(s: &str) -> Result<u32> {
+    let (h, m) = s.split_once(':').ok_or_else(|| anyhow!("Expected HH:MM, got {s:?}"))?;
+    let (h, m): (u32, u32) = (
+        h.trim().parse().map_err(|_| anyhow!("Bad hour in {s:?}"))?,
+        m.trim().parse().map_err(|_| anyhow!("Bad minute in {s:?}"))?,
+    );
+    if h > 24 || m > 59 || h * 60 + m > 24 * 60 {
+        return Err(anyhow!("Time out of range: {s:?}"));
+    }
+    Ok(h * 60 + m)
+}
+
+/// Picks the active profile from the rules and the focused application.
+pub struct Schedule {
+    // (name, priority, rules, applications), highest priority first
+    profiles: Vec<(String, i32, Vec<ParsedRule>, Vec<String>)>,
+    calendar: Option<Calendar>,
+    // How often the focused application is looked up; None = it isn't
+    focus: Option<Duration>,
+}
+
+impl Schedule {
+    pub fn new(profiles: &BTreeMap<String, Profile>, cfg: &ScheduleConfig) -> Result<Self> {
+        let mut parsed = Vec::new();
+        for (name, p) in profiles {
+            let rules = p
+                .when
+                .iter()
+                .map(ParsedRule::parse)
+                .collect::<Result<Vec<_>>>()
+                .map_err(|e| anyhow!("Profile {name}: {e}"))?;
+            if rules.iter().any(|r| r.busy.is_some()) && cfg.calendar.is_none() {
+                tracing::warn!("profile {name} has busy rules but [schedule] sets no calendar");
+            }
+            // A profile is also the one for the application it is named after
+            let apps = p.apps.iter().cloned().chain(std::iter::once(name.clone())).collect();
+            parsed.push((name.clone(), p.priority, rules, apps));
+        }
+        // Stable sort keeps name order among equal priorities
+        parsed.sort_by_key(|(_, prio, _, _)| std::cmp::Reverse(*prio));
+        Ok(Self {
+            profiles: parsed,
+            calendar: cfg.calendar.as_ref().map(|p| Calendar::new(p.into())),
+            focus: cfg.follow_focus.then(|| Duration::from_millis(cfg.focus_ms.max(50))),
+        })
+    }
+
+    /// Name of the profile whose rules match now, if any.
+    pub fn active(&mut self) -> Option<&str> {
+        let now = clock::now();
+        let app = self.focus.and_then(focus::current);
+        let calendar = &mut self.calendar;
+        let mut busy = None;
+        self.profiles
+            .iter()
+            .find(|(_, _, rules, apps)| {
+                app.as_deref().is_some_and(|app| apps.iter().any(|a| focus::same_app(a, app)))
+                    || rules.iter().any(|r| {
+                        r.matches(&now, || *busy.get_or_insert_with(|| calendar.as_mut().is_some_and(|c| c.busy(&now))))
+                    })
+            })
+            .map(|(name, _, _, _)| name.as_str())
+    }
+}
+
+// Event span in minutes since 1970 and whether those are UTC (else local) minutes
+struct Event {
+    start: i64,
+    end: i64,
+    utc: bool,
+}
+
+/// Single (non-recurring) events from an .ics file, reloaded when it changes.
+struct Calendar {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    events: Vec<Event>,
+}
+
+impl Calendar {
+    fn new(path: PathBuf) -> Self {
+        Self { path, modified: None, events: Vec::new() }
+    }
+
+    fn busy(&mut self, now: &LocalTime) -> bool {
+        let modified = std::fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        if modified != self.modified {
+            self.modified = modified;
+            self.events = match std::fs::read_to_string(&self.path) {
+                Ok(text) => parse_ics(&text),
+                Err(e) => {
+                    tracing::warn!("cannot read calendar {}: {e}", self.path.display());
+                    Vec::new()
+                }
+            };
+        }
+        self.events.iter().any(|e| {
+            let t = if e.utc { now.utc_minutes } else { now.local_minutes };
+            t >= e.start && t < e.end
+        })
+    }
+}
+
+fn parse_ics(text: &str) -> Vec<Event> {
+    // Unfold continuation lines (they start with a space or tab)
+    let mut lines: Vec<String> = Vec::new();
+    for raw in text.lines() {
+        match (raw.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ => lines.push(raw.to_string()),
+        }
+    }
+    let mut events = Vec::new();
+    let mut current: Option<RawEvent> = None;
+    for line in &lines {
+        let Some((name, value)) = line.split_once(':') else { continue };
+        let key = name.split(';').next().unwrap_or(name).to_ascii_uppercase();
+        match (key.as_str(), current.as_mut()) {
+            ("BEGIN", _) if value.eq_ignore_ascii_case("VEVENT") => {
+                current = Some(RawEvent { start: None, end: None, free: false })
+            }
+            ("END", Some(_)) if value.eq_ignore_ascii_case("VEVENT") => {
+                let Some(ev) = current.take() else { continue };
+                let Some((start, utc, all_day)) = ev.start.filter(|_| !ev.free) else { continue };
+                // Without an end, a date event lasts the day and a timed one is an instant
+                let end = ev.end.map_or(if all_day { start + 1440 } else { start }, |e| e.0);
+                events.push(Event { start, end, utc });
+            }
+            ("DTSTART", Some(ev)) => ev.start = parse_ics_time(value),
+            ("DTEND", Some(ev)) => ev.end = parse_ics_time(value),
+            // Cancelled and "show as free" events don't make the calendar busy
+            ("STATUS", Some(ev)) if value.eq_ignore_ascii_case("CANCELLED") => ev.free = true,
+            ("TRANSP", Some(ev)) if value.eq_ignore_ascii_case("TRANSPARENT") => ev.free = true,
+            _ => {}
+        }
+    }
+    events
+}
+
+// VEVENT being read: (minutes, utc, all day) times as in parse_ics_time
+struct RawEvent {
+    start: Option<(i64, bool, bool)>,
+    end: Option<(i64, bool, bool)>,
+    free: bool,
+}
+
+// "20261017" or "20261017T093000" or "20261017T093000Z" -> (minutes, utc, all day)
+fn parse_ics_time(value: &str) -> Option<(i64, bool, bool)> {
+    let v = value.trim();
+    let num = |a: usize, b: usize| v.get(a..b)?.parse::<u32>().ok();
+    let (y, mo, d) = (num(0, 4)?, num(4, 6)?, num(6, 8)?);
+    if v.len() == 8 {
+        return Some((clock::civil_minutes(y as i64, mo, d, 0, 0), false, true));
+    }
+    let (h, mi) = (num(9, 11)?, num(11, 13)?);
+    Some((clock::civil_minutes(y as i64, mo, d, h, mi), v.ends_with('Z'), false))
+}
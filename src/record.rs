@@ -0,0 +1,268 @@
+// ---------------------------- Session recording ----------------------------
+//
+// `[record]` (or --record <PATH>) appends the session to a JSON-lines file,
+// so a misfire can be looked into afterwards: what the detector heard on
+// every frame, every trigger, and every status event, one object per line.
+// The first line of a session holds the command line and the config file as
+// it was read; a reload adds the new version. With `audio`, the input the
+// analysis read is kept as a WAV file too, so `replay` can play the session
+// back through another config.
+//
+//   {"type":"session","version":"0.1.0","args":[...],"config_file":"config.toml","config":"...","t":0.0,"ms":...}
+//   {"type":"pitch","hz":440.2,"note":"A4","cents":0.8,"confidence":0.97,"t":12.345,"ms":...}
+//   {"type":"silence","t":12.365,"ms":...}
+//   {"type":"trigger","note":"A4","action":"keys:Space","t":12.401,"ms":...}
+//   {"type":"status","text":"Profile: daw","t":13.0,"ms":...}
+//   {"type":"config","config_file":"config.toml","config":"...","t":60.2,"ms":...}
+//   {"type":"audio","file":"session.wav","rate":48000,"t":0.2,"ms":...}
+//
+// `t` is seconds since the session started, `ms` the time since the Unix
+// epoch. Unlike WebSocket events, silence is written every frame. Lines go
+// to a thread of their own, which writes them out whenever it catches up,
+// so a slow disk never holds up detection.
+
+use anyhow::{Context, Result};
+use crossbeam_channel::{bounded, Receiver, Sender};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use hound::{SampleFormat, WavSpec, WavWriter};
+
+use crate::pipeline::NoteEvent;
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct RecordConfig {
+    // Also enabled by --record, which names the file too
+    #[serde(default)]
+    pub enabled: bool,
+    // Appended to, so several sessions can share one file
+    #[serde(default = "default_file")]
+    pub file: String,
+    // Keep the input as a WAV file too (replaced each session)
+    #[serde(default)]
+    pub audio: Option<String>,
+}
+
+fn default_file() -> String { "session.jsonl".to_string() }
+
+impl Default for RecordConfig {
+    fn default() -> Self {
+        Self { enabled: false, file: default_file(), audio: None }
+    }
+}
+
+// Lines waiting to be written; more than this and new ones are dropped
+const QUEUE: usize = 8192;
+
+enum Entry {
+    Line(Value),
+    // The WAV file to start at this sample rate, and its samples
+    AudioFile(String, u32),
+    Audio(Vec<f32>),
+    // Answered once everything before it is on disk
+    Flush(Sender<()>),
+}
+
+struct Recorder {
+    tx: Sender<Entry>,
+    start: Instant,
+    audio: Option<String>,
+}
+
+static RECORDER: OnceLock<Recorder> = OnceLock::new();
+// A full queue has been reported
+static DROPPING: AtomicBool = AtomicBool::new(false);
+// The WAV file has been started
+static AUDIO: AtomicBool = AtomicBool::new(false);
+
+/// Start recording to the configured file, with `config_file` as the
+/// session's config snapshot.
+pub fn start(cfg: &RecordConfig, config_file: &Path) -> Result<()> {
+    if !cfg.enabled || RECORDER.get().is_some() { return Ok(()); }
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&cfg.file)
+        .with_context(|| format!("cannot record the session to {}", cfg.file))?;
+    let (tx, rx) = bounded(QUEUE);
+    std::thread::spawn(move || write(BufWriter::new(file), rx));
+    RECORDER.set(Recorder { tx, start: Instant::now(), audio: cfg.audio.clone() }).ok();
+    let mut header = snapshot("session", config_file);
+    header["version"] = env!("CARGO_PKG_VERSION").into();
+    header["args"] = std::env::args().collect::<Vec<_>>().into();
+    send(header);
+    println!("Recording the session to {}", cfg.file);
+    Ok(())
+}
+
+/// Whether a session is being recorded.
+pub fn active() -> bool { RECORDER.get().is_some() }
+
+/// Record what the status line showed; `performer` labels it.
+pub fn publish(performer: Option<&str>, event: &NoteEvent) {
+    if !active() { return; }
+    let mut value = match event {
+        NoteEvent::Pitch { hz, note, cents, confidence } => {
+            json!({
+                "type": "pitch",
+                "hz": round(*hz as f64, 2),
+                "note": note,
+                "cents": round(*cents as f64, 1),
+                "confidence": round(*confidence as f64, 3),
+            })
+        }
+        NoteEvent::Silence => json!({ "type": "silence" }),
+        NoteEvent::Trigger { note, action } => json!({ "type": "trigger", "note": note, "action": crate::action_name(action) }),
+        NoteEvent::Status(text) => json!({ "type": "status", "text": text }),
+    };
+    if let Some(p) = performer { value["performer"] = p.into(); }
+    send(value);
+}
+
+/// Record the config that was just reloaded.
+pub fn config(config_file: &Path) {
+    if active() { send(snapshot("config", config_file)); }
+}
+
+/// The input arrives at `sample_rate`; start its WAV file, if one is kept.
+/// Only the first input is kept, and only a single pipeline's.
+pub fn audio_format(sample_rate: u32) {
+    let Some(r) = RECORDER.get() else { return };
+    let Some(file) = r.audio.clone() else { return };
+    if AUDIO.swap(true, Ordering::Relaxed) { return; }
+    send(json!({ "type": "audio", "file": file, "rate": sample_rate }));
+    r.tx.send(Entry::AudioFile(file, sample_rate)).ok();
+}
+
+/// Samples the analysis has just read.
+pub fn audio(samples: &[f32]) {
+    if !AUDIO.load(Ordering::Relaxed) || samples.is_empty() { return; }
+    let Some(r) = RECORDER.get() else { return };
+    if r.tx.try_send(Entry::Audio(samples.to_vec())).is_err() && !DROPPING.swap(true, Ordering::Relaxed) {
+        tracing::warn!("the session recording can't keep up; some audio is missing from it");
+    }
+}
+
+/// Wait until everything recorded so far is in the file.
+pub fn finish() {
+    let Some(r) = RECORDER.get() else { return };
+    let (done, written) = bounded(1);
+    if r.tx.send(Entry::Flush(done)).is_ok() { written.recv().ok(); }
+}
+
+fn snapshot(kind: &str, config_file: &Path) -> Value {
+    // Without a file the defaults are in use
+    let text = std::fs::read_to_string(config_file).ok();
+    json!({ "type": kind, "config_file": config_file.display().to_string(), "config": text })
+}
+
+fn send(mut value: Value) {
+    let Some(r) = RECORDER.get() else { return };
+    value["t"] = round(r.start.elapsed().as_secs_f64(), 3).into();
+    value["ms"] = (SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64).into();
+    if r.tx.try_send(Entry::Line(value)).is_err() && !DROPPING.swap(true, Ordering::Relaxed) {
+        tracing::warn!("the session recording can't keep up; some frames are missing from it");
+    }
+}
+
+// `x` to `places` decimals; an f32 widened to f64 would otherwise print
+// digits it never had
+fn round(x: f64, places: i32) -> f64 {
+    let scale = 10f64.powi(places);
+    (x * scale).round() / scale
+}
+
+fn write(mut out: BufWriter<File>, entries: Receiver<Entry>) {
+    let mut failed = false;
+    let mut wav: Option<WavWriter<BufWriter<File>>> = None;
+    for entry in entries.iter() {
+        let mut flushes = Vec::new();
+        // Everything waiting goes out in one write
+        for entry in std::iter::once(entry).chain(entries.try_iter()) {
+            match entry {
+                Entry::Line(value) if !failed => {
+                    if let Err(e) = writeln!(out, "{value}") {
+                        tracing::error!("session recording stopped: {e}");
+                        failed = true;
+                    }
+                }
+                Entry::Line(_) => {}
+                Entry::AudioFile(file, sample_rate) => {
+                    let spec = WavSpec { channels: 1, sample_rate, bits_per_sample: 32, sample_format: SampleFormat::Float };
+                    match WavWriter::create(&file, spec) {
+                        Ok(w) => wav = Some(w),
+                        Err(e) => tracing::error!("cannot record the session's audio to {file}: {e}"),
+                    }
+                }
+                Entry::Audio(samples) => {
+                    let Some(w) = wav.as_mut() else { continue };
+                    if let Err(e) = samples.into_iter().try_for_each(|s| w.write_sample(s)) {
+                        tracing::error!("session audio stopped: {e}");
+                        wav = None;
+                    }
+                }
+                Entry::Flush(done) => flushes.push(done),
+            }
+        }
+        if let Err(e) = out.flush() {
+            if !failed { tracing::error!("session recording stopped: {e}"); }
+            failed = true;
+        }
+        // Also brings the WAV header up to date, so the file can be read
+        // even if the program never gets to finish it
+        if let Some(Err(e)) = wav.as_mut().map(|w| w.flush()) {
+            tracing::error!("session audio stopped: {e}");
+            wav = None;
+        }
+        for done in flushes { done.send(()).ok(); }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Action;
+
+    #[test]
+    fn a_session_is_written_line_by_line() {
+        let dir = std::env::temp_dir().join(format!("record_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config_file = dir.join("config.toml");
+        std::fs::write(&config_file, "tolerance_cents = 20.0\n").unwrap();
+        let file = dir.join("session.jsonl");
+        let wav = dir.join("session.wav");
+        let cfg = RecordConfig { enabled: true, file: file.display().to_string(), audio: Some(wav.display().to_string()) };
+        start(&cfg, &config_file).unwrap();
+
+        audio_format(8000);
+        audio(&[0.25; 100]);
+        publish(None, &NoteEvent::Pitch { hz: 440.0, note: "A4".to_string(), cents: 0.5, confidence: 0.9 });
+        publish(None, &NoteEvent::Trigger { note: "A4".to_string(), action: Action::Text { text: "hi".to_string() } });
+        publish(Some("cello"), &NoteEvent::Silence);
+        finish();
+
+        let lines: Vec<Value> = std::fs::read_to_string(&file)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        let types: Vec<&str> = lines.iter().map(|l| l["type"].as_str().unwrap()).collect();
+        assert_eq!(types, ["session", "audio", "pitch", "trigger", "silence"]);
+        assert_eq!(lines[0]["config"], "tolerance_cents = 20.0\n");
+        assert_eq!(lines[1]["rate"], 8000);
+        assert_eq!(lines[2]["confidence"].as_f64(), Some(0.9));
+        assert_eq!(lines[3]["action"], "text:hi");
+        assert_eq!(lines[4]["performer"], "cello");
+        // Readable without being finished
+        let samples: Vec<f32> = hound::WavReader::open(&wav).unwrap().samples().map(|s| s.unwrap()).collect();
+        assert_eq!(samples, [0.25; 100]);
+        assert!(lines.windows(2).all(|w| w[0]["t"].as_f64() <= w[1]["t"].as_f64()));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
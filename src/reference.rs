@@ -0,0 +1,90 @@
+// ---------------------------- Reference pitch ----------------------------
+//
+// Ensembles rarely sit exactly at A = 440. Sustaining the ensemble's tuning
+// note (the oboe's A, a piano's A) gives the actual reference, and every note
+// boundary moves with it. The measured frequency of the reference note is
+// converted to the equivalent A4.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::Path;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ReferenceConfig {
+    // The note the ensemble tunes to
+    #[serde(default = "default_note")]
+    pub note: String,
+    // Measure the reference at every start and use it for that session
+    #[serde(default)]
+    pub on_start: bool,
+    // Seconds of steady pitch to average
+    #[serde(default = "default_listen_secs")]
+    pub listen_secs: f32,
+}
+
+fn default_note() -> String { "A4".to_string() }
+fn default_listen_secs() -> f32 { 2.0 }
+
+impl Default for ReferenceConfig {
+    fn default() -> Self {
+        Self { note: default_note(), on_start: false, listen_secs: default_listen_secs() }
+    }
+}
+
+// References further than this from equal temperament at 440 Hz are taken to
+// be a different note (415 Hz baroque pitch is exactly one semitone down)
+const MAX_OFFSET_CENTS: f32 = 110.0;
+// A steady reference stays within this many cents of its median
+const MAX_WOBBLE_CENTS: f32 = 10.0;
+
+/// Collects pitch frames of the sustained reference note.
+pub struct ReferenceMeter {
+    // MIDI number of the reference note
+    note: i32,
+    // Offsets in cents from the note at A4 = 440
+    cents: Vec<f32>,
+}
+
+impl ReferenceMeter {
+    pub fn new(note: i32) -> Self {
+        Self { note, cents: Vec::new() }
+    }
+
+    /// Add one detected pitch; returns false (and ignores it) if it is too
+    /// far from the reference note to be it.
+    pub fn record(&mut self, freq: f32) -> bool {
+        let cents = 1200.0 * (freq / crate::midi_to_freq_at(self.note, 440.0)).log2();
+        let close = cents.abs() <= MAX_OFFSET_CENTS;
+        if close { self.cents.push(cents); }
+        close
+    }
+
+    pub fn frames(&self) -> usize {
+        self.cents.len()
+    }
+
+    /// Restart after the note was interrupted.
+    pub fn clear(&mut self) {
+        self.cents.clear();
+    }
+
+    /// Effective A4 in Hz, and whether the note was steady enough to trust.
+    pub fn a4_hz(&self) -> Option<(f32, bool)> {
+        if self.cents.is_empty() { return None; }
+        let mut sorted = self.cents.clone();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let median = sorted[sorted.len() / 2];
+        // Ignore the outer tenth on either side (attack, release)
+        let trim = sorted.len() / 10;
+        let steady = sorted[trim..sorted.len() - trim].iter().all(|c| (c - median).abs() <= MAX_WOBBLE_CENTS);
+        Some((440.0 * 2f32.powf(median / 1200.0), steady))
+    }
+}
+
+/// Store `a4_hz` in the config file, keeping everything else as it was.
+pub fn save(path: &Path, a4_hz: f32) -> Result<()> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("Reading {}", path.display()))?;
+    let mut doc: toml_edit::DocumentMut = text.parse().with_context(|| format!("Parsing {}", path.display()))?;
+    doc["a4_hz"] = toml_edit::value((a4_hz as f64 * 10.0).round() / 10.0);
+    std::fs::write(path, doc.to_string()).with_context(|| format!("Writing {}", path.display()))
+}
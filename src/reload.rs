@@ -0,0 +1,140 @@
+// ---------------------------- Config hot reload ----------------------------
+//
+// While trigger mode runs, the config file's directory is watched for
+// changes (inotify, FSEvents or ReadDirectoryChangesW through notify); the
+// directory rather than the file, because editors often save by writing a
+// new file and renaming it over the old one. A change is read once the file
+// has stopped changing, so a save that writes in several steps is read whole.
+// It is parsed on the watcher thread, and only a config that loads is handed
+// to the trigger loop, which swaps it in between frames. A broken edit is
+// reported and the running config stays. The tray's "Reload config" reads
+// the file at once whether it changed or not, even with hot_reload off.
+
+use anyhow::Result;
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+// Quiet time after the last change before the file is read
+const SETTLE: Duration = Duration::from_millis(250);
+
+enum Wake {
+    // Something happened to the file
+    Changed,
+    // A reload was asked for
+    Requested,
+}
+
+static WAKE: OnceLock<Sender<Wake>> = OnceLock::new();
+
+/// Whether `request` will be heard.
+pub fn watching() -> bool {
+    WAKE.get().is_some()
+}
+
+/// Read the config again now.
+pub fn request() {
+    if let Some(wake) = WAKE.get() { wake.send(Wake::Requested).ok(); }
+}
+
+// A version of the file. The contents rather than the modification time,
+// which some file systems only keep to the clock tick, so two quick saves of
+// the same length could look alike.
+fn version(path: &Path) -> Option<Vec<u8>> {
+    std::fs::read(path).ok()
+}
+
+/// Watch `path` and send what `load` makes of every new version of it
+/// (with `follow_edits`) and of every `request`.
+pub fn watch<T, F>(path: PathBuf, follow_edits: bool, load: F) -> Receiver<T>
+where
+    T: Send + 'static,
+    F: Fn() -> Result<T> + Send + 'static,
+{
+    let (tx, rx) = unbounded();
+    let (wake, woken) = unbounded();
+    let watcher = follow_edits.then(|| watch_file(&path, wake.clone())).flatten();
+    WAKE.set(wake).ok();
+    // Taken now, so an edit made before the thread starts still counts
+    let mut seen = version(&path);
+    std::thread::spawn(move || {
+        // Dropping it would end the notifications
+        let _watcher = watcher;
+        for reason in woken.iter() {
+            if let Wake::Changed = reason {
+                // Wait for the writer to finish
+                while let Ok(Wake::Changed) = woken.recv_timeout(SETTLE) {}
+                let now = version(&path);
+                // Deleted (for now), or touched without a change
+                if now.is_none() || now == seen { continue; }
+                seen = now;
+            } else {
+                seen = version(&path);
+            }
+            match load() {
+                Ok(value) => {
+                    if tx.send(value).is_err() { return; }
+                }
+                Err(e) => tracing::warn!("{} not reloaded: {e:#}", path.display()),
+            }
+        }
+    });
+    rx
+}
+
+// Wake on changes to `path`; None (with a warning) if the system can't watch it
+fn watch_file(path: &Path, wake: Sender<Wake>) -> Option<notify::RecommendedWatcher> {
+    let name = path.file_name()?.to_owned();
+    let dir = match path.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let handler = move |event: notify::Result<notify::Event>| {
+        let Ok(event) = event else { return };
+        if matches!(event.kind, EventKind::Access(_)) { return; }
+        if event.paths.iter().any(|p| p.file_name() == Some(name.as_os_str())) {
+            wake.send(Wake::Changed).ok();
+        }
+    };
+    let watched = notify::recommended_watcher(handler).and_then(|mut w| w.watch(&dir, RecursiveMode::NonRecursive).map(|_| w));
+    match watched {
+        Ok(w) => Some(w),
+        Err(e) => {
+            tracing::warn!("hot_reload is off: can't watch {}: {e}", dir.display());
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+
+    #[test]
+    fn a_broken_edit_keeps_the_running_config() {
+        let dir = std::env::temp_dir().join(format!("reload_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        std::fs::write(&path, "tolerance_cents = 20.0\n").unwrap();
+        let file = path.clone();
+        let changes = watch(path.clone(), true, move || Config::load(&file));
+        let next = || changes.recv_timeout(Duration::from_secs(3)).ok().map(|c| c.tolerance_cents);
+
+        std::fs::write(&path, "tolerance_cents = 25.0\n").unwrap();
+        assert_eq!(next(), Some(25.0));
+        // Not TOML: reported, and nothing replaces the running config
+        std::fs::write(&path, "tolerance_cents = [\n").unwrap();
+        assert_eq!(next(), None);
+        // Saved the way many editors do: a new file renamed over the old one
+        std::fs::write(dir.join("config.toml.tmp"), "tolerance_cents = 30.0\n").unwrap();
+        std::fs::rename(dir.join("config.toml.tmp"), &path).unwrap();
+        assert_eq!(next(), Some(30.0));
+        // Asked for without a change
+        request();
+        assert_eq!(next(), Some(30.0));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
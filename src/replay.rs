@@ -0,0 +1,299 @@
+// ---------------------------- Session replay ----------------------------
+//
+// `replay <SESSION>` plays a recorded session's audio back through trigger
+// mode with the current config, and compares what fires now with what fired
+// when it was recorded: a check that a new threshold or detector fixes the
+// misfire it was meant to, without breaking the notes that worked. The audio
+// is the session's own WAV file ([record] audio), or any WAV given with
+// --audio, such as a take recorded in a DAW during the run that misfired.
+// It plays in real time, as --input does, so retrigger_ms, hold times and
+// sequences behave as they did live. Actions are reported, not run.
+//
+// Triggers are compared in order, as a diff, so the two runs needn't line up
+// to the millisecond. A difference makes the command fail, so a script can
+// use a session as a regression test for a config.
+
+use anyhow::{anyhow, Context, Result};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::pipeline::NoteEvent;
+use crate::{action_name, apply_settings, pcm, ring, trigger_loop, AudioInput, Config};
+
+/// A mapping that fired, `t` seconds into the audio.
+#[derive(Debug, Clone, PartialEq)]
+struct Fired {
+    t: f64,
+    note: String,
+    action: String,
+}
+
+impl Fired {
+    fn same_as(&self, other: &Fired) -> bool {
+        self.note == other.note && self.action == other.action
+    }
+
+    fn describe(&self) -> String {
+        format!("{:8.2} s  {} => {}", self.t, crate::notation::spell(&self.note), self.action)
+    }
+}
+
+// What a session file says about its last session
+#[derive(Debug, Default)]
+struct Session {
+    triggers: Vec<Fired>,
+    // Its WAV file, as recorded
+    audio: Option<String>,
+}
+
+// The last session in a session file. Trigger times are made relative to the
+// audio's start. Performers' triggers are left out: replay runs one pipeline.
+fn read_session(text: &str) -> Result<Session> {
+    let mut session: Option<Session> = None;
+    let mut audio_t = 0.0;
+    let mut unreadable = 0;
+    for line in text.lines().filter(|l| !l.trim().is_empty()) {
+        // A line cut short when the program was stopped
+        let Ok(line) = serde_json::from_str::<Value>(line) else {
+            unreadable += 1;
+            continue;
+        };
+        let t = line["t"].as_f64().unwrap_or_default();
+        match line["type"].as_str() {
+            Some("session") => {
+                session = Some(Session::default());
+                audio_t = 0.0;
+            }
+            Some("audio") => {
+                if let Some(s) = session.as_mut() { s.audio = line["file"].as_str().map(str::to_string); }
+                audio_t = t;
+            }
+            Some("trigger") if line["performer"].is_null() => {
+                if let Some(s) = session.as_mut() {
+                    s.triggers.push(Fired {
+                        t: t - audio_t,
+                        note: line["note"].as_str().unwrap_or_default().to_string(),
+                        action: line["action"].as_str().unwrap_or_default().to_string(),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    if unreadable > 0 { tracing::warn!("skipped {unreadable} unreadable line(s) of the session"); }
+    session.ok_or_else(|| anyhow!("no session in it; record one with --record"))
+}
+
+// Mono samples (all channels mixed) and the sample rate of a WAV file
+fn read_wav(path: &Path) -> Result<(Vec<f32>, u32)> {
+    let mut reader = hound::WavReader::open(path).with_context(|| format!("cannot read {}", path.display()))?;
+    let spec = reader.spec();
+    let samples: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+        hound::SampleFormat::Int => {
+            let full_scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader.samples::<i32>().map(|s| s.map(|s| s as f32 / full_scale)).collect::<Result<_, _>>()?
+        }
+    };
+    let channels = spec.channels.max(1) as usize;
+    let mono = samples.chunks_exact(channels).map(|frame| frame.iter().sum::<f32>() / channels as f32).collect();
+    Ok((mono, spec.sample_rate))
+}
+
+// Where a session's WAV file is: as recorded, or next to the session file
+fn locate(recorded: &str, session: &Path) -> PathBuf {
+    let path = PathBuf::from(recorded);
+    match session.parent() {
+        Some(dir) if path.is_relative() && !path.exists() => dir.join(path),
+        _ => path,
+    }
+}
+
+/// Replay `session_file` (or the WAV file `audio` instead of its audio)
+/// through `cfg` and report how the triggers differ.
+pub fn run(cfg: &Config, session_file: &Path, audio: Option<&Path>) -> Result<()> {
+    let text = std::fs::read_to_string(session_file).with_context(|| format!("cannot read {}", session_file.display()))?;
+    let session = read_session(&text).with_context(|| format!("in {}", session_file.display()))?;
+    let audio = match (audio, &session.audio) {
+        (Some(path), _) => path.to_path_buf(),
+        (None, Some(recorded)) => locate(recorded, session_file),
+        (None, None) => {
+            return Err(anyhow!(
+                "{} was recorded without its audio ([record] audio); give the take with --audio <WAV>",
+                session_file.display()
+            ))
+        }
+    };
+    let (samples, sample_rate) = read_wav(&audio)?;
+    println!(
+        "Replaying {} ({:.1} s) against {} trigger(s) recorded in {}",
+        audio.display(),
+        samples.len() as f64 / sample_rate as f64,
+        session.triggers.len(),
+        session_file.display()
+    );
+
+    let mut cfg = cfg.clone();
+    cfg.dry_run = true;
+    apply_settings(&cfg);
+    let (mut tx, rx) = ring::channel(sample_rate as usize);
+    let mut input = AudioInput::new(&cfg, rx, sample_rate, 1);
+
+    let started = Instant::now();
+    let fired = Arc::new(Mutex::new(Vec::new()));
+    let listener = {
+        let fired = fired.clone();
+        move |event: &NoteEvent| {
+            if let NoteEvent::Trigger { note, action } = event {
+                let t = started.elapsed().as_secs_f64();
+                let trigger = Fired { t, note: note.clone(), action: action_name(action) };
+                fired.lock().unwrap_or_else(|e| e.into_inner()).push(trigger);
+            }
+        }
+    };
+    std::thread::Builder::new()
+        .name("replay".into())
+        .spawn(move || {
+            // Real time, as live audio arrives; dropping the writer ends the input
+            let chunk = (sample_rate as usize / 100).max(1);
+            for (i, samples) in samples.chunks(chunk).enumerate() {
+                let due = Duration::from_secs_f64((i * chunk) as f64 / sample_rate as f64);
+                if let Some(ahead) = due.checked_sub(started.elapsed() + pcm::LEAD) { std::thread::sleep(ahead); }
+                if tx.write_all(samples).is_err() { return; }
+            }
+        })
+        .context("Failed to start the replay thread")?;
+    match trigger_loop(&cfg, &mut input, None, Some(Box::new(listener))) {
+        Err(e) if e.downcast_ref::<ring::StreamEnded>().is_none() => return Err(e),
+        _ => {}
+    }
+
+    let now = std::mem::take(&mut *fired.lock().unwrap_or_else(|e| e.into_inner()));
+    let changes = diff(&session.triggers, &now);
+    println!("\n{}", summary(&session.triggers, &now, &changes));
+    for change in &changes {
+        match change {
+            Change::Same(..) => {}
+            Change::Gone(f) => println!("  - {}  (no longer fires)", f.describe()),
+            Change::New(f) => println!("  + {}  (new)", f.describe()),
+        }
+    }
+    if changes.iter().all(|c| matches!(c, Change::Same(..))) {
+        Ok(())
+    } else {
+        Err(anyhow!("the triggers differ from the recorded session"))
+    }
+}
+
+#[derive(Debug)]
+enum Change<'a> {
+    // Fired then and now, at these times
+    Same(&'a Fired, &'a Fired),
+    Gone(&'a Fired),
+    New(&'a Fired),
+}
+
+// The triggers in order, matched up as a line diff matches lines: the
+// longest run of them in common, and what was removed or added around it
+fn diff<'a>(then: &'a [Fired], now: &'a [Fired]) -> Vec<Change<'a>> {
+    let (n, m) = (then.len(), now.len());
+    // common[i][j]: length of the longest common run of then[i..] and now[j..]
+    let mut common = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            common[i][j] = if then[i].same_as(&now[j]) {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut changes = Vec::new();
+    while i < n || j < m {
+        if i < n && j < m && then[i].same_as(&now[j]) {
+            changes.push(Change::Same(&then[i], &now[j]));
+            (i, j) = (i + 1, j + 1);
+        } else if j == m || (i < n && common[i + 1][j] >= common[i][j + 1]) {
+            changes.push(Change::Gone(&then[i]));
+            i += 1;
+        } else {
+            changes.push(Change::New(&now[j]));
+            j += 1;
+        }
+    }
+    changes
+}
+
+fn summary(then: &[Fired], now: &[Fired], changes: &[Change]) -> String {
+    let count = |want: fn(&Change) -> bool| changes.iter().filter(|c| want(c)).count();
+    let same = count(|c| matches!(c, Change::Same(..)));
+    // How far the matching triggers moved
+    let shift = changes
+        .iter()
+        .filter_map(|c| match c {
+            Change::Same(a, b) => Some((b.t - a.t).abs()),
+            _ => None,
+        })
+        .fold(0.0, f64::max);
+    if same == then.len() && same == now.len() {
+        return format!("The same {same} trigger(s) fired, within {:.0} ms of the recording", shift * 1000.0);
+    }
+    format!(
+        "Recorded {} trigger(s), replayed {}: {same} the same (within {:.0} ms), {} no longer fire, {} new",
+        then.len(),
+        now.len(),
+        shift * 1000.0,
+        count(|c| matches!(c, Change::Gone(_))),
+        count(|c| matches!(c, Change::New(_)))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fired(t: f64, note: &str) -> Fired {
+        Fired { t, note: note.to_string(), action: "keys:Space".to_string() }
+    }
+
+    #[test]
+    fn the_last_session_is_read_from_the_audio_start() {
+        let text = r#"
+{"type":"session","t":0.0}
+{"type":"trigger","note":"E4","action":"keys:E","t":3.0}
+{"type":"session","t":0.0}
+{"type":"audio","file":"session.wav","rate":48000,"t":0.5}
+{"type":"pitch","hz":440.0,"note":"A4","cents":0.0,"confidence":0.9,"t":1.9}
+{"type":"trigger","note":"A4","action":"keys:Space","t":2.0}
+{"type":"trigger","note":"A4","action":"keys:Space","performer":"cello","t":2.1}
+{"type":"trig"#;
+        let session = read_session(text).unwrap();
+        assert_eq!(session.audio.as_deref(), Some("session.wav"));
+        assert_eq!(session.triggers, [fired(1.5, "A4")]);
+        assert!(read_session("").is_err());
+    }
+
+    #[test]
+    fn triggers_are_diffed_in_order() {
+        let then = [fired(1.0, "A4"), fired(2.0, "B4"), fired(3.0, "C5"), fired(4.0, "A4")];
+        let now = [fired(1.02, "A4"), fired(3.01, "C5"), fired(3.5, "D5"), fired(4.03, "A4")];
+        let changes = diff(&then, &now);
+        let marks: Vec<String> = changes
+            .iter()
+            .map(|c| match c {
+                Change::Same(a, _) => format!("={}", a.note),
+                Change::Gone(a) => format!("-{}", a.note),
+                Change::New(b) => format!("+{}", b.note),
+            })
+            .collect();
+        assert_eq!(marks, ["=A4", "-B4", "=C5", "+D5", "=A4"]);
+        assert_eq!(
+            summary(&then, &now, &changes),
+            "Recorded 4 trigger(s), replayed 4: 3 the same (within 30 ms), 1 no longer fire, 1 new"
+        );
+        assert_eq!(summary(&then, &then, &diff(&then, &then)), "The same 4 trigger(s) fired, within 0 ms of the recording");
+    }
+}
@@ -0,0 +1,99 @@
+// ---------------------------- Resampling ----------------------------
+//
+// Converts the device's sample rate to the fixed analysis rate, so window and
+// hop sizes, lag ranges and CPU cost are the same on every interface. A
+// streaming windowed-sinc filter: each output sample is a dot product of the
+// input around its position with one of PHASES precomputed kernels, picked by
+// the position's fractional part. Its low-pass sits at 90% of the lower
+// Nyquist frequency, far above any fundamental worth detecting. Nothing is
+// padded in front of the stream, so the first output waits for a filter's
+// worth of input (under a millisecond) and there is no startup transient.
+
+use std::f64::consts::PI;
+
+use crate::pitch;
+
+// Kernels per input sample; the worst timing error is half of 1/PHASES
+const PHASES: usize = 256;
+// Zero crossings of the sinc on each side of the centre
+const ZEROS: f64 = 16.0;
+// Passband edge as a fraction of the lower Nyquist frequency
+const CUTOFF: f64 = 0.9;
+
+pub struct Resampler {
+    // Input samples per output sample
+    step: f64,
+    // Kernel half-width, in input samples
+    half: usize,
+    // PHASES + 1 kernels of 2 * half taps, one after the other
+    kernels: Vec<f32>,
+    // Input not yet consumed, and where the next output falls within it
+    history: Vec<f32>,
+    pos: f64,
+}
+
+impl Resampler {
+    /// A resampler from `from` Hz to `to` Hz.
+    pub fn new(from: u32, to: u32) -> Self {
+        let step = from as f64 / to as f64;
+        // Normalised to the input rate; downsampling lowers it
+        let cutoff = CUTOFF * (1.0 / step).min(1.0);
+        let half = (ZEROS / cutoff).ceil() as usize;
+        let taps = 2 * half;
+        let mut kernels = Vec::with_capacity((PHASES + 1) * taps);
+        for phase in 0..=PHASES {
+            let frac = phase as f64 / PHASES as f64;
+            let start = kernels.len();
+            for k in 0..taps {
+                // Distance from the output position to this tap
+                let d = frac + half as f64 - 1.0 - k as f64;
+                let x = cutoff * d;
+                let sinc = if x == 0.0 { 1.0 } else { (PI * x).sin() / (PI * x) };
+                // Blackman window over (-half, half)
+                let w = (d / half as f64 + 1.0) / 2.0;
+                let window = if (0.0..=1.0).contains(&w) {
+                    0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos()
+                } else { 0.0 };
+                kernels.push((cutoff * sinc * window) as f32);
+            }
+            // Unity gain at DC for every phase
+            let gain: f32 = kernels[start..].iter().sum();
+            kernels[start..].iter_mut().for_each(|t| *t /= gain);
+        }
+        let mut resampler = Self { step, half, kernels, history: Vec::new(), pos: 0.0 };
+        resampler.reset();
+        resampler
+    }
+
+    /// How many more input samples it takes to produce `n` output samples.
+    pub fn needed(&self, n: usize) -> usize {
+        if n == 0 { return 0; }
+        let last = (self.pos + (n - 1) as f64 * self.step).floor() as usize;
+        (last + self.half + 1).saturating_sub(self.history.len())
+    }
+
+    /// Take in `input` and append every output sample it completes to `out`.
+    pub fn process(&mut self, input: &[f32], out: &mut Vec<f32>) {
+        self.history.extend_from_slice(input);
+        let taps = 2 * self.half;
+        while self.pos.floor() as usize + self.half < self.history.len() {
+            let base = self.pos.floor() as usize;
+            let phase = ((self.pos - base as f64) * PHASES as f64).round() as usize;
+            let kernel = &self.kernels[phase * taps..(phase + 1) * taps];
+            let start = base + 1 - self.half;
+            out.push(pitch::dot(&self.history[start..start + taps], kernel) as f32);
+            self.pos += self.step;
+        }
+        // Keep only what the next output still reads
+        let consumed = (self.pos.floor() as usize + 1).saturating_sub(self.half).min(self.history.len());
+        self.history.drain(..consumed);
+        self.pos -= consumed as f64;
+    }
+
+    /// Forget the input so far, as after a gap in the stream.
+    pub fn reset(&mut self) {
+        self.history.clear();
+        // The first output has a full kernel of real input behind it
+        self.pos = (self.half - 1) as f64;
+    }
+}
@@ -0,0 +1,205 @@
+// ---------------------------- Rhythm patterns ----------------------------
+//
+// `[[rhythms]]` entries fire on a rhythm tapped out on one note, or on
+// anything at all, e.g. short-short-long ("..-") within 1.5 s: a trigger
+// vocabulary for drums and one-string instruments that doesn't need several
+// pitches. Only the timing of attacks counts. A note's length is the time
+// from its attack to the next one: shorter than long_ms is short, else long.
+// The last note of the pattern is long once long_ms passes without another
+// attack, so a pattern ending in "-" fires then, while the note may still
+// ring. A pattern begins after a pause of at least long_ms, so "..-" doesn't
+// also count as ".-".
+
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+use crate::Mapping;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Rhythm {
+    // '.' for a short note and '-' for a long one, in playing order
+    pub pattern: String,
+    // Only attacks of this note count (e.g. "A4"); any attack, pitched or
+    // not, if unset
+    #[serde(default)]
+    pub note: Option<String>,
+    // The whole pattern must be played within this many ms of its first note
+    #[serde(default = "default_within_ms")]
+    pub within_ms: u64,
+    // Notes at least this long (attack to attack) are long
+    #[serde(default = "default_long_ms")]
+    pub long_ms: u64,
+    // The action and per-mapping options, as in note_map
+    #[serde(flatten)]
+    pub mapping: Mapping,
+}
+
+fn default_within_ms() -> u64 { 1500 }
+fn default_long_ms() -> u64 { 400 }
+
+impl Rhythm {
+    /// Display name, e.g. "A4 ..-".
+    pub fn label(&self) -> String {
+        match &self.note {
+            Some(n) => format!("{} {}", crate::notation::spell(n), self.pattern),
+            None => self.pattern.clone(),
+        }
+    }
+}
+
+/// Whether `pattern` is made of '.' and '-' only, and isn't empty.
+pub fn valid(pattern: &str) -> bool {
+    !pattern.is_empty() && pattern.chars().all(|c| c == '.' || c == '-')
+}
+
+// A pitch heard this soon after an attack is that attack's note
+const ATTRIBUTE: Duration = Duration::from_millis(200);
+
+// One entry, ready to match
+struct Compiled {
+    long: Vec<bool>,
+    note: Option<i32>,
+    within: Duration,
+    long_ms: Duration,
+}
+
+// An attack and the note it played, once heard
+#[derive(Clone, Copy)]
+struct Attack {
+    at: Instant,
+    note: Option<i32>,
+}
+
+pub struct RhythmTracker {
+    rhythms: Vec<Compiled>,
+    attacks: Vec<Attack>,
+    // The newest attack has been judged long already
+    timed_out: bool,
+    // Attacks older than this can't be part of a pattern
+    keep: Duration,
+}
+
+impl RhythmTracker {
+    /// None without any usable entry.
+    pub fn new(rhythms: &[Rhythm]) -> Option<Self> {
+        let rhythms: Vec<Compiled> = rhythms
+            .iter()
+            .map(|r| Compiled {
+                long: r.pattern.chars().filter(|c| *c == '.' || *c == '-').map(|c| c == '-').collect(),
+                note: r.note.as_deref().and_then(crate::note_to_midi),
+                within: Duration::from_millis(r.within_ms),
+                long_ms: Duration::from_millis(r.long_ms.max(1)),
+            })
+            .collect();
+        if rhythms.iter().all(|r| r.long.is_empty()) { return None; }
+        let keep = rhythms.iter().map(|r| r.within + r.long_ms * 2).max().unwrap_or_default();
+        Some(Self { rhythms, attacks: Vec::new(), timed_out: false, keep })
+    }
+
+    /// Called every frame: `attack` if one was just heard, `heard` the MIDI
+    /// note of the pitch, if any. Returns the indices of entries completed.
+    pub fn update(&mut self, attack: bool, heard: Option<i32>, now: Instant) -> Vec<usize> {
+        let mut done = Vec::new();
+        if attack {
+            // The attack ends the length of the note before it
+            for (i, r) in self.rhythms.iter().enumerate() {
+                if self.completes(r, self.attacks.len(), Some(now)) { done.push(i); }
+            }
+            self.attacks.push(Attack { at: now, note: None });
+            self.attacks.retain(|a| now.duration_since(a.at) <= self.keep);
+            self.timed_out = false;
+        }
+        if let (Some(midi), Some(last)) = (heard, self.attacks.last_mut()) {
+            if last.note.is_none() && now.duration_since(last.at) <= ATTRIBUTE { last.note = Some(midi); }
+        }
+        // Nothing followed the newest attack: its note is long
+        if !self.timed_out {
+            if let Some(last) = self.attacks.last().copied() {
+                let mut waiting = false;
+                for (i, r) in self.rhythms.iter().enumerate() {
+                    if now.duration_since(last.at) < r.long_ms {
+                        waiting = true;
+                    } else if self.completes(r, self.attacks.len(), None) && !done.contains(&i) {
+                        done.push(i);
+                    }
+                }
+                self.timed_out = !waiting;
+            }
+        }
+        if !done.is_empty() {
+            // A pattern is used once
+            self.attacks.clear();
+            self.timed_out = true;
+        }
+        done
+    }
+
+    // Whether the `end` attacks before index `end` finish `r`: the last
+    // note's length runs to `next` (an attack), or is long if there is none
+    fn completes(&self, r: &Compiled, end: usize, next: Option<Instant>) -> bool {
+        let n = r.long.len();
+        if n == 0 || end < n { return false; }
+        let notes = &self.attacks[end - n..end];
+        let first = notes[0].at;
+        // After a pause, or at the start
+        if end > n && first.duration_since(self.attacks[end - n - 1].at) < r.long_ms { return false; }
+        let finished = next.unwrap_or_else(|| notes[n - 1].at + r.long_ms);
+        if finished.duration_since(first) > r.within { return false; }
+        if r.note.is_some() && notes.iter().any(|a| a.note != r.note) { return false; }
+        notes.iter().enumerate().all(|(k, a)| {
+            let length = match notes.get(k + 1) {
+                Some(b) => b.at.duration_since(a.at),
+                None => next.map_or(r.long_ms, |t| t.duration_since(a.at)),
+            };
+            (length >= r.long_ms) == r.long[k]
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Action;
+
+    fn rhythm(pattern: &str, note: Option<&str>) -> Rhythm {
+        Rhythm {
+            pattern: pattern.to_string(),
+            note: note.map(str::to_string),
+            within_ms: 1500,
+            long_ms: 400,
+            mapping: Mapping::from(Action::TapTempo),
+        }
+    }
+
+    // Attacks at these times (ms), each heard as `note`; the entries that
+    // fire, and when, frame by frame every 10 ms until `until`
+    fn play(tracker: &mut RhythmTracker, attacks: &[u64], note: i32, until: u64) -> Vec<(usize, u64)> {
+        let t0 = Instant::now();
+        let mut fired = Vec::new();
+        for ms in (0..=until).step_by(10) {
+            let attack = attacks.contains(&ms);
+            for i in tracker.update(attack, Some(note), t0 + Duration::from_millis(ms)) { fired.push((i, ms)); }
+        }
+        fired
+    }
+
+    #[test]
+    fn short_short_long_fires_once_the_last_note_is_long() {
+        let mut tracker = RhythmTracker::new(&[rhythm("..-", None), rhythm(".-", None)]).unwrap();
+        // Only the whole pattern: ".-" would need a pause before its first note
+        assert_eq!(play(&mut tracker, &[0, 200, 400], 69, 2000), [(0, 800)]);
+        // Too slow for within_ms
+        let slow = Rhythm { within_ms: 1000, ..rhythm("..-", None) };
+        let mut tracker = RhythmTracker::new(&[slow]).unwrap();
+        assert!(play(&mut tracker, &[0, 390, 780], 69, 3000).is_empty());
+    }
+
+    #[test]
+    fn a_pattern_ending_short_fires_at_the_next_attack() {
+        let mut tracker = RhythmTracker::new(&[rhythm("-.", Some("A4"))]).unwrap();
+        assert_eq!(play(&mut tracker, &[0, 500, 700], 69, 1500), [(0, 700)]);
+        // Another note doesn't count
+        let mut tracker = RhythmTracker::new(&[rhythm("-.", Some("A4"))]).unwrap();
+        assert!(play(&mut tracker, &[0, 500, 700], 71, 1500).is_empty());
+    }
+}
@@ -0,0 +1,155 @@
+// ---------------------------- Audio transport ----------------------------
+//
+// Samples travel from the audio callback to the analysis thread through a
+// single-producer, single-consumer ring buffer (rtrb). The callback writes a
+// whole buffer at a time with no locks, allocations or system calls, and
+// the analysis thread takes a hop at a time as slices. It waits for audio
+// by polling briefly, which costs the callback nothing. When the analysis
+// falls a whole ring behind, the callback drops what doesn't fit and counts
+// it, and the reader is told. The writer also notes when the latest samples
+// arrived (a clock read), so the reader can tell how long its audio waited.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The writer is gone: the stream (or every SampleSink) has ended.
+#[derive(Debug, thiserror::Error)]
+#[error("audio stream ended")]
+pub struct StreamEnded;
+
+// How long the reader sleeps while waiting for samples; well under a hop
+const POLL: Duration = Duration::from_millis(1);
+
+/// A ring of `capacity` samples.
+pub fn channel(capacity: usize) -> (Writer, Reader) {
+    let (producer, consumer) = rtrb::RingBuffer::new(capacity);
+    let shared = Arc::new(Shared {
+        dropped: AtomicU64::new(0),
+        written: AtomicU64::new(0),
+        arrived: AtomicU64::new(0),
+        start: Instant::now(),
+    });
+    (Writer { ring: producer, shared: shared.clone() }, Reader { ring: consumer, shared, read: 0 })
+}
+
+// What both ends keep track of
+struct Shared {
+    // Samples there was no room for, until the reader takes the count
+    dropped: AtomicU64,
+    // Samples written in all, and when the latest of them arrived (ns after start)
+    written: AtomicU64,
+    arrived: AtomicU64,
+    start: Instant,
+}
+
+impl Shared {
+    fn wrote(&self, n: usize) {
+        self.written.fetch_add(n as u64, Ordering::Relaxed);
+        self.arrived.store(self.start.elapsed().as_nanos() as u64, Ordering::Release);
+    }
+}
+
+/// The audio callback's end.
+pub struct Writer {
+    ring: rtrb::Producer<f32>,
+    shared: Arc<Shared>,
+}
+
+impl Writer {
+    /// Write as many of `samples` as there is room for, without waiting,
+    /// and count the rest as dropped; how many were written.
+    pub fn write_from(&mut self, samples: impl ExactSizeIterator<Item = f32>) -> usize {
+        let wanted = samples.len();
+        let n = wanted.min(self.ring.slots());
+        let written = match self.ring.write_chunk_uninit(n) {
+            Ok(chunk) => chunk.fill_from_iter(samples),
+            Err(_) => 0,
+        };
+        if written < wanted { self.shared.dropped.fetch_add((wanted - written) as u64, Ordering::Relaxed); }
+        self.shared.wrote(written);
+        written
+    }
+
+    /// Count `n` samples that never reached the writer (lost on the way
+    /// from another machine) as dropped.
+    pub fn skipped(&mut self, n: u64) {
+        self.shared.dropped.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Write all of `samples`, waiting for room as needed.
+    pub fn write_all(&mut self, mut samples: &[f32]) -> Result<(), StreamEnded> {
+        while !samples.is_empty() {
+            if self.ring.is_abandoned() { return Err(StreamEnded); }
+            let n = samples.len().min(self.ring.slots());
+            if let Ok(chunk) = self.ring.write_chunk_uninit(n) {
+                chunk.fill_from_iter(samples[..n].iter().copied());
+                self.shared.wrote(n);
+            }
+            samples = &samples[n..];
+            if n == 0 { std::thread::sleep(POLL); }
+        }
+        Ok(())
+    }
+}
+
+/// The analysis thread's end.
+pub struct Reader {
+    ring: rtrb::Consumer<f32>,
+    shared: Arc<Shared>,
+    // Samples taken out in all
+    read: u64,
+}
+
+impl Reader {
+    /// Wait for the next `n` samples and append them to `out`.
+    pub fn read(&mut self, n: usize, out: &mut Vec<f32>) -> Result<(), StreamEnded> {
+        loop {
+            if let Ok(chunk) = self.ring.read_chunk(n) {
+                let (first, second) = chunk.as_slices();
+                out.extend_from_slice(first);
+                out.extend_from_slice(second);
+                chunk.commit_all();
+                self.read += n as u64;
+                return Ok(());
+            }
+            if self.ring.is_abandoned() { return Err(StreamEnded); }
+            std::thread::sleep(POLL);
+        }
+    }
+
+    /// Samples the writer has dropped since the last call.
+    pub fn take_dropped(&mut self) -> u64 {
+        self.shared.dropped.swap(0, Ordering::Relaxed)
+    }
+
+    /// About when the newest sample read so far arrived, for audio at
+    /// `sample_rate`: the latest write, less the audio written after that
+    /// sample. None before anything was written.
+    pub fn arrived(&self, sample_rate: u32) -> Option<Instant> {
+        let arrived = self.shared.arrived.load(Ordering::Acquire);
+        let later = self.shared.written.load(Ordering::Relaxed).saturating_sub(self.read);
+        if arrived == 0 { return None; }
+        let at = self.shared.start + Duration::from_nanos(arrived);
+        Some(at.checked_sub(Duration::from_secs_f64(later as f64 / sample_rate as f64)).unwrap_or(at))
+    }
+
+    /// Drop whatever has been written but not read, and forget what the
+    /// writer dropped before now.
+    pub fn clear(&mut self) {
+        let n = self.ring.slots();
+        if let Ok(chunk) = self.ring.read_chunk(n) {
+            chunk.commit_all();
+            self.read += n as u64;
+        }
+        self.take_dropped();
+    }
+}
+
+// A reader whose stream has already ended; what's left behind when a
+// reader is moved out
+impl Default for Reader {
+    fn default() -> Self {
+        channel(0).1
+    }
+}
@@ -0,0 +1,207 @@
+// ---------------------------- Rumble ----------------------------
+//
+// Haptic confirmation through a game controller: one firm pulse when a
+// mapping fires, two light taps when a mapped note is held out of tune.
+// Uses XInput on Windows (physical pads and virtual ones such as ViGEm) and
+// evdev force feedback on Linux. The controller is looked up again whenever
+// it isn't available, so it can be plugged in at any time.
+
+use crossbeam_channel::{bounded, Sender};
+use serde::Deserialize;
+use std::time::Duration;
+
+use crate::feedback::Feedback;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct RumbleConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    // Which controller: XInput slot 0-3 on Windows, Nth rumble-capable
+    // /dev/input/event device on Linux
+    #[serde(default)]
+    pub controller: u32,
+    // Motor strength for the trigger pulse (0..1)
+    #[serde(default = "default_strength")]
+    pub strength: f32,
+    #[serde(default = "default_pulse_ms")]
+    pub pulse_ms: u64,
+    // Also signal mapped notes held out of tune
+    #[serde(default = "default_on_rejected")]
+    pub on_rejected: bool,
+}
+
+fn default_strength() -> f32 { 0.8 }
+fn default_pulse_ms() -> u64 { 80 }
+fn default_on_rejected() -> bool { true }
+
+impl Default for RumbleConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            controller: 0,
+            strength: default_strength(),
+            pulse_ms: default_pulse_ms(),
+            on_rejected: default_on_rejected(),
+        }
+    }
+}
+
+// (motor strength 0..1, on time, pause after)
+type Pattern = Vec<(f32, Duration, Duration)>;
+
+pub struct Rumble {
+    tx: Sender<Pattern>,
+    strength: f32,
+    pulse: Duration,
+    on_rejected: bool,
+}
+
+impl Rumble {
+    pub fn start(cfg: &RumbleConfig) -> Self {
+        // Only one pattern waits; more would lag behind the playing
+        let (tx, rx) = bounded::<Pattern>(1);
+        let index = cfg.controller;
+        std::thread::spawn(move || {
+            let mut motor: Option<Motor> = None;
+            for pattern in rx {
+                if motor.is_none() { motor = Motor::open(index); }
+                let Some(m) = motor.as_mut() else { continue };
+                for (strength, on, pause) in pattern {
+                    if !m.pulse(strength, on) {
+                        motor = None;
+                        break;
+                    }
+                    std::thread::sleep(on + pause);
+                }
+            }
+        });
+        Self {
+            tx,
+            strength: cfg.strength.clamp(0.0, 1.0),
+            pulse: Duration::from_millis(cfg.pulse_ms),
+            on_rejected: cfg.on_rejected,
+        }
+    }
+}
+
+impl Feedback for Rumble {
+    fn trigger(&mut self) {
+        let _ = self.tx.try_send(vec![(self.strength, self.pulse, Duration::ZERO)]);
+    }
+
+    fn rejected(&mut self) {
+        if !self.on_rejected { return; }
+        let tap = (self.strength * 0.5, self.pulse / 2, Duration::from_millis(80));
+        let _ = self.tx.try_send(vec![tap, tap]);
+    }
+}
+
+#[cfg(windows)]
+struct Motor {
+    index: u32,
+}
+
+#[cfg(windows)]
+impl Motor {
+    fn open(index: u32) -> Option<Self> {
+        use windows_sys::Win32::UI::Input::XboxController::{XInputGetState, XINPUT_STATE};
+        // SAFETY: XInputGetState fills the XINPUT_STATE we pass in
+        let mut state: XINPUT_STATE = unsafe { std::mem::zeroed() };
+        (unsafe { XInputGetState(index, &mut state) } == 0).then_some(Self { index })
+    }
+
+    // Run the motors at `strength` for `length`; false if the pad is gone
+    fn pulse(&mut self, strength: f32, length: Duration) -> bool {
+        use windows_sys::Win32::UI::Input::XboxController::{XInputSetState, XINPUT_VIBRATION};
+        let speed = (strength * 65535.0) as u16;
+        let mut on = XINPUT_VIBRATION { wLeftMotorSpeed: speed, wRightMotorSpeed: speed };
+        let mut off = XINPUT_VIBRATION { wLeftMotorSpeed: 0, wRightMotorSpeed: 0 };
+        // SAFETY: plain value structs passed by pointer
+        if unsafe { XInputSetState(self.index, &mut on) } != 0 { return false; }
+        std::thread::sleep(length);
+        unsafe { XInputSetState(self.index, &mut off) == 0 }
+    }
+}
+
+#[cfg(target_os = "linux")]
+struct Motor {
+    file: std::fs::File,
+    // Uploaded effect id, reused for every pulse
+    effect: i16,
+}
+
+#[cfg(target_os = "linux")]
+impl Motor {
+    const EV_FF: u16 = 0x15;
+    const FF_RUMBLE: u16 = 0x50;
+
+    fn open(index: u32) -> Option<Self> {
+        use std::os::fd::AsRawFd;
+        let mut paths: Vec<_> = std::fs::read_dir("/dev/input")
+            .ok()?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.file_name().and_then(|n| n.to_str()).is_some_and(|n| n.starts_with("event")))
+            .collect();
+        paths.sort_by_key(|p| p.to_string_lossy().trim_start_matches("/dev/input/event").parse::<u32>().unwrap_or(0));
+        paths
+            .into_iter()
+            .filter_map(|p| std::fs::OpenOptions::new().read(true).write(true).open(p).ok())
+            .filter(|f| {
+                // EVIOCGBIT(EV_FF): which force-feedback effects the device supports
+                let mut bits = [0u8; 16];
+                let req = ioc(2, 0x20 + Self::EV_FF as u64, bits.len());
+                // SAFETY: the kernel writes at most bits.len() bytes
+                let ok = unsafe { libc::ioctl(f.as_raw_fd(), req as _, bits.as_mut_ptr()) } >= 0;
+                ok && bits[(Self::FF_RUMBLE / 8) as usize] & (1 << (Self::FF_RUMBLE % 8)) != 0
+            })
+            .nth(index as usize)
+            .map(|file| Self { file, effect: -1 })
+    }
+
+    fn pulse(&mut self, strength: f32, length: Duration) -> bool {
+        use std::io::Write;
+        use std::os::fd::AsRawFd;
+        let magnitude = (strength * 65535.0) as u64;
+        // SAFETY: ff_effect is plain data; the rumble parameters sit at the
+        // start of its union
+        let mut effect: libc::ff_effect = unsafe { std::mem::zeroed() };
+        effect.type_ = Self::FF_RUMBLE;
+        effect.id = self.effect;
+        effect.replay.length = length.as_millis().min(u16::MAX as u128) as u16;
+        effect.u[0] = (magnitude | magnitude << 16) as _;
+        let req = ioc(1, 0x80, std::mem::size_of::<libc::ff_effect>());
+        // SAFETY: EVIOCSFF reads the effect and writes back its id
+        if unsafe { libc::ioctl(self.file.as_raw_fd(), req as _, &mut effect) } < 0 { return false; }
+        self.effect = effect.id;
+        // Play it once
+        let mut event: libc::input_event = unsafe { std::mem::zeroed() };
+        event.type_ = Self::EV_FF;
+        event.code = effect.id as u16;
+        event.value = 1;
+        // SAFETY: input_event is plain data
+        let bytes = unsafe {
+            std::slice::from_raw_parts(&event as *const _ as *const u8, std::mem::size_of::<libc::input_event>())
+        };
+        self.file.write_all(bytes).is_ok()
+    }
+}
+
+// Linux _IOC(dir, 'E', nr, size) for the evdev ioctls
+#[cfg(target_os = "linux")]
+fn ioc(dir: u64, nr: u64, size: usize) -> u64 {
+    (dir << 30) | ((size as u64) << 16) | (0x45 << 8) | nr
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+struct Motor;
+
+#[cfg(not(any(windows, target_os = "linux")))]
+impl Motor {
+    fn open(_index: u32) -> Option<Self> {
+        None
+    }
+
+    fn pulse(&mut self, _strength: f32, _length: Duration) -> bool {
+        false
+    }
+}
@@ -0,0 +1,97 @@
+// ---------------------------- Switch scanning ----------------------------
+//
+// Assistive switch access: a menu highlight steps through the configured
+// actions and any confident note acts as the "select" switch. An optional
+// second note steps the highlight manually (two-switch scanning).
+
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+use crate::Action;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ScanItem {
+    pub label: String,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ScanningConfig {
+    // Menu entries; note_map actions are used when empty
+    #[serde(default)]
+    pub items: Vec<ScanItem>,
+    // Time each entry stays highlighted (0 = only advance with advance_note)
+    #[serde(default = "default_interval_ms")]
+    pub interval_ms: u64,
+    // Optional note that steps to the next entry instead of selecting
+    #[serde(default)]
+    pub advance_note: Option<String>,
+    // Speak each highlighted label, e.g. ["espeak"] or ["say"]
+    #[serde(default)]
+    pub speak_command: Vec<String>,
+}
+
+fn default_interval_ms() -> u64 { 1500 }
+
+impl Default for ScanningConfig {
+    fn default() -> Self {
+        Self {
+            items: Vec::new(),
+            interval_ms: default_interval_ms(),
+            advance_note: None,
+            speak_command: Vec::new(),
+        }
+    }
+}
+
+pub struct Scanner {
+    items: Vec<ScanItem>,
+    interval: Option<Duration>,
+    index: usize,
+    highlighted_at: Instant,
+}
+
+impl Scanner {
+    pub fn new(items: Vec<ScanItem>, interval_ms: u64, now: Instant) -> Self {
+        Self {
+            items,
+            interval: (interval_ms > 0).then(|| Duration::from_millis(interval_ms)),
+            index: 0,
+            highlighted_at: now,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool { self.items.is_empty() }
+
+    pub fn current(&self) -> &ScanItem { &self.items[self.index] }
+
+    /// Move the highlight on once the dwell time has passed. Returns true when it moved.
+    pub fn tick(&mut self, now: Instant) -> bool {
+        match self.interval {
+            Some(d) if now.duration_since(self.highlighted_at) >= d => {
+                self.advance(now);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn advance(&mut self, now: Instant) {
+        self.index = (self.index + 1) % self.items.len();
+        self.highlighted_at = now;
+    }
+
+    /// Restart the dwell timer on the current entry (e.g. after a selection).
+    pub fn hold(&mut self, now: Instant) { self.highlighted_at = now; }
+
+    /// Single status line with the highlighted entry in brackets.
+    pub fn render(&self) -> String {
+        self.items
+            .iter()
+            .enumerate()
+            .map(|(i, it)| if i == self.index { format!("[{}]", it.label) } else { format!(" {} ", it.label) })
+            .collect::<Vec<_>>()
+            .join("")
+    }
+}
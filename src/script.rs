@@ -0,0 +1,252 @@
+// ---------------------------- Script mappings ----------------------------
+//
+// `type = "script"` mappings run a Rhai script (https://rhai.rs) when they
+// trigger, for logic a plain mapping can't express: act differently by how
+// loud or how far out of tune the note was, count triggers, pick an action
+// by the time of day. The script sees the event as variables (key, note, hz,
+// cents, level, confidence, time, unix_time) and a `state` map that keeps its
+// contents between runs. It doesn't act while it runs: send_keys, type_text,
+// run_command and action queue steps, sleep delays the next one, and the
+// queue runs like a macro once the script returns. A script file is read
+// again when it changes, so it can be edited while the program runs.
+
+use crate::{Action, MacroStep};
+use anyhow::{anyhow, Context, Result};
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map, Scope, AST};
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Parameters of a `type = "script"` mapping: a file, or the code inline.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ScriptAction {
+    #[serde(default)]
+    pub file: Option<String>,
+    #[serde(default)]
+    pub code: Option<String>,
+}
+
+impl ScriptAction {
+    pub fn label(&self) -> String {
+        match &self.file {
+            Some(file) => format!("script:{file}"),
+            None => "script".to_string(),
+        }
+    }
+
+    // Where the script's compiled form and state are kept
+    fn id(&self) -> String {
+        match (&self.file, &self.code) {
+            (Some(file), _) => format!("file:{file}"),
+            (None, code) => format!("code:{}", code.as_deref().unwrap_or_default()),
+        }
+    }
+
+    // The source, and when the file last changed
+    fn source(&self) -> Result<(String, Option<SystemTime>)> {
+        match (&self.file, &self.code) {
+            (Some(_), Some(_)) => Err(anyhow!("a script mapping takes file or code, not both")),
+            (Some(file), None) => {
+                let modified = std::fs::metadata(file).and_then(|m| m.modified()).ok();
+                let code = std::fs::read_to_string(file).with_context(|| format!("cannot read script {file}"))?;
+                Ok((code, modified))
+            }
+            (None, Some(code)) => Ok((code.clone(), None)),
+            (None, None) => Err(anyhow!("a script mapping needs file = \"...\" or code = \"...\"")),
+        }
+    }
+}
+
+// Enough for any sensible script; a runaway loop ends here instead of
+// stalling detection
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// What the latest frame heard, as the next script sees it.
+#[derive(Debug, Clone, Default)]
+pub struct Heard {
+    pub note: String,
+    pub hz: f32,
+    pub cents: f32,
+    pub level: f32,
+    pub confidence: f32,
+}
+
+// A compiled script and what it keeps between runs
+struct Loaded {
+    ast: AST,
+    modified: Option<SystemTime>,
+    state: Map,
+}
+
+static HEARD: Mutex<Option<Heard>> = Mutex::new(None);
+static KEY: Mutex<String> = Mutex::new(String::new());
+static SCRIPTS: Mutex<Option<HashMap<String, Loaded>>> = Mutex::new(None);
+static ENGINE: OnceLock<Engine> = OnceLock::new();
+static START: OnceLock<Instant> = OnceLock::new();
+
+thread_local! {
+    // Steps queued by the running script, and the delay before the next one
+    static QUEUE: RefCell<(Vec<MacroStep>, u64)> = const { RefCell::new((Vec::new(), 0)) };
+}
+
+/// Remember the frame's reading for scripts.
+pub fn heard(heard: Heard) {
+    START.get_or_init(Instant::now);
+    *HEARD.lock().unwrap_or_else(|e| e.into_inner()) = Some(heard);
+}
+
+/// The mapping key (note, chord or sequence) that is about to trigger.
+pub fn triggering(key: &str) {
+    *KEY.lock().unwrap_or_else(|e| e.into_inner()) = key.to_string();
+}
+
+/// The mapping key about to trigger and the latest reading, for plugins.
+pub fn event() -> (String, Heard) {
+    let key = KEY.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    (key, HEARD.lock().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_default())
+}
+
+/// Run the script and return the steps it queued.
+pub fn run(action: &ScriptAction) -> Result<Vec<MacroStep>> {
+    let id = action.id();
+    let mut scripts = SCRIPTS.lock().unwrap_or_else(|e| e.into_inner());
+    let scripts = scripts.get_or_insert_with(HashMap::new);
+    let stale = match (scripts.get(&id), &action.file) {
+        (None, _) => true,
+        (Some(loaded), Some(file)) => std::fs::metadata(file).and_then(|m| m.modified()).ok() != loaded.modified,
+        (Some(_), None) => false,
+    };
+    if stale {
+        let (code, modified) = action.source()?;
+        let ast = compile(&code).with_context(|| format!("in {}", action.label()))?;
+        // An edited script starts over with its state
+        scripts.insert(id.clone(), Loaded { ast, modified, state: Map::new() });
+    }
+    let loaded = scripts.get_mut(&id).expect("just loaded");
+
+    let mut scope = Scope::new();
+    let heard = HEARD.lock().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_default();
+    scope.push_constant("key", KEY.lock().unwrap_or_else(|e| e.into_inner()).clone());
+    scope.push_constant("note", heard.note);
+    scope.push_constant("hz", heard.hz as f64);
+    scope.push_constant("cents", heard.cents as f64);
+    scope.push_constant("level", heard.level as f64);
+    scope.push_constant("confidence", heard.confidence as f64);
+    scope.push_constant("time", START.get_or_init(Instant::now).elapsed().as_secs_f64());
+    let unix_time = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or_default();
+    scope.push_constant("unix_time", unix_time);
+    scope.push("state", std::mem::take(&mut loaded.state));
+
+    QUEUE.with(|q| *q.borrow_mut() = (Vec::new(), 0));
+    let result = engine().run_ast_with_scope(&mut scope, &loaded.ast);
+    loaded.state = scope.get_value::<Map>("state").unwrap_or_default();
+    let (steps, _) = QUEUE.with(|q| q.take());
+    result.map_err(|e| anyhow!("{} failed: {e}", action.label()))?;
+    Ok(steps)
+}
+
+/// Whether the script can be read and compiled.
+pub fn check(action: &ScriptAction) -> Result<()> {
+    let (code, _) = action.source()?;
+    compile(&code)?;
+    Ok(())
+}
+
+fn compile(code: &str) -> Result<AST> {
+    engine().compile(code).map_err(|e| anyhow!("{e}"))
+}
+
+fn engine() -> &'static Engine {
+    ENGINE.get_or_init(|| {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.on_print(|text| println!("{text}"));
+        engine.on_debug(|text, _, pos| tracing::debug!("script ({pos}): {text}"));
+        engine.register_fn("send_keys", |sequence: &str| queue(Action::Keys { sequence: sequence.to_string() }));
+        engine.register_fn("type_text", |text: &str| queue(Action::Text { text: text.to_string() }));
+        engine.register_fn("run_command", |program: &str| command(program, Array::new()));
+        engine.register_fn("run_command", command);
+        engine.register_fn("sleep", |ms: i64| QUEUE.with(|q| q.borrow_mut().1 += ms.max(0) as u64));
+        engine.register_fn("action", |action: Map| -> Result<(), Box<EvalAltResult>> {
+            queue(rhai::serde::from_dynamic(&Dynamic::from_map(action))?);
+            Ok(())
+        });
+        engine
+    })
+}
+
+fn queue(action: Action) {
+    QUEUE.with(|q| {
+        let (steps, delay_ms) = &mut *q.borrow_mut();
+        steps.push(MacroStep { delay_ms: std::mem::take(delay_ms), action });
+    });
+}
+
+fn command(program: &str, args: Array) {
+    queue(Action::Command {
+        program: program.to_string(),
+        args: args.into_iter().map(|a| a.to_string()).collect(),
+        wait: false,
+        cwd: None,
+        env: Default::default(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn script(code: &str) -> ScriptAction {
+        ScriptAction { file: None, code: Some(code.to_string()) }
+    }
+
+    fn names(steps: &[MacroStep]) -> Vec<String> {
+        steps
+            .iter()
+            .map(|s| format!("{}ms {}", s.delay_ms, crate::action_name(&s.action)))
+            .collect()
+    }
+
+    #[test]
+    fn helpers_queue_steps_in_order() {
+        let s = script(
+            r#"
+            send_keys("Ctrl+S");
+            sleep(150);
+            type_text("done");
+            run_command("notify-send", ["saved", 1]);
+            action(#{ type: "osc", address: "/play" });
+            "#,
+        );
+        assert_eq!(
+            names(&run(&s).unwrap()),
+            ["0ms keys:Ctrl+S", "150ms text:done", "0ms cmd:notify-send saved 1", "0ms osc:/play"]
+        );
+    }
+
+    #[test]
+    fn the_event_decides_and_state_persists() {
+        let s = script(
+            r#"
+            state.count = (state.count ?? 0) + 1;
+            if cents > 10.0 { send_keys("Up") } else if state.count > 1 { send_keys("B") } else { send_keys("A") }
+            "#,
+        );
+        heard(Heard { note: "A4".to_string(), hz: 440.0, cents: 2.0, level: 0.1, confidence: 0.9 });
+        assert_eq!(names(&run(&s).unwrap()), ["0ms keys:A"]);
+        assert_eq!(names(&run(&s).unwrap()), ["0ms keys:B"]);
+        heard(Heard { note: "A4".to_string(), hz: 444.0, cents: 15.7, level: 0.1, confidence: 0.9 });
+        assert_eq!(names(&run(&s).unwrap()), ["0ms keys:Up"]);
+    }
+
+    #[test]
+    fn mistakes_are_reported() {
+        assert!(check(&script("send_keys(")).is_err());
+        assert!(run(&script("action(#{ type: \"nonsense\" })")).is_err());
+        // A runaway loop ends instead of hanging detection
+        assert!(run(&script("loop {}")).is_err());
+        assert!(check(&ScriptAction { file: Some("no/such/script.rhai".to_string()), code: None }).is_err());
+    }
+}
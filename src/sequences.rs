@@ -0,0 +1,86 @@
+// ---------------------------- Note sequences ----------------------------
+//
+// `[[sequences]]` entries fire when their notes are played in order within
+// a time window, e.g. C4 E4 G4 inside 1.5 s. A short phrase is much harder to
+// play by accident than a single note. Every recognized note moves each
+// sequence forward, starts it over or resets it.
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+use crate::Mapping;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Sequence {
+    // Note names in playing order
+    pub notes: Vec<String>,
+    // The whole phrase must be played within this many ms of its first note
+    #[serde(default = "default_within_ms")]
+    pub within_ms: u64,
+    // The action and per-mapping options, as in note_map
+    #[serde(flatten)]
+    pub mapping: Mapping,
+}
+
+fn default_within_ms() -> u64 { 1500 }
+
+impl Sequence {
+    /// Display name, e.g. "C4 E4 G4".
+    pub fn label(&self) -> String {
+        self.notes.iter().map(|n| crate::notation::spell(n)).collect::<Vec<_>>().join(" ")
+    }
+}
+
+// Progress through one sequence
+struct Progress {
+    notes: Vec<i32>,
+    within: Duration,
+    matched: usize,
+    started: Instant,
+}
+
+pub struct SequenceTracker {
+    progress: Vec<Progress>,
+}
+
+impl SequenceTracker {
+    pub fn new(sequences: &[Sequence], now: Instant) -> Result<Self> {
+        let progress = sequences
+            .iter()
+            .map(|s| {
+                if s.notes.is_empty() { return Err(anyhow!("A [[sequences]] entry has no notes")); }
+                let notes = s
+                    .notes
+                    .iter()
+                    .map(|n| crate::note_to_midi(n).ok_or_else(|| anyhow!("Sequence note {n:?} is not a note name")))
+                    .collect::<Result<Vec<i32>>>()?;
+                Ok(Progress { notes, within: Duration::from_millis(s.within_ms), matched: 0, started: now })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { progress })
+    }
+
+    /// A note was recognized; returns the indices of sequences it completes.
+    pub fn note(&mut self, midi: i32, now: Instant) -> Vec<usize> {
+        let mut done = Vec::new();
+        for (i, p) in self.progress.iter_mut().enumerate() {
+            if p.matched > 0 && now.duration_since(p.started) > p.within { p.matched = 0; }
+            if p.notes[p.matched] == midi {
+                if p.matched == 0 { p.started = now; }
+                p.matched += 1;
+            } else if p.notes[0] == midi {
+                // A wrong note that could begin the phrase starts it over
+                p.matched = 1;
+                p.started = now;
+            } else {
+                p.matched = 0;
+            }
+            if p.matched == p.notes.len() {
+                p.matched = 0;
+                done.push(i);
+            }
+        }
+        done
+    }
+}
@@ -0,0 +1,162 @@
+// ---------------------------- Pitch-track smoothing ----------------------------
+//
+// Single-frame glitches (an octave slip, a dropout) reset note_hold_frames
+// and delay triggers. The pitch of each frame can be smoothed over the last
+// `frames` frames before notes are classified, at the cost of that many
+// frames of delay:
+//   - "median" reports the median of the voiced frames, or silence when
+//     most frames are silent;
+//   - "hmm" decodes the most likely note path (one state per semitone plus
+//     silence, Viterbi with a fixed lag), so a note only changes when the
+//     new one is heard for more than a frame.
+
+use serde::Deserialize;
+use std::collections::VecDeque;
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Method {
+    #[default]
+    None,
+    Median,
+    Hmm,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct SmoothingConfig {
+    #[serde(default)]
+    pub method: Method,
+    // Frames looked at (median) or decoding lag (hmm)
+    #[serde(default = "default_frames")]
+    pub frames: usize,
+}
+
+fn default_frames() -> usize { 5 }
+
+impl Default for SmoothingConfig {
+    fn default() -> Self {
+        Self { method: Method::default(), frames: default_frames() }
+    }
+}
+
+// HMM probabilities: a note state reports its own note, reads an octave off,
+// drops out or reads something else; the silent state mostly reads nothing
+const HIT: f32 = 0.75;
+const OCTAVE: f32 = 0.05;
+const DROPOUT: f32 = 0.15;
+const SILENT_QUIET: f32 = 0.9;
+// Chance per frame of moving to another state, spread over all of them
+const SWITCH: f32 = 0.05;
+
+struct Viterbi {
+    // Lowest MIDI note; state 0 is silence, state i the note lowest + i - 1
+    lowest: i32,
+    // Log probability of the best path ending in each state
+    score: Vec<f32>,
+    // Predecessor of each state, one entry per frame still in the lag
+    back: VecDeque<Vec<usize>>,
+}
+
+pub struct Smoother {
+    cfg: SmoothingConfig,
+    // Raw pitch of the most recent frames, oldest first
+    history: VecDeque<Option<f32>>,
+    viterbi: Option<Viterbi>,
+}
+
+impl Smoother {
+    pub fn new(cfg: &SmoothingConfig, min_hz: f32, max_hz: f32) -> Self {
+        let viterbi = (cfg.method == Method::Hmm).then(|| {
+            let lowest = crate::freq_to_midi(min_hz.max(1.0)).0;
+            let states = (crate::freq_to_midi(max_hz.max(min_hz + 1.0)).0 - lowest + 2).max(2) as usize;
+            Viterbi { lowest, score: vec![0.0; states], back: VecDeque::new() }
+        });
+        Self { cfg: cfg.clone(), history: VecDeque::new(), viterbi }
+    }
+
+    /// Feed one frame's pitch and return the smoothed pitch of the frame
+    /// `frames` - 1 (hmm) or about half that (median) frames back.
+    pub fn push(&mut self, f0: Option<f32>) -> Option<f32> {
+        let frames = self.cfg.frames.max(1);
+        self.history.push_back(f0);
+        if self.history.len() > frames { self.history.pop_front(); }
+        match self.cfg.method {
+            Method::None => f0,
+            Method::Median => {
+                let mut voiced: Vec<f32> = self.history.iter().flatten().copied().collect();
+                if voiced.len() * 2 <= self.history.len() { return None; }
+                voiced.sort_by(f32::total_cmp);
+                Some(voiced[voiced.len() / 2])
+            }
+            Method::Hmm => {
+                let viterbi = self.viterbi.as_mut()?;
+                let state = viterbi.step(f0.map(|f| crate::freq_to_midi(f).0), frames);
+                let note = state.checked_sub(1).map(|i| viterbi.lowest + i as i32)?;
+                // The decoded frame's own reading if it agrees, else the
+                // nearest one that does, else the note's exact pitch
+                let oldest = self.history.len() - viterbi.back.len();
+                self.history
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, f)| f.filter(|&f| crate::freq_to_midi(f).0 == note).map(|f| (i.abs_diff(oldest), f)))
+                    .min_by_key(|(d, _)| *d)
+                    .map(|(_, f)| f)
+                    .or_else(|| Some(crate::midi_to_freq(note)))
+            }
+        }
+    }
+}
+
+impl Viterbi {
+    // Add a frame (observed note, None when silent) and return the state of
+    // the frame `lag` - 1 frames back on the best path
+    fn step(&mut self, observed: Option<i32>, lag: usize) -> usize {
+        let states = self.score.len();
+        let notes = (states - 1) as f32;
+        let stay = (1.0 - SWITCH).ln();
+        let switch = (SWITCH / notes).ln();
+        let (best, best_score) = argmax(&self.score);
+        let mut back = vec![0; states];
+        let mut score = vec![0.0; states];
+        for s in 0..states {
+            let (from, prior) =
+                if self.score[s] + stay >= best_score + switch { (s, self.score[s] + stay) } else { (best, best_score + switch) };
+            back[s] = from;
+            score[s] = prior + self.emission(s, observed, notes);
+        }
+        // Keep the scores near zero
+        let (_, top) = argmax(&score);
+        score.iter_mut().for_each(|v| *v -= top);
+        self.score = score;
+        self.back.push_back(back);
+        if self.back.len() > lag { self.back.pop_front(); }
+        let mut state = argmax(&self.score).0;
+        for back in self.back.iter().skip(1).rev() {
+            state = back[state];
+        }
+        state
+    }
+
+    fn emission(&self, state: usize, observed: Option<i32>, notes: f32) -> f32 {
+        let p = match (state, observed) {
+            (0, None) => SILENT_QUIET,
+            (0, Some(_)) => (1.0 - SILENT_QUIET) / notes,
+            (_, None) => DROPOUT,
+            (s, Some(m)) => {
+                let note = self.lowest + s as i32 - 1;
+                if m == note {
+                    HIT
+                } else if (m - note).abs() == 12 {
+                    OCTAVE
+                } else {
+                    (1.0 - HIT - OCTAVE - DROPOUT) / notes
+                }
+            }
+        };
+        p.ln()
+    }
+}
+
+fn argmax(values: &[f32]) -> (usize, f32) {
+    values.iter().copied().enumerate().fold((0, f32::NEG_INFINITY), |b, (i, v)| if v > b.1 { (i, v) } else { b })
+}
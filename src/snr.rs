@@ -0,0 +1,34 @@
+// ---------------------------- SNR estimation ----------------------------
+//
+// The noise floor follows the quietest recent level: it drops at once when
+// the signal gets quieter and creeps up slowly otherwise, so notes barely
+// move it while a fan that starts up is learned within seconds. The SNR of a
+// frame is its level above that floor.
+
+// How fast the floor may rise, in dB per second
+const RISE_DB_PER_SEC: f32 = 2.0;
+// Floor never drops below this level (digital silence)
+const MIN_FLOOR: f32 = 1e-5;
+
+pub struct NoiseFloor {
+    floor: Option<f32>,
+    // Multiplier applied to the floor each hop while the level is above it
+    rise: f32,
+}
+
+impl NoiseFloor {
+    pub fn new(hop_secs: f32) -> Self {
+        Self { floor: None, rise: 10f32.powf(RISE_DB_PER_SEC * hop_secs / 20.0) }
+    }
+
+    /// Feed one frame's RMS level and return its SNR in dB.
+    pub fn update(&mut self, level: f32) -> f32 {
+        let level = level.max(MIN_FLOOR);
+        let floor = match self.floor {
+            Some(f) => (f * self.rise).min(level),
+            None => level,
+        };
+        self.floor = Some(floor);
+        20.0 * (level / floor).log10()
+    }
+}
@@ -0,0 +1,42 @@
+// ---------------------------- Speech output ----------------------------
+//
+// Minimal text-to-speech by handing text to an external program (espeak,
+// say, a PowerShell one-liner, ...). Each announcement replaces the previous
+// one so speech never lags behind what is on screen.
+
+use std::process::{Child, Command, Stdio};
+
+pub struct Speaker {
+    // Program and arguments; "{text}" in an argument is replaced, otherwise
+    // the text is appended as the last argument
+    command: Vec<String>,
+    current: Option<Child>,
+}
+
+impl Speaker {
+    pub fn new(command: &[String]) -> Option<Self> {
+        (!command.is_empty()).then(|| Self { command: command.to_vec(), current: None })
+    }
+
+    pub fn say(&mut self, text: &str) {
+        if let Some(mut child) = self.current.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        let mut args: Vec<String> = self.command[1..].to_vec();
+        if args.iter().any(|a| a.contains("{text}")) {
+            for a in &mut args { *a = a.replace("{text}", text); }
+        } else {
+            args.push(text.to_string());
+        }
+        match Command::new(&self.command[0])
+            .args(&args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Ok(child) => self.current = Some(child),
+            Err(e) => tracing::warn!("Speech command failed: {e}"),
+        }
+    }
+}
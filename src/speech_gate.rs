@@ -0,0 +1,194 @@
+// ---------------------------- Speech gate ----------------------------
+//
+// Talking produces voiced, pitched frames too, but speech pitch glides
+// constantly and voiced stretches are short. A sung (or played) note holds a
+// steady center for a while, with at most vibrato around it. The gate only
+// lets a note through once the recent pitch track looks like that.
+//
+// The optional classifier looks at timbre as well, for melodic talkers:
+// speech mixes noisy consonants (flat spectrum) with breathy vowels (weak
+// harmonics relative to the noise between them), and its loudness rises and
+// falls with every syllable. Instruments are the opposite on all three.
+
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct SpeechGateConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    // How long the pitch must be continuously voiced and steady
+    #[serde(default = "default_sustain_ms")]
+    pub sustain_ms: u64,
+    // Largest pitch spread (max - min) allowed over that time; vibrato fits
+    // comfortably inside 100 cents, speech intonation usually doesn't
+    #[serde(default = "default_max_spread_cents")]
+    pub max_spread_cents: f32,
+    // Also suppress triggers while the timbre classifier hears speech
+    // (independent of `enabled`)
+    #[serde(default)]
+    pub classifier: bool,
+    // Spectral flatness (0 = pure tones, 1 = white noise) above which a frame sounds noisy
+    #[serde(default = "default_max_flatness")]
+    pub max_flatness: f32,
+    // Harmonic-to-between-harmonic level in dB below which a frame sounds breathy
+    #[serde(default = "default_min_harmonicity_db")]
+    pub min_harmonicity_db: f32,
+    // Average up-and-down level fluctuation (dB per hop) above which it sounds syllabic
+    #[serde(default = "default_max_modulation_db")]
+    pub max_modulation_db: f32,
+}
+
+fn default_sustain_ms() -> u64 { 350 }
+fn default_max_spread_cents() -> f32 { 120.0 }
+fn default_max_flatness() -> f32 { 0.35 }
+fn default_min_harmonicity_db() -> f32 { 6.0 }
+fn default_max_modulation_db() -> f32 { 1.5 }
+
+impl Default for SpeechGateConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            sustain_ms: default_sustain_ms(),
+            max_spread_cents: default_max_spread_cents(),
+            classifier: false,
+            max_flatness: default_max_flatness(),
+            min_harmonicity_db: default_min_harmonicity_db(),
+            max_modulation_db: default_max_modulation_db(),
+        }
+    }
+}
+
+pub struct SpeechGate {
+    sustain: Duration,
+    max_spread: f32,
+    // Recent voiced frames as (time, pitch in cents relative to A4)
+    track: VecDeque<(Instant, f32)>,
+}
+
+impl SpeechGate {
+    pub fn new(cfg: &SpeechGateConfig) -> Self {
+        Self {
+            sustain: Duration::from_millis(cfg.sustain_ms),
+            max_spread: cfg.max_spread_cents,
+            track: VecDeque::new(),
+        }
+    }
+
+    /// Feed one frame (None = unvoiced). Returns true when the signal looks
+    /// like a sustained note rather than speech.
+    pub fn update(&mut self, freq: Option<f32>, now: Instant) -> bool {
+        let Some(f0) = freq else {
+            self.track.clear();
+            return false;
+        };
+        self.track.push_back((now, 1200.0 * (f0 / 440.0).log2()));
+        // Keep just enough history to cover the sustain window
+        while self.track.len() > 2
+            && self.track.get(1).is_some_and(|(t, _)| now.duration_since(*t) >= self.sustain)
+        {
+            self.track.pop_front();
+        }
+        let Some(&(first, _)) = self.track.front() else { return false };
+        if now.duration_since(first) < self.sustain {
+            return false;
+        }
+        let (lo, hi) = self
+            .track
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(lo, hi), &(_, c)| (lo.min(c), hi.max(c)));
+        hi - lo <= self.max_spread
+    }
+}
+
+// How much recent signal the classifier weighs
+const CLASSIFIER_SPAN: Duration = Duration::from_millis(1000);
+// Spectrum points for the flatness measure, spread evenly up to this frequency
+const FLATNESS_POINTS: usize = 64;
+const FLATNESS_TOP_HZ: f32 = 5000.0;
+
+pub struct SpeechClassifier {
+    max_flatness: f32,
+    min_harmonicity_db: f32,
+    max_modulation_db: f32,
+    floor: f32,
+    // Recent loud frames as (time, level in dB, looked like speech)
+    frames: VecDeque<(Instant, f32, bool)>,
+}
+
+impl SpeechClassifier {
+    pub fn new(cfg: &SpeechGateConfig, floor: f32) -> Self {
+        Self {
+            max_flatness: cfg.max_flatness,
+            min_harmonicity_db: cfg.min_harmonicity_db,
+            max_modulation_db: cfg.max_modulation_db,
+            floor: floor.max(0.002),
+            frames: VecDeque::new(),
+        }
+    }
+
+    /// Feed the analysis window and this frame's pitch. Returns true while
+    /// most of the last second sounded like speech.
+    pub fn update(&mut self, window: &[f32], sample_rate: f32, freq: Option<f32>, now: Instant) -> bool {
+        while self.frames.front().is_some_and(|(t, _, _)| now.duration_since(*t) > CLASSIFIER_SPAN) {
+            self.frames.pop_front();
+        }
+        let level = crate::rms(window);
+        if level >= self.floor && window.len() >= 64 {
+            let x = hann(window);
+            let noisy = flatness(&x, sample_rate) > self.max_flatness;
+            let breathy = freq.is_some_and(|f0| harmonicity_db(&x, sample_rate, f0) < self.min_harmonicity_db);
+            let db = 20.0 * level.log10();
+            self.frames.push_back((now, db, false));
+            let syllabic = self.modulation_db() > self.max_modulation_db;
+            let votes = [noisy, breathy, syllabic].iter().filter(|&&v| v).count();
+            if let Some(last) = self.frames.back_mut() { last.2 = votes >= 2; }
+        }
+        let speech = self.frames.iter().filter(|f| f.2).count();
+        speech > 0 && 2 * speech >= self.frames.len()
+    }
+
+    // Level movement that isn't part of a steady rise or decay: total
+    // absolute change minus net change, per hop
+    fn modulation_db(&self) -> f32 {
+        if self.frames.len() < 3 { return 0.0; }
+        let levels: Vec<f32> = self.frames.iter().map(|f| f.1).collect();
+        let total: f32 = levels.windows(2).map(|w| (w[1] - w[0]).abs()).sum();
+        let net = (levels[levels.len() - 1] - levels[0]).abs();
+        (total - net) / (levels.len() - 1) as f32
+    }
+}
+
+fn hann(window: &[f32]) -> Vec<f32> {
+    let n = window.len() as f32;
+    window
+        .iter()
+        .enumerate()
+        .map(|(i, &s)| s * (0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / (n - 1.0)).cos()))
+        .collect()
+}
+
+// Geometric over arithmetic mean of the magnitude spectrum
+fn flatness(x: &[f32], sample_rate: f32) -> f32 {
+    let top = FLATNESS_TOP_HZ.min(sample_rate / 2.0);
+    let mags: Vec<f32> = (1..=FLATNESS_POINTS)
+        .map(|i| crate::strings::goertzel(x, sample_rate, top * i as f32 / (FLATNESS_POINTS + 1) as f32) + 1e-9)
+        .collect();
+    let n = mags.len() as f32;
+    let geometric = (mags.iter().map(|m| m.ln()).sum::<f32>() / n).exp();
+    let arithmetic = mags.iter().sum::<f32>() / n;
+    geometric / arithmetic
+}
+
+// Mean level at the first harmonics of f0 against the level halfway between them
+fn harmonicity_db(x: &[f32], sample_rate: f32, f0: f32) -> f32 {
+    let (mut on, mut off) = (0.0f32, 0.0f32);
+    for k in 1..=8 {
+        let f = k as f32 * f0;
+        if (f + 0.5 * f0) >= sample_rate / 2.0 { break; }
+        on += crate::strings::goertzel(x, sample_rate, f);
+        off += crate::strings::goertzel(x, sample_rate, f + 0.5 * f0);
+    }
+    20.0 * ((on + 1e-9) / (off + 1e-9)).log10()
+}
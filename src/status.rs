@@ -0,0 +1,340 @@
+// ---------------------------- Status output ----------------------------
+//
+// Live status for the console. The default is the single carriage-return
+// line that is redrawn every frame. Accessible output instead prints
+// discrete, rate-limited lines ("A4, 5 cents sharp", "Triggered keys:Ctrl+S")
+// that braille displays and screen readers can follow, optionally spoken.
+// Verbose output prints every frame's reading on its own line instead of
+// redrawing one. A listener (see Pipeline) gets the same events as values.
+//
+// JSON output (--output json) is for other programs: one object per frame,
+// trigger and status event on standard output, shaped like the WebSocket
+// events. Everything else the program prints goes to standard error then,
+// so the pipe carries nothing but events.
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::notation;
+use crate::pipeline::NoteEvent;
+use crate::speech::Speaker;
+use crate::{dbus, mqtt, record, websocket, Action};
+
+/// Called with every event shown on the status line.
+pub type Listener = Box<dyn FnMut(&NoteEvent) + Send>;
+
+/// How the status is shown (--output).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// The status line, or accessible output
+    Text,
+    /// One JSON object per frame, trigger and status event
+    Json,
+}
+
+// Where JSON events go: the real standard output
+static JSON: OnceLock<Mutex<Box<dyn Write + Send>>> = OnceLock::new();
+
+/// Show events as JSON from now on, on what is standard output now, and
+/// send the rest of the program's output to standard error.
+pub fn json_output() -> std::io::Result<()> {
+    if JSON.get().is_some() { return Ok(()); }
+    std::io::stdout().flush().ok();
+    JSON.set(Mutex::new(take_stdout()?)).ok();
+    Ok(())
+}
+
+// A handle to standard output, which from now on goes to standard error
+#[cfg(unix)]
+fn take_stdout() -> std::io::Result<Box<dyn Write + Send>> {
+    use std::os::fd::FromRawFd;
+    // SAFETY: dup and dup2 only duplicate the process's own standard
+    // descriptors, and the File takes sole ownership of the new one
+    unsafe {
+        let fd = libc::dup(libc::STDOUT_FILENO);
+        if fd < 0 || libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Box::new(File::from_raw_fd(fd)))
+    }
+}
+
+#[cfg(windows)]
+fn take_stdout() -> std::io::Result<Box<dyn Write + Send>> {
+    use std::os::windows::io::FromRawHandle;
+    use windows_sys::Win32::System::Console::{GetStdHandle, SetStdHandle, STD_ERROR_HANDLE, STD_OUTPUT_HANDLE};
+    // SAFETY: the handles come straight from GetStdHandle; the standard
+    // library looks its output handle up on every write, so after the swap
+    // the File is the only user of the original one
+    unsafe {
+        let out = GetStdHandle(STD_OUTPUT_HANDLE);
+        if SetStdHandle(STD_OUTPUT_HANDLE, GetStdHandle(STD_ERROR_HANDLE)) == 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Box::new(File::from_raw_handle(out as _)))
+    }
+}
+
+// Elsewhere the events share standard output with everything else
+#[cfg(not(any(unix, windows)))]
+fn take_stdout() -> std::io::Result<Box<dyn Write + Send>> {
+    Ok(Box::new(std::io::stdout()))
+}
+
+// One event as a JSON line
+fn print_json(performer: Option<&str>, event: &NoteEvent) {
+    let Some(out) = JSON.get() else { return };
+    let round = |x: f32, places: i32| (x as f64 * 10f64.powi(places)).round() / 10f64.powi(places);
+    let mut value = match event {
+        NoteEvent::Pitch { hz, note, cents, confidence } => json!({
+            "type": "pitch",
+            "hz": round(*hz, 2),
+            "note": note,
+            "cents": round(*cents, 1),
+            "confidence": round(*confidence, 3),
+        }),
+        NoteEvent::Silence => json!({ "type": "silence" }),
+        NoteEvent::Trigger { note, action } => json!({ "type": "trigger", "note": note, "action": crate::action_name(action) }),
+        NoteEvent::Status(text) => json!({ "type": "status", "text": text }),
+    };
+    value["ms"] = Value::from(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64);
+    if let Some(p) = performer { value["performer"] = p.into(); }
+    let mut out = out.lock().unwrap_or_else(|e| e.into_inner());
+    // A reader that has gone away only loses events
+    writeln!(out, "{value}").and_then(|_| out.flush()).ok();
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct AccessibleConfig {
+    // Also enabled by --accessible-output
+    #[serde(default)]
+    pub enabled: bool,
+    // Minimum time between pitch announcements
+    #[serde(default = "default_min_interval_ms")]
+    pub min_interval_ms: u64,
+    // Speak announcements through this program, e.g. ["espeak"]
+    #[serde(default)]
+    pub speak_command: Vec<String>,
+}
+
+fn default_min_interval_ms() -> u64 { 1500 }
+
+impl Default for AccessibleConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_interval_ms: default_min_interval_ms(),
+            speak_command: Vec::new(),
+        }
+    }
+}
+
+struct Announcer {
+    min_gap: Duration,
+    speaker: Option<Speaker>,
+    // Last pitch announcement and when it was made
+    last: Option<(String, Instant)>,
+}
+
+impl Announcer {
+    fn announce(&mut self, text: &str) {
+        println!("{text}");
+        if let Some(s) = self.speaker.as_mut() { s.say(text); }
+    }
+}
+
+pub struct StatusOutput {
+    accessible: Option<Announcer>,
+    // Performer name put in front of every line. Performers share the
+    // console, so with a label the redrawn pitch line is left out.
+    label: Option<String>,
+    verbose: bool,
+    // Silence has been reported since the last pitch (verbose only)
+    quiet: bool,
+    listener: Option<Listener>,
+    // Events are printed as JSON instead
+    json: bool,
+}
+
+impl StatusOutput {
+    pub fn new(cfg: &AccessibleConfig) -> Self {
+        let json = JSON.get().is_some();
+        let accessible = (cfg.enabled && !json).then(|| Announcer {
+            min_gap: Duration::from_millis(cfg.min_interval_ms),
+            speaker: Speaker::new(&cfg.speak_command),
+            last: None,
+        });
+        Self { accessible, label: None, verbose: false, quiet: false, listener: None, json }
+    }
+
+    /// One line per frame instead of the redrawn status line (--verbose).
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Prefix output with a performer name (see `label`).
+    pub fn labeled(mut self, label: Option<&str>) -> Self {
+        self.label = label.map(str::to_string);
+        self
+    }
+
+    /// Also hand every event to `listener`.
+    pub fn listener(mut self, listener: Option<Listener>) -> Self {
+        self.listener = listener;
+        self
+    }
+
+    fn notify(&mut self, event: impl FnOnce() -> NoteEvent) {
+        let publish = websocket::active() || mqtt::active() || dbus::active() || record::active();
+        if self.listener.is_none() && !publish && !self.json { return; }
+        let event = event();
+        if self.json { print_json(self.label.as_deref(), &event); }
+        if publish {
+            websocket::publish(self.label.as_deref(), &event);
+            mqtt::publish(self.label.as_deref(), &event);
+            dbus::publish(self.label.as_deref(), &event);
+            record::publish(self.label.as_deref(), &event);
+        }
+        if let Some(l) = self.listener.as_mut() { l(&event); }
+    }
+
+    /// A pitch was detected this frame, with the detector's confidence in it.
+    pub fn pitch(&mut self, f0: f32, note: &str, cents: f32, confidence: f32, now: Instant) {
+        self.notify(|| NoteEvent::Pitch { hz: f0, note: note.to_string(), cents, confidence });
+        let note = &notation::spell(note);
+        let Some(a) = self.accessible.as_mut() else {
+            if self.label.is_some() && !self.verbose { return; }
+            self.quiet = false;
+            self.redraw(format!("{:6.1} Hz  {:>3.0} cents  {:>3}  ", f0, cents, note));
+            return;
+        };
+        let text = describe_pitch(note, cents);
+        let due = match &a.last {
+            Some((prev, at)) => *prev != text && now.duration_since(*at) >= a.min_gap,
+            None => true,
+        };
+        if due {
+            a.announce(&with_label(self.label.as_deref(), &text));
+            a.last = Some((text, now));
+        }
+    }
+
+    /// No confident pitch this frame.
+    pub fn silence(&mut self) {
+        self.notify(|| NoteEvent::Silence);
+        match self.accessible.as_mut() {
+            // Announce the next note again even if it is the same one
+            Some(a) => if let Some((prev, _)) = a.last.as_mut() { prev.clear() },
+            None if self.label.is_some() && !self.verbose => {}
+            None if self.verbose && self.quiet => {}
+            None => {
+                self.quiet = true;
+                self.redraw("(no pitch)                                 ".to_string());
+            }
+        }
+    }
+
+    /// A pitch was found but rejected as too close to the noise floor.
+    pub fn low_snr(&mut self, snr_db: f32) {
+        self.notify(|| NoteEvent::Silence);
+        let Some(a) = self.accessible.as_mut() else {
+            if self.label.is_some() && !self.verbose { return; }
+            self.redraw(format!("(low SNR: {:>4.1} dB)                        ", snr_db));
+            return;
+        };
+        let text = "Low signal-to-noise ratio".to_string();
+        if a.last.as_ref().is_none_or(|(prev, _)| *prev != text) {
+            a.announce(&with_label(self.label.as_deref(), &text));
+            a.last = Some((text, Instant::now()));
+        }
+    }
+
+    /// A mapping fired; `label` describes its action.
+    pub fn trigger(&mut self, note: &str, action: &Action, label: &str) {
+        self.notify(|| NoteEvent::Trigger { note: note.to_string(), action: action.clone() });
+        tracing::info!(performer = self.label.as_deref(), note, action = label, "trigger");
+        let text = match self.accessible {
+            Some(_) => format!("Triggered {label}"),
+            None => format!("Trigger: {} => {label:?}", notation::spell(note)),
+        };
+        self.show(&text);
+    }
+
+    pub fn is_accessible(&self) -> bool { self.accessible.is_some() }
+
+    /// Any other discrete event (queued trigger, menu selection, ...); always reported.
+    pub fn event(&mut self, text: &str) {
+        self.notify(|| NoteEvent::Status(text.to_string()));
+        tracing::info!(performer = self.label.as_deref(), "{text}");
+        self.show(text);
+    }
+
+    fn show(&mut self, text: &str) {
+        if self.json { return; }
+        let text = with_label(self.label.as_deref(), text);
+        match self.accessible.as_mut() {
+            Some(a) => a.announce(&text),
+            // Without a label, end the redrawn status line first
+            None if self.label.is_some() || self.verbose => println!("{text}"),
+            None => println!("\n{text}"),
+        }
+    }
+
+    // Replace the status line, or add a line when verbose
+    fn redraw(&self, text: String) {
+        if self.json { return; }
+        if self.verbose {
+            println!("{}", with_label(self.label.as_deref(), text.trim_end()));
+        } else {
+            print!("\r{text}");
+            std::io::stdout().flush().ok();
+        }
+    }
+}
+
+fn with_label(label: Option<&str>, text: &str) -> String {
+    match label {
+        Some(l) => format!("[{l}] {text}"),
+        None => text.to_string(),
+    }
+}
+
+fn describe_pitch(note: &str, cents: f32) -> String {
+    // Round to 5 cents so tiny wobbles don't produce a new announcement
+    let c = (cents / 5.0).round() as i32 * 5;
+    match c {
+        0 => format!("{note}, in tune"),
+        c if c > 0 => format!("{note}, {c} cents sharp"),
+        c => format!("{note}, {} cents flat", -c),
+    }
+}
+
+/// Passes on what changed: a pitch when its note differs from the last one
+/// heard, silence only after a note, and every other event. For outputs
+/// that announce notes rather than frames.
+#[derive(Default)]
+pub struct NoteChanges {
+    // Last note per performer ("" for none); None = silence
+    last: HashMap<String, Option<String>>,
+}
+
+impl NoteChanges {
+    pub fn changed(&mut self, performer: Option<&str>, event: &NoteEvent) -> bool {
+        let label = performer.unwrap_or_default();
+        match event {
+            NoteEvent::Pitch { note, .. } => {
+                if self.last.get(label).is_some_and(|l| l.as_ref() == Some(note)) { return false; }
+                self.last.insert(label.to_string(), Some(note.clone()));
+                true
+            }
+            NoteEvent::Silence => self.last.insert(label.to_string(), None).flatten().is_some(),
+            _ => true,
+        }
+    }
+}
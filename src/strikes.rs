@@ -0,0 +1,184 @@
+// ---------------------------- Repeated strikes ----------------------------
+//
+// A note_map key such as "A4 x2" fires when the note is struck twice in a
+// row, like a double-click: each strike within [strikes] within_ms of the one
+// before. "A4 x3" takes three strikes. A note with such mappings doesn't fire
+// on its first strike; it waits to see whether another follows. Its plain
+// mapping fires once the window passes without one, and the highest count
+// fires as soon as it is reached. A strike is the note being recognized anew,
+// after a gap or (with [onsets]) a fresh attack; a note held on doesn't count
+// again.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::Mapping;
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct StrikesConfig {
+    // Longest gap between one strike and the next
+    #[serde(default = "default_within_ms")]
+    pub within_ms: u64,
+}
+
+fn default_within_ms() -> u64 { 400 }
+
+impl Default for StrikesConfig {
+    fn default() -> Self {
+        Self { within_ms: default_within_ms() }
+    }
+}
+
+/// The note and count of a key such as "A4 x2".
+pub fn parse(key: &str) -> Option<(&str, u32)> {
+    let (note, count) = key.trim().rsplit_once(' ')?;
+    let count = count.strip_prefix(['x', 'X', '×'])?.parse().ok()?;
+    Some((note.trim_end(), count))
+}
+
+/// The key for `count` strikes of `note`.
+pub fn key(note: &str, count: u32) -> String { format!("{note} x{count}") }
+
+/// The most strikes anything is mapped to for `note`: 1 when none are.
+pub fn most(map: &HashMap<String, Mapping>, note: &str) -> u32 {
+    map.keys().filter_map(|k| parse(k)).filter(|(n, _)| *n == note).map(|(_, c)| c).fold(1, u32::max)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    // Struck; the trigger loop hasn't looked at it yet
+    Struck,
+    // Waiting for another strike
+    Waiting,
+    // Fired, or ended without a mapping
+    Done,
+}
+
+// Strikes of one note in a row
+struct Run<'a> {
+    note: String,
+    count: u32,
+    last: Instant,
+    state: State,
+    // What to fire if no more strikes come
+    pick: Option<(String, &'a Mapping)>,
+}
+
+/// A mapping a run of strikes came to: its key, the note and the mapping.
+pub type Fire<'a> = (String, String, &'a Mapping);
+
+pub struct StrikeCounter<'a> {
+    within: Duration,
+    run: Option<Run<'a>>,
+}
+
+impl<'a> StrikeCounter<'a> {
+    pub fn new(cfg: &StrikesConfig) -> Self {
+        Self { within: Duration::from_millis(cfg.within_ms), run: None }
+    }
+
+    /// `note` was recognized anew. A run of another note that was waiting
+    /// ends here, and what it came to is returned.
+    pub fn strike(&mut self, note: &str, now: Instant) -> Option<Fire<'a>> {
+        match self.run.as_mut() {
+            Some(r) if r.note == note && r.state != State::Done && now.duration_since(r.last) <= self.within => {
+                r.count += 1;
+                r.last = now;
+                r.state = State::Struck;
+                None
+            }
+            _ => {
+                let ended = self.run.take().and_then(ended);
+                self.run = Some(Run { note: note.to_string(), count: 1, last: now, state: State::Struck, pick: None });
+                ended
+            }
+        }
+    }
+
+    /// How many times `note` has been struck in a row, if the latest strike
+    /// is still to be decided on.
+    pub fn undecided(&self, note: &str) -> Option<u32> {
+        self.run.as_ref().filter(|r| r.note == note && r.state == State::Struck).map(|r| r.count)
+    }
+
+    /// Decide on the latest strike: `pick` is the mapping for the strikes so
+    /// far, fired now when no more can follow (`last`), else once the window
+    /// passes without another strike.
+    pub fn decide(&mut self, pick: Option<(String, &'a Mapping)>, last: bool) -> Option<Fire<'a>> {
+        let r = self.run.as_mut()?;
+        r.pick = pick;
+        r.state = State::Waiting;
+        if last { self.run.take().and_then(ended) } else { None }
+    }
+
+    /// A run whose window has passed without another strike.
+    pub fn due(&mut self, now: Instant) -> Option<Fire<'a>> {
+        let r = self.run.as_ref()?;
+        if r.state != State::Waiting || now.duration_since(r.last) <= self.within { return None; }
+        self.run.take().and_then(ended)
+    }
+}
+
+// What a run comes to when it ends: a waiting run's pick
+fn ended(r: Run) -> Option<Fire> {
+    if r.state != State::Waiting { return None; }
+    match r.pick {
+        Some((key, mapping)) => Some((key, r.note, mapping)),
+        None => {
+            tracing::debug!(note = %r.note, strikes = r.count, "no mapping for this many strikes");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Action;
+
+    #[test]
+    fn keys_name_a_note_and_a_count() {
+        assert_eq!(parse("A4 x2"), Some(("A4", 2)));
+        assert_eq!(parse(" Bb3  ×3 "), Some(("Bb3", 3)));
+        assert_eq!(parse("A4"), None);
+        assert_eq!(parse("A4 xx"), None);
+        let mapping = Mapping::from(Action::TapTempo);
+        let map: HashMap<String, Mapping> = [("A4".to_string(), mapping.clone()), ("A4 x3".to_string(), mapping)].into();
+        assert_eq!(most(&map, "A4"), 3);
+        assert_eq!(most(&map, "B4"), 1);
+    }
+
+    #[test]
+    fn a_double_strike_fires_instead_of_the_single_one() {
+        let single = Mapping::from(Action::Text { text: "one".to_string() });
+        let double = Mapping::from(Action::Text { text: "two".to_string() });
+        let mut counter = StrikeCounter::new(&StrikesConfig::default());
+        let t0 = Instant::now();
+        let ms = |n| t0 + Duration::from_millis(n);
+
+        // Struck once: the single mapping waits out the window
+        assert!(counter.strike("A4", t0).is_none());
+        assert_eq!(counter.undecided("A4"), Some(1));
+        assert!(counter.decide(Some(("A4".to_string(), &single)), false).is_none());
+        assert_eq!(counter.undecided("A4"), None);
+        assert!(counter.due(ms(300)).is_none());
+        let (key, note, _) = counter.due(ms(500)).unwrap();
+        assert_eq!((key.as_str(), note.as_str()), ("A4", "A4"));
+
+        // Struck twice: the double fires at once, and the single never does
+        counter.strike("A4", ms(1000));
+        counter.decide(Some(("A4".to_string(), &single)), false);
+        assert!(counter.strike("A4", ms(1300)).is_none());
+        assert_eq!(counter.undecided("A4"), Some(2));
+        let (key, _, _) = counter.decide(Some(("A4 x2".to_string(), &double)), true).unwrap();
+        assert_eq!(key, "A4 x2");
+        assert!(counter.due(ms(2000)).is_none());
+
+        // Another note ends the wait early
+        counter.strike("A4", ms(3000));
+        counter.decide(Some(("A4".to_string(), &single)), false);
+        let (key, _, _) = counter.strike("B4", ms(3100)).unwrap();
+        assert_eq!(key, "A4");
+    }
+}
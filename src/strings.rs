@@ -0,0 +1,238 @@
+// ---------------------------- String estimation ----------------------------
+//
+// The same pitch can be fretted on several strings. Thicker strings played
+// higher up the neck sound darker (less energy in upper harmonics) and more
+// inharmonic (partials drift sharp of k*f0). We measure both cues from the
+// harmonic series and pick the most likely string among those that can play
+// the note, using per-string calibration data when available.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+use std::path::Path;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct StringsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    // Open-string notes from the 1st (highest) string down
+    #[serde(default = "default_tuning")]
+    pub tuning: Vec<String>,
+    // Highest fret considered playable
+    #[serde(default = "default_frets")]
+    pub frets: i32,
+    // Where `mode = "string-calibration"` stores its measurements
+    #[serde(default = "default_calibration_file")]
+    pub calibration_file: String,
+    // Seconds of playing collected per string during calibration
+    #[serde(default = "default_calibration_secs")]
+    pub calibration_secs: u64,
+}
+
+fn default_tuning() -> Vec<String> {
+    ["E4", "B3", "G3", "D3", "A2", "E2"].iter().map(|s| s.to_string()).collect()
+}
+fn default_frets() -> i32 { 20 }
+fn default_calibration_file() -> String { "string_calibration.toml".to_string() }
+fn default_calibration_secs() -> u64 { 10 }
+
+impl Default for StringsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            tuning: default_tuning(),
+            frets: default_frets(),
+            calibration_file: default_calibration_file(),
+            calibration_secs: default_calibration_secs(),
+        }
+    }
+}
+
+/// Timbre cues for one analysis window.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Features {
+    // Amplitude-weighted mean harmonic number (brightness)
+    pub centroid: f32,
+    // Inharmonicity coefficient B, scaled by 1e4 to keep numbers readable
+    pub inharmonicity: f32,
+}
+
+/// Goertzel magnitude of `x` at `freq`.
+pub fn goertzel(x: &[f32], sample_rate: f32, freq: f32) -> f32 {
+    let w = 2.0 * PI * freq / sample_rate;
+    let coeff = 2.0 * w.cos();
+    let (mut s1, mut s2) = (0.0f32, 0.0f32);
+    for &v in x {
+        let s0 = v + coeff * s1 - s2;
+        s2 = s1;
+        s1 = s0;
+    }
+    (s1 * s1 + s2 * s2 - coeff * s1 * s2).max(0.0).sqrt()
+}
+
+/// Measure brightness and inharmonicity from the first harmonics of `f0`.
+pub fn measure(window: &[f32], sample_rate: f32, f0: f32) -> Option<Features> {
+    const HARMONICS: usize = 10;
+    let n = window.len();
+    if n < 64 { return None; }
+    let x: Vec<f32> = window
+        .iter()
+        .enumerate()
+        .map(|(i, &s)| s * (0.5 - 0.5 * (2.0 * PI * i as f32 / (n as f32 - 1.0)).cos()))
+        .collect();
+    let limit = (sample_rate / 2.0).min(8000.0);
+
+    let mut weighted = 0.0f32;
+    let mut total = 0.0f32;
+    let mut b_sum = 0.0f32;
+    let mut b_weight = 0.0f32;
+    for k in 1..=HARMONICS {
+        let nominal = k as f32 * f0;
+        if nominal * 1.03 >= limit { break; }
+        // Sweep ±3% around k*f0 and take the strongest point as the partial
+        let steps = 24;
+        let (mut peak_f, mut peak_a) = (nominal, 0.0f32);
+        for s in 0..=steps {
+            let f = nominal * (0.97 + 0.06 * s as f32 / steps as f32);
+            let a = goertzel(&x, sample_rate, f);
+            if a > peak_a {
+                peak_a = a;
+                peak_f = f;
+            }
+        }
+        weighted += k as f32 * peak_a;
+        total += peak_a;
+        if k >= 2 {
+            let ratio = peak_f / nominal;
+            b_sum += peak_a * (ratio * ratio - 1.0) / (k * k) as f32;
+            b_weight += peak_a;
+        }
+    }
+    if total <= 1e-9 { return None; }
+    Some(Features {
+        centroid: weighted / total,
+        inharmonicity: if b_weight > 0.0 { 1e4 * b_sum / b_weight } else { 0.0 },
+    })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sample {
+    pub midi: i32,
+    pub features: Features,
+}
+
+/// Calibration measurements keyed by string number (1 = highest string).
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Calibration {
+    pub strings: std::collections::BTreeMap<String, Vec<Sample>>,
+}
+
+impl Calibration {
+    pub fn load(path: &Path) -> Option<Self> {
+        let text = std::fs::read_to_string(path).ok()?;
+        match toml::from_str(&text) {
+            Ok(c) => Some(c),
+            Err(e) => {
+                tracing::warn!("ignoring {}: {e}", path.display());
+                None
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let text = toml::to_string(self).context("Serializing string calibration")?;
+        std::fs::write(path, text).with_context(|| format!("Writing {}", path.display()))
+    }
+
+    // Expected features for `string` at `midi`, from a least-squares line
+    // through that string's samples (timbre changes steadily along the neck)
+    fn predict(&self, string: usize, midi: i32) -> Option<Features> {
+        let samples = self.strings.get(&string.to_string())?;
+        if samples.is_empty() { return None; }
+        let fit = |get: fn(&Features) -> f32| -> f32 {
+            let n = samples.len() as f32;
+            let mx = samples.iter().map(|s| s.midi as f32).sum::<f32>() / n;
+            let my = samples.iter().map(|s| get(&s.features)).sum::<f32>() / n;
+            let sxx: f32 = samples.iter().map(|s| (s.midi as f32 - mx).powi(2)).sum();
+            let sxy: f32 = samples.iter().map(|s| (s.midi as f32 - mx) * (get(&s.features) - my)).sum();
+            let slope = if sxx > 1e-6 { sxy / sxx } else { 0.0 };
+            my + slope * (midi as f32 - mx)
+        };
+        Some(Features { centroid: fit(|f| f.centroid), inharmonicity: fit(|f| f.inharmonicity) })
+    }
+}
+
+pub struct StringEstimator {
+    // Open-string MIDI notes, index 0 = 1st string
+    open: Vec<i32>,
+    frets: i32,
+    calibration: Option<Calibration>,
+}
+
+impl StringEstimator {
+    pub fn new(open: Vec<i32>, frets: i32, calibration: Option<Calibration>) -> Self {
+        Self { open, frets, calibration }
+    }
+
+    /// String numbers (1-based) that can play `midi`, highest string first.
+    pub fn candidates(&self, midi: i32) -> Vec<usize> {
+        self.open
+            .iter()
+            .enumerate()
+            .filter(|(_, &o)| midi >= o && midi <= o + self.frets)
+            .map(|(i, _)| i + 1)
+            .collect()
+    }
+
+    /// Most likely string for `midi` with the measured `features`. Without
+    /// calibration for the candidates, assume the lowest fret position.
+    pub fn estimate(&self, midi: i32, features: Option<Features>) -> Option<usize> {
+        let candidates = self.candidates(midi);
+        let first = *candidates.first()?;
+        let (Some(cal), Some(f)) = (self.calibration.as_ref(), features) else { return Some(first) };
+        candidates
+            .iter()
+            .filter_map(|&s| {
+                let p = cal.predict(s, midi)?;
+                // Centroid spans a few harmonic numbers, B a few units; weight similarly
+                let d = (f.centroid - p.centroid).powi(2) + (0.5 * (f.inharmonicity - p.inharmonicity)).powi(2);
+                Some((s, d))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(s, _)| s)
+            .or(Some(first))
+    }
+}
+
+/// Canonical note_map key for a note on a string, e.g. "E3@5".
+pub fn string_key(note: &str, string: usize) -> String {
+    format!("{note}@{string}")
+}
+
+/// Normalize "E3@5th_string" / "E3@5th" / "E3@5" to "E3@5", keeping any
+/// ":qualifier" and "+modifier" suffixes; other keys unchanged.
+pub fn normalize_key(key: &str) -> String {
+    let (rest, modifier) = match key.split_once('+') {
+        Some((r, m)) if !m.trim().is_empty() => (r, format!("+{}", m.trim())),
+        _ => (key, String::new()),
+    };
+    let (base, qualifier) = match rest.split_once(':') {
+        Some((b, q)) => (b, format!(":{}", q.trim())),
+        None => (rest, String::new()),
+    };
+    let Some((note, string)) = base.split_once('@') else { return key.to_string() };
+    let digits: String = string.trim().chars().take_while(|c| c.is_ascii_digit()).collect();
+    if digits.is_empty() { key.to_string() } else { format!("{}@{}{}{}", note.trim(), digits, qualifier, modifier) }
+}
+
+/// "1st", "2nd", "3rd", "4th", ...
+pub fn ordinal(n: usize) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (1, 11) | (2, 12) | (3, 13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{n}{suffix}")
+}
@@ -0,0 +1,120 @@
+// ---------------------------- Tempo tracking ----------------------------
+//
+// Estimates the player's tempo from inter-onset intervals. Intervals between
+// each onset and its few predecessors are folded into the BPM range (so
+// eighth notes and half notes still vote for the beat) and the densest
+// cluster wins. The metronome can follow the result.
+
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TempoConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    // Range the estimate is folded into
+    #[serde(default = "default_min_bpm")]
+    pub min_bpm: f32,
+    #[serde(default = "default_max_bpm")]
+    pub max_bpm: f32,
+    // Level jump (ratio over the recent minimum) that counts as an attack
+    #[serde(default = "default_onset_ratio")]
+    pub onset_ratio: f32,
+    // Seconds of onsets considered
+    #[serde(default = "default_window_secs")]
+    pub window_secs: f32,
+}
+
+fn default_min_bpm() -> f32 { 60.0 }
+fn default_max_bpm() -> f32 { 180.0 }
+fn default_onset_ratio() -> f32 { 2.0 }
+fn default_window_secs() -> f32 { 8.0 }
+
+impl Default for TempoConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_bpm: default_min_bpm(),
+            max_bpm: default_max_bpm(),
+            onset_ratio: default_onset_ratio(),
+            window_secs: default_window_secs(),
+        }
+    }
+}
+
+// Onsets needed before a tempo is reported
+const MIN_ONSETS: usize = 4;
+// How many predecessors each onset is paired with
+const SPAN: usize = 4;
+// Candidates within this relative distance support each other
+const CLUSTER: f32 = 0.03;
+// Share of the total vote the winning cluster needs
+const MIN_CONFIDENCE: f32 = 0.3;
+
+pub struct TempoTracker {
+    min_bpm: f32,
+    max_bpm: f32,
+    window: Duration,
+    onsets: VecDeque<Instant>,
+    bpm: Option<f32>,
+}
+
+impl TempoTracker {
+    pub fn new(cfg: &TempoConfig) -> Self {
+        let min_bpm = cfg.min_bpm.max(1.0);
+        Self {
+            min_bpm,
+            // The range must span an octave for folding to land in it
+            max_bpm: cfg.max_bpm.max(2.0 * min_bpm),
+            window: Duration::from_secs_f32(cfg.window_secs.max(1.0)),
+            onsets: VecDeque::new(),
+            bpm: None,
+        }
+    }
+
+    /// Record an attack and re-estimate the tempo.
+    pub fn onset(&mut self, now: Instant) {
+        self.onsets.push_back(now);
+        while self.onsets.front().is_some_and(|t| now.duration_since(*t) > self.window) {
+            self.onsets.pop_front();
+        }
+        self.bpm = self.estimate();
+    }
+
+    /// Current tempo estimate; None until enough regular onsets were heard
+    /// (or after the player stopped for a whole window).
+    pub fn bpm(&self, now: Instant) -> Option<f32> {
+        let recent = self.onsets.back().is_some_and(|t| now.duration_since(*t) <= self.window);
+        self.bpm.filter(|_| recent)
+    }
+
+    fn estimate(&self) -> Option<f32> {
+        if self.onsets.len() < MIN_ONSETS { return None; }
+        let times: Vec<Instant> = self.onsets.iter().copied().collect();
+        // (bpm, weight): closer neighbours are more likely to be one beat apart
+        let mut votes = Vec::new();
+        for j in 1..times.len() {
+            for k in 1..=SPAN.min(j) {
+                let secs = times[j].duration_since(times[j - k]).as_secs_f32();
+                if secs <= 0.0 { continue; }
+                let mut bpm = 60.0 / secs;
+                while bpm < self.min_bpm { bpm *= 2.0; }
+                while bpm > self.max_bpm { bpm /= 2.0; }
+                if bpm >= self.min_bpm { votes.push((bpm, 1.0 / k as f32)); }
+            }
+        }
+        let total: f32 = votes.iter().map(|v| v.1).sum();
+        let support = |c: f32| -> (f32, f32) {
+            votes
+                .iter()
+                .filter(|(b, _)| (b - c).abs() <= CLUSTER * c)
+                .fold((0.0, 0.0), |(w, s), &(b, v)| (w + v, s + b * v))
+        };
+        let (weight, sum) = votes
+            .iter()
+            .map(|&(c, _)| support(c))
+            .max_by(|a, b| a.0.total_cmp(&b.0))?;
+        (total > 0.0 && weight / total >= MIN_CONFIDENCE).then(|| sum / weight)
+    }
+}
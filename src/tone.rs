@@ -0,0 +1,182 @@
+// ---------------------------- Tone output ----------------------------
+//
+// Plays short reference tones and metronome clicks through the default output
+// device. The audio callback renders whatever voice is currently armed; `play`
+// just swaps in a new voice, so the caller never blocks on the audio thread.
+// Clicks are scheduled sample-accurately inside the callback.
+
+use anyhow::{anyhow, Context, Result};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use std::f32::consts::PI;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// Fade in/out length to avoid clicks at tone boundaries
+const FADE_SECS: f32 = 0.01;
+
+#[derive(Default)]
+struct Voice {
+    freq: f32,
+    volume: f32,
+    phase: f32,
+    // Samples rendered so far and total length of the tone
+    pos: usize,
+    len: usize,
+}
+
+impl Voice {
+    fn next_sample(&mut self, sample_rate: f32) -> f32 {
+        if self.pos >= self.len { return 0.0; }
+        let fade = (FADE_SECS * sample_rate).max(1.0);
+        let remaining = (self.len - self.pos) as f32;
+        let env = (self.pos as f32 / fade).min(1.0).min(remaining / fade);
+        let s = (2.0 * PI * self.phase).sin() * self.volume * env;
+        self.phase = (self.phase + self.freq / sample_rate).fract();
+        self.pos += 1;
+        s
+    }
+}
+
+// Regular click train; the first tick of each bar is accented
+struct ClickTrack {
+    samples_per_tick: f64,
+    ticks_per_bar: u32,
+    ticks_per_beat: u32,
+    volume: f32,
+    // Sample position within the track and index of the next tick
+    pos: f64,
+    next_tick: u64,
+    burst: Voice,
+}
+
+impl ClickTrack {
+    fn next_sample(&mut self, sample_rate: f32) -> f32 {
+        if self.pos >= self.next_tick as f64 * self.samples_per_tick {
+            let tick = self.next_tick;
+            self.next_tick += 1;
+            let (freq, gain) = if tick.is_multiple_of(self.ticks_per_bar as u64) {
+                (1760.0, 1.0)
+            } else if tick.is_multiple_of(self.ticks_per_beat as u64) {
+                (1320.0, 0.8)
+            } else {
+                (990.0, 0.5)
+            };
+            self.burst = Voice {
+                freq,
+                volume: self.volume * gain,
+                phase: 0.0,
+                pos: 0,
+                len: (0.03 * sample_rate) as usize,
+            };
+        }
+        self.pos += 1.0;
+        self.burst.next_sample(sample_rate)
+    }
+}
+
+#[derive(Default)]
+struct Mixer {
+    voice: Voice,
+    click: Option<ClickTrack>,
+}
+
+impl Mixer {
+    fn next_sample(&mut self, sample_rate: f32) -> f32 {
+        let click = self.click.as_mut().map_or(0.0, |c| c.next_sample(sample_rate));
+        (self.voice.next_sample(sample_rate) + click).clamp(-1.0, 1.0)
+    }
+}
+
+pub struct ToneOutput {
+    mixer: Arc<Mutex<Mixer>>,
+    sample_rate: f32,
+    _stream: cpal::Stream, // keep stream alive
+}
+
+impl ToneOutput {
+    pub fn open() -> Result<Self> {
+        let host = cpal::default_host();
+        let device = host
+            .default_output_device()
+            .ok_or_else(|| anyhow!("No default output device"))?;
+        let config = device
+            .default_output_config()
+            .context("Failed to get default output config")?;
+        let sample_rate = config.sample_rate().0 as f32;
+        let mixer = Arc::new(Mutex::new(Mixer::default()));
+
+        let stream = match config.sample_format() {
+            cpal::SampleFormat::F32 => build_output::<f32>(&device, &config.into(), mixer.clone())?,
+            cpal::SampleFormat::I16 => build_output::<i16>(&device, &config.into(), mixer.clone())?,
+            cpal::SampleFormat::U16 => build_output::<u16>(&device, &config.into(), mixer.clone())?,
+            other => return Err(anyhow!("Unsupported output sample format: {:?}", other)),
+        };
+        stream.play().context("Failed to start output stream")?;
+
+        Ok(Self { mixer, sample_rate, _stream: stream })
+    }
+
+    /// Start a sine tone, replacing anything still playing. Returns immediately.
+    pub fn play(&self, freq: f32, duration: Duration, volume: f32) {
+        let mut m = self.mixer.lock().unwrap_or_else(|e| e.into_inner());
+        m.voice = Voice {
+            freq,
+            volume: volume.clamp(0.0, 1.0),
+            phase: 0.0,
+            pos: 0,
+            len: (duration.as_secs_f32() * self.sample_rate) as usize,
+        };
+    }
+
+    /// Start a click track at `bpm` with `subdivision` clicks per beat. The first
+    /// click sounds immediately.
+    pub fn start_clicks(&self, bpm: f32, beats_per_bar: u32, subdivision: u32, volume: f32) {
+        let ticks_per_beat = subdivision.max(1);
+        let mut m = self.mixer.lock().unwrap_or_else(|e| e.into_inner());
+        m.click = Some(ClickTrack {
+            samples_per_tick: 60.0 / bpm.max(1.0) as f64 * self.sample_rate as f64 / ticks_per_beat as f64,
+            ticks_per_bar: beats_per_bar.max(1) * ticks_per_beat,
+            ticks_per_beat,
+            volume: volume.clamp(0.0, 1.0),
+            pos: 0.0,
+            next_tick: 0,
+            burst: Voice::default(),
+        });
+    }
+
+    /// Retune a running click track, keeping its position within the current tick.
+    pub fn set_click_bpm(&self, bpm: f32) {
+        let mut m = self.mixer.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(c) = m.click.as_mut() {
+            let per_tick = 60.0 / bpm.max(1.0) as f64 * self.sample_rate as f64 / c.ticks_per_beat as f64;
+            c.pos *= per_tick / c.samples_per_tick;
+            c.samples_per_tick = per_tick;
+        }
+    }
+}
+
+fn build_output<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    mixer: Arc<Mutex<Mixer>>,
+) -> Result<cpal::Stream>
+where
+    T: cpal::SizedSample + cpal::FromSample<f32>,
+{
+    let channels = config.channels as usize;
+    let sample_rate = config.sample_rate.0 as f32;
+    let err_fn = |err| tracing::error!("Output stream error: {err}");
+    let stream = device.build_output_stream(
+        config,
+        move |data: &mut [T], _| {
+            let mut m = mixer.lock().unwrap_or_else(|e| e.into_inner());
+            for frame in data.chunks_mut(channels) {
+                let s = T::from_sample(m.next_sample(sample_rate));
+                for out in frame.iter_mut() { *out = s; }
+            }
+        },
+        err_fn,
+        None,
+    )?;
+    Ok(stream)
+}
@@ -0,0 +1,87 @@
+// ---------------------------- Mapping trainer ----------------------------
+//
+// Drills the note_map vocabulary: prompts a random mapping, times how long it
+// takes to play the right note, and reports the slowest/least accurate ones.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Prompt {
+    // Show the note name to play
+    #[default]
+    Note,
+    // Show the action and expect its note (tests recall of the mapping)
+    Action,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TrainerConfig {
+    #[serde(default)]
+    pub prompt: Prompt,
+    // Number of prompts (0 = endless)
+    #[serde(default = "default_rounds")]
+    pub rounds: u32,
+    // Time allowed per prompt
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_rounds() -> u32 { 30 }
+fn default_timeout_secs() -> u64 { 5 }
+
+impl Default for TrainerConfig {
+    fn default() -> Self {
+        Self {
+            prompt: Prompt::default(),
+            rounds: default_rounds(),
+            timeout_secs: default_timeout_secs(),
+        }
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+struct MappingStats {
+    attempts: u32,
+    hits: u32,
+    total_reaction: Duration,
+}
+
+#[derive(Default)]
+pub struct TrainerStats {
+    per_note: HashMap<String, MappingStats>,
+}
+
+impl TrainerStats {
+    /// Record a prompt outcome; `reaction` is Some only for correct answers.
+    pub fn record(&mut self, note: &str, reaction: Option<Duration>) {
+        let st = self.per_note.entry(note.to_string()).or_default();
+        st.attempts += 1;
+        if let Some(t) = reaction {
+            st.hits += 1;
+            st.total_reaction += t;
+        }
+    }
+
+    /// Table of mappings, slowest (and least accurate) first.
+    pub fn report(&self, describe: impl Fn(&str) -> String) -> String {
+        let mut rows: Vec<(&String, &MappingStats)> = self.per_note.iter().collect();
+        let avg_ms = |st: &MappingStats| {
+            if st.hits == 0 { f64::INFINITY } else { st.total_reaction.as_secs_f64() * 1000.0 / st.hits as f64 }
+        };
+        rows.sort_by(|a, b| {
+            let acc = |st: &MappingStats| st.hits as f64 / st.attempts.max(1) as f64;
+            acc(a.1)
+                .total_cmp(&acc(b.1))
+                .then(avg_ms(b.1).total_cmp(&avg_ms(a.1)))
+        });
+        let mut out = String::from("Note  Hits  Avg ms  Action\n");
+        for (note, st) in rows {
+            let avg = if st.hits == 0 { "-".to_string() } else { format!("{:.0}", avg_ms(st)) };
+            out.push_str(&format!("{:<4} {:>2}/{:<2} {:>6}  {}\n", crate::notation::spell(note), st.hits, st.attempts, avg, describe(note)));
+        }
+        out
+    }
+}
@@ -0,0 +1,386 @@
+// ---------------------------- System tray ----------------------------
+//
+// With [tray] enabled (or --tray), trigger mode puts an icon in the system
+// tray that shows whether it is listening, with a menu to pause and resume,
+// pick a profile, reload the config and quit. Paused, the audio is still
+// read but nothing is detected or triggered. The icon is a
+// StatusNotifierItem on Linux desktops and a notification-area icon on
+// Windows, where the console window can be hidden; macOS has no tray yet.
+// The trigger loop and the tray only share the state below: the loop
+// reports its profiles, the menu sets the flags. The control port can set
+// them too.
+
+use serde::Deserialize;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::{profiles, reload};
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TrayConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    // Windows: hide the console window while the tray icon is there
+    #[cfg_attr(not(windows), allow(dead_code))]
+    #[serde(default = "default_hide_console")]
+    pub hide_console: bool,
+}
+
+fn default_hide_console() -> bool { true }
+
+impl Default for TrayConfig {
+    fn default() -> Self {
+        Self { enabled: false, hide_console: default_hide_console() }
+    }
+}
+
+static PAUSED: AtomicBool = AtomicBool::new(false);
+static QUIT: AtomicBool = AtomicBool::new(false);
+// Profiles of the running config and the active one, for the menu
+static PROFILES: Mutex<(Vec<String>, Option<String>)> = Mutex::new((Vec::new(), None));
+// Bumped on every change the icon should show
+static CHANGES: AtomicU64 = AtomicU64::new(0);
+
+/// Detection is paused from the tray.
+pub fn paused() -> bool {
+    PAUSED.load(Ordering::Relaxed)
+}
+
+/// Quit was chosen from the tray.
+pub fn quitting() -> bool {
+    QUIT.load(Ordering::Relaxed)
+}
+
+/// The profiles the menu offers, after a (re)load.
+pub fn set_profiles<'a>(names: impl IntoIterator<Item = &'a String>) {
+    let mut p = PROFILES.lock().unwrap_or_else(|e| e.into_inner());
+    p.0 = names.into_iter().cloned().collect();
+    p.1 = None;
+    CHANGES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// The profile now in use (None = the top-level note_map).
+pub fn set_profile(active: Option<&str>) {
+    PROFILES.lock().unwrap_or_else(|e| e.into_inner()).1 = active.map(str::to_string);
+    CHANGES.fetch_add(1, Ordering::Relaxed);
+}
+
+fn toggle_pause() {
+    PAUSED.fetch_xor(true, Ordering::Relaxed);
+    CHANGES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Pause or resume detection, as the menu does.
+pub fn set_paused(paused: bool) {
+    PAUSED.store(paused, Ordering::Relaxed);
+    CHANGES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// The running config's profiles and the active one.
+pub fn profiles() -> (Vec<String>, Option<String>) {
+    PROFILES.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+// The trigger loop stops at its next frame; if it is stuck waiting for
+// audio, the process ends anyway
+fn quit() {
+    QUIT.store(true, Ordering::Relaxed);
+    std::thread::spawn(|| {
+        std::thread::sleep(std::time::Duration::from_secs(2));
+        std::process::exit(0);
+    });
+}
+
+// What the icon shows, taken from the shared state
+struct Shown {
+    paused: bool,
+    profiles: Vec<String>,
+    active: Option<String>,
+    reloadable: bool,
+}
+
+impl Shown {
+    fn now() -> Self {
+        let (profiles, active) = profiles();
+        Self { paused: paused(), profiles, active, reloadable: reload::watching() }
+    }
+
+    fn summary(&self) -> String {
+        let state = if self.paused { "Paused" } else { "Listening" };
+        match &self.active {
+            Some(p) => format!("{state} (profile {p})"),
+            None => state.to_string(),
+        }
+    }
+
+    // The active profile's place in the menu; 0 is the default
+    fn selected(&self) -> usize {
+        self.active.as_ref().and_then(|a| self.profiles.iter().position(|p| p == a)).map_or(0, |i| i + 1)
+    }
+}
+
+// Menu choice of profile `i`, counting the default as 0
+fn pick_profile(profiles: &[String], i: usize) {
+    let name = i.checked_sub(1).and_then(|i| profiles.get(i)).cloned();
+    profiles::request(profiles::Switch::To(name));
+}
+
+/// Show the tray icon, if enabled. Without a tray to show it in, trigger
+/// mode carries on in the console.
+pub fn start(cfg: &TrayConfig) {
+    if !cfg.enabled { return; }
+    if let Err(e) = show(cfg) { tracing::warn!("no tray icon: {e:#}"); }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn show(_cfg: &TrayConfig) -> anyhow::Result<()> {
+    use ksni::blocking::TrayMethods;
+    use ksni::menu::{CheckmarkItem, RadioGroup, RadioItem, StandardItem, SubMenu};
+    use ksni::MenuItem;
+
+    struct Icon(Shown);
+
+    impl ksni::Tray for Icon {
+        fn id(&self) -> String {
+            env!("CARGO_PKG_NAME").into()
+        }
+
+        fn title(&self) -> String {
+            "Rusty Strings Control".into()
+        }
+
+        fn icon_name(&self) -> String {
+            if self.0.paused { "microphone-sensitivity-muted" } else { "audio-input-microphone" }.into()
+        }
+
+        fn tool_tip(&self) -> ksni::ToolTip {
+            ksni::ToolTip { title: self.title(), description: self.0.summary(), ..Default::default() }
+        }
+
+        fn menu(&self) -> Vec<MenuItem<Self>> {
+            let mut options = vec![RadioItem { label: "(default)".into(), ..Default::default() }];
+            options.extend(self.0.profiles.iter().map(|p| RadioItem { label: p.clone(), ..Default::default() }));
+            let profiles = self.0.profiles.clone();
+            vec![
+                StandardItem { label: self.0.summary(), enabled: false, ..Default::default() }.into(),
+                MenuItem::Separator,
+                CheckmarkItem {
+                    label: "Paused".into(),
+                    checked: self.0.paused,
+                    activate: Box::new(|_| toggle_pause()),
+                    ..Default::default()
+                }
+                .into(),
+                SubMenu {
+                    label: "Profile".into(),
+                    enabled: !self.0.profiles.is_empty(),
+                    submenu: vec![RadioGroup {
+                        selected: self.0.selected(),
+                        select: Box::new(move |_, i| pick_profile(&profiles, i)),
+                        options,
+                    }
+                    .into()],
+                    ..Default::default()
+                }
+                .into(),
+                StandardItem {
+                    label: "Reload config".into(),
+                    enabled: self.0.reloadable,
+                    activate: Box::new(|_| reload::request()),
+                    ..Default::default()
+                }
+                .into(),
+                MenuItem::Separator,
+                StandardItem { label: "Quit".into(), activate: Box::new(|_| quit()), ..Default::default() }.into(),
+            ]
+        }
+    }
+
+    let mut seen = CHANGES.load(Ordering::Relaxed);
+    let handle = Icon(Shown::now()).spawn().map_err(|e| anyhow::anyhow!("{e}"))?;
+    // Redraw whenever the shared state changes
+    std::thread::spawn(move || {
+        while !handle.is_closed() {
+            std::thread::sleep(std::time::Duration::from_millis(200));
+            let now = CHANGES.load(Ordering::Relaxed);
+            if now != seen {
+                seen = now;
+                handle.update(|icon| icon.0 = Shown::now());
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn show(_cfg: &TrayConfig) -> anyhow::Result<()> {
+    Err(anyhow::anyhow!("the menu bar isn't supported on macOS yet"))
+}
+
+#[cfg(windows)]
+fn show(cfg: &TrayConfig) -> anyhow::Result<()> {
+    if cfg.hide_console {
+        use windows_sys::Win32::System::Console::GetConsoleWindow;
+        use windows_sys::Win32::UI::WindowsAndMessaging::{ShowWindow, SW_HIDE};
+        // SAFETY: GetConsoleWindow returns 0 or this process's console window
+        unsafe {
+            let console = GetConsoleWindow();
+            if console != 0 { ShowWindow(console, SW_HIDE); }
+        }
+    }
+    // The window and its message loop live on their own thread
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || windows::run(tx));
+    rx.recv().map_err(|_| anyhow::anyhow!("the tray thread stopped"))?
+}
+
+#[cfg(windows)]
+mod windows {
+    use super::{pick_profile, quit, toggle_pause, Shown, CHANGES};
+    use crate::reload;
+    use std::sync::atomic::Ordering;
+    use std::sync::mpsc::Sender;
+    use windows_sys::Win32::Foundation::{HWND, LPARAM, LRESULT, POINT, WPARAM};
+    use windows_sys::Win32::System::LibraryLoader::GetModuleHandleW;
+    use windows_sys::Win32::UI::Shell::{
+        Shell_NotifyIconW, NIF_ICON, NIF_MESSAGE, NIF_TIP, NIM_ADD, NIM_DELETE, NIM_MODIFY, NOTIFYICONDATAW,
+    };
+    use windows_sys::Win32::UI::WindowsAndMessaging::{
+        AppendMenuW, CreatePopupMenu, CreateWindowExW, DefWindowProcW, DestroyMenu, DispatchMessageW, GetCursorPos,
+        GetMessageW, LoadIconW, PostQuitMessage, RegisterClassW, SetForegroundWindow, SetTimer, TrackPopupMenu,
+        TranslateMessage, IDI_APPLICATION, MF_CHECKED, MF_GRAYED, MF_POPUP, MF_SEPARATOR, MF_STRING, MSG,
+        TPM_RETURNCMD, TPM_RIGHTBUTTON, WM_APP, WM_DESTROY, WM_LBUTTONUP, WM_RBUTTONUP, WM_TIMER, WNDCLASSW,
+    };
+
+    const CALLBACK: u32 = WM_APP + 1;
+    // Menu command ids; profiles follow PROFILE (the default first)
+    const PAUSE: usize = 1;
+    const RELOAD: usize = 2;
+    const QUIT: usize = 3;
+    const PROFILE: usize = 100;
+
+    fn wide(s: &str) -> Vec<u16> {
+        s.encode_utf16().chain(Some(0)).collect()
+    }
+
+    // Icon data for `hwnd` with the current tooltip
+    fn icon_data(hwnd: HWND, shown: &Shown) -> NOTIFYICONDATAW {
+        // SAFETY: NOTIFYICONDATAW is plain data; all-zero is its empty value
+        let mut data: NOTIFYICONDATAW = unsafe { std::mem::zeroed() };
+        data.cbSize = std::mem::size_of::<NOTIFYICONDATAW>() as u32;
+        data.hWnd = hwnd;
+        data.uID = 1;
+        data.uFlags = NIF_ICON | NIF_MESSAGE | NIF_TIP;
+        data.uCallbackMessage = CALLBACK;
+        // SAFETY: a stock icon, loaded without a module
+        data.hIcon = unsafe { LoadIconW(0, IDI_APPLICATION) };
+        let tip = wide(&format!("Rusty Strings Control: {}", shown.summary()));
+        let n = tip.len().min(data.szTip.len() - 1);
+        data.szTip[..n].copy_from_slice(&tip[..n]);
+        data
+    }
+
+    pub fn run(ready: Sender<anyhow::Result<()>>) {
+        let class = wide("RustyStringsControlTray");
+        // SAFETY: the class and window are created, used and destroyed on
+        // this thread; the wide strings outlive the calls that read them
+        unsafe {
+            let instance = GetModuleHandleW(std::ptr::null());
+            let mut wc: WNDCLASSW = std::mem::zeroed();
+            wc.lpfnWndProc = Some(window_proc);
+            wc.hInstance = instance;
+            wc.lpszClassName = class.as_ptr();
+            RegisterClassW(&wc);
+            let title = wide("Rusty Strings Control");
+            let hwnd = CreateWindowExW(
+                0,
+                class.as_ptr(),
+                title.as_ptr(),
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+                instance,
+                std::ptr::null(),
+            );
+            if hwnd == 0 {
+                let _ = ready.send(Err(anyhow::anyhow!("can't create the tray window")));
+                return;
+            }
+            if Shell_NotifyIconW(NIM_ADD, &icon_data(hwnd, &Shown::now())) == 0 {
+                let _ = ready.send(Err(anyhow::anyhow!("the notification area refused the icon")));
+                return;
+            }
+            // Redraw whenever the shared state changes
+            SetTimer(hwnd, 1, 200, None);
+            let _ = ready.send(Ok(()));
+            let mut msg: MSG = std::mem::zeroed();
+            while GetMessageW(&mut msg, 0, 0, 0) > 0 {
+                TranslateMessage(&msg);
+                DispatchMessageW(&msg);
+            }
+            Shell_NotifyIconW(NIM_DELETE, &icon_data(hwnd, &Shown::now()));
+        }
+    }
+
+    unsafe extern "system" fn window_proc(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+        static SEEN: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+        match msg {
+            CALLBACK if matches!(lparam as u32, WM_LBUTTONUP | WM_RBUTTONUP) => {
+                show_menu(hwnd);
+                0
+            }
+            WM_TIMER => {
+                let now = CHANGES.load(Ordering::Relaxed);
+                if SEEN.swap(now, Ordering::Relaxed) != now {
+                    Shell_NotifyIconW(NIM_MODIFY, &icon_data(hwnd, &Shown::now()));
+                }
+                0
+            }
+            WM_DESTROY => {
+                PostQuitMessage(0);
+                0
+            }
+            _ => DefWindowProcW(hwnd, msg, wparam, lparam),
+        }
+    }
+
+    // Pop up the menu at the pointer and carry out the choice
+    unsafe fn show_menu(hwnd: HWND) {
+        let shown = Shown::now();
+        let menu = CreatePopupMenu();
+        let profiles = CreatePopupMenu();
+        let names = std::iter::once("(default)".to_string()).chain(shown.profiles.iter().cloned());
+        for (i, name) in names.enumerate() {
+            let checked = if i == shown.selected() { MF_CHECKED } else { 0 };
+            AppendMenuW(profiles, MF_STRING | checked, PROFILE + i, wide(&name).as_ptr());
+        }
+        AppendMenuW(menu, MF_STRING | MF_GRAYED, 0, wide(&shown.summary()).as_ptr());
+        AppendMenuW(menu, MF_SEPARATOR, 0, std::ptr::null());
+        let paused = if shown.paused { MF_CHECKED } else { 0 };
+        AppendMenuW(menu, MF_STRING | paused, PAUSE, wide("Paused").as_ptr());
+        let grayed = if shown.profiles.is_empty() { MF_GRAYED } else { 0 };
+        AppendMenuW(menu, MF_POPUP | grayed, profiles as usize, wide("Profile").as_ptr());
+        let grayed = if shown.reloadable { 0 } else { MF_GRAYED };
+        AppendMenuW(menu, MF_STRING | grayed, RELOAD, wide("Reload config").as_ptr());
+        AppendMenuW(menu, MF_SEPARATOR, 0, std::ptr::null());
+        AppendMenuW(menu, MF_STRING, QUIT, wide("Quit").as_ptr());
+
+        let mut at = POINT { x: 0, y: 0 };
+        GetCursorPos(&mut at);
+        // Without this the menu stays open when clicking elsewhere
+        SetForegroundWindow(hwnd);
+        let chosen = TrackPopupMenu(menu, TPM_RETURNCMD | TPM_RIGHTBUTTON, at.x, at.y, 0, hwnd, std::ptr::null()) as usize;
+        DestroyMenu(menu);
+        match chosen {
+            PAUSE => toggle_pause(),
+            RELOAD => reload::request(),
+            QUIT => quit(),
+            i if i >= PROFILE => pick_profile(&shown.profiles, i - PROFILE),
+            _ => {}
+        }
+    }
+}
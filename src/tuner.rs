@@ -0,0 +1,209 @@
+// ---------------------------- Tuner display ----------------------------
+//
+// The `tuner` command draws the nearest note in large letters above a needle
+// on a ±50 cent scale, redrawn in place with ANSI cursor movement. Within
+// in_tune_cents the note and needle turn green, otherwise red. The needle
+// follows the average of the last few frames of the same note so it
+// doesn't jitter. Accessible and verbose output keep their plain lines.
+
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::io::Write;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TunerConfig {
+    // Within this many cents counts as in tune
+    #[serde(default = "default_in_tune_cents")]
+    pub in_tune_cents: f32,
+    // Frames the needle is averaged over (1 = every frame as it comes)
+    #[serde(default = "default_average_frames")]
+    pub average_frames: usize,
+    // Columns of the cents scale
+    #[serde(default = "default_width")]
+    pub width: usize,
+}
+
+fn default_in_tune_cents() -> f32 { 5.0 }
+fn default_average_frames() -> usize { 4 }
+fn default_width() -> usize { 61 }
+
+impl Default for TunerConfig {
+    fn default() -> Self {
+        Self {
+            in_tune_cents: default_in_tune_cents(),
+            average_frames: default_average_frames(),
+            width: default_width(),
+        }
+    }
+}
+
+// 5x5 letters for the big note name; '#' is a lit cell
+const GLYPH_ROWS: usize = 5;
+const GLYPHS: [(char, [&str; GLYPH_ROWS]); 20] = [
+    ('A', [" ### ", "#   #", "#####", "#   #", "#   #"]),
+    ('B', ["#### ", "#   #", "#### ", "#   #", "#### "]),
+    ('C', [" ####", "#    ", "#    ", "#    ", " ####"]),
+    ('D', ["#### ", "#   #", "#   #", "#   #", "#### "]),
+    ('E', ["#####", "#    ", "#### ", "#    ", "#####"]),
+    ('F', ["#####", "#    ", "#### ", "#    ", "#    "]),
+    ('G', [" ####", "#    ", "#  ##", "#   #", " ####"]),
+    ('#', [" # # ", "#####", " # # ", "#####", " # # "]),
+    ('b', ["#    ", "#    ", "#### ", "#   #", "#### "]),
+    ('-', ["     ", "     ", "#####", "     ", "     "]),
+    ('0', [" ### ", "#   #", "#   #", "#   #", " ### "]),
+    ('1', ["  #  ", " ##  ", "  #  ", "  #  ", " ### "]),
+    ('2', [" ### ", "#   #", "  ## ", " #   ", "#####"]),
+    ('3', ["#### ", "    #", " ### ", "    #", "#### "]),
+    ('4', ["#   #", "#   #", "#####", "    #", "    #"]),
+    ('5', ["#####", "#    ", "#### ", "    #", "#### "]),
+    ('6', [" ### ", "#    ", "#### ", "#   #", " ### "]),
+    ('7', ["#####", "    #", "   # ", "  #  ", "  #  "]),
+    ('8', [" ### ", "#   #", " ### ", "#   #", " ### "]),
+    ('9', [" ### ", "#   #", " ####", "    #", " ### "]),
+];
+
+// Width of the "-50 " in front of the scale
+const SCALE_LABEL: usize = 4;
+
+const GREEN: &str = "\x1b[32m";
+const RED: &str = "\x1b[31m";
+const RESET: &str = "\x1b[0m";
+
+pub struct TunerDisplay {
+    cfg: TunerConfig,
+    // Lines written by the last draw, to move back over
+    drawn: usize,
+    // Cents of the recent frames of the note shown
+    recent: VecDeque<f32>,
+    note: Option<String>,
+}
+
+impl TunerDisplay {
+    pub fn new(cfg: &TunerConfig) -> Self {
+        enable_ansi();
+        Self { cfg: cfg.clone(), drawn: 0, recent: VecDeque::new(), note: None }
+    }
+
+    /// Redraw for this frame: the pitch, its (spelled) note name and cents
+    /// off, or None when nothing is heard.
+    pub fn show(&mut self, reading: Option<(f32, &str, f32)>) {
+        let lines = match reading {
+            Some((f0, note, cents)) => {
+                if self.note.as_deref() != Some(note) {
+                    self.note = Some(note.to_string());
+                    self.recent.clear();
+                }
+                self.recent.push_back(cents);
+                if self.recent.len() > self.cfg.average_frames.max(1) { self.recent.pop_front(); }
+                let cents = self.recent.iter().sum::<f32>() / self.recent.len() as f32;
+                render(&self.cfg, Some((f0, note, cents)))
+            }
+            None => {
+                self.note = None;
+                self.recent.clear();
+                render(&self.cfg, None)
+            }
+        };
+        let mut out = std::io::stdout().lock();
+        if self.drawn > 0 { write!(out, "\x1b[{}A", self.drawn).ok(); }
+        for line in &lines {
+            writeln!(out, "\r\x1b[2K{line}").ok();
+        }
+        out.flush().ok();
+        self.drawn = lines.len();
+    }
+}
+
+// The display as lines: big note name, reading, scale and needle
+fn render(cfg: &TunerConfig, reading: Option<(f32, &str, f32)>) -> Vec<String> {
+    let width = cfg.width.max(21) | 1; // odd, so there is a centre column
+    let centre = width / 2;
+    let mut lines = Vec::new();
+    let Some((f0, note, cents)) = reading else {
+        lines.extend(std::iter::repeat_n(String::new(), GLYPH_ROWS));
+        lines.push("  (no pitch)".to_string());
+        lines.push(format!("  {}", scale(width)));
+        lines.push(String::new());
+        return lines;
+    };
+    let in_tune = cents.abs() <= cfg.in_tune_cents;
+    let colour = if in_tune { GREEN } else { RED };
+    for row in big(note) {
+        lines.push(format!("  {colour}{row}{RESET}"));
+    }
+    let verdict = match cents {
+        _ if in_tune => "in tune",
+        c if c > 0.0 => "sharp",
+        _ => "flat",
+    };
+    lines.push(format!("  {f0:7.1} Hz  {cents:+4.0} cents  {colour}{verdict}{RESET}"));
+    lines.push(format!("  {}", scale(width)));
+    // ±50 cents across the scale; further out pins the needle to the end
+    let offset = (cents.clamp(-50.0, 50.0) / 50.0 * centre as f32).round() as isize;
+    let at = (centre as isize + offset) as usize;
+    lines.push(format!("  {}{colour}^{RESET}", " ".repeat(SCALE_LABEL + at)));
+    lines
+}
+
+// "-50 |----+----|----+----| +50": ends and centre, and ticks at ±25
+fn scale(width: usize) -> String {
+    let quarter = width / 4;
+    let marks: String = (0..width)
+        .map(|i| match i {
+            _ if i == 0 || i == width - 1 || i == width / 2 => '|',
+            _ if i == quarter || i == width - 1 - quarter => '+',
+            _ => '-',
+        })
+        .collect();
+    format!("-50 {marks} +50")
+}
+
+// A note name in 5x5 letters. Characters without a glyph (such as Helmholtz
+// octave marks) are printed as they are on the bottom row.
+fn big(note: &str) -> Vec<String> {
+    let mut rows = vec![String::new(); GLYPH_ROWS];
+    for (i, c) in note.chars().enumerate() {
+        // The letter may be lower case (Helmholtz); after it, b is a flat
+        let c = match c {
+            '♯' => '#',
+            '♭' => 'b',
+            c if i == 0 => c.to_ascii_uppercase(),
+            c => c,
+        };
+        match GLYPHS.iter().find(|(g, _)| *g == c) {
+            Some((_, glyph)) => {
+                for (row, line) in rows.iter_mut().zip(glyph) {
+                    row.push_str(&line.replace('#', "█"));
+                    row.push(' ');
+                }
+            }
+            None => {
+                for (i, row) in rows.iter_mut().enumerate() {
+                    row.push(if i == GLYPH_ROWS - 1 { c } else { ' ' });
+                    row.push(' ');
+                }
+            }
+        }
+    }
+    rows
+}
+
+// Windows consoles only follow ANSI sequences once asked to
+#[cfg(windows)]
+fn enable_ansi() {
+    use windows_sys::Win32::System::Console::{
+        GetConsoleMode, GetStdHandle, SetConsoleMode, ENABLE_VIRTUAL_TERMINAL_PROCESSING, STD_OUTPUT_HANDLE,
+    };
+    // SAFETY: the handle comes straight from GetStdHandle and the mode is a
+    // plain integer we pass by pointer
+    unsafe {
+        let out = GetStdHandle(STD_OUTPUT_HANDLE);
+        let mut mode = 0;
+        if GetConsoleMode(out, &mut mode) != 0 {
+            SetConsoleMode(out, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING);
+        }
+    }
+}
+
+#[cfg(not(windows))]
+fn enable_ansi() {}
@@ -0,0 +1,327 @@
+// ---------------------------- Config validation ----------------------------
+//
+// What `check` looks for beyond what loading already rejects: note_map keys
+// that can never match ("H4", "A3:mutd"), key sequences that can't be sent
+// ("Ctl+S"), settings outside their useful range, and keys that mean the
+// same thing so only one of them is ever used ("A#" and "Bb"). Errors are
+// things that can't work as written; warnings are legal but probably not
+// what was meant. Each problem names the config field it is about.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    articulation, chords, dynamics, find_host, keys, logging, metronome, morse, note_keys, note_to_midi, plugins, polyphony, profiles, rhythms, script, strikes, vibrato, Action,
+    Config, Detector, Mapping, MappingMode,
+};
+
+pub struct Problem {
+    pub error: bool,
+    // Config field, e.g. note_map."A3:mutd" or profiles.work.note_map.A4.sequence
+    pub place: String,
+    pub message: String,
+}
+
+#[derive(Default)]
+struct Problems(Vec<Problem>);
+
+impl Problems {
+    fn error(&mut self, place: impl Into<String>, message: impl Into<String>) {
+        self.0.push(Problem { error: true, place: place.into(), message: message.into() });
+    }
+
+    fn warning(&mut self, place: impl Into<String>, message: impl Into<String>) {
+        self.0.push(Problem { error: false, place: place.into(), message: message.into() });
+    }
+}
+
+/// Everything wrong with `cfg` and its performers, errors first.
+pub fn check(cfg: &Config) -> Vec<Problem> {
+    let mut found = Problems::default();
+    check_one(cfg, "", &mut found);
+    for p in &cfg.performers {
+        let Some(name) = &p.performer else { continue };
+        let prefix = match p.string {
+            Some(n) => format!("hexaphonic.strings.{n}."),
+            None => format!("performers.{}.", field(name)),
+        };
+        let mut own = Problems::default();
+        check_one(p, &prefix, &mut own);
+        // Performers inherit the top level; its problems are reported once
+        let inherited = |q: &Problem| found.0.iter().any(|f| format!("{prefix}{}", f.place) == q.place && f.message == q.message);
+        own.0.retain(|q| !inherited(q));
+        found.0.extend(own.0);
+    }
+    found.0.sort_by_key(|p| !p.error);
+    found.0
+}
+
+fn check_one(cfg: &Config, prefix: &str, found: &mut Problems) {
+    let at = |name: &str| format!("{prefix}{name}");
+
+    // Settings
+    if cfg.a4_hz <= 0.0 {
+        found.error(at("a4_hz"), format!("must be above 0, got {}", cfg.a4_hz));
+    } else if !(380.0..=500.0).contains(&cfg.a4_hz) {
+        found.warning(at("a4_hz"), format!("{} Hz is far from any usual concert pitch (415-466)", cfg.a4_hz));
+    }
+    tolerance(found, at("tolerance_cents"), cfg.tolerance_cents);
+    correlation(found, at("corr_threshold"), cfg.corr_threshold);
+    if cfg.min_hz <= 0.0 { found.error(at("min_hz"), format!("must be above 0, got {}", cfg.min_hz)); }
+    if cfg.min_hz >= cfg.max_hz {
+        found.error(at("max_hz"), format!("must be above min_hz ({} >= {})", cfg.min_hz, cfg.max_hz));
+    }
+    if cfg.analysis_rate > 0 && cfg.max_hz * 2.5 > cfg.analysis_rate as f32 {
+        found.warning(at("max_hz"), format!(
+            "{} Hz is too high to detect at an analysis_rate of {} Hz; raise analysis_rate to at least {:.0}",
+            cfg.max_hz, cfg.analysis_rate, cfg.max_hz * 2.5
+        ));
+    }
+    if let Some(lowest) = cfg.instrument.as_ref().and_then(|_| cfg.strings.tuning.iter().filter_map(|n| note_to_midi(n)).min()) {
+        if crate::midi_to_freq(lowest) < cfg.min_hz {
+            found.warning(at("min_hz"), format!(
+                "{} Hz is above the lowest open string ({}), so it can't be heard",
+                cfg.min_hz,
+                crate::notation::spell_midi(lowest)
+            ));
+        }
+    }
+    if cfg.min_rms < 0.0 { found.error(at("min_rms"), format!("can't be negative, got {}", cfg.min_rms)); }
+    if let Detector::Plugin(name) = &cfg.detector {
+        if let Err(e) = plugins::check(&cfg.plugins, name, true) {
+            found.error(at("detector.plugin"), format!("{e:#}; autocorr is used instead"));
+        }
+    }
+    if let Some(Err(e)) = cfg.host.as_deref().map(find_host) {
+        found.warning(at("host"), format!("{e:#}; the default one is used"));
+    }
+    if cfg.loopback.is_some() {
+        if cfg.network_input.enabled {
+            found.warning(at("loopback"), "network_input is enabled, so the audio comes from the network instead");
+        } else if cfg.input_device.is_some() {
+            found.warning(at("input_device"), "ignored while loopback is on");
+        }
+    }
+
+    // Mappings
+    note_map(cfg, &cfg.note_map, &at("note_map"), found);
+    for (name, p) in &cfg.profiles {
+        let place = at(&format!("profiles.{}", field(name)));
+        note_map(cfg, &p.note_map, &format!("{place}.note_map"), found);
+        if let Some(t) = p.tolerance_cents { tolerance(found, format!("{place}.tolerance_cents"), t); }
+        if let Some(c) = p.corr_threshold { correlation(found, format!("{place}.corr_threshold"), c); }
+    }
+    if let Some(point) = &cfg.split.point {
+        if note_to_midi(point).is_none() { found.error(at("split.point"), not_a_note(point)); }
+    }
+    for (zone, profile, mapping) in [
+        ("lower", &cfg.split.lower_profile, &cfg.split.lower),
+        ("upper", &cfg.split.upper_profile, &cfg.split.upper),
+    ] {
+        if let Some(name) = profile.as_ref().filter(|n| !cfg.profiles.contains_key(*n)) {
+            found.error(at(&format!("split.{zone}_profile")), format!("there is no profile {name:?}"));
+        }
+        if let Some(m) = mapping { self::mapping(cfg, m, &at(&format!("split.{zone}")), found); }
+    }
+    for (i, s) in cfg.sequences.iter().enumerate() {
+        let place = at(&format!("sequences[{i}]"));
+        if s.notes.is_empty() { found.error(format!("{place}.notes"), "has no notes"); }
+        for n in s.notes.iter().filter(|n| note_to_midi(n).is_none()) {
+            found.error(format!("{place}.notes"), not_a_note(n));
+        }
+        mapping(cfg, &s.mapping, &place, found);
+    }
+    for (i, r) in cfg.rhythms.iter().enumerate() {
+        let place = at(&format!("rhythms[{i}]"));
+        if !rhythms::valid(&r.pattern) {
+            found.error(format!("{place}.pattern"), format!("{:?} should be '.' (short) and '-' (long), such as \"..-\"", r.pattern));
+        }
+        if let Some(n) = r.note.as_ref().filter(|n| note_to_midi(n).is_none()) { found.error(format!("{place}.note"), not_a_note(n)); }
+        mapping(cfg, &r.mapping, &place, found);
+    }
+    for (i, item) in cfg.scanning.items.iter().enumerate() {
+        action(cfg, &item.action, &at(&format!("scanning.items[{i}]")), found);
+    }
+    if let Some(n) = cfg.scanning.advance_note.as_ref().filter(|n| note_to_midi(n).is_none()) {
+        found.error(at("scanning.advance_note"), not_a_note(n));
+    }
+    if let Some(n) = cfg.morse.note.as_ref().filter(|n| note_to_midi(n).is_none()) {
+        found.error(at("morse.note"), not_a_note(n));
+    }
+    for (direction, a) in [("up", &cfg.glissando.up), ("down", &cfg.glissando.down)] {
+        if let Some(a) = a { action(cfg, a, &at(&format!("glissando.{direction}")), found); }
+    }
+    for n in cfg.strings.tuning.iter().filter(|n| note_to_midi(n).is_none()) {
+        found.error(at("strings.tuning"), not_a_note(n));
+    }
+    for (code, entry) in &cfg.morse.alphabet {
+        if let morse::Entry::Keys { keys } = entry {
+            if let Err(e) = keys::parse(keys) { found.error(at(&format!("morse.alphabet.{}", field(code))), format!("{e:#}")); }
+        }
+    }
+    for (name, sequence) in [("toggle_actions", &cfg.hotkeys.toggle_actions), ("next_profile", &cfg.hotkeys.next_profile)] {
+        if let Some(Err(e)) = sequence.as_deref().map(keys::parse) {
+            found.error(at(&format!("hotkeys.{name}")), format!("{e:#}"));
+        }
+    }
+    for (name, message) in logging::problems(&cfg.log) {
+        found.error(at(&format!("log.{name}")), message);
+    }
+}
+
+fn tolerance(found: &mut Problems, place: String, cents: f32) {
+    if cents <= 0.0 {
+        found.error(place, format!("must be above 0, got {cents}"));
+    } else if cents > 50.0 {
+        found.warning(place, format!("±{cents} cents reaches past the neighbouring notes (±50)"));
+    }
+}
+
+fn correlation(found: &mut Problems, place: String, value: f32) {
+    if !(0.0..=1.0).contains(&value) { found.error(place, format!("must be between 0 and 1, got {value}")); }
+}
+
+// Keys, their mappings, and keys that mean the same thing
+fn note_map(cfg: &Config, map: &HashMap<String, Mapping>, place: &str, found: &mut Problems) {
+    let mut meanings: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+    let mut keys: Vec<&String> = map.keys().collect();
+    keys.sort();
+    for key in keys {
+        let at = format!("{place}.{}", field(key));
+        match meaning(key) {
+            Ok(m) => meanings.entry(m).or_default().push(key),
+            Err(e) => found.error(&at, e),
+        }
+        mapping(cfg, &map[key], &at, found);
+    }
+    for same in meanings.values().filter(|keys| keys.len() > 1) {
+        let listed = same.iter().map(|k| format!("{k:?}")).collect::<Vec<_>>().join(" and ");
+        found.error(place, format!("{listed} are the same key; only one of them is used"));
+    }
+    // Equally wide ranges over the same note are picked between by name
+    let ranges: Vec<(&String, i32, i32)> = map
+        .keys()
+        .filter_map(|k| match note_keys::parse(k) {
+            Some(note_keys::Pattern::Range(lo, hi)) if note_to_midi(k).is_none() => Some((k, lo, hi)),
+            _ => None,
+        })
+        .collect();
+    for (i, (a, lo, hi)) in ranges.iter().enumerate() {
+        for (b, lo2, hi2) in &ranges[i + 1..] {
+            if hi - lo == hi2 - lo2 && lo <= hi2 && lo2 <= hi {
+                let (a, b) = if a < b { (a, b) } else { (b, a) };
+                found.warning(place, format!("{a:?} and {b:?} overlap and are as wide; {a:?} wins where they do"));
+            }
+        }
+    }
+}
+
+// What a note_map key stands for, so that spellings of the same thing
+// compare equal; or why it can never match
+fn meaning(key: &str) -> Result<String, String> {
+    if key == "tap" || key == "slap" { return Ok(key.to_string()); }
+    if let Some((note, count)) = strikes::parse(key) {
+        return match note_to_midi(note) {
+            Some(midi) if count >= 2 => Ok(format!("{midi} x{count}")),
+            _ => Err(format!("{key:?} should be a note and a number of strikes from 2 up, such as \"A4 x2\"")),
+        };
+    }
+    if key.contains('&') {
+        let mut notes = polyphony::parse(key).ok_or_else(|| {
+            format!("{key:?} should be two or more note names joined with &, such as \"E2&B2\"")
+        })?;
+        notes.sort_unstable();
+        return Ok(format!("{notes:?}"));
+    }
+    let (rest, vibrato) = match key.split_once('+') {
+        Some((rest, m)) if format!("+{m}") == vibrato::MODIFIER => (rest, true),
+        Some((_, m)) => return Err(format!("unknown modifier \"+{m}\" (only {} exists)", vibrato::MODIFIER)),
+        None => (key, false),
+    };
+    let (rest, qualifier) = match rest.split_once(':') {
+        Some((rest, q)) if articulation::is_name(q) || dynamics::is_name(q) => (rest, Some(q)),
+        Some((_, q)) => {
+            return Err(format!("unknown qualifier \":{q}\" (pluck, strum, bowed, muted, piano, mezzo or forte)"));
+        }
+        None => (rest, None),
+    };
+    let (note, string) = match rest.split_once('@') {
+        Some((note, s)) => match s.parse::<usize>() {
+            Ok(s) if s >= 1 => (note, Some(s)),
+            _ => return Err(format!("unknown string {s:?} (a number such as 5 or 5th_string)")),
+        },
+        None => (rest, None),
+    };
+    let plain = !vibrato && qualifier.is_none() && string.is_none();
+    if let Some(midi) = note_to_midi(note) {
+        return Ok(format!("{midi}@{string:?}:{qualifier:?}+{vibrato}"));
+    }
+    if plain {
+        if let Some(pattern) = note_keys::parse(note) { return Ok(format!("{pattern:?}")); }
+        if let Some(chord) = chords::parse(note) { return Ok(chord.name()); }
+        return Err(format!(
+            "{note:?} isn't a note name; expected a note (A4, Bb3, La4, 61), pitch class (F#), range (C3-C4), chord (Am), notes joined with & or +, an interval (P5 above A3), tap or slap"
+        ));
+    }
+    Err(not_a_note(note))
+}
+
+fn not_a_note(name: &str) -> String {
+    format!("{name:?} isn't a note name (such as A4, Bb3, La4 or MIDI number 61)")
+}
+
+fn mapping(cfg: &Config, m: &Mapping, place: &str, found: &mut Problems) {
+    action(cfg, &m.action, place, found);
+    if let Some(a) = &m.on_release { action(cfg, a, &format!("{place}.on_release"), found); }
+    if let Some(undo) = m.undo.as_deref().filter(|u| !u.eq_ignore_ascii_case("repeat")) {
+        if let Err(e) = keys::parse(undo) { found.error(format!("{place}.undo"), format!("{e:#}")); }
+    }
+    if m.mode == MappingMode::Hold && !m.holds() {
+        found.warning(format!("{place}.mode"), "\"hold\" only applies to keys and gamepad button mappings; this one triggers once");
+    }
+    if m.repeat_while_held && m.holds() {
+        found.warning(format!("{place}.repeat_while_held"), "a \"hold\" mapping keeps its keys down for the note instead of repeating them");
+    } else if m.repeat_while_held && m.quantize != metronome::Quantize::Off {
+        found.warning(format!("{place}.repeat_while_held"), "a quantized mapping fires once on its beat and doesn't repeat");
+    }
+    if m.repeat_ms.is_some() && !m.repeat_while_held {
+        found.warning(format!("{place}.repeat_ms"), "has no effect without repeat_while_held = true");
+    }
+    if let Some(t) = m.tolerance_cents { tolerance(found, format!("{place}.tolerance_cents"), t); }
+    if let Some(c) = m.corr_threshold { correlation(found, format!("{place}.corr_threshold"), c); }
+}
+
+fn action(cfg: &Config, a: &Action, place: &str, found: &mut Problems) {
+    match a {
+        Action::Keys { sequence } => {
+            if let Err(e) = keys::parse(sequence) { found.error(format!("{place}.sequence"), format!("{e:#}")); }
+        }
+        Action::Profile { name } => {
+            if let profiles::Switch::To(Some(name)) = profiles::Switch::parse(name) {
+                if !cfg.profiles.contains_key(&name) {
+                    found.error(format!("{place}.name"), format!("there is no profile {name:?}"));
+                }
+            }
+        }
+        Action::Plugin(p) => {
+            if let Err(e) = plugins::check(&cfg.plugins, &p.name, false) { found.error(format!("{place}.name"), format!("{e:#}")); }
+        }
+        Action::Script(s) => {
+            if let Err(e) = script::check(s) { found.error(place.to_string(), format!("{e:#}")); }
+        }
+        Action::Macro { steps } => {
+            for (i, step) in steps.iter().enumerate() {
+                action(cfg, &step.action, &format!("{place}.steps[{i}]"), found);
+            }
+        }
+        _ => {}
+    }
+}
+
+// A key as written in a dotted path: bare when TOML allows it, else quoted
+fn field(key: &str) -> String {
+    if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        key.to_string()
+    } else {
+        format!("{key:?}")
+    }
+}
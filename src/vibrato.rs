@@ -0,0 +1,108 @@
+// ---------------------------- Vibrato ----------------------------
+//
+// "A4+vibrato" keys fire when the note is played with vibrato, plain "A4"
+// when it is held straight. The pitch of the current note is followed hop by
+// hop; once it has sounded for `window_ms` the cents track is judged: a
+// vibrato swings back and forth a few times per second by more than a few
+// cents, while a straight note only jitters.
+
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct VibratoConfig {
+    // How long a note is followed before it is judged
+    #[serde(default = "default_window_ms")]
+    pub window_ms: u64,
+    // Swing (half of peak to peak, in cents) from which it counts as vibrato
+    #[serde(default = "default_min_depth_cents")]
+    pub min_depth_cents: f32,
+    // Range of vibrato rates, in cycles per second
+    #[serde(default = "default_min_rate_hz")]
+    pub min_rate_hz: f32,
+    #[serde(default = "default_max_rate_hz")]
+    pub max_rate_hz: f32,
+}
+
+fn default_window_ms() -> u64 { 500 }
+fn default_min_depth_cents() -> f32 { 8.0 }
+fn default_min_rate_hz() -> f32 { 3.5 }
+fn default_max_rate_hz() -> f32 { 9.0 }
+
+impl Default for VibratoConfig {
+    fn default() -> Self {
+        Self {
+            window_ms: default_window_ms(),
+            min_depth_cents: default_min_depth_cents(),
+            min_rate_hz: default_min_rate_hz(),
+            max_rate_hz: default_max_rate_hz(),
+        }
+    }
+}
+
+/// Key suffix of vibrato variants.
+pub const MODIFIER: &str = "+vibrato";
+
+pub struct VibratoTracker {
+    cfg: VibratoConfig,
+    // Pitch in fractional MIDI notes since the note began
+    track: Vec<(Instant, f32)>,
+    // Note being followed
+    note: Option<i32>,
+}
+
+impl VibratoTracker {
+    pub fn new(cfg: &VibratoConfig) -> Self {
+        Self { cfg: cfg.clone(), track: Vec::new(), note: None }
+    }
+
+    /// Feed one frame: the pitch in fractional MIDI notes, None when silent.
+    pub fn update(&mut self, now: Instant, pitch: Option<f32>) {
+        let Some(exact) = pitch else {
+            self.track.clear();
+            self.note = None;
+            return;
+        };
+        // A wide vibrato may cross into the neighbouring semitone and is still
+        // the same note
+        if self.note.is_none_or(|n| (exact - n as f32).abs() > 1.0) {
+            self.track.clear();
+            self.note = Some(exact.round() as i32);
+        }
+        self.track.push((now, exact));
+        // Only the newest window is judged
+        let window = Duration::from_millis(self.cfg.window_ms);
+        let keep = self.track.iter().position(|(t, _)| now.duration_since(*t) <= window).unwrap_or(0);
+        if keep > 1 { self.track.drain(..keep - 1); }
+    }
+
+    /// Whether the current note has vibrato; None until it has sounded for
+    /// window_ms.
+    pub fn judged(&self, now: Instant) -> Option<bool> {
+        let (start, _) = *self.track.first()?;
+        let span = now.duration_since(start);
+        if span < Duration::from_millis(self.cfg.window_ms) || self.track.len() < 4 { return None; }
+        // Cents around a straight line, so a slow drift isn't a swing
+        let n = self.track.len() as f32;
+        let xs: Vec<f32> = self.track.iter().map(|(t, _)| t.duration_since(start).as_secs_f32()).collect();
+        let ys: Vec<f32> = self.track.iter().map(|(_, p)| p * 100.0).collect();
+        let (mx, my) = (xs.iter().sum::<f32>() / n, ys.iter().sum::<f32>() / n);
+        let sxx: f32 = xs.iter().map(|x| (x - mx).powi(2)).sum();
+        let slope = if sxx > 0.0 { xs.iter().zip(&ys).map(|(x, y)| (x - mx) * (y - my)).sum::<f32>() / sxx } else { 0.0 };
+        let residual: Vec<f32> = xs.iter().zip(&ys).map(|(x, y)| y - my - slope * (x - mx)).collect();
+        let depth = residual.iter().map(|r| r * r).sum::<f32>().sqrt() / n.sqrt() * std::f32::consts::SQRT_2;
+        if depth < self.cfg.min_depth_cents { return Some(false); }
+        // Count swings through the center, ignoring jitter smaller than a
+        // quarter of the depth
+        let band = depth / 4.0;
+        let mut side = 0.0f32;
+        let mut crossings = 0;
+        for r in residual {
+            if r.abs() < band { continue; }
+            if side != 0.0 && r.signum() != side { crossings += 1; }
+            side = r.signum();
+        }
+        let rate = crossings as f32 / 2.0 / span.as_secs_f32();
+        Some((self.cfg.min_rate_hz..=self.cfg.max_rate_hz).contains(&rate))
+    }
+}
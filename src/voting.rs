@@ -0,0 +1,79 @@
+// ---------------------------- Note voting ----------------------------
+//
+// Instead of requiring note_hold_frames consecutive frames of the same note,
+// the last `frames` frames vote: the note with the most confidence behind it
+// wins once it holds at least `agree` of them with an average confidence of
+// `min_confidence`. One bad frame in a held note then costs nothing; while a
+// note holds the vote its last good reading stands in for frames that read
+// something else.
+
+use serde::Deserialize;
+use std::collections::VecDeque;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct VotingConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    // Frames that vote
+    #[serde(default = "default_frames")]
+    pub frames: usize,
+    // Share of them that must agree (0..1)
+    #[serde(default = "default_agree")]
+    pub agree: f32,
+    // Average correlation of the agreeing frames
+    #[serde(default = "default_min_confidence")]
+    pub min_confidence: f32,
+}
+
+fn default_frames() -> usize { 6 }
+fn default_agree() -> f32 { 0.7 }
+fn default_min_confidence() -> f32 { 0.5 }
+
+impl Default for VotingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            frames: default_frames(),
+            agree: default_agree(),
+            min_confidence: default_min_confidence(),
+        }
+    }
+}
+
+pub struct NoteVote {
+    cfg: VotingConfig,
+    // (note, pitch, confidence) of the recent frames, None when silent
+    votes: VecDeque<Option<(i32, f32, f32)>>,
+}
+
+impl NoteVote {
+    pub fn new(cfg: &VotingConfig) -> Self {
+        Self { cfg: cfg.clone(), votes: VecDeque::new() }
+    }
+
+    /// Feed one frame's pitch and confidence; the winning note's pitch, or
+    /// None while no note has won.
+    pub fn push(&mut self, detected: Option<(f32, f32)>) -> Option<f32> {
+        if !self.cfg.enabled { return detected.map(|(f0, _)| f0); }
+        let frames = self.cfg.frames.max(1);
+        self.votes.push_back(detected.map(|(f0, clarity)| (crate::freq_to_midi(f0).0, f0, clarity)));
+        if self.votes.len() > frames { self.votes.pop_front(); }
+
+        // Confidence behind each note
+        let mut tally: Vec<(i32, f32, usize)> = Vec::new();
+        for &(note, _, clarity) in self.votes.iter().flatten() {
+            match tally.iter_mut().find(|(n, ..)| *n == note) {
+                Some((_, sum, count)) => {
+                    *sum += clarity;
+                    *count += 1;
+                }
+                None => tally.push((note, clarity, 1)),
+            }
+        }
+        let (winner, sum, count) = tally.into_iter().max_by(|a, b| a.1.total_cmp(&b.1))?;
+        let won = count as f32 >= self.cfg.agree * frames as f32 && sum / count as f32 >= self.cfg.min_confidence;
+        if !won { return None; }
+        // The newest reading of the winner
+        self.votes.iter().rev().flatten().find(|(n, ..)| *n == winner).map(|&(_, f0, _)| f0)
+    }
+}
@@ -0,0 +1,144 @@
+// ---------------------------- WebSocket events ----------------------------
+//
+// `[websocket]` broadcasts what the status line shows as JSON text messages,
+// one per event, to every connected client: browser overlays (an OBS browser
+// source showing the pitch), dashboards and other tools that want the
+// detector's output without linking the engine. Clients only listen; anything
+// they send is ignored. Events go through a bounded queue to a thread of
+// their own, so a slow or stuck client loses events (and is dropped) instead
+// of holding up detection.
+//
+//   {"type":"pitch","hz":440.2,"note":"A4","cents":0.8,"confidence":0.97,"ms":...}
+//   {"type":"silence","ms":...}
+//   {"type":"trigger","note":"A4","action":"keys:Space","ms":...}
+//   {"type":"status","text":"Profile: daw","ms":...}
+//
+// `ms` is the time since the Unix epoch. Performers and hexaphonic strings
+// add "performer". Silence is sent once when a pitch ends, not every frame.
+
+use crossbeam_channel::{bounded, Receiver, Sender};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::net::{TcpListener, TcpStream};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tungstenite::{Message, WebSocket};
+
+use crate::pipeline::NoteEvent;
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct WebsocketConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    // Address and port to serve on; 127.0.0.1 keeps it to this machine
+    #[serde(default = "default_listen")]
+    pub listen: String,
+    // A pitch event every analysis frame; false sends only triggers,
+    // silence and status events
+    #[serde(default = "default_pitch_frames")]
+    pub pitch_frames: bool,
+}
+
+fn default_listen() -> String { "127.0.0.1:7402".to_string() }
+fn default_pitch_frames() -> bool { true }
+
+impl Default for WebsocketConfig {
+    fn default() -> Self {
+        Self { enabled: false, listen: default_listen(), pitch_frames: default_pitch_frames() }
+    }
+}
+
+// Events waiting to go out; more than this and new ones are dropped
+const QUEUE: usize = 1024;
+// A client that can't take a message within this long is disconnected
+const WRITE_TIMEOUT: Duration = Duration::from_millis(250);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(2);
+
+static SERVER: OnceLock<Sender<Value>> = OnceLock::new();
+
+/// Start serving events for as long as the program runs. An address that
+/// can't be listened on only gets a warning.
+pub fn start(cfg: &WebsocketConfig) {
+    if !cfg.enabled || SERVER.get().is_some() { return; }
+    let listener = match TcpListener::bind(&cfg.listen) {
+        Ok(l) => l,
+        Err(e) => {
+            tracing::warn!("WebSocket events unavailable on {}: {e}", cfg.listen);
+            return;
+        }
+    };
+    let (tx, rx) = bounded(QUEUE);
+    let (clients_tx, clients_rx) = bounded(16);
+    std::thread::spawn(move || accept(listener, clients_tx));
+    let pitch_frames = cfg.pitch_frames;
+    std::thread::spawn(move || broadcast(rx, clients_rx, pitch_frames));
+    SERVER.set(tx).ok();
+    println!("WebSocket events on ws://{}", cfg.listen);
+}
+
+/// Whether anything is listening for events.
+pub fn active() -> bool { SERVER.get().is_some() }
+
+/// Send `event` to every client; `performer` labels it.
+pub fn publish(performer: Option<&str>, event: &NoteEvent) {
+    let Some(tx) = SERVER.get() else { return };
+    let mut value = match event {
+        NoteEvent::Pitch { hz, note, cents, confidence } => {
+            json!({ "type": "pitch", "hz": hz, "note": note, "cents": cents, "confidence": confidence })
+        }
+        NoteEvent::Silence => json!({ "type": "silence" }),
+        NoteEvent::Trigger { note, action } => json!({ "type": "trigger", "note": note, "action": crate::action_name(action) }),
+        NoteEvent::Status(text) => json!({ "type": "status", "text": text }),
+    };
+    let ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+    value["ms"] = ms.into();
+    if let Some(p) = performer { value["performer"] = p.into(); }
+    // A full queue means the clients are behind; they miss this one
+    tx.try_send(value).ok();
+}
+
+fn accept(listener: TcpListener, clients: Sender<WebSocket<TcpStream>>) {
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else { continue };
+        let from = stream.peer_addr().map_or_else(|_| "?".to_string(), |a| a.to_string());
+        // A client that never finishes the handshake mustn't hold up the next
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT)).ok();
+        stream.set_write_timeout(Some(WRITE_TIMEOUT)).ok();
+        stream.set_nodelay(true).ok();
+        match tungstenite::accept(stream) {
+            Ok(socket) => {
+                tracing::info!("WebSocket client {from} connected");
+                if clients.send(socket).is_err() { return; }
+            }
+            Err(e) => tracing::debug!("WebSocket handshake with {from} failed: {e}"),
+        }
+    }
+}
+
+fn broadcast(events: Receiver<Value>, clients: Receiver<WebSocket<TcpStream>>, pitch_frames: bool) {
+    let mut sockets: Vec<WebSocket<TcpStream>> = Vec::new();
+    // Performers ("" for none) whose last event was silence
+    let mut silent: HashSet<String> = HashSet::new();
+    for event in events {
+        sockets.extend(clients.try_iter());
+        let performer = event["performer"].as_str().unwrap_or_default();
+        match event["type"].as_str() {
+            Some("silence") if !silent.insert(performer.to_string()) => continue,
+            Some("pitch") => {
+                silent.remove(performer);
+                if !pitch_frames { continue; }
+            }
+            _ => {}
+        }
+        if sockets.is_empty() { continue; }
+        let text = event.to_string();
+        sockets.retain_mut(|s| match s.send(Message::Text(text.clone())) {
+            Ok(()) => true,
+            Err(e) => {
+                tracing::info!("WebSocket client dropped: {e}");
+                false
+            }
+        });
+    }
+}
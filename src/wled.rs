@@ -0,0 +1,110 @@
+// ---------------------------- WLED ----------------------------
+//
+// Drives a WLED LED controller so stage lighting follows the playing: the
+// strip takes the note color (hue by pitch class, brightness by intonation)
+// and flashes white on triggers. UDP uses WLED's realtime DRGB protocol and
+// needs the LED count; HTTP sets the main segment color through the JSON API.
+
+use anyhow::{anyhow, Context, Result};
+use crossbeam_channel::bounded;
+use serde::Deserialize;
+use std::io::{Read, Write};
+use std::net::{TcpStream, UdpSocket};
+use std::time::Duration;
+
+use crate::feedback::{drive_light, Light};
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct WledConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    // Address of the WLED controller, e.g. "192.168.1.50" or "wled.local"
+    #[serde(default)]
+    pub host: String,
+    #[serde(default)]
+    pub transport: WledTransport,
+    // LEDs on the strip (UDP only)
+    #[serde(default = "default_leds")]
+    pub leds: usize,
+    #[serde(default = "default_flash_ms")]
+    pub flash_ms: u64,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WledTransport {
+    // Realtime UDP on port 21324: low latency
+    #[default]
+    Udp,
+    // JSON API over HTTP: no LED count needed, slower
+    Http,
+}
+
+fn default_leds() -> usize { 30 }
+fn default_flash_ms() -> u64 { 150 }
+
+impl Default for WledConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: String::new(),
+            transport: WledTransport::default(),
+            leds: default_leds(),
+            flash_ms: default_flash_ms(),
+        }
+    }
+}
+
+const UDP_PORT: u16 = 21324;
+// DRGB realtime packet type, and seconds WLED waits before resuming its own effects
+const DRGB: u8 = 2;
+const REALTIME_TIMEOUT_SECS: u8 = 2;
+
+pub fn start(cfg: &WledConfig, tolerance: f32) -> Light {
+    let (tx, rx) = bounded(16);
+    let cfg = cfg.clone();
+    std::thread::spawn(move || {
+        let flash = Duration::from_millis(cfg.flash_ms);
+        let result = match cfg.transport {
+            WledTransport::Udp => UdpSocket::bind("0.0.0.0:0")
+                .context("UDP socket")
+                .and_then(|socket| drive_light(&rx, flash, |color| send_udp(&socket, &cfg, color))),
+            WledTransport::Http => drive_light(&rx, flash, |color| {
+                // A controller that is briefly unreachable shouldn't end the feedback
+                if let Err(e) = send_http(&cfg.host, color) { tracing::warn!("WLED: {e:#}"); }
+                Ok(())
+            }),
+        };
+        if let Err(e) = result { tracing::warn!("WLED: {e:#}"); }
+    });
+    Light::new(tx, tolerance)
+}
+
+fn send_udp(socket: &UdpSocket, cfg: &WledConfig, [r, g, b]: [u8; 3]) -> Result<()> {
+    let mut packet = Vec::with_capacity(2 + 3 * cfg.leds);
+    packet.extend_from_slice(&[DRGB, REALTIME_TIMEOUT_SECS]);
+    for _ in 0..cfg.leds { packet.extend_from_slice(&[r, g, b]); }
+    // Unreachable hosts only produce transient errors; keep going
+    if let Err(e) = socket.send_to(&packet, (cfg.host.as_str(), UDP_PORT)) {
+        tracing::warn!("WLED: {e}");
+    }
+    Ok(())
+}
+
+fn send_http(host: &str, [r, g, b]: [u8; 3]) -> Result<()> {
+    let body = format!(r#"{{"on":true,"bri":255,"tt":0,"seg":[{{"col":[[{r},{g},{b}]]}}]}}"#);
+    let addr = if host.contains(':') { host.to_string() } else { format!("{host}:80") };
+    let mut stream = TcpStream::connect(&addr).with_context(|| format!("cannot connect to {addr}"))?;
+    stream.set_read_timeout(Some(Duration::from_secs(2))).ok();
+    write!(
+        stream,
+        "POST /json/state HTTP/1.1\r\nHost: {host}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    let mut status = [0u8; 12];
+    stream.read_exact(&mut status).context("no response")?;
+    match &status[9..12] {
+        b"200" => Ok(()),
+        code => Err(anyhow!("HTTP {}", String::from_utf8_lossy(code))),
+    }
+}